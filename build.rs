@@ -0,0 +1,61 @@
+//! Prepares the files under `static/` for `src/assets.rs`: each gets a
+//! name with a hash of its contents, so it can be cached forever, and
+//! brotli and gzip copies for clients that accept one. Every file in the
+//! directory is served, so adding one there is all it takes.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::{Compression, Crc};
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+    let static_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("static");
+    // picks up added and removed files
+    println!("cargo:rerun-if-changed={}", static_dir.display());
+
+    let mut names: Vec<String> = fs::read_dir(&static_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+
+    let mut table = String::from("&[\n");
+    for name in &names {
+        let path = static_dir.join(name);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let data = fs::read(&path).unwrap();
+
+        let mut crc = Crc::new();
+        crc.update(&data);
+        let (stem, extension) = name.rsplit_once('.').unwrap();
+        let hashed_name = format!("{}.{:08x}.{}", stem, crc.sum(), extension);
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+        gzip.write_all(&data).unwrap();
+        let gzip_path = out_dir.join(format!("{}.gz", name));
+        fs::write(&gzip_path, gzip.finish().unwrap()).unwrap();
+
+        // quality 11 with a 4 MiB window, as slow as brotli gets; it only
+        // runs when a file changes
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        brotli.write_all(&data).unwrap();
+        let brotli_path = out_dir.join(format!("{}.br", name));
+        fs::write(&brotli_path, brotli.into_inner()).unwrap();
+
+        table.push_str(&format!(
+            "    ({:?}, {:?}, include_bytes!({:?}), include_bytes!({:?}), include_bytes!({:?})),\n",
+            name,
+            hashed_name,
+            path.display().to_string(),
+            gzip_path.display().to_string(),
+            brotli_path.display().to_string(),
+        ));
+    }
+    table.push(']');
+    fs::write(out_dir.join("static_files.rs"), table).unwrap();
+}
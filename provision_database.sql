@@ -1,25 +1,397 @@
+DROP TABLE signing_key CASCADE;
+DROP TABLE digest_run CASCADE;
+DROP TABLE secret_finding CASCADE;
+DROP TABLE protection_rule CASCADE;
+DROP TABLE edit_filter_hit CASCADE;
+DROP TABLE edit_filter CASCADE;
+DROP TABLE restore_checkpoint CASCADE;
+DROP TABLE render_generation CASCADE;
 
+DROP TABLE sync_conflict CASCADE;
+DROP TABLE sync_page CASCADE;
+DROP TABLE sync_cursor CASCADE;
+DROP TABLE page_data CASCADE;
+DROP TABLE page_read CASCADE;
+DROP TABLE site_appearance CASCADE;
+DROP TABLE user_preferences CASCADE;
+DROP TABLE revision_tags CASCADE;
+DROP TABLE move_log CASCADE;
+DROP TABLE document_link CASCADE;
+DROP TABLE attachment_preview CASCADE;
+DROP TABLE attachment CASCADE;
+DROP TABLE attachment_blob CASCADE;
+DROP TABLE recovery_code CASCADE;
+DROP TABLE user_session CASCADE;
+DROP TABLE wiki_user CASCADE;
+DROP TABLE document_annotation CASCADE;
 DROP TABLE document_history CASCADE;
 DROP TABLE document CASCADE;
+DROP FUNCTION stamp_change CASCADE;
+DROP FUNCTION stamp_current_revision CASCADE;
+DROP SEQUENCE change_seq;
 
 
+-- numbers every change an incremental backup has to carry, see backup.rs
+CREATE SEQUENCE change_seq;
+
+CREATE FUNCTION stamp_change() RETURNS trigger AS $$
+BEGIN
+    NEW.change_id := nextval('change_seq');
+    NEW.changed_at := NOW();
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
 CREATE TABLE document (
     id BIGSERIAL PRIMARY KEY,
     name character varying UNIQUE NOT NULL,
     last_modified timestamp with time zone NOT NULL,
-    current_revision_id BIGINT NULL
+    current_revision_id BIGINT NULL,
+    -- set when the page was merged into another, see Queries::merge_document
+    redirect_to character varying NULL,
+    -- the current revision's `expires:` front matter, see front_matter.rs
+    expires_on DATE NULL
 );
 
+-- typo-tolerant name matching, see titles.rs
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX document_name_trgm ON document USING gin (name gin_trgm_ops);
+
 CREATE TABLE document_history (
     id BIGSERIAL PRIMARY KEY,
     created_at timestamp with time zone NOT NULL,
     document_id BIGINT NOT NULL,
     modified_by character varying NOT NULL,
-    document_data TEXT NOT NULL
+    document_data TEXT NOT NULL,
+    -- 'published' unless the edit was held for review, see --moderation
+    status character varying NOT NULL DEFAULT 'published',
+    reviewed_by character varying NULL,
+    reviewed_at timestamp with time zone NULL,
+    -- hex SHA-256 of document_data, so saves that change nothing can be
+    -- spotted without fetching the text
+    content_hash character varying NOT NULL,
+    -- set when an admin replaced document_data with a tombstone
+    redacted_by character varying NULL,
+    redacted_at timestamp with time zone NULL,
+    redaction_reason character varying NULL,
+    -- the page this revision was written on, when it was merged in from
+    -- another, see Queries::merge_document
+    merged_from character varying NULL,
+    -- why an automatic edit was made, see rename_links.rs
+    summary character varying NULL,
+    -- the page's current revision when this one was written, so approving
+    -- a held edit can't overwrite what was saved after it
+    base_revision_id BIGINT NULL,
+    -- from change_seq on every insert or update, and when the revision
+    -- becomes current
+    change_id BIGINT NOT NULL DEFAULT 0,
+    changed_at timestamp with time zone NOT NULL DEFAULT NOW()
 );
 
+CREATE TRIGGER document_history_change BEFORE INSERT OR UPDATE ON document_history
+    FOR EACH ROW EXECUTE FUNCTION stamp_change();
+CREATE INDEX document_history_change_id ON document_history(change_id);
+
+-- restamps the new current revision, which fires document_history_change
+CREATE FUNCTION stamp_current_revision() RETURNS trigger AS $$
+BEGIN
+    UPDATE document_history SET change_id = 0 WHERE id = NEW.current_revision_id;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER document_current_revision_change AFTER UPDATE OF current_revision_id ON document
+    FOR EACH ROW WHEN (NEW.current_revision_id IS DISTINCT FROM OLD.current_revision_id)
+    EXECUTE FUNCTION stamp_current_revision();
+
 ALTER TABLE document_history ADD CONSTRAINT fk_document_history_document FOREIGN KEY (document_id) REFERENCES document (id);
 CREATE INDEX document_history_document_id ON document_history(document_id);
+CREATE INDEX document_history_pending ON document_history(id) WHERE status = 'pending';
+CREATE INDEX document_history_modified_by ON document_history(modified_by, created_at);
 
 ALTER TABLE document ADD CONSTRAINT fk_document_document_history FOREIGN KEY (current_revision_id) REFERENCES document_history (id);
 
+CREATE TABLE document_annotation (
+    id BIGSERIAL PRIMARY KEY,
+    document_history_id BIGINT NOT NULL,
+    created_at timestamp with time zone NOT NULL,
+    created_by character varying NOT NULL,
+    start_offset INTEGER NOT NULL,
+    end_offset INTEGER NOT NULL,
+    quote TEXT NOT NULL,
+    body TEXT NOT NULL
+);
+
+ALTER TABLE document_annotation ADD CONSTRAINT fk_document_annotation_document_history FOREIGN KEY (document_history_id) REFERENCES document_history (id);
+CREATE INDEX document_annotation_document_history_id ON document_annotation(document_history_id);
+
+CREATE TABLE wiki_user (
+    id BIGSERIAL PRIMARY KEY,
+    username character varying UNIQUE NOT NULL,
+    password_hash character varying NOT NULL,
+    created_at timestamp with time zone NOT NULL,
+    -- signed up at /register rather than added by an admin, see accounts.rs
+    registered BOOLEAN NOT NULL DEFAULT FALSE,
+    -- the address the user proved they receive mail at
+    verified_email character varying NULL,
+    -- base32, NULL unless two-factor authentication is on, see two_factor.rs
+    totp_secret character varying NULL,
+    -- the time step of the last code accepted, so codes work once
+    totp_last_step BIGINT NULL
+);
+
+CREATE TABLE user_session (
+    token character varying PRIMARY KEY,
+    -- names the session at /settings/sessions without giving away the token
+    id BIGSERIAL UNIQUE,
+    user_id BIGINT NOT NULL,
+    created_at timestamp with time zone NOT NULL,
+    -- updated at most every few minutes, see Handler::current_user
+    last_seen_at timestamp with time zone NOT NULL,
+    -- the client address and user agent when last seen
+    addr character varying NULL,
+    user_agent character varying NULL,
+    -- logged in with a second factor as well as the password, see
+    -- two_factor.rs
+    two_factor BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+ALTER TABLE user_session ADD CONSTRAINT fk_user_session_wiki_user FOREIGN KEY (user_id) REFERENCES wiki_user (id);
+CREATE INDEX user_session_user_id ON user_session(user_id);
+
+-- unused two-factor recovery codes, hashed, see two_factor.rs
+CREATE TABLE recovery_code (
+    user_id BIGINT NOT NULL,
+    code_hash character varying NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
+
+ALTER TABLE recovery_code ADD CONSTRAINT fk_recovery_code_wiki_user FOREIGN KEY (user_id) REFERENCES wiki_user (id);
+
+-- attachment contents, shared by every attachment with the same bytes
+CREATE TABLE attachment_blob (
+    -- hex SHA-256 of data
+    content_hash character varying PRIMARY KEY,
+    data BYTEA NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    -- attachments using this blob; blobs at zero are removed by gc-attachments
+    ref_count BIGINT NOT NULL,
+    created_at timestamp with time zone NOT NULL
+);
+
+CREATE TABLE attachment (
+    id BIGSERIAL PRIMARY KEY,
+    document_id BIGINT NOT NULL,
+    filename character varying NOT NULL,
+    content_type character varying NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    content_hash character varying NOT NULL,
+    uploaded_by character varying NOT NULL,
+    uploaded_at timestamp with time zone NOT NULL,
+    UNIQUE (document_id, filename)
+);
+
+ALTER TABLE attachment ADD CONSTRAINT fk_attachment_document FOREIGN KEY (document_id) REFERENCES document (id);
+ALTER TABLE attachment ADD CONSTRAINT fk_attachment_attachment_blob FOREIGN KEY (content_hash) REFERENCES attachment_blob (content_hash);
+CREATE INDEX attachment_content_hash ON attachment(content_hash);
+CREATE INDEX attachment_uploaded_by ON attachment(uploaded_by);
+
+-- previews made from attachment_blob on first view, see previews.rs
+CREATE TABLE attachment_preview (
+    content_hash character varying NOT NULL REFERENCES attachment_blob (content_hash) ON DELETE CASCADE,
+    -- pdf-page or thumbnail (PNG), excerpt (UTF-8 text) or resized-WxH (see resize.rs)
+    kind character varying NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (content_hash, kind)
+);
+
+CREATE TABLE document_link (
+    source_document_id BIGINT NOT NULL,
+    target_name character varying NOT NULL,
+    PRIMARY KEY (source_document_id, target_name)
+);
+
+ALTER TABLE document_link ADD CONSTRAINT fk_document_link_document FOREIGN KEY (source_document_id) REFERENCES document (id);
+CREATE INDEX document_link_target_name ON document_link(target_name);
+
+CREATE TABLE move_log (
+    id BIGSERIAL PRIMARY KEY,
+    document_id BIGINT NOT NULL,
+    old_name character varying NOT NULL,
+    new_name character varying NOT NULL,
+    moved_by character varying NOT NULL,
+    moved_at timestamp with time zone NOT NULL,
+    reason TEXT NOT NULL,
+    -- see document_history.change_id
+    change_id BIGINT NOT NULL DEFAULT 0,
+    changed_at timestamp with time zone NOT NULL DEFAULT NOW()
+);
+
+CREATE TRIGGER move_log_change BEFORE INSERT OR UPDATE ON move_log
+    FOR EACH ROW EXECUTE FUNCTION stamp_change();
+
+ALTER TABLE move_log ADD CONSTRAINT fk_move_log_document FOREIGN KEY (document_id) REFERENCES document (id);
+CREATE INDEX move_log_document_id ON move_log(document_id);
+CREATE INDEX move_log_moved_at ON move_log(moved_at);
+
+CREATE TABLE revision_tags (
+    document_id BIGINT NOT NULL,
+    label character varying NOT NULL,
+    document_history_id BIGINT NOT NULL,
+    created_by character varying NOT NULL,
+    created_at timestamp with time zone NOT NULL,
+    PRIMARY KEY (document_id, label)
+);
+
+ALTER TABLE revision_tags ADD CONSTRAINT fk_revision_tags_document FOREIGN KEY (document_id) REFERENCES document (id);
+ALTER TABLE revision_tags ADD CONSTRAINT fk_revision_tags_document_history FOREIGN KEY (document_history_id) REFERENCES document_history (id);
+CREATE INDEX revision_tags_document_history_id ON revision_tags(document_history_id);
+
+CREATE TABLE user_preferences (
+    user_id BIGINT PRIMARY KEY,
+    display_name character varying NULL,
+    timezone character varying NOT NULL,
+    theme character varying NULL,
+    editor character varying NOT NULL,
+    email character varying NULL,
+    digest BOOLEAN NOT NULL DEFAULT FALSE,
+    -- space-separated, see Preferences::digest_namespaces
+    digest_namespaces character varying NOT NULL DEFAULT '',
+    -- space-separated, see Preferences::follow
+    follow character varying NOT NULL DEFAULT '',
+    updated_at timestamp with time zone NOT NULL
+);
+
+ALTER TABLE user_preferences ADD CONSTRAINT fk_user_preferences_wiki_user FOREIGN KEY (user_id) REFERENCES wiki_user (id);
+
+-- the newest revision of each page a user has seen, for /unread
+CREATE TABLE page_read (
+    user_id BIGINT NOT NULL REFERENCES wiki_user (id),
+    document_id BIGINT NOT NULL REFERENCES document (id) ON DELETE CASCADE,
+    revision_id BIGINT NOT NULL,
+    read_at timestamp with time zone NOT NULL,
+    PRIMARY KEY (user_id, document_id)
+);
+
+-- branding set from /admin, see appearance.rs; at most one row
+CREATE TABLE site_appearance (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    accent_color character varying NULL,
+    link_color character varying NULL,
+    custom_css TEXT NOT NULL DEFAULT '',
+    logo BYTEA NULL,
+    logo_content_type character varying NULL,
+    logo_hash character varying NULL,
+    updated_by character varying NOT NULL,
+    updated_at timestamp with time zone NOT NULL
+);
+
+CREATE TABLE page_data (
+    document_id BIGINT NOT NULL,
+    key character varying NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (document_id, key)
+);
+
+ALTER TABLE page_data ADD CONSTRAINT fk_page_data_document FOREIGN KEY (document_id) REFERENCES document (id);
+CREATE INDEX page_data_key_value ON page_data(key, value);
+
+CREATE TABLE sync_cursor (
+    remote character varying PRIMARY KEY,
+    local_after BIGINT NOT NULL,
+    remote_after BIGINT NOT NULL
+);
+
+CREATE TABLE sync_page (
+    remote character varying NOT NULL,
+    name character varying NOT NULL,
+    local_revision_id BIGINT NOT NULL,
+    remote_revision_id BIGINT NOT NULL,
+    PRIMARY KEY (remote, name)
+);
+
+CREATE TABLE sync_conflict (
+    remote character varying NOT NULL,
+    name character varying NOT NULL,
+    local_revision_id BIGINT NOT NULL,
+    remote_revision_id BIGINT NOT NULL,
+    detected_at timestamp with time zone NOT NULL,
+    PRIMARY KEY (remote, name)
+);
+
+-- bumped by every change that can alter how a page renders, so cached
+-- renderings from before it are ignored, see render_cache.rs; at most one row
+CREATE TABLE render_generation (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    generation BIGINT NOT NULL
+);
+
+-- the revision id, in the source wiki, that the last backup archive restored
+-- with `wiki restore` ended at, so increments are applied in order; at most
+-- one row
+CREATE TABLE restore_checkpoint (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    restored_until BIGINT NOT NULL
+);
+
+-- rules run over every save, see edit_filter.rs
+CREATE TABLE edit_filter (
+    id BIGSERIAL PRIMARY KEY,
+    name character varying NOT NULL,
+    rule TEXT NOT NULL,
+    -- 'warn', 'tag' or 'block'
+    action character varying NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    updated_by character varying NOT NULL,
+    updated_at timestamp with time zone NOT NULL
+);
+
+CREATE TABLE edit_filter_hit (
+    id BIGSERIAL PRIMARY KEY,
+    filter_id BIGINT NOT NULL,
+    page_name character varying NOT NULL,
+    attribution character varying NOT NULL,
+    action character varying NOT NULL,
+    -- the revision saved despite the filter; NULL if the save was refused
+    revision_id BIGINT NULL,
+    hit_at timestamp with time zone NOT NULL
+);
+
+ALTER TABLE edit_filter_hit ADD CONSTRAINT fk_edit_filter_hit_edit_filter FOREIGN KEY (filter_id) REFERENCES edit_filter (id) ON DELETE CASCADE;
+CREATE INDEX edit_filter_hit_revision_id ON edit_filter_hit(revision_id) WHERE revision_id IS NOT NULL;
+
+-- likely credentials found in saves, see secrets.rs; the secret itself
+-- isn't kept
+CREATE TABLE secret_finding (
+    id BIGSERIAL PRIMARY KEY,
+    kind character varying NOT NULL,
+    line INTEGER NOT NULL,
+    page_name character varying NOT NULL,
+    attribution character varying NOT NULL,
+    -- the revision saved despite the finding; NULL if the save was refused
+    revision_id BIGINT NULL,
+    found_at timestamp with time zone NOT NULL
+);
+
+-- page protection by name pattern, see protection.rs
+CREATE TABLE protection_rule (
+    id BIGSERIAL PRIMARY KEY,
+    pattern character varying NOT NULL UNIQUE,
+    -- 'logged-in' or 'admin'
+    level character varying NOT NULL,
+    reason character varying NOT NULL,
+    created_by character varying NOT NULL,
+    created_at timestamp with time zone NOT NULL
+);
+
+-- how far the weekly digest has got, see digest.rs
+CREATE TABLE digest_run (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    sent_until timestamp with time zone NOT NULL
+);
+
+-- signs the links mailed by accounts.rs, kept so they survive restarts
+CREATE TABLE signing_key (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    key BYTEA NOT NULL
+);
@@ -0,0 +1,227 @@
+//! Self-service accounts. Under `--open-registration` anyone may sign up at
+//! `/register`, but can't log in until they follow the link mailed to the
+//! address they gave. Wherever mail is set up, `/forgot` mails a link for
+//! setting a new password to an account's verified address.
+//!
+//! Links carry signed tokens rather than anything stored, and the key is
+//! kept in the database so they survive restarts. Each token is bound to
+//! what it acts on: a verification link stops working once the address
+//! changes, and a reset link once the password has, so it works only once.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use askama::Template;
+use chrono::Utc;
+use ring::hmac;
+use serde::Deserialize;
+
+use crate::auth;
+use crate::digest::Mailer;
+use crate::queries::Account;
+use crate::query_params::{QueryError, QueryParams};
+use crate::routes::Route;
+use crate::views::account::AccountMail;
+use crate::{DynResult, HandlerInner};
+
+/// How long a verification link works, in seconds.
+const VERIFY_LIFETIME: i64 = 48 * 60 * 60;
+
+/// How long a password reset link works, in seconds.
+const RESET_LIFETIME: i64 = 60 * 60;
+
+/// Mails sent to one account, or asked for from one address, per hour.
+const MAILS_PER_HOUR: usize = 3;
+
+pub const MIN_PASSWORD_CHARS: usize = 8;
+
+const MAX_USERNAME_CHARS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    Verify,
+    Reset,
+}
+
+impl Purpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Purpose::Verify => "verify",
+            Purpose::Reset => "reset",
+        }
+    }
+
+    fn lifetime(self) -> i64 {
+        match self {
+            Purpose::Verify => VERIFY_LIFETIME,
+            Purpose::Reset => RESET_LIFETIME,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TokenError {
+    Invalid,
+    Expired,
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TokenError::Invalid => write!(f, "This link isn't valid. It may have been used already."),
+            TokenError::Expired => write!(f, "This link has expired. Ask for a new one."),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Why a username can't be signed up with, if it can't.
+pub fn username_problem(username: &str) -> Option<&'static str> {
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_CHARS {
+        Some("Usernames are 1 to 40 characters long.")
+    } else if username.parse::<IpAddr>().is_ok() {
+        // anonymous edits are attributed to the client address
+        Some("Usernames can't look like an IP address.")
+    } else if !username.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        Some("Usernames may only contain letters, digits, '-', '_' and '.'.")
+    } else {
+        None
+    }
+}
+
+/// Why a new password won't do, if it won't.
+pub fn password_problem(password: &str, confirm: &str) -> Option<&'static str> {
+    if password.chars().count() < MIN_PASSWORD_CHARS {
+        Some("Passwords are at least 8 characters long.")
+    } else if password != confirm {
+        Some("The passwords don't match.")
+    } else {
+        None
+    }
+}
+
+/// Issues and checks the tokens in mailed links, and sends the mail.
+pub struct Accounts {
+    key: hmac::Key,
+    mailer: Mailer,
+    site_name: String,
+    /// When mail went out, by `user:ID` or `addr:IP`, over the last hour.
+    sent: Mutex<HashMap<String, Vec<i64>>>,
+}
+
+impl Accounts {
+    /// Loads the signing key, creating it on the first start.
+    pub async fn load(inner: &HandlerInner, mailer: Mailer, site_name: String) -> DynResult<Accounts> {
+        let mut candidate = [0u8; 32];
+        auth::random_bytes(&mut candidate)?;
+        let key = inner
+            .queries
+            .fetch_or_create_signing_key(&inner.db, &candidate)
+            .await?;
+        Ok(Accounts {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            mailer,
+            site_name,
+            sent: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn payload(purpose: Purpose, user_id: i64, expires: i64, binding: &str) -> String {
+        format!("{}.{}.{}.{}", purpose.as_str(), user_id, expires, binding)
+    }
+
+    /// A token for `purpose` on `user_id`, bound to `binding`: the address
+    /// being verified or the password hash being replaced.
+    fn issue(&self, purpose: Purpose, user_id: i64, binding: &str) -> String {
+        let expires = Utc::now().timestamp() + purpose.lifetime();
+        let tag = hmac::sign(&self.key, Accounts::payload(purpose, user_id, expires, binding).as_bytes());
+        format!("{}.{}.{}", user_id, expires, auth::to_hex(tag.as_ref()))
+    }
+
+    /// The user a token claims to be for, to look up what it should be
+    /// bound to before checking it with `verify`.
+    pub fn user_of(token: &str) -> Option<i64> {
+        token.split('.').next()?.parse().ok()
+    }
+
+    pub fn verify(&self, purpose: Purpose, token: &str, binding: &str) -> Result<(), TokenError> {
+        let mut parts = token.splitn(3, '.');
+        let (user_id, expires, tag) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(user_id), Some(expires), Some(tag)) => (user_id, expires, tag),
+            _ => return Err(TokenError::Invalid),
+        };
+        let user_id: i64 = user_id.parse().map_err(|_| TokenError::Invalid)?;
+        let expires: i64 = expires.parse().map_err(|_| TokenError::Invalid)?;
+        let tag = auth::from_hex(tag).ok_or(TokenError::Invalid)?;
+        let payload = Accounts::payload(purpose, user_id, expires, binding);
+        hmac::verify(&self.key, payload.as_bytes(), &tag).map_err(|_| TokenError::Invalid)?;
+        if expires < Utc::now().timestamp() {
+            return Err(TokenError::Expired);
+        }
+        Ok(())
+    }
+
+    /// Whether another mail may go out on behalf of `key`, counting it if
+    /// so.
+    fn allow(&self, key: String) -> bool {
+        let now = Utc::now().timestamp();
+        let mut sent = self.sent.lock().unwrap();
+        sent.retain(|_, times| {
+            times.retain(|&at| at > now - 60 * 60);
+            !times.is_empty()
+        });
+        let times = sent.entry(key).or_default();
+        if times.len() >= MAILS_PER_HOUR {
+            return false;
+        }
+        times.push(now);
+        true
+    }
+
+    /// Whether `addr` may ask for another mail this hour, counting the
+    /// request if so. Checked before looking anything up.
+    pub fn allow_client(&self, addr: Option<IpAddr>) -> bool {
+        match addr {
+            Some(addr) => self.allow(format!("addr:{}", addr)),
+            None => true,
+        }
+    }
+
+    /// Mails `account` a link for `purpose` at `to`, unless it has had its
+    /// hourly allowance already. Whether it was sent.
+    pub async fn send(&self, purpose: Purpose, account: &Account, to: &str) -> DynResult<bool> {
+        if !self.allow(format!("user:{}", account.id)) {
+            return Ok(false);
+        }
+        let (binding, route, subject) = match purpose {
+            Purpose::Verify => (to, Route::VerifyEmail, "Confirm your email address"),
+            Purpose::Reset => (&account.password_hash[..], Route::ResetPassword, "Reset your password"),
+        };
+        let subject = format!("{}: {}", self.site_name, subject);
+        let token = self.issue(purpose, account.id, binding);
+        let mail = AccountMail {
+            site_name: &self.site_name,
+            subject: &subject,
+            username: &account.username,
+            reset: purpose == Purpose::Reset,
+            link: format!("{}{}?token={}", self.mailer.base_url, route, token),
+            hours: purpose.lifetime() / (60 * 60),
+        };
+        self.mailer.send(to, &subject, &mail.render()?).await?;
+        Ok(true)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenParams {
+    #[serde(default)]
+    token: String,
+}
+
+/// `?token=` from a mailed link, empty if missing.
+pub fn parse_token(query: Option<&str>) -> Result<String, QueryError> {
+    let QueryParams(params) = QueryParams::<TokenParams>::parse(query.unwrap_or(""))?;
+    Ok(params.token)
+}
@@ -0,0 +1,58 @@
+//! Site branding set by admins at `/admin`: a logo, the colours behind the
+//! CSS variables in `base.html` and a snippet of custom CSS, all applied to
+//! every page.
+
+use std::sync::{Arc, RwLock};
+
+use crate::{DynResult, HandlerInner};
+
+/// Largest logo accepted, in bytes.
+pub const MAX_LOGO_SIZE: usize = 512 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct Appearance {
+    /// Sets `--accent`, as `#rrggbb`.
+    pub accent_color: Option<String>,
+    /// Sets `--link`, as `#rrggbb`.
+    pub link_color: Option<String>,
+    pub custom_css: String,
+    /// Hash of the logo served at `/logo`, if one has been uploaded.
+    pub logo_hash: Option<String>,
+}
+
+/// Whether `color` is a `#rrggbb` colour, as `<input type="color">` sends.
+pub fn is_valid_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Custom CSS goes straight into a `<style>` element, so it can't contain
+/// anything that would close it.
+pub fn check_custom_css(css: &str) -> Result<(), String> {
+    if css.contains("</") {
+        return Err("Custom CSS can't contain \"</\".".to_string());
+    }
+    Ok(())
+}
+
+/// The current appearance, read at startup and replaced whenever `/admin`
+/// or `/logo` saves a change.
+#[derive(Default)]
+pub struct SiteAppearance {
+    current: RwLock<Arc<Appearance>>,
+}
+
+impl SiteAppearance {
+    pub async fn load(&self, inner: &HandlerInner) -> DynResult<()> {
+        let appearance = inner.queries.fetch_appearance(&inner.db).await?;
+        self.set(appearance);
+        Ok(())
+    }
+
+    pub fn get(&self) -> Arc<Appearance> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn set(&self, appearance: Appearance) {
+        *self.current.write().unwrap() = Arc::new(appearance);
+    }
+}
@@ -0,0 +1,89 @@
+/// Returns the level of an ATX heading line (`## Foo` is 2) and its text.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    // four spaces make an indented code block
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &trimmed[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') && !rest.starts_with('\t') {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// The heading on each line, if it has one; lines in fenced code blocks
+/// have none, so a `#` comment in a shell snippet isn't taken for one.
+fn headings<'a>(lines: &[&'a str]) -> Vec<Option<(usize, &'a str)>> {
+    let mut fence: Option<&str> = None;
+    lines
+        .iter()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let marker = ["```", "~~~"].iter().find(|marker| trimmed.starts_with(*marker));
+            match (fence, marker) {
+                (None, Some(marker)) => fence = Some(marker),
+                (Some(open), Some(marker)) if open == *marker => fence = None,
+                (None, None) => return heading(line),
+                _ => (),
+            }
+            None
+        })
+        .collect()
+}
+
+/// Appends `block` to `document`, either at the very end or at the end of the
+/// section under the heading named `under`. A missing heading is created at
+/// the end of the document.
+pub fn append_block(document: &str, block: &str, under: Option<&str>) -> String {
+    let block = block.trim_matches('\n');
+
+    let under = match under {
+        Some(h) => h.trim(),
+        None => return join_blocks(document, block),
+    };
+
+    let lines: Vec<&str> = document.split_inclusive('\n').collect();
+    let headings = headings(&lines);
+    let found = headings.iter().enumerate().find_map(|(idx, heading)| match *heading {
+        Some((level, text)) if text == under => Some((idx, level)),
+        _ => None,
+    });
+
+    let (start, level) = match found {
+        Some(found) => found,
+        None => {
+            let section = format!("## {}\n\n{}", under, block);
+            return join_blocks(document, &section);
+        }
+    };
+
+    // the section ends at the next heading of the same or a higher level
+    let end = headings[start + 1..]
+        .iter()
+        .position(|heading| matches!(heading, Some((l, _)) if *l <= level))
+        .map(|p| start + 1 + p)
+        .unwrap_or(lines.len());
+
+    let section: String = lines[..end].concat();
+    let rest: String = lines[end..].concat();
+    let mut out = join_blocks(&section, block);
+    if !rest.is_empty() {
+        out.push('\n');
+        out.push_str(&rest);
+    }
+    out
+}
+
+fn join_blocks(document: &str, block: &str) -> String {
+    let document = document.trim_end_matches('\n');
+    if document.is_empty() {
+        format!("{}\n", block)
+    } else {
+        format!("{}\n\n{}\n", document, block)
+    }
+}
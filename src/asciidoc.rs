@@ -0,0 +1,817 @@
+//! Renders the commonly used parts of AsciiDoc, for teams moving pages over
+//! from wikis that keep them in it: section titles, paragraphs, lists,
+//! listing, literal, quote, example and sidebar blocks, tables, admonitions,
+//! block titles, images and inline formatting.
+//!
+//! Cross references name wiki pages, so `<<Deploy runbook>>` and
+//! `xref:Deploy runbook.adoc[the runbook]` both link to `/wiki/Deploy runbook`.
+//! Attribute entries are skipped and passthrough blocks are left out, as
+//! raw HTML is in Markdown pages.
+
+use std::collections::HashMap;
+
+use comrak::adapters::SyntaxHighlighterAdapter;
+
+use crate::markup::Markup;
+use crate::routes::RouteWiki;
+use crate::{front_matter, highlight, links, DynResult};
+
+const ADMONITIONS: &[(&str, &str)] = &[
+    ("NOTE", "Note"),
+    ("TIP", "Tip"),
+    ("IMPORTANT", "Important"),
+    ("WARNING", "Warning"),
+    ("CAUTION", "Caution"),
+];
+
+pub struct AsciiDoc;
+
+impl Markup for AsciiDoc {
+    fn render(&self, text: &str) -> DynResult<String> {
+        let lines: Vec<&str> = front_matter::strip(text).lines().collect();
+        let mut html = String::new();
+        render_blocks(&lines, &mut html);
+        Ok(html)
+    }
+
+    fn link_targets(&self, text: &str) -> Vec<String> {
+        match self.render(text) {
+            Ok(html) => links::html_link_targets(&html),
+            Err(..) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delimited {
+    Listing,
+    Literal,
+    Quote,
+    Example,
+    Sidebar,
+    Passthrough,
+    Comment,
+    Table,
+}
+
+/// The kind of block `line` opens, if it's a block delimiter. A block ends
+/// at the next line identical to the one that opened it.
+fn delimiter(line: &str) -> Option<Delimited> {
+    if line.len() >= 4 && line.starts_with("|=") && line[1..].bytes().all(|b| b == b'=') {
+        return Some(Delimited::Table);
+    }
+    let first = line.bytes().next()?;
+    if line.len() < 4 || !line.bytes().all(|b| b == first) {
+        return None;
+    }
+    match first {
+        b'-' => Some(Delimited::Listing),
+        b'.' => Some(Delimited::Literal),
+        b'_' => Some(Delimited::Quote),
+        b'=' => Some(Delimited::Example),
+        b'*' => Some(Delimited::Sidebar),
+        b'+' => Some(Delimited::Passthrough),
+        b'/' => Some(Delimited::Comment),
+        _ => None,
+    }
+}
+
+/// The index of the line closing the block opened at `open`, or the end of
+/// `lines` if it's never closed.
+fn closing_line(lines: &[&str], open: usize) -> usize {
+    let delimiter = lines[open].trim_end();
+    lines[open + 1..]
+        .iter()
+        .position(|line| line.trim_end() == delimiter)
+        .map_or(lines.len(), |i| open + 1 + i)
+}
+
+/// `(level, title)` for a section title such as `== Setup`.
+fn section_title(line: &str) -> Option<(usize, &str)> {
+    let level = line.bytes().take_while(|&b| b == b'=').count();
+    let title = line[level..].strip_prefix(' ')?.trim();
+    if !(1..=6).contains(&level) || title.is_empty() {
+        return None;
+    }
+    let closing = format!(" {}", &line[..level]);
+    Some((level, title.strip_suffix(&closing[..]).unwrap_or(title).trim_end()))
+}
+
+/// `:name: value` lines set document attributes, which aren't supported.
+fn is_attribute_entry(line: &str) -> bool {
+    let rest = match line.strip_prefix(':') {
+        Some(rest) => rest,
+        None => return false,
+    };
+    match rest.find(':') {
+        Some(end) if end > 0 => {
+            !rest[..end].contains(char::is_whitespace)
+                && (rest.len() == end + 1 || rest[end + 1..].starts_with(' '))
+        }
+        _ => false,
+    }
+}
+
+/// The `n`th positional attribute in a `[style,...]` line.
+fn positional(attributes: Option<&str>, n: usize) -> Option<&str> {
+    attributes?
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.contains('='))
+        .nth(n)
+        .filter(|a| !a.is_empty())
+}
+
+fn admonition_label(name: &str) -> Option<(&'static str, &'static str)> {
+    ADMONITIONS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(n, label)| (n, label))
+}
+
+/// The marker and text of a list item line. Markers name the list level:
+/// `*`, `**` and `-` for bullets, `.`, `..` and `1.` for numbers.
+fn list_item(line: &str) -> Option<(String, &str)> {
+    let line = line.trim_start();
+    let first = line.bytes().next()?;
+    let marker_len = match first {
+        b'*' | b'.' => line.bytes().take_while(|&b| b == first).count(),
+        b'-' => 1,
+        b'0'..=b'9' => {
+            let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+            if line[digits..].starts_with(". ") {
+                let text = line[digits + 2..].trim();
+                return (!text.is_empty()).then(|| (".".to_string(), text));
+            }
+            return None;
+        }
+        _ => return None,
+    };
+    if marker_len > 5 {
+        return None;
+    }
+    let text = line[marker_len..].strip_prefix(' ')?.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some((line[..marker_len].to_string(), text))
+}
+
+fn render_blocks(lines: &[&str], out: &mut String) {
+    let mut title: Option<&str> = None;
+    let mut attributes: Option<&str> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+        if line.is_empty() {
+            i += 1;
+            continue;
+        }
+
+        if let Some(kind) = delimiter(line) {
+            let close = closing_line(lines, i);
+            let body = &lines[i + 1..close];
+            if kind != Delimited::Comment {
+                push_title(out, title.take());
+            }
+            render_delimited(kind, body, attributes.take(), out);
+            i = close + 1;
+            continue;
+        }
+        if line.starts_with("//") {
+            i += 1;
+            continue;
+        }
+        if is_attribute_entry(line) {
+            i += 1;
+            continue;
+        }
+        if line.starts_with("[[") && line.ends_with("]]") {
+            i += 1;
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            attributes = Some(&line[1..line.len() - 1]);
+            i += 1;
+            continue;
+        }
+        if line.len() > 1 && line.starts_with('.') && !line[1..].starts_with(['.', ' '].as_ref()) {
+            title = Some(&line[1..]);
+            i += 1;
+            continue;
+        }
+        if let Some((level, text)) = section_title(line) {
+            out.push_str(&format!("<h{}>{}</h{}>\n", level, inline(text), level));
+            title = None;
+            attributes = None;
+            i += 1;
+            continue;
+        }
+        if line == "'''" {
+            out.push_str("<hr />\n");
+            i += 1;
+            continue;
+        }
+
+        push_title(out, title.take());
+        let attributes = attributes.take();
+        if let Some(image) = line.strip_prefix("image::").and_then(|rest| macro_parts(rest)) {
+            let (target, alt) = image;
+            out.push_str(&format!(
+                "<div class=\"image\"><img src=\"{}\" alt=\"{}\" /></div>\n",
+                escape(&safe_url(target)),
+                escape(alt)
+            ));
+            i += 1;
+        } else if list_item(line).is_some() {
+            i = render_list(lines, i, out);
+        } else if lines[i].starts_with(char::is_whitespace) {
+            let end = paragraph_end(lines, i);
+            let body = &lines[i..end];
+            let indent = body
+                .iter()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| l.len() - l.trim_start().len())
+                .min()
+                .unwrap_or(0);
+            let text: Vec<&str> = body.iter().map(|l| l.get(indent..).unwrap_or("")).collect();
+            out.push_str(&format!("<pre><code>{}</code></pre>\n", escape(&text.join("\n"))));
+            i = end;
+        } else {
+            let end = paragraph_end(lines, i);
+            let text = lines[i..end].join("\n");
+            let label = match positional(attributes, 0) {
+                Some(style) => admonition_label(style).map(|label| (label, &text[..])),
+                None => ADMONITIONS.iter().find_map(|&(name, label)| {
+                    let rest = text.strip_prefix(name)?.strip_prefix(": ")?;
+                    Some(((name, label), rest))
+                }),
+            };
+            match label {
+                Some(((name, label), text)) => {
+                    open_admonition(out, name, label);
+                    out.push_str(&format!("<p>{}</p>\n</div>\n", inline(text.trim())));
+                }
+                None => out.push_str(&format!("<p>{}</p>\n", inline(&text))),
+            }
+            i = end;
+        }
+    }
+}
+
+/// Paragraphs run until a blank line or a block delimiter.
+fn paragraph_end(lines: &[&str], start: usize) -> usize {
+    lines[start + 1..]
+        .iter()
+        .position(|line| line.trim().is_empty() || delimiter(line.trim_end()).is_some())
+        .map_or(lines.len(), |i| start + 1 + i)
+}
+
+fn push_title(out: &mut String, title: Option<&str>) {
+    if let Some(title) = title {
+        out.push_str(&format!("<p class=\"block-title\">{}</p>\n", inline(title)));
+    }
+}
+
+fn open_admonition(out: &mut String, name: &str, label: &str) {
+    out.push_str(&format!(
+        "<div class=\"admonition {}\">\n<p class=\"admonition-label\">{}</p>\n",
+        name.to_ascii_lowercase(),
+        label
+    ));
+}
+
+fn render_delimited(kind: Delimited, body: &[&str], attributes: Option<&str>, out: &mut String) {
+    match kind {
+        Delimited::Listing => {
+            let code = body.join("\n") + "\n";
+            match positional(attributes, 0) {
+                Some("source") => {
+                    let lang = positional(attributes, 1);
+                    let highlighter = highlight::highlighter();
+                    let mut code_attributes = HashMap::new();
+                    if let Some(lang) = lang {
+                        code_attributes.insert("class".to_string(), format!("language-{}", lang));
+                    }
+                    out.push_str(&highlighter.build_pre_tag(&HashMap::new()));
+                    out.push_str(&highlighter.build_code_tag(&code_attributes));
+                    out.push_str(&highlighter.highlight(lang, &code));
+                    out.push_str("</code></pre>\n");
+                }
+                _ => out.push_str(&format!("<pre><code>{}</code></pre>\n", escape(&code))),
+            }
+        }
+        Delimited::Literal => {
+            out.push_str(&format!("<pre><code>{}\n</code></pre>\n", escape(&body.join("\n"))));
+        }
+        Delimited::Quote => {
+            out.push_str("<blockquote>\n");
+            render_blocks(body, out);
+            if let Some(attribution) = positional(attributes, 1) {
+                out.push_str(&format!("<p class=\"attribution\">— {}</p>\n", inline(attribution)));
+            }
+            out.push_str("</blockquote>\n");
+        }
+        Delimited::Example => {
+            match positional(attributes, 0).and_then(admonition_label) {
+                Some((name, label)) => open_admonition(out, name, label),
+                None => out.push_str("<div class=\"example\">\n"),
+            }
+            render_blocks(body, out);
+            out.push_str("</div>\n");
+        }
+        Delimited::Sidebar => {
+            out.push_str("<aside class=\"asciidoc-sidebar\">\n");
+            render_blocks(body, out);
+            out.push_str("</aside>\n");
+        }
+        Delimited::Passthrough => out.push_str("<!-- raw HTML omitted -->\n"),
+        Delimited::Comment => (),
+        Delimited::Table => render_table(body, attributes, out),
+    }
+}
+
+/// Rows have as many cells as the first line. The first row is the header
+/// if a blank line follows it or the table has the `header` option.
+fn render_table(body: &[&str], attributes: Option<&str>, out: &mut String) {
+    let first = match body.iter().position(|line| line.trim_start().starts_with('|')) {
+        Some(first) => first,
+        None => return,
+    };
+    let cells_of = |line: &str| -> Vec<String> {
+        line.trim()
+            .split('|')
+            .skip(1)
+            .map(|cell| cell.trim().to_string())
+            .collect()
+    };
+    let columns = cells_of(body[first]).len().max(1);
+    let has_header = matches!(attributes, Some(a) if a.contains("header"))
+        || matches!(body.get(first + 1), Some(line) if line.trim().is_empty());
+
+    let mut cells = Vec::new();
+    for line in &body[first..] {
+        let line = line.trim();
+        if line.starts_with('|') {
+            cells.extend(cells_of(line));
+        } else if !line.is_empty() {
+            // a cell continued from the line before
+            if let Some(last) = cells.last_mut() {
+                last.push('\n');
+                last.push_str(line);
+            }
+        }
+    }
+
+    out.push_str("<table>\n");
+    for (n, row) in cells.chunks(columns).enumerate() {
+        let tag = if n == 0 && has_header { "th" } else { "td" };
+        if n == 0 && has_header {
+            out.push_str("<thead>\n");
+        } else if n == 0 || (n == 1 && has_header) {
+            out.push_str("<tbody>\n");
+        }
+        out.push_str("<tr>\n");
+        for cell in row {
+            out.push_str(&format!("<{}>{}</{}>\n", tag, inline(cell), tag));
+        }
+        out.push_str("</tr>\n");
+        if n == 0 && has_header {
+            out.push_str("</thead>\n");
+        }
+    }
+    let rows = cells.len().div_ceil(columns);
+    if rows > usize::from(has_header) {
+        out.push_str("</tbody>\n");
+    }
+    out.push_str("</table>\n");
+}
+
+/// Renders the list starting at `lines[start]`, returning the index of the
+/// first line after it. A `+` line attaches the block after it to the item.
+fn render_list(lines: &[&str], start: usize, out: &mut String) -> usize {
+    let mut open: Vec<(String, &str)> = Vec::new();
+    let mut i = start;
+    while let Some((marker, text)) = lines.get(i).and_then(|line| list_item(line)) {
+        match open.iter().position(|(m, _)| *m == marker) {
+            Some(level) => {
+                while open.len() > level + 1 {
+                    let (_, tag) = open.pop().unwrap();
+                    out.push_str(&format!("</li>\n</{}>\n", tag));
+                }
+                out.push_str("</li>\n");
+            }
+            None => {
+                let tag = if marker.starts_with('.') { "ol" } else { "ul" };
+                out.push_str(&format!("<{}>\n", tag));
+                open.push((marker, tag));
+            }
+        }
+
+        let mut text = text.to_string();
+        i += 1;
+        while let Some(line) = lines.get(i) {
+            let line = line.trim();
+            if line.is_empty() || line == "+" || list_item(line).is_some() || delimiter(line).is_some() {
+                break;
+            }
+            text.push('\n');
+            text.push_str(line);
+            i += 1;
+        }
+        out.push_str(&format!("<li>{}\n", inline(&text)));
+
+        while lines.get(i).map(|line| line.trim()) == Some("+") {
+            let block_start = i + 1;
+            let block_end = match lines.get(block_start) {
+                Some(line) if delimiter(line.trim_end()).is_some() => {
+                    (closing_line(lines, block_start) + 1).min(lines.len())
+                }
+                Some(_) => paragraph_end(lines, block_start),
+                None => block_start,
+            };
+            render_blocks(&lines[block_start..block_end], out);
+            i = block_end;
+        }
+
+        // items may be separated by blank lines
+        let next = lines[i.min(lines.len())..]
+            .iter()
+            .position(|line| !line.trim().is_empty())
+            .map_or(lines.len(), |n| i + n);
+        if lines.get(next).and_then(|line| list_item(line)).is_none() {
+            break;
+        }
+        i = next;
+    }
+    while let Some((_, tag)) = open.pop() {
+        out.push_str(&format!("</li>\n</{}>\n", tag));
+    }
+    i
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Blanks out links that aren't `http:`, `https:`, `mailto:` or relative.
+/// Browsers ignore control characters and surrounding spaces in URLs, so
+/// they're dropped first: `\x01javascript:` would otherwise get through.
+fn safe_url(url: &str) -> String {
+    let url: String = url.chars().filter(|c| !c.is_control()).collect();
+    let url = url.trim().to_string();
+    let scheme_end = url.find([':', '/', '?', '#']);
+    let scheme = match scheme_end {
+        Some(end) if url[end..].starts_with(':') => &url[..end],
+        _ => return url,
+    };
+    if ["http", "https", "mailto"].iter().any(|safe| scheme.eq_ignore_ascii_case(safe)) {
+        url
+    } else {
+        String::new()
+    }
+}
+
+/// Splits the `target[text]` after a macro name.
+fn macro_parts(rest: &str) -> Option<(&str, &str)> {
+    let open = rest.find('[')?;
+    let close = open + rest[open..].find(']')?;
+    let target = &rest[..open];
+    if target.trim().is_empty() {
+        return None;
+    }
+    Some((target, &rest[open + 1..close]))
+}
+
+/// Where a cross reference to `target` goes: a wiki page, optionally with
+/// an anchor, or an anchor on this page.
+fn xref_href(target: &str) -> String {
+    let (page, fragment) = match target.split_once('#') {
+        Some((page, fragment)) => (page, Some(fragment)),
+        None => (target, None),
+    };
+    let page = page.trim();
+    let page = page.strip_suffix(".adoc").unwrap_or(page);
+    let mut href = if page.is_empty() {
+        String::new()
+    } else {
+        RouteWiki::to(page).to_string()
+    };
+    if let Some(fragment) = fragment {
+        href.push('#');
+        href.push_str(fragment);
+    }
+    href
+}
+
+fn link(href: &str, text: &str) -> String {
+    format!("<a href=\"{}\">{}</a>", escape(&safe_url(href)), text)
+}
+
+fn starts_with_at(chars: &[char], at: usize, prefix: &str) -> bool {
+    (at..).zip(prefix.chars()).all(|(n, c)| chars.get(n) == Some(&c))
+}
+
+fn find_at(chars: &[char], from: usize, needle: &str) -> Option<usize> {
+    (from..chars.len()).find(|&n| starts_with_at(chars, n, needle))
+}
+
+fn is_word(c: Option<&char>) -> bool {
+    matches!(c, Some(c) if c.is_alphanumeric())
+}
+
+/// The end of a `*strong*`-style span opened with `marker` at `at`, if it
+/// has one: the closing marker must follow text and not be followed by a
+/// letter. Doubled markers close anywhere.
+fn closing_marker(chars: &[char], at: usize, marker: char) -> Option<(usize, usize)> {
+    let doubled = chars.get(at + 1) == Some(&marker);
+    if doubled {
+        let pair: String = [marker, marker].iter().collect();
+        let close = find_at(chars, at + 2, &pair)?;
+        return (close > at + 2).then_some((2, close));
+    }
+    if is_word(at.checked_sub(1).and_then(|p| chars.get(p))) {
+        return None;
+    }
+    if matches!(chars.get(at + 1), None | Some(' ') | Some('\n')) {
+        return None;
+    }
+    (at + 2..chars.len())
+        .find(|&n| chars[n] == marker && !chars[n - 1].is_whitespace() && !is_word(chars.get(n + 1)))
+        .map(|close| (1, close))
+}
+
+/// Renders inline formatting, links and images in `text`.
+fn inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    // byte offsets of the chars, for handing macros the rest of `text`
+    let offsets: Vec<usize> = text.char_indices().map(|(at, _)| at).collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let at_word_start = !is_word(i.checked_sub(1).and_then(|p| chars.get(p)));
+
+        if c == '\\' && i + 1 < chars.len() {
+            out.push_str(&escape(&chars[i + 1].to_string()));
+            i += 2;
+            continue;
+        }
+        if c == ' ' && chars.get(i + 1) == Some(&'+') && matches!(chars.get(i + 2), None | Some('\n')) {
+            out.push_str("<br />");
+            i += 2;
+            continue;
+        }
+        if c == '`' {
+            if let Some(close) = find_at(&chars, i + 1, "`") {
+                let code: String = chars[i + 1..close].iter().collect();
+                out.push_str(&format!("<code>{}</code>", escape(&code)));
+                i = close + 1;
+                continue;
+            }
+        }
+        let tag = match c {
+            '*' => Some("strong"),
+            '_' => Some("em"),
+            '#' => Some("mark"),
+            _ => None,
+        };
+        if let Some(tag) = tag {
+            if let Some((width, close)) = closing_marker(&chars, i, c) {
+                let inner: String = chars[i + width..close].iter().collect();
+                out.push_str(&format!("<{}>{}</{}>", tag, inline(&inner), tag));
+                i = close + width;
+                continue;
+            }
+        }
+        if starts_with_at(&chars, i, "<<") {
+            if let Some(close) = find_at(&chars, i + 2, ">>") {
+                let inner: String = chars[i + 2..close].iter().collect();
+                let (target, text) = match inner.split_once(',') {
+                    Some((target, text)) => (target.trim(), inline(text.trim())),
+                    None => (inner.trim(), escape(inner.trim())),
+                };
+                if !target.is_empty() {
+                    out.push_str(&link(&xref_href(target), &text));
+                    i = close + 2;
+                    continue;
+                }
+            }
+        }
+        if at_word_start {
+            let rest = &text[offsets[i]..];
+            if let Some(consumed) = inline_macro(rest, &mut out) {
+                i += rest[..consumed].chars().count();
+                continue;
+            }
+        }
+
+        out.push_str(&escape(&c.to_string()));
+        i += 1;
+    }
+    out
+}
+
+/// Renders the macro or URL at the start of `rest` into `out`, returning
+/// how many bytes of `rest` it used.
+fn inline_macro(rest: &str, out: &mut String) -> Option<usize> {
+    let macro_end = |prefix: &str| -> Option<(&str, &str, usize)> {
+        let after = rest.strip_prefix(prefix)?;
+        let (target, text) = macro_parts(after)?;
+        if target.contains(char::is_whitespace) && prefix != "xref:" {
+            return None;
+        }
+        Some((target, text, prefix.len() + target.len() + text.len() + 2))
+    };
+
+    if let Some((target, text, used)) = macro_end("xref:") {
+        let text = if text.is_empty() { escape(target) } else { inline(text) };
+        out.push_str(&link(&xref_href(target), &text));
+        return Some(used);
+    }
+    if let Some((target, text, used)) = macro_end("link:") {
+        let text = if text.is_empty() { escape(target) } else { inline(text) };
+        out.push_str(&link(target, &text));
+        return Some(used);
+    }
+    if !rest.starts_with("image::") {
+        if let Some((target, alt, used)) = macro_end("image:") {
+            out.push_str(&format!(
+                "<img src=\"{}\" alt=\"{}\" />",
+                escape(&safe_url(target)),
+                escape(alt)
+            ));
+            return Some(used);
+        }
+    }
+
+    let scheme = ["https://", "http://", "mailto:"]
+        .iter()
+        .find(|scheme| rest.starts_with(*scheme))?;
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == '[' || c == '<' || c == '>')
+        .unwrap_or(rest.len());
+    if rest[end..].starts_with('[') {
+        if let Some(close) = rest[end..].find(']') {
+            let url = &rest[..end];
+            let text = &rest[end + 1..end + close];
+            let text = if text.is_empty() { escape(url) } else { inline(text) };
+            out.push_str(&link(url, &text));
+            return Some(end + close + 1);
+        }
+    }
+    let url = rest[..end].trim_end_matches(&['.', ',', ';', ':', ')', '!', '?'][..]);
+    if url.len() <= scheme.len() {
+        return None;
+    }
+    out.push_str(&link(url, &escape(url)));
+    Some(url.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str) -> String {
+        AsciiDoc.render(text).unwrap()
+    }
+
+    #[test]
+    fn unsafe_links_are_blanked() {
+        for target in &[
+            "javascript:alert(1)",
+            "\x01javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "vbscript:msgbox(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "file:///etc/passwd",
+        ] {
+            let html = render(&format!("link:{}[x]", target));
+            assert!(html.contains("<a href=\"\">x</a>"), "{:?} gave {}", target, html);
+        }
+        assert_eq!(safe_url("java\tscript:alert(1)"), "");
+        assert_eq!(safe_url(" javascript:alert(1)"), "");
+        assert_eq!(safe_url("\u{7f}javascript:alert(1)"), "");
+    }
+
+    #[test]
+    fn safe_links_are_kept() {
+        assert_eq!(safe_url("https://example.com/a?b#c"), "https://example.com/a?b#c");
+        assert_eq!(safe_url("HTTP://example.com"), "HTTP://example.com");
+        assert_eq!(safe_url("mailto:someone@example.com"), "mailto:someone@example.com");
+        assert_eq!(safe_url("/wiki/Deploy%20runbook"), "/wiki/Deploy%20runbook");
+        assert_eq!(safe_url("page?at=12:30"), "page?at=12:30");
+        assert_eq!(safe_url("#setup"), "#setup");
+        assert_eq!(safe_url("image:x.png"), "");
+    }
+
+    #[test]
+    fn images_are_sanitised() {
+        let html = render("image:javascript:alert(1)[alt]");
+        assert!(html.contains("<img src=\"\" alt=\"alt\" />"), "{}", html);
+        let html = render("image::https://example.com/a.png[A \"diagram\"]");
+        assert!(html.contains("src=\"https://example.com/a.png\""), "{}", html);
+        assert!(html.contains("alt=\"A &quot;diagram&quot;\""), "{}", html);
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(render("<script>alert(1)</script>"), "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n");
+        let html = render("link:https://example.com[<b>bold</b>]");
+        assert!(html.contains("&lt;b&gt;bold&lt;/b&gt;"), "{}", html);
+    }
+
+    #[test]
+    fn sections_and_paragraphs() {
+        let html = render("= Title\n\n== Setup ==\n\nFirst line\nsecond line\n\nNext");
+        assert!(html.contains("Setup</h2>"), "{}", html);
+        assert!(html.contains("<p>First line\nsecond line</p>"), "{}", html);
+        assert!(html.contains("<p>Next</p>"), "{}", html);
+    }
+
+    #[test]
+    fn inline_formatting() {
+        assert_eq!(inline("*bold* _em_ `code` #mark#"), "<strong>bold</strong> <em>em</em> <code>code</code> <mark>mark</mark>");
+        assert_eq!(inline("snake_case_name"), "snake_case_name");
+        assert_eq!(inline("**un**bold"), "<strong>un</strong>bold");
+        assert_eq!(inline("\\*not bold*"), "*not bold*");
+        assert_eq!(inline("`<tag>`"), "<code>&lt;tag&gt;</code>");
+    }
+
+    #[test]
+    fn links_and_cross_references() {
+        assert_eq!(inline("<<Deploy runbook>>"), "<a href=\"/wiki/Deploy%20runbook\">Deploy runbook</a>");
+        assert_eq!(
+            inline("xref:Deploy runbook.adoc#steps[the runbook]"),
+            "<a href=\"/wiki/Deploy%20runbook#steps\">the runbook</a>"
+        );
+        assert_eq!(
+            inline("see https://example.com."),
+            "see <a href=\"https://example.com\">https://example.com</a>."
+        );
+        assert_eq!(inline("https://example.com[Example]"), "<a href=\"https://example.com\">Example</a>");
+        assert_eq!(inline("mailto:a@b.c[mail]"), "<a href=\"mailto:a@b.c\">mail</a>");
+    }
+
+    #[test]
+    fn multibyte_text_around_macros() {
+        assert_eq!(
+            inline("日本 https://例え.jp/ページ 語"),
+            "日本 <a href=\"https://例え.jp/ページ\">https://例え.jp/ページ</a> 語"
+        );
+    }
+
+    #[test]
+    fn long_lines_render() {
+        let line = "word ".repeat(20_000);
+        assert_eq!(inline(&line), line);
+    }
+
+    #[test]
+    fn blocks_and_lists() {
+        let html = render("----\n<code>\n----\n\n* one\n** nested\n* two\n\n. first\n. second");
+        assert!(html.contains("<pre><code>&lt;code&gt;\n</code></pre>"), "{}", html);
+        assert!(html.contains("<ul>"), "{}", html);
+        assert!(html.contains("nested"), "{}", html);
+        assert!(html.contains("<ol>"), "{}", html);
+    }
+
+    #[test]
+    fn tables() {
+        let html = render("|===\n|Name |Role\n|Ada |Admin\n|===");
+        assert!(html.contains("<table>"), "{}", html);
+        assert!(html.contains("Ada"), "{}", html);
+        assert!(html.contains("Admin"), "{}", html);
+    }
+
+    #[test]
+    fn admonitions() {
+        let html = render("NOTE: Back up first.");
+        assert!(html.contains("Note"), "{}", html);
+        assert!(html.contains("Back up first."), "{}", html);
+    }
+
+    #[test]
+    fn attribute_entries_and_comments_are_skipped() {
+        let html = render(":toc: left\n\n////\nhidden\n////\n\nShown");
+        assert!(!html.contains("toc"), "{}", html);
+        assert!(!html.contains("hidden"), "{}", html);
+        assert!(html.contains("Shown"), "{}", html);
+    }
+
+    #[test]
+    fn link_targets_name_pages() {
+        let targets = AsciiDoc.link_targets("<<Deploy runbook>> and xref:Other.adoc[other]");
+        assert_eq!(targets, vec!["Deploy runbook".to_string(), "Other".to_string()]);
+    }
+}
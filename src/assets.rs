@@ -0,0 +1,177 @@
+//! Files under `static/`, compiled into the binary and served from
+//! `/static/<name>`. Pages link to each file under a name carrying a hash of
+//! its contents, see `build.rs`, so browsers can keep it until a build
+//! changes it. The plain name is still served for anything that kept an old
+//! link.
+//!
+//! With `--dev`, files are read from the source tree's `static/` on every
+//! request instead and linked by their plain names, so edits to scripts and
+//! the theme stylesheet, `theme.css`, show on reload without a rebuild.
+//! Templates are still compiled in by askama, which can't load them at
+//! runtime, so `--dev` only warns about ones edited since the build.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use tracing::{event, Level};
+
+/// `(name, hashed name, contents, gzipped contents, brotli contents)`.
+type StaticFile = (&'static str, &'static str, &'static [u8], &'static [u8], &'static [u8]);
+
+const FILES: &[StaticFile] = include!(concat!(env!("OUT_DIR"), "/static_files.rs"));
+
+/// Content codings each file is compressed with, most preferred first.
+pub const CODINGS: &[&str] = &["br", "gzip"];
+
+static DEV_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Serves files from `dir` from now on, see `--dev`. Call once at startup.
+pub fn serve_from_disk(dir: PathBuf) {
+    let _ = DEV_DIR.set(dir);
+}
+
+static TEMPLATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+static STALE_TEMPLATES: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Checks templates in `dir` from now on, see `warn_stale_templates`. Call
+/// once at startup.
+pub fn watch_templates(dir: PathBuf) {
+    let _ = TEMPLATE_DIR.set(dir);
+}
+
+/// With `--dev`, logs each template edited since this binary was built,
+/// once: unlike `static/`, those edits only show after a rebuild.
+pub fn warn_stale_templates() {
+    let dir = match TEMPLATE_DIR.get() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let built = match std::env::current_exe().and_then(|exe| exe.metadata()?.modified()) {
+        Ok(built) => built,
+        Err(..) => return,
+    };
+    let mut warned = STALE_TEMPLATES.lock().unwrap();
+    for path in edited_since(dir, built) {
+        if warned.insert(path.clone()) {
+            event!(Level::WARN, "{} changed since this binary was built; rebuild to see it", path.display());
+        }
+    }
+}
+
+fn edited_since(dir: &Path, since: SystemTime) -> Vec<PathBuf> {
+    let mut edited = Vec::new();
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() => edited.extend(edited_since(&path, since)),
+            Ok(meta) if meta.modified().is_ok_and(|modified| modified > since) => edited.push(path),
+            _ => (),
+        }
+    }
+    edited
+}
+
+/// With `--dev`, the current contents of `name` and their content type, or
+/// `None` if there's no such file. `None` too without `--dev`.
+pub fn from_disk(name: &str) -> Option<(&'static str, Vec<u8>)> {
+    let dir = DEV_DIR.get()?;
+    // the route has one segment, but it's been percent-decoded
+    if name.starts_with('.') || name.contains(&['/', '\\'][..]) {
+        return None;
+    }
+    let data = std::fs::read(dir.join(name)).ok()?;
+    Some((content_type(name), data))
+}
+
+/// A compiled-in static file.
+pub struct Asset {
+    pub content_type: &'static str,
+    pub data: &'static [u8],
+    pub gzipped: &'static [u8],
+    pub brotli: &'static [u8],
+    /// The name pages link to it by.
+    pub hashed_name: &'static str,
+    /// Whether it was asked for by its hashed name, so it can be cached for
+    /// good.
+    pub immutable: bool,
+}
+
+impl Asset {
+    /// A strong entity tag for the contents in `coding`, one of `CODINGS`,
+    /// or as they are. The hashed name already changes with the contents.
+    pub fn etag(&self, coding: Option<&str>) -> String {
+        match coding {
+            Some(coding) => format!("\"{}-{}\"", self.hashed_name, coding),
+            None => format!("\"{}\"", self.hashed_name),
+        }
+    }
+
+    /// The contents in `coding`, one of `CODINGS`, or as they are.
+    pub fn body(&self, coding: Option<&str>) -> &'static [u8] {
+        match coding {
+            Some("br") => self.brotli,
+            Some(_) => self.gzipped,
+            None => self.data,
+        }
+    }
+
+    pub fn cache_control(&self) -> &'static str {
+        if self.immutable {
+            IMMUTABLE_CACHE_CONTROL
+        } else {
+            CACHE_CONTROL
+        }
+    }
+}
+
+/// Lifetime sent with static files requested by their plain name.
+pub const CACHE_CONTROL: &str = "public, max-age=3600";
+
+/// Sent with files served by `from_disk`, which may change any moment.
+pub const DEV_CACHE_CONTROL: &str = "no-store";
+
+/// Lifetime sent with static files requested by their hashed name, which
+/// always refers to the same contents.
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Every name `get` knows, for copying the whole set out.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    FILES.iter().map(|&(name, ..)| name)
+}
+
+/// Looks up a static file by its name under `static/` or its hashed name.
+pub fn get(name: &str) -> Option<Asset> {
+    let &(plain_name, hashed_name, data, gzipped, brotli) = FILES
+        .iter()
+        .find(|(plain_name, hashed_name, ..)| name == *plain_name || name == *hashed_name)?;
+    Some(Asset {
+        content_type: content_type(plain_name),
+        data,
+        gzipped,
+        brotli,
+        hashed_name,
+        immutable: name == hashed_name,
+    })
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit('.').next() {
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The name to link to `name` by, falling back to `name` itself for a file
+/// that isn't compiled in, or for every file with `--dev`.
+pub fn hashed_name(name: &'static str) -> &'static str {
+    if DEV_DIR.get().is_some() {
+        return name;
+    }
+    FILES
+        .iter()
+        .find(|(plain_name, ..)| *plain_name == name)
+        .map_or(name, |&(_, hashed_name, ..)| hashed_name)
+}
@@ -0,0 +1,162 @@
+use ring::digest;
+
+use crate::auth;
+
+/// For responses addressed by content hash, which never change.
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// A file type that may be uploaded, identified by extension and checked
+/// against the file's leading bytes.
+pub struct AllowedType {
+    pub extensions: &'static [&'static str],
+    pub content_type: &'static str,
+    magic: fn(&[u8]) -> bool,
+}
+
+pub const ALLOWED_TYPES: &[AllowedType] = &[
+    AllowedType {
+        extensions: &["png"],
+        content_type: "image/png",
+        magic: |d| d.starts_with(b"\x89PNG\r\n\x1a\n"),
+    },
+    AllowedType {
+        extensions: &["jpg", "jpeg"],
+        content_type: "image/jpeg",
+        magic: |d| d.starts_with(b"\xff\xd8\xff"),
+    },
+    AllowedType {
+        extensions: &["gif"],
+        content_type: "image/gif",
+        magic: |d| d.starts_with(b"GIF87a") || d.starts_with(b"GIF89a"),
+    },
+    AllowedType {
+        extensions: &["webp"],
+        content_type: "image/webp",
+        magic: |d| d.len() >= 12 && &d[..4] == b"RIFF" && &d[8..12] == b"WEBP",
+    },
+    AllowedType {
+        extensions: &["pdf"],
+        content_type: "application/pdf",
+        magic: |d| d.starts_with(b"%PDF-"),
+    },
+    AllowedType {
+        extensions: &["txt", "md", "csv"],
+        content_type: "text/plain; charset=utf-8",
+        magic: |d| !d.contains(&0) && std::str::from_utf8(d).is_ok(),
+    },
+];
+
+#[derive(Debug)]
+pub enum AttachmentError {
+    BadFilename,
+    DisallowedType(String),
+    ContentMismatch(&'static str),
+    TooLarge { size: u64, limit: u64 },
+    UserQuotaExceeded { limit: u64 },
+    SiteQuotaExceeded { limit: u64 },
+}
+
+impl std::fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AttachmentError::BadFilename => write!(f, "invalid attachment filename"),
+            AttachmentError::DisallowedType(ext) => {
+                write!(f, "files of type {:?} may not be uploaded", ext)
+            }
+            AttachmentError::ContentMismatch(expected) => {
+                write!(f, "file contents do not look like {}", expected)
+            }
+            AttachmentError::TooLarge { size, limit } => write!(
+                f,
+                "file is {} bytes, the largest allowed upload is {} bytes",
+                size, limit
+            ),
+            AttachmentError::UserQuotaExceeded { limit } => {
+                write!(f, "this upload would exceed your {} byte quota", limit)
+            }
+            AttachmentError::SiteQuotaExceeded { limit } => {
+                write!(f, "this upload would exceed the site's {} byte quota", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AttachmentError {}
+
+pub fn is_valid_filename(filename: &str) -> bool {
+    !filename.is_empty()
+        && !filename.starts_with('.')
+        && !filename.contains(&['/', '\\', '\0'][..])
+        && filename.len() <= 255
+}
+
+/// Attachments are stored once per distinct contents, keyed by the hex
+/// SHA-256 of the bytes.
+pub fn content_hash(data: &[u8]) -> String {
+    auth::to_hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+pub fn is_valid_content_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Checks `filename` against the allowlist and sniffs `data` to confirm it
+/// matches, returning the content type to serve it with.
+pub fn check_type(filename: &str, data: &[u8]) -> Result<&'static str, AttachmentError> {
+    if !is_valid_filename(filename) {
+        return Err(AttachmentError::BadFilename);
+    }
+    let ext = match filename.rsplit_once('.') {
+        Some((_, ext)) => ext.to_ascii_lowercase(),
+        None => return Err(AttachmentError::DisallowedType(String::new())),
+    };
+    let allowed = ALLOWED_TYPES
+        .iter()
+        .find(|t| t.extensions.contains(&&ext[..]))
+        .ok_or(AttachmentError::DisallowedType(ext))?;
+
+    if !(allowed.magic)(data) {
+        return Err(AttachmentError::ContentMismatch(allowed.content_type));
+    }
+    Ok(allowed.content_type)
+}
+
+/// The content type of `data` if it is one of the allowed image types,
+/// going by its leading bytes alone.
+pub fn sniff_image(data: &[u8]) -> Option<&'static str> {
+    ALLOWED_TYPES
+        .iter()
+        .find(|t| t.content_type.starts_with("image/") && (t.magic)(data))
+        .map(|t| t.content_type)
+}
+
+/// Upload limits, in bytes. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UploadLimits {
+    pub max_file_size: Option<u64>,
+    pub user_quota: Option<u64>,
+    pub site_quota: Option<u64>,
+}
+
+impl UploadLimits {
+    /// `user_used` and `site_used` are the bytes already stored, not counting
+    /// any file this upload replaces.
+    pub fn check(&self, size: u64, user_used: u64, site_used: u64) -> Result<(), AttachmentError> {
+        if let Some(limit) = self.max_file_size {
+            if size > limit {
+                return Err(AttachmentError::TooLarge { size, limit });
+            }
+        }
+        if let Some(limit) = self.user_quota {
+            if user_used + size > limit {
+                return Err(AttachmentError::UserQuotaExceeded { limit });
+            }
+        }
+        if let Some(limit) = self.site_quota {
+            if site_used + size > limit {
+                return Err(AttachmentError::SiteQuotaExceeded { limit });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::num::NonZeroU32;
+use std::sync::OnceLock;
+
+use hyper::{header, Body, Request};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, pbkdf2};
+
+use crate::preferences::Preferences;
+use crate::proxy::ClientInfo;
+use crate::trust::TrustLevel;
+
+pub const SESSION_COOKIE: &str = "session";
+
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const PASSWORD_HASH_PREFIX: &str = "pbkdf2-sha256";
+
+/// User agents are cut to this many characters before they're stored.
+const MAX_USER_AGENT_CHARS: usize = 300;
+
+#[derive(Debug, Clone)]
+pub struct User {
+    pub username: String,
+    pub preferences: Preferences,
+    /// Listed with `--admin`.
+    pub is_admin: bool,
+    /// Published revisions attributed to the user.
+    pub edit_count: i64,
+    pub trust: TrustLevel,
+}
+
+impl User {
+    /// The name to show in page chrome.
+    pub fn display_name(&self) -> &str {
+        self.preferences.display_name.as_deref().unwrap_or(&self.username)
+    }
+}
+
+/// The authenticated user for a request, if any. Inserted into the request
+/// extensions by `Handler::handle`.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub Option<User>);
+
+impl CurrentUser {
+    pub fn of(req: &Request<Body>) -> Option<&User> {
+        req.extensions()
+            .get::<CurrentUser>()
+            .and_then(|cu| cu.0.as_ref())
+    }
+
+    /// Whether the request's user has reached `level`. Anonymous visitors
+    /// never have.
+    pub fn has_trust(req: &Request<Body>, level: TrustLevel) -> bool {
+        matches!(CurrentUser::of(req), Some(user) if user.trust >= level)
+    }
+
+    /// The name edits and comments are attributed to: the username, or the
+    /// client address for anonymous requests.
+    pub fn attribution(req: &Request<Body>) -> String {
+        match (CurrentUser::of(req), ClientInfo::of(req)) {
+            (Some(user), _) => user.username.clone(),
+            (None, Some(client)) => client.addr.to_string(),
+            (None, None) => "Anonymous".to_string(),
+        }
+    }
+}
+
+/// Where a session is used from, kept for `/settings/sessions`.
+#[derive(Debug, Clone, Default)]
+pub struct SessionClient {
+    pub addr: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl SessionClient {
+    pub fn of(req: &Request<Body>) -> SessionClient {
+        SessionClient {
+            addr: ClientInfo::of(req).map(|client| client.addr.to_string()),
+            user_agent: req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|agent| agent.chars().take(MAX_USER_AGENT_CHARS).collect()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RandomError;
+
+impl std::fmt::Display for RandomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "system random number generator failed")
+    }
+}
+
+impl std::error::Error for RandomError {}
+
+pub fn random_bytes(buf: &mut [u8]) -> Result<(), RandomError> {
+    SystemRandom::new().fill(buf).map_err(|_| RandomError)
+}
+
+pub fn new_session_token() -> Result<String, RandomError> {
+    let mut token = [0u8; 32];
+    random_bytes(&mut token)?;
+    Ok(to_hex(&token))
+}
+
+pub fn hash_password(password: &str) -> Result<String, RandomError> {
+    let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).unwrap();
+    let mut salt = [0u8; 16];
+    random_bytes(&mut salt)?;
+
+    let mut hash = [0u8; digest::SHA256_OUTPUT_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+
+    Ok(format!(
+        "{}${}${}${}",
+        PASSWORD_HASH_PREFIX,
+        iterations,
+        base64::encode(salt),
+        base64::encode(hash)
+    ))
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (iterations, salt, hash) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(PASSWORD_HASH_PREFIX), Some(i), Some(s), Some(h)) => (i, s, h),
+        _ => return false,
+    };
+    let iterations = match iterations.parse().ok().and_then(NonZeroU32::new) {
+        Some(i) => i,
+        None => return false,
+    };
+    let (salt, hash) = match (base64::decode(salt), base64::decode(hash)) {
+        (Ok(s), Ok(h)) => (s, h),
+        _ => return false,
+    };
+
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+/// Takes as long as `verify_password` does for a real account, so logging
+/// in as someone who doesn't exist isn't answered any faster than with a
+/// wrong password.
+pub fn verify_no_password(password: &str) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let stored = DUMMY_HASH.get_or_init(|| {
+        format!(
+            "{}${}${}${}",
+            PASSWORD_HASH_PREFIX,
+            PBKDF2_ITERATIONS,
+            base64::encode([0u8; 16]),
+            base64::encode([0u8; digest::SHA256_OUTPUT_LEN])
+        )
+    });
+    verify_password(password, stored);
+}
+
+pub fn cookie<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    for value in req.headers().get_all(header::COOKIE) {
+        let value = match value.to_str() {
+            Ok(v) => v,
+            Err(..) => continue,
+        };
+        for pair in value.split(';') {
+            if let Some((k, v)) = pair.trim().split_once('=') {
+                if k == name {
+                    return Some(v);
+                }
+            }
+        }
+    }
+    None
+}
+
+pub fn session_cookie(token: &str) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax",
+        SESSION_COOKIE, token
+    )
+}
+
+pub fn expired_session_cookie() -> String {
+    format!(
+        "{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0",
+        SESSION_COOKIE
+    )
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        out.push_str(&format!("{:02x}", b));
+    }
+    out
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
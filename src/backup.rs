@@ -0,0 +1,291 @@
+//! `wiki backup` and `wiki restore`: page history as a gzipped file of JSON
+//! lines. A full backup holds every revision; `--since` takes only what
+//! changed after a checkpoint, so a large wiki can take one full backup and
+//! cheap nightly increments, each continuing from the `until` change id of
+//! the one before:
+//!
+//! ```text
+//! wiki backup --output full.jsonl.gz                  # until 1200
+//! wiki backup --output mon.jsonl.gz --since 1200      # until 1350
+//! wiki backup --output tue.jsonl.gz --since 1350
+//! ```
+//!
+//! Change ids come from one database sequence, `change_seq`, stamped on a
+//! revision when it's saved, reviewed, redacted or made current, and on
+//! each move. An increment so carries approvals and rejections of older
+//! revisions as well as new ones.
+//!
+//! The first line is a manifest, then come the moves, then the revisions in
+//! id order, each under its page's name at backup time. Merges and
+//! attachments aren't included.
+//!
+//! Restoring applies archives in order to a freshly provisioned database,
+//! refusing one that doesn't continue from the last archive restored.
+//! Revision ids are kept, so a revision changed since an earlier archive is
+//! updated in place.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use tokio_postgres::IsolationLevel;
+
+use crate::queries::{BackupMove, BackupRevision, MoveEntry};
+use crate::{index_document, DynResult, HandlerInner};
+
+const FORMAT: i64 = 2;
+
+/// Where an incremental backup starts: after a change id, as printed by
+/// the previous backup, or after a point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    Change(i64),
+    Time(DateTime<Utc>),
+}
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Since, String> {
+        if let Ok(id) = s.parse() {
+            return Ok(Since::Change(id));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|time| Since::Time(time.with_timezone(&Utc)))
+            .map_err(|_| format!("expected a change id or an RFC 3339 time, got {:?}", s))
+    }
+}
+
+impl fmt::Display for Since {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Since::Change(id) => write!(f, "change {}", id),
+            Since::Time(time) => write!(f, "{}", time.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub revisions: usize,
+    pub moves: usize,
+    /// The newest change id covered, for the next `--since`.
+    pub until: i64,
+}
+
+/// Writes the revisions and moves after `since`, or all of them, to `path`.
+pub async fn backup(inner: &mut HandlerInner, since: Option<Since>, path: &Path) -> DynResult<Summary> {
+    let queries = &inner.queries;
+    // one snapshot, so the manifest's `until` matches what's written
+    let tx = inner
+        .db
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()
+        .await?;
+
+    let epoch = Utc.timestamp_opt(0, 0).unwrap();
+    let (after_change, after_time) = match since {
+        None => (0, epoch),
+        Some(Since::Change(id)) => (id, epoch),
+        Some(Since::Time(time)) => (0, time),
+    };
+    let until = queries.fetch_max_change_id(&tx).await?;
+    if after_change > until {
+        return Err(format!("no change {} to back up from", after_change).into());
+    }
+    let moves = queries
+        .fetch_moves_after(&tx, after_change, &after_time, until)
+        .await?;
+    let revisions = queries
+        .fetch_revisions_after(&tx, after_change, &after_time, until)
+        .await?;
+    tx.commit().await?;
+
+    let since = match since {
+        None => Value::Null,
+        Some(Since::Change(id)) => json!({ "change": id }),
+        Some(Since::Time(time)) => json!({ "time": time.to_rfc3339() }),
+    };
+    let manifest = json!({
+        "format": FORMAT,
+        "since": since,
+        "until": until,
+        "created_at": Utc::now().to_rfc3339(),
+    });
+
+    let mut out = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    write_line(&mut out, &manifest)?;
+    for entry in &moves {
+        write_line(&mut out, &move_json(entry))?;
+    }
+    for revision in &revisions {
+        write_line(&mut out, &revision_json(revision))?;
+    }
+    out.finish()?.flush()?;
+
+    Ok(Summary {
+        revisions: revisions.len(),
+        moves: moves.len(),
+        until,
+    })
+}
+
+/// Applies the archive at `path` in one transaction. Moves whose old name
+/// doesn't exist, or whose new name already does, are skipped.
+pub async fn restore(inner: &mut HandlerInner, path: &Path) -> DynResult<Summary> {
+    let mut lines = BufReader::new(GzDecoder::new(File::open(path)?)).lines();
+    let manifest: Value = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(format!("{}: empty archive", path.display()).into()),
+    };
+    if manifest["format"].as_i64() != Some(FORMAT) {
+        return Err(format!("{}: not a backup archive this version can read", path.display()).into());
+    }
+    let until = manifest["until"].as_i64().ok_or("manifest without `until`")?;
+
+    let queries = &inner.queries;
+    let tx = inner.db.transaction().await?;
+
+    let restored_until = queries.fetch_restore_checkpoint(&tx).await?;
+    let since = &manifest["since"];
+    let continues = if since.is_null() {
+        restored_until.is_none()
+    } else if let Some(id) = since["change"].as_i64() {
+        restored_until == Some(id)
+    } else {
+        // a time can't be checked against a change id
+        restored_until.is_some()
+    };
+    if !continues {
+        let expected = match restored_until {
+            Some(id) => format!("an archive continuing from change {}", id),
+            None => "a full backup".to_string(),
+        };
+        return Err(format!("{}: doesn't follow what's been restored, expected {}", path.display(), expected).into());
+    }
+
+    let mut summary = Summary {
+        until,
+        ..Summary::default()
+    };
+    // moves of pages whose revisions come later in the archive, already
+    // under the name they were moved to
+    let mut deferred = Vec::new();
+    for line in lines {
+        let line = line?;
+        let value: Value = serde_json::from_str(&line)?;
+        match value["kind"].as_str() {
+            Some("move") => {
+                let BackupMove { entry, name } = parse_move(&value)?;
+                let renamed = queries.fetch_document_id(&tx, &entry.new_name).await?.is_none()
+                    && queries.restore_move(&tx, &entry).await?;
+                if renamed {
+                    summary.moves += 1;
+                } else {
+                    deferred.push(BackupMove { entry, name });
+                }
+            }
+            Some("revision") => {
+                let revision = parse_revision(&value)?;
+                let index = revision.current.then(|| index_document(&revision.document_data));
+                queries.restore_revision(&tx, &revision, index.as_ref()).await?;
+                summary.revisions += 1;
+            }
+            _ => return Err(format!("{}: unknown line {:?}", path.display(), line).into()),
+        }
+    }
+    for BackupMove { entry, name } in &deferred {
+        if queries.restore_move_log(&tx, name, entry).await? {
+            summary.moves += 1;
+        }
+    }
+
+    queries.reset_revision_ids(&tx).await?;
+    queries.set_restore_checkpoint(&tx, until).await?;
+    tx.commit().await?;
+    Ok(summary)
+}
+
+fn write_line(out: &mut impl Write, value: &Value) -> DynResult<()> {
+    serde_json::to_writer(&mut *out, value)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+fn move_json(BackupMove { entry, name }: &BackupMove) -> Value {
+    json!({
+        "kind": "move",
+        "name": name,
+        "old_name": entry.old_name,
+        "new_name": entry.new_name,
+        "moved_by": entry.moved_by,
+        "moved_at": entry.moved_at.to_rfc3339(),
+        "reason": entry.reason,
+    })
+}
+
+fn revision_json(revision: &BackupRevision) -> Value {
+    json!({
+        "kind": "revision",
+        "id": revision.id,
+        "name": revision.name,
+        "created_at": revision.created_at.to_rfc3339(),
+        "modified_by": revision.modified_by,
+        "data": revision.document_data,
+        "status": revision.status,
+        "reviewed_by": revision.reviewed_by,
+        "reviewed_at": revision.reviewed_at.map(|time| time.to_rfc3339()),
+        "current": revision.current,
+    })
+}
+
+fn parse_move(value: &Value) -> DynResult<BackupMove> {
+    let entry = MoveEntry {
+        old_name: string(value, "old_name")?,
+        new_name: string(value, "new_name")?,
+        moved_by: string(value, "moved_by")?,
+        moved_at: time(value, "moved_at")?,
+        reason: string(value, "reason")?,
+    };
+    Ok(BackupMove {
+        name: string(value, "name")?,
+        entry,
+    })
+}
+
+fn parse_revision(value: &Value) -> DynResult<BackupRevision> {
+    Ok(BackupRevision {
+        id: value["id"].as_i64().ok_or("revision without an id")?,
+        name: string(value, "name")?,
+        created_at: time(value, "created_at")?,
+        modified_by: string(value, "modified_by")?,
+        document_data: string(value, "data")?,
+        status: string(value, "status")?,
+        reviewed_by: value["reviewed_by"].as_str().map(str::to_string),
+        reviewed_at: match value["reviewed_at"].is_null() {
+            true => None,
+            false => Some(time(value, "reviewed_at")?),
+        },
+        current: value["current"].as_bool().unwrap_or(false),
+    })
+}
+
+fn string(value: &Value, key: &str) -> DynResult<String> {
+    match value[key].as_str() {
+        Some(s) => Ok(s.to_string()),
+        None => Err(format!("missing {:?}", key).into()),
+    }
+}
+
+fn time(value: &Value, key: &str) -> DynResult<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(&string(value, key)?)?.with_timezone(&Utc))
+}
@@ -0,0 +1,49 @@
+//! Turns away anonymous requests to edit that look like they come from a
+//! crawler, so a bot following every link can't open editors or submit
+//! forms and leave noise in the history. Going by headers alone, this only
+//! stops bots that say what they are; see `--anonymous-challenge` for the
+//! rest. Logged-in users are never checked.
+
+use hyper::header::{HeaderMap, FROM, USER_AGENT};
+
+/// Case-insensitive `User-Agent` substrings treated as bots unless
+/// `--allow-bot-edits` is given. `--bot-user-agent` adds to them.
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "archiver",
+    "facebookexternalhit",
+    "headlesschrome",
+];
+
+pub struct BotFilter {
+    /// Lowercased.
+    patterns: Vec<String>,
+}
+
+impl BotFilter {
+    pub fn new(extra: &[String]) -> BotFilter {
+        let patterns = DEFAULT_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .chain(extra.iter().map(|p| p.to_lowercase()))
+            .collect();
+        BotFilter { patterns }
+    }
+
+    /// Whether a request with `headers` looks automated: its user agent
+    /// matches a pattern, or it has a `From` header, which RFC 9110 asks
+    /// robots to send and browsers never do.
+    pub fn is_bot(&self, headers: &HeaderMap) -> bool {
+        if headers.contains_key(FROM) {
+            return true;
+        }
+        let user_agent = match headers.get(USER_AGENT).and_then(|v| v.to_str().ok()) {
+            Some(user_agent) => user_agent.to_lowercase(),
+            None => return false,
+        };
+        self.patterns.iter().any(|p| user_agent.contains(p.as_str()))
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Default and largest link depth followed by `export-bundle`.
+pub const DEFAULT_DEPTH: usize = 2;
+pub const MAX_DEPTH: usize = 5;
+
+/// Stop following links once this many pages are in the bundle.
+pub const MAX_PAGES: usize = 500;
+
+const BLOCK: usize = 512;
+
+/// Writes a gzip-compressed tar archive. Only regular files are supported;
+/// paths that don't fit the ustar header get a PAX extended header.
+pub struct TarWriter<W: Write> {
+    out: GzEncoder<W>,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(out: W) -> TarWriter<W> {
+        TarWriter {
+            out: GzEncoder::new(out, Compression::default()),
+        }
+    }
+
+    pub fn append(&mut self, path: &str, data: &[u8], mtime: i64) -> io::Result<()> {
+        if path.len() > 100 || !path.is_ascii() {
+            let record = pax_record("path", path);
+            self.write_entry("PaxHeader", b'x', record.as_bytes(), mtime)?;
+        }
+        self.write_entry(path, b'0', data, mtime)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0; BLOCK * 2])?;
+        self.out.finish()
+    }
+
+    fn write_entry(&mut self, path: &str, kind: u8, data: &[u8], mtime: i64) -> io::Result<()> {
+        let mut header = [0u8; BLOCK];
+        let name = truncate_to_boundary(path, 100);
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        write_octal(&mut header[100..108], 0o644);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], data.len() as u64);
+        write_octal(&mut header[136..148], mtime.max(0) as u64);
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // the checksum is computed with its own field set to spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        write_octal(&mut header[148..155], checksum as u64);
+
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+        self.out.write_all(&[0; BLOCK][..padding])
+    }
+}
+
+/// Writes `value` as zero-padded octal, leaving room for the trailing NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// A PAX record is `"<len> <key>=<value>\n"`, where `<len>` counts itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let body_len = key.len() + value.len() + 3;
+    let mut len = body_len + 1;
+    while len != body_len + len.to_string().len() {
+        len = body_len + len.to_string().len();
+    }
+    format!("{} {}={}\n", len, key, value)
+}
+
+fn truncate_to_boundary(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
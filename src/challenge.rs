@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use hyper::HeaderMap;
+use ring::{digest, hmac};
+
+use crate::auth::{self, RandomError};
+
+/// How long an issued challenge may be solved and submitted, in seconds.
+const CHALLENGE_LIFETIME: i64 = 600;
+
+pub const CHALLENGE_HEADER: &str = "x-challenge";
+pub const SOLUTION_HEADER: &str = "x-challenge-solution";
+
+/// Which challenge anonymous editors must pass before saving, from
+/// `--anonymous-challenge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeKind {
+    /// The browser finds a nonce whose SHA-256 hash, combined with the
+    /// challenge, starts with `difficulty` zero bits.
+    ProofOfWork { difficulty: u8 },
+}
+
+#[derive(Debug)]
+pub enum ChallengeError {
+    Missing,
+    Invalid,
+    Expired,
+    AlreadyUsed,
+    WrongSolution,
+}
+
+impl std::fmt::Display for ChallengeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChallengeError::Missing => write!(f, "anonymous edits must include a solved challenge"),
+            ChallengeError::Invalid => write!(f, "the challenge was not issued by this server"),
+            ChallengeError::Expired => write!(f, "the challenge has expired, reload the page"),
+            ChallengeError::AlreadyUsed => write!(f, "the challenge has already been used"),
+            ChallengeError::WrongSolution => write!(f, "the challenge solution is incorrect"),
+        }
+    }
+}
+
+impl std::error::Error for ChallengeError {}
+
+/// A challenge handed to the edit page, to be solved in the browser and sent
+/// back with the save.
+#[derive(Debug, Clone)]
+pub struct IssuedChallenge {
+    pub token: String,
+    pub difficulty: u8,
+}
+
+/// Issues and checks challenges. Tokens are signed rather than stored, so
+/// only tokens that have been redeemed need remembering, until they expire.
+pub struct Challenger {
+    kind: ChallengeKind,
+    key: hmac::Key,
+    spent: Mutex<HashMap<String, i64>>,
+}
+
+impl Challenger {
+    pub fn new(kind: ChallengeKind) -> Result<Challenger, RandomError> {
+        let mut key = [0u8; 32];
+        auth::random_bytes(&mut key)?;
+        Ok(Challenger {
+            kind,
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            spent: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn issue(&self) -> Result<IssuedChallenge, RandomError> {
+        let ChallengeKind::ProofOfWork { difficulty } = self.kind;
+
+        let mut nonce = [0u8; 16];
+        auth::random_bytes(&mut nonce)?;
+        let expires = chrono::Utc::now().timestamp() + CHALLENGE_LIFETIME;
+        let payload = format!("{}.{}", expires, auth::to_hex(&nonce));
+        let tag = hmac::sign(&self.key, payload.as_bytes());
+
+        Ok(IssuedChallenge {
+            token: format!("{}.{}", payload, auth::to_hex(tag.as_ref())),
+            difficulty,
+        })
+    }
+
+    /// Checks the challenge and solution headers of a save, marking the
+    /// challenge as used if it passes.
+    pub fn verify(&self, headers: &HeaderMap) -> Result<(), ChallengeError> {
+        let ChallengeKind::ProofOfWork { difficulty } = self.kind;
+
+        let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+        let (token, solution) = match (header(CHALLENGE_HEADER), header(SOLUTION_HEADER)) {
+            (Some(token), Some(solution)) => (token, solution),
+            _ => return Err(ChallengeError::Missing),
+        };
+
+        let (payload, tag) = token.rsplit_once('.').ok_or(ChallengeError::Invalid)?;
+        let tag = auth::from_hex(tag).ok_or(ChallengeError::Invalid)?;
+        hmac::verify(&self.key, payload.as_bytes(), &tag).map_err(|_| ChallengeError::Invalid)?;
+
+        let expires: i64 = payload
+            .split('.')
+            .next()
+            .and_then(|e| e.parse().ok())
+            .ok_or(ChallengeError::Invalid)?;
+        let now = chrono::Utc::now().timestamp();
+        if expires < now {
+            return Err(ChallengeError::Expired);
+        }
+
+        let hash = digest::digest(&digest::SHA256, format!("{}:{}", token, solution).as_bytes());
+        if leading_zero_bits(hash.as_ref()) < difficulty as u32 {
+            return Err(ChallengeError::WrongSolution);
+        }
+
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, &mut expires| expires >= now);
+        if spent.insert(token.to_string(), expires).is_some() {
+            return Err(ChallengeError::AlreadyUsed);
+        }
+        Ok(())
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for &b in bytes {
+        bits += b.leading_zeros();
+        if b != 0 {
+            break;
+        }
+    }
+    bits
+}
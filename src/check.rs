@@ -0,0 +1,152 @@
+use std::collections::BTreeSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+use crate::plugins::Plugins;
+use crate::{data, markup, transclusion, DynResult, HandlerInner};
+
+/// How often `--nightly-check` re-checks every page.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug)]
+pub enum Problem {
+    /// Rendering returned an error or panicked.
+    RenderFailed(String),
+    /// The link graph doesn't match the links in the current revision.
+    StaleLinks {
+        unrecorded: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub name: String,
+    pub problem: Problem,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.problem {
+            Problem::RenderFailed(ref err) => write!(f, "{}: failed to render: {}", self.name, err),
+            Problem::StaleLinks {
+                ref unrecorded,
+                ref removed,
+            } => write!(
+                f,
+                "{}: stale link graph, unrecorded links {:?}, removed links {:?}",
+                self.name, unrecorded, removed
+            ),
+        }
+    }
+}
+
+/// Re-renders the current revision of `name` and compares its links with the
+/// link graph. With `fix`, stale links are rewritten.
+pub async fn check_page(
+    inner: &HandlerInner,
+    plugins: &Plugins,
+    name: &str,
+    fix: bool,
+) -> DynResult<Option<Finding>> {
+    let revision = match inner.queries.fetch_current_revision(&inner.db, name).await? {
+        Some(revision) => revision,
+        None => return Ok(None),
+    };
+
+    let mut markdown = revision.document_data.clone();
+    plugins.pre_render(&mut markdown);
+    let markup = markup::of(&markdown);
+    let expanded = if markup.expands_markdown() {
+        match transclusion::expand(inner, &markdown).await {
+            Ok(expanded) => data::expand(inner, &expanded).await,
+            Err(err) => Err(err),
+        }
+    } else {
+        Ok(markdown)
+    };
+    let expanded = match expanded {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            return Ok(Some(Finding {
+                name: name.to_string(),
+                problem: Problem::RenderFailed(err.to_string()),
+            }))
+        }
+    };
+    let rendered = panic::catch_unwind(AssertUnwindSafe(|| markup.render(&expanded)));
+    let err = match rendered {
+        Ok(Ok(_)) => None,
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(..) => Some("renderer panicked".to_string()),
+    };
+    if let Some(err) = err {
+        return Ok(Some(Finding {
+            name: name.to_string(),
+            problem: Problem::RenderFailed(err),
+        }));
+    }
+
+    let links = markup::of(&revision.document_data).link_targets(&revision.document_data);
+    let current: BTreeSet<&String> = links.iter().collect();
+    let recorded = inner.queries.fetch_links(&inner.db, name).await?;
+    let recorded: BTreeSet<&String> = recorded.iter().collect();
+    if current == recorded {
+        return Ok(None);
+    }
+
+    if fix {
+        inner.queries.replace_links(&inner.db, name, &links).await?;
+    }
+    Ok(Some(Finding {
+        name: name.to_string(),
+        problem: Problem::StaleLinks {
+            unrecorded: current.difference(&recorded).map(|s| s.to_string()).collect(),
+            removed: recorded.difference(&current).map(|s| s.to_string()).collect(),
+        },
+    }))
+}
+
+/// Checks every page with a current revision. The lock is taken per page so
+/// a long check doesn't hold up edits.
+pub async fn check_all(
+    inner: &RwLock<HandlerInner>,
+    plugins: &Plugins,
+    fix: bool,
+) -> DynResult<Vec<Finding>> {
+    let names = {
+        let locked = inner.read().await;
+        locked.queries.fetch_current_names(&locked.db).await?
+    };
+
+    let mut findings = Vec::new();
+    for name in names {
+        let finding = if fix {
+            check_page(&*inner.write().await, plugins, &name, true).await?
+        } else {
+            check_page(&*inner.read().await, plugins, &name, false).await?
+        };
+        findings.extend(finding);
+    }
+    Ok(findings)
+}
+
+/// Runs `check_all` every `CHECK_INTERVAL`, logging what it finds.
+pub async fn run_nightly(inner: Arc<RwLock<HandlerInner>>, plugins: Arc<Plugins>) {
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        match check_all(&inner, &plugins, false).await {
+            Ok(findings) => {
+                for finding in &findings {
+                    event!(Level::WARN, "check: {}", finding);
+                }
+                event!(Level::INFO, "check: {} pages with problems", findings.len());
+            }
+            Err(err) => event!(Level::ERROR, "check failed: {}", err),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Conditional and partial GETs for attachments and static files: entity
+//! tag matching for `If-None-Match` and `If-Range`, and single byte ranges
+//! from `Range`, so browsers can revalidate cheaply and seek through videos
+//! and large PDFs without downloading them whole.
+
+/// Whether an `If-None-Match` header matches `etag`. The comparison is
+/// weak, as RFC 9110 asks, so `W/"x"` matches `"x"`.
+pub fn none_match(if_none_match: Option<&str>, etag: &str) -> bool {
+    match if_none_match {
+        Some(value) => value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*"),
+        None => false,
+    }
+}
+
+/// Whether a `Range` header should be honoured given the request's
+/// `If-Range`. Dates aren't compared, only strong entity tags, so a date
+/// means the whole file is sent.
+pub fn if_range_allows(if_range: Option<&str>, etag: &str) -> bool {
+    match if_range {
+        Some(value) => value.trim() == etag,
+        None => true,
+    }
+}
+
+/// A `Range` header that asks for bytes past the end of the file.
+#[derive(Debug, PartialEq)]
+pub struct Unsatisfiable;
+
+/// The inclusive byte range a `Range` header asks for out of `len` bytes.
+/// `None` means the whole file: no header, a unit other than bytes, more
+/// than one range, or a header that doesn't parse, which RFC 9110 says to
+/// ignore.
+pub fn byte_range(range: Option<&str>, len: u64) -> Result<Option<(u64, u64)>, Unsatisfiable> {
+    let spec = match range.and_then(|r| r.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+
+    if first.is_empty() {
+        // `-N`, the last N bytes
+        let suffix: u64 = match last.parse() {
+            Ok(suffix) => suffix,
+            Err(_) => return Ok(None),
+        };
+        if suffix == 0 || len == 0 {
+            return Err(Unsatisfiable);
+        }
+        return Ok(Some((len.saturating_sub(suffix), len - 1)));
+    }
+
+    let first: u64 = match first.parse() {
+        Ok(first) => first,
+        Err(_) => return Ok(None),
+    };
+    let last = match last {
+        "" => u64::MAX,
+        last => match last.parse() {
+            Ok(last) if last >= first => last,
+            _ => return Ok(None),
+        },
+    };
+    if first >= len {
+        return Err(Unsatisfiable);
+    }
+    Ok(Some((first, last.min(len - 1))))
+}
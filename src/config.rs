@@ -0,0 +1,328 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use crate::attachments::UploadLimits;
+use crate::bots::BotFilter;
+use crate::challenge::ChallengeKind;
+use crate::cors::CorsPolicy;
+use crate::digest::Mailer;
+use crate::languages::Languages;
+use crate::listen::ListenSpec;
+use crate::names::NameCase;
+use crate::permissions::SitePolicy;
+use crate::proxy::IpRange;
+use crate::search::SearchWeights;
+use crate::secrets;
+use crate::site_token;
+use crate::timeouts::Timeouts;
+use crate::trust::TrustThresholds;
+
+pub struct Config {
+    pub listen: Vec<ListenSpec>,
+    pub site_policy: SitePolicy,
+    pub site_name: String,
+    pub theme: String,
+    pub trusted_proxies: Vec<IpRange>,
+    pub upload_limits: UploadLimits,
+    pub anonymous_challenge: Option<ChallengeKind>,
+    /// Serve static files from the source tree, see `assets.rs`.
+    pub dev: bool,
+    /// Answer read-only GraphQL queries at `/graphql`, see `graphql.rs`.
+    pub graphql: bool,
+    /// Hold anonymous edits for review instead of publishing them.
+    pub moderation: bool,
+    /// New pages an anonymous visitor may submit for review per hour, even
+    /// where the site policy doesn't let them edit.
+    pub anonymous_new_pages: Option<u32>,
+    pub timeouts: Timeouts,
+    /// How long an out-of-date rendering may be served, see
+    /// `render_cache.rs`.
+    pub render_stale: Option<Duration>,
+    /// What to do with saves that look like they contain credentials.
+    pub secrets_policy: secrets::Policy,
+    /// Turns away anonymous edits from crawlers, `None` under
+    /// `--allow-bot-edits`, see `bots.rs`.
+    pub bots: Option<BotFilter>,
+    /// How `/search` ranks text matches, see `search.rs`.
+    pub search_weights: SearchWeights,
+    /// Update links to a moved page without asking, see `rename_links.rs`.
+    pub update_links_on_move: bool,
+    /// Languages pages may be translated into, see `languages.rs`.
+    pub languages: Languages,
+    /// Shared secret every request must carry, see `site_token.rs`.
+    pub site_token: Option<String>,
+    /// Applied with `names::canonical` to every page name in a request.
+    pub page_name_case: NameCase,
+    /// Queries slower than this are logged at WARN, see `metrics.rs`.
+    pub slow_query_threshold: Duration,
+    /// Postgres `statement_timeout` for the server's connection, outside
+    /// transactions; see `Handler::transaction`.
+    pub statement_timeout: Option<Duration>,
+    /// Users who may merge pages.
+    pub admins: Vec<String>,
+    /// Admins only count as admins with two-factor authentication on, see
+    /// `two_factor.rs`.
+    pub require_admin_2fa: bool,
+    /// When users become autoconfirmed and trusted, see `trust.rs`.
+    pub trust: TrustThresholds,
+    /// `None` unless `--cors-origin` was given.
+    pub cors: Option<CorsPolicy>,
+    /// Extra fence language aliases, see `highlight::DEFAULT_ALIASES`.
+    pub highlight_aliases: Vec<(String, String)>,
+    /// Directory of additional `.sublime-syntax` definitions.
+    pub syntax_dir: Option<PathBuf>,
+    /// `None` unless `--digest-sendmail` was given.
+    pub digest: Option<Mailer>,
+    /// Anyone may sign up at `/register`, see `accounts.rs`.
+    pub open_registration: bool,
+}
+
+impl Config {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Config, String> {
+        let site_policy = matches
+            .value_of("site-policy")
+            .unwrap_or("open")
+            .parse()?;
+
+        let mut trusted_proxies = Vec::new();
+        for range in matches.values_of("trusted-proxy").into_iter().flatten() {
+            trusted_proxies.push(range.parse()?);
+        }
+
+        let upload_limits = UploadLimits {
+            max_file_size: parse_bytes(matches, "max-upload-size")?,
+            user_quota: parse_bytes(matches, "upload-quota-user")?,
+            site_quota: parse_bytes(matches, "upload-quota-site")?,
+        };
+
+        let anonymous_challenge = match matches.value_of("anonymous-challenge") {
+            Some("proof-of-work") => {
+                let difficulty = matches.value_of("challenge-difficulty").unwrap_or("16");
+                let difficulty = difficulty
+                    .parse()
+                    .ok()
+                    .filter(|&d| d <= 32)
+                    .ok_or_else(|| {
+                        format!("--challenge-difficulty expects 0 to 32 bits, got {:?}", difficulty)
+                    })?;
+                Some(ChallengeKind::ProofOfWork { difficulty })
+            }
+            Some("none") | None => None,
+            Some(other) => return Err(format!("unknown challenge {:?}", other)),
+        };
+
+        let origins: Vec<String> = matches
+            .values_of("cors-origin")
+            .into_iter()
+            .flatten()
+            .map(|o| o.trim_end_matches('/').to_string())
+            .collect();
+        let cors = if origins.is_empty() {
+            None
+        } else {
+            let methods = comma_separated(matches, "cors-methods")
+                .map(|m| {
+                    m.to_ascii_uppercase()
+                        .parse()
+                        .map_err(|_| format!("--cors-methods: invalid method {:?}", m))
+                })
+                .collect::<Result<_, _>>()?;
+            let max_age = matches.value_of("cors-max-age").unwrap_or("600");
+            Some(CorsPolicy {
+                origins,
+                methods,
+                headers: comma_separated(matches, "cors-headers")
+                    .map(|h| h.to_ascii_lowercase())
+                    .collect(),
+                max_age: max_age
+                    .parse()
+                    .map_err(|_| format!("--cors-max-age expects seconds, got {:?}", max_age))?,
+            })
+        };
+
+        let timeouts = Timeouts {
+            render: parse_seconds(matches, "timeout-render")?,
+            write: parse_seconds(matches, "timeout-write")?,
+            export: parse_seconds(matches, "timeout-export")?,
+        };
+
+        let render_stale = match matches.value_of("render-stale-secs") {
+            Some(_) => Some(parse_seconds(matches, "render-stale-secs")?),
+            None => None,
+        };
+
+        let site_token = match matches.value_of("site-token-file") {
+            Some(path) => {
+                let token = std::fs::read_to_string(path)
+                    .map_err(|e| format!("--site-token-file {}: {}", path, e))?;
+                let token = token.trim().to_string();
+                if !site_token::is_valid(&token) {
+                    return Err(format!(
+                        "--site-token-file {}: the token must be printable ASCII without spaces, quotes, commas or semicolons",
+                        path
+                    ));
+                }
+                Some(token)
+            }
+            None => None,
+        };
+
+        let slow_query_ms = matches.value_of("slow-query-ms").unwrap_or("");
+        let slow_query_threshold = slow_query_ms
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| format!("--slow-query-ms expects milliseconds, got {:?}", slow_query_ms))?;
+
+        let statement_timeout_ms = matches.value_of("statement-timeout-ms").unwrap_or("");
+        let statement_timeout = statement_timeout_ms
+            .parse()
+            .map(|ms| (ms > 0).then(|| Duration::from_millis(ms)))
+            .map_err(|_| format!("--statement-timeout-ms expects milliseconds, got {:?}", statement_timeout_ms))?;
+
+        let anonymous_new_pages = match matches.value_of("anonymous-new-pages") {
+            Some(value) => Some(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|&per_hour| per_hour > 0)
+                    .ok_or_else(|| format!("--anonymous-new-pages expects a positive number per hour, got {:?}", value))?,
+            ),
+            None => None,
+        };
+
+        let mut highlight_aliases = Vec::new();
+        for alias in matches.values_of("highlight-alias").into_iter().flatten() {
+            let (from, to) = alias
+                .split_once('=')
+                .filter(|(from, to)| !from.trim().is_empty() && !to.trim().is_empty())
+                .ok_or_else(|| format!("--highlight-alias expects LANG=SYNTAX, got {:?}", alias))?;
+            highlight_aliases.push((from.trim().to_string(), to.trim().to_string()));
+        }
+
+        let digest = match matches.value_of("digest-sendmail") {
+            Some(command) => {
+                let mut words = command.split_whitespace().map(str::to_string);
+                let program = words
+                    .next()
+                    .ok_or_else(|| "--digest-sendmail expects a command".to_string())?;
+                let from = matches
+                    .value_of("digest-from")
+                    .ok_or_else(|| "--digest-sendmail needs --digest-from".to_string())?;
+                let base_url = matches
+                    .value_of("digest-base-url")
+                    .ok_or_else(|| "--digest-sendmail needs --digest-base-url".to_string())?;
+                Some(Mailer {
+                    program,
+                    args: words.collect(),
+                    from: from.to_string(),
+                    base_url: base_url.trim_end_matches('/').to_string(),
+                })
+            }
+            None => None,
+        };
+        let open_registration = matches.is_present("open-registration");
+        if open_registration && digest.is_none() {
+            return Err("--open-registration needs --digest-sendmail to mail verification links".to_string());
+        }
+
+        let bots = if matches.is_present("allow-bot-edits") {
+            None
+        } else {
+            let mut extra = Vec::new();
+            for pattern in matches.values_of("bot-user-agent").into_iter().flatten() {
+                let pattern = pattern.trim();
+                if pattern.is_empty() {
+                    return Err("--bot-user-agent expects a non-empty pattern".to_string());
+                }
+                extra.push(pattern.to_string());
+            }
+            Some(BotFilter::new(&extra))
+        };
+
+        let mut trust = TrustThresholds::default();
+        if let Some(value) = matches.value_of("autoconfirmed-after") {
+            trust.autoconfirmed = value.parse().map_err(|e| format!("--autoconfirmed-after: {}", e))?;
+        }
+        if let Some(value) = matches.value_of("trusted-after") {
+            trust.trusted = value.parse().map_err(|e| format!("--trusted-after: {}", e))?;
+        }
+
+        let mut listen = Vec::new();
+        for spec in matches.values_of("listen").into_iter().flatten() {
+            listen.push(spec.parse()?);
+        }
+
+        Ok(Config {
+            listen,
+            site_policy,
+            site_name: matches.value_of("site-name").unwrap_or("wiki").to_string(),
+            theme: matches.value_of("theme").unwrap_or("light").to_string(),
+            trusted_proxies,
+            upload_limits,
+            anonymous_challenge,
+            dev: matches.is_present("dev"),
+            graphql: matches.is_present("graphql"),
+            moderation: matches.is_present("moderation"),
+            anonymous_new_pages,
+            timeouts,
+            render_stale,
+            secrets_policy: matches.value_of("secrets-policy").unwrap_or("warn").parse()?,
+            bots,
+            search_weights: match matches.value_of("search-weights") {
+                Some(weights) => weights.parse().map_err(|e| format!("--search-weights: {}", e))?,
+                None => SearchWeights::default(),
+            },
+            update_links_on_move: matches.is_present("update-links-on-move"),
+            languages: Languages::parse(matches.value_of("languages").unwrap_or(""))
+                .map_err(|e| format!("--languages: {}", e))?,
+            site_token,
+            page_name_case: matches.value_of("page-name-case").unwrap_or("sensitive").parse()?,
+            slow_query_threshold,
+            statement_timeout,
+            admins: matches
+                .values_of("admin")
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect(),
+            require_admin_2fa: matches.is_present("require-admin-2fa"),
+            trust,
+            cors,
+            highlight_aliases,
+            syntax_dir: matches.value_of("syntax-dir").map(PathBuf::from),
+            digest,
+            open_registration,
+        })
+    }
+}
+
+fn comma_separated<'a>(matches: &'a ArgMatches, name: &str) -> impl Iterator<Item = &'a str> {
+    matches
+        .value_of(name)
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+fn parse_seconds(matches: &ArgMatches, name: &str) -> Result<Duration, String> {
+    let value = matches.value_of(name).unwrap_or("");
+    value
+        .parse()
+        .ok()
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("--{} expects a positive number of seconds, got {:?}", name, value))
+}
+
+fn parse_bytes(matches: &ArgMatches, name: &str) -> Result<Option<u64>, String> {
+    match matches.value_of(name) {
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("--{} expects a number of bytes, got {:?}", name, v)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,95 @@
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+/// Which other sites may call the API from a browser, see `--cors-origin`.
+/// Credentials are never allowed, so cross-origin requests always run as an
+/// anonymous user.
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    /// Exact origins such as `https://app.example.com`, or `*` for any.
+    pub origins: Vec<String>,
+    pub methods: Vec<Method>,
+    /// Request headers a preflight may ask for, lowercased.
+    pub headers: Vec<String>,
+    /// How long browsers may cache a preflight response, in seconds.
+    pub max_age: u32,
+}
+
+impl CorsPolicy {
+    /// The value for `Access-Control-Allow-Origin`, if the request's origin
+    /// is allowed.
+    pub fn allowed_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        if self.origins.iter().any(|o| o == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let allowed = self
+            .origins
+            .iter()
+            .any(|o| o.as_bytes() == origin.as_bytes());
+        if allowed {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Answers a preflight request from an allowed origin. Methods or headers
+    /// outside the policy get a 403 without CORS headers, which the browser
+    /// reports as a CORS failure.
+    pub fn preflight(&self, req: &Request<Body>, origin: HeaderValue) -> Response<Body> {
+        let requested = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| m.to_str().ok())
+            .unwrap_or("");
+        let method_allowed = self.methods.iter().any(|m| m.as_str() == requested);
+        let headers_allowed = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("")
+            .split(',')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .all(|h| self.headers.contains(&h));
+
+        if !method_allowed || !headers_allowed {
+            return Response::builder()
+                .header("Content-Type", "text/plain; charset=utf8")
+                .header(header::VARY, "Origin")
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("Cross-origin request not allowed."))
+                .expect("unable to build response");
+        }
+
+        let methods: Vec<&str> = self.methods.iter().map(Method::as_str).collect();
+        Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, methods.join(", "))
+            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, self.headers.join(", "))
+            .header(header::ACCESS_CONTROL_MAX_AGE, self.max_age)
+            .header(header::VARY, "Origin")
+            .body(Body::empty())
+            .expect("unable to build response")
+    }
+
+    /// Adds the headers that let the browser hand `response` to the page.
+    pub fn apply(&self, response: &mut Response<Body>, origin: HeaderValue) {
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        // the API answers 202 with a Location for held edits, and tags
+        // revisions with an ETag for If-Match
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static("Location, ETag"),
+        );
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+}
+
+pub fn is_preflight(req: &Request<Body>) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
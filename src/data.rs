@@ -0,0 +1,267 @@
+//! Structured data blocks. A page declares key/value pairs in a fenced block
+//! with the `data` info string:
+//!
+//! ````markdown
+//! ```data
+//! type: project
+//! status: active
+//! owner: alice
+//! ```
+//! ````
+//!
+//! The pairs are stored in `page_data` when the page is saved. A fenced
+//! `query` block renders a table of the pages whose data matches:
+//!
+//! ````markdown
+//! ```query
+//! where: type = project
+//! where: status = active
+//! columns: owner, status
+//! sort: owner
+//! ```
+//! ````
+//!
+//! `/api/v1/data` takes the same directives as query parameters.
+
+use std::collections::BTreeMap;
+
+use crate::routes::RouteWiki;
+use crate::{DynResult, HandlerInner};
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 500;
+
+/// A fenced block with the given info string.
+struct Block {
+    start: usize,
+    end: usize,
+    info: String,
+    body: String,
+}
+
+/// Finds fenced code blocks, with their info strings lowercased.
+fn fenced_blocks(markdown: &str) -> Vec<Block> {
+    let mut out = Vec::new();
+    // the opening fence, its offset and its info string
+    let mut open: Option<(&str, usize, String)> = None;
+    let mut body = String::new();
+    let mut line_start = 0;
+
+    for line in markdown.split_inclusive('\n') {
+        let offset = line_start;
+        line_start += line.len();
+        let trimmed = line.trim();
+
+        match open.take() {
+            Some((fence, start, info)) => {
+                if closes(trimmed, fence) {
+                    out.push(Block {
+                        start,
+                        end: line_start,
+                        info,
+                        body: std::mem::take(&mut body),
+                    });
+                } else {
+                    body.push_str(line);
+                    open = Some((fence, start, info));
+                }
+            }
+            None => {
+                if let Some((fence, info)) = opening_fence(trimmed) {
+                    open = Some((fence, offset, info.trim().to_lowercase()));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Splits a line opening a fenced block into the fence and the info string.
+fn opening_fence(line: &str) -> Option<(&str, &str)> {
+    let c = line.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let run = line.len() - line.trim_start_matches(c).len();
+    if run < 3 {
+        return None;
+    }
+    Some((&line[..run], &line[run..]))
+}
+
+/// A closing fence uses the same character and is at least as long.
+fn closes(line: &str, fence: &str) -> bool {
+    let c = fence.as_bytes()[0] as char;
+    line.len() >= fence.len() && line.chars().all(|l| l == c)
+}
+
+/// Parses `key: value` lines, skipping blank lines and lines without a colon.
+fn pairs(body: &str) -> Vec<(String, String)> {
+    body.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_string()))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// Every key/value pair in the page's `data` blocks. A key set more than once
+/// keeps its last value.
+pub fn extract(markdown: &str) -> Vec<(String, String)> {
+    let mut data = BTreeMap::new();
+    for block in fenced_blocks(markdown) {
+        if block.info == "data" {
+            data.extend(pairs(&block.body));
+        }
+    }
+    data.into_iter().collect()
+}
+
+/// A parsed `query` block or `/api/v1/data` request.
+#[derive(Debug)]
+pub struct Query {
+    /// `key = value` conditions that must all hold.
+    pub filters: Vec<(String, String)>,
+    pub columns: Vec<String>,
+    pub sort: Option<String>,
+    pub limit: usize,
+}
+
+impl Query {
+    /// Builds a query from `(directive, argument)` pairs such as
+    /// `("where", "status = active")`.
+    pub fn from_directives<I, K, V>(directives: I) -> Result<Query, String>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut query = Query {
+            filters: Vec::new(),
+            columns: Vec::new(),
+            sort: None,
+            limit: DEFAULT_LIMIT,
+        };
+        for (directive, argument) in directives {
+            let argument = argument.as_ref().trim();
+            match &directive.as_ref().trim().to_lowercase()[..] {
+                "where" => {
+                    let (key, value) = argument
+                        .split_once('=')
+                        .ok_or_else(|| format!("expected `where: key = value`, got {:?}", argument))?;
+                    query
+                        .filters
+                        .push((key.trim().to_lowercase(), value.trim().to_string()));
+                }
+                "columns" => query.columns.extend(
+                    argument
+                        .split(',')
+                        .map(|c| c.trim().to_lowercase())
+                        .filter(|c| !c.is_empty()),
+                ),
+                "sort" => query.sort = Some(argument.to_lowercase()),
+                "limit" => {
+                    query.limit = argument
+                        .parse()
+                        .ok()
+                        .filter(|&l| 0 < l && l <= MAX_LIMIT)
+                        .ok_or_else(|| format!("limit must be 1 to {}, got {:?}", MAX_LIMIT, argument))?;
+                }
+                other => return Err(format!("unknown query directive {:?}", other)),
+            }
+        }
+        if query.filters.is_empty() {
+            return Err("a query needs at least one `where` condition".to_string());
+        }
+        Ok(query)
+    }
+
+    /// Runs the query. Rows are pages with their data, sorted by the `sort`
+    /// key and then by name.
+    pub async fn run(&self, inner: &HandlerInner) -> DynResult<Vec<(String, BTreeMap<String, String>)>> {
+        let (keys, values): (Vec<String>, Vec<String>) = self.filters.iter().cloned().unzip();
+        let mut pages: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for (name, key, value) in inner
+            .queries
+            .fetch_matching_page_data(&inner.db, &keys, &values)
+            .await?
+        {
+            pages.entry(name).or_default().insert(key, value);
+        }
+
+        let mut rows: Vec<_> = pages.into_iter().collect();
+        if let Some(ref sort) = self.sort {
+            // stable, so pages with equal values stay in name order
+            rows.sort_by(|a, b| a.1.get(sort).cmp(&b.1.get(sort)));
+        }
+        rows.truncate(self.limit);
+        Ok(rows)
+    }
+}
+
+/// Escapes a value for a Markdown table cell.
+fn escape_cell(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\`*_[]<>|{}!&".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Renders a `data` block as a table. Like `results_table`, the table starts
+/// with a blank line so it isn't read as part of a preceding paragraph.
+fn data_table(pairs: &[(String, String)]) -> String {
+    let mut out = String::from("\n| Key | Value |\n| --- | --- |\n");
+    for (key, value) in pairs {
+        out.push_str(&format!("| {} | {} |\n", escape_cell(key), escape_cell(value)));
+    }
+    out
+}
+
+fn results_table(query: &Query, rows: &[(String, BTreeMap<String, String>)]) -> String {
+    if rows.is_empty() {
+        return "_No pages match this query._\n".to_string();
+    }
+    let mut out = String::from("\n| Page |");
+    for column in &query.columns {
+        out.push_str(&format!(" {} |", escape_cell(column)));
+    }
+    out.push_str("\n| --- |");
+    out.push_str(&" --- |".repeat(query.columns.len()));
+    out.push('\n');
+    for (name, data) in rows {
+        out.push_str(&format!("| [{}](<{}>) |", escape_cell(name), RouteWiki::to(name)));
+        for column in &query.columns {
+            let value = data.get(column).map(|v| escape_cell(v)).unwrap_or_default();
+            out.push_str(&format!(" {} |", value));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Replaces `data` blocks with a key/value table and `query` blocks with
+/// their results.
+pub async fn expand(inner: &HandlerInner, markdown: &str) -> DynResult<String> {
+    let blocks: Vec<Block> = fenced_blocks(markdown)
+        .into_iter()
+        .filter(|b| b.info == "data" || b.info == "query")
+        .collect();
+    if blocks.is_empty() {
+        return Ok(markdown.to_string());
+    }
+
+    let mut out = markdown.to_string();
+    for block in blocks.iter().rev() {
+        let replacement = if block.info == "data" {
+            data_table(&pairs(&block.body))
+        } else {
+            let directives = block.body.lines().filter_map(|line| line.split_once(':'));
+            match Query::from_directives(directives) {
+                Ok(query) => results_table(&query, &query.run(inner).await?),
+                Err(err) => format!("**Invalid query: {}**\n", escape_cell(&err)),
+            }
+        };
+        out.replace_range(block.start..block.end, &replacement);
+    }
+    Ok(out)
+}
@@ -0,0 +1,177 @@
+//! The Postgres connection. When it drops, `Database::supervise` reconnects
+//! with exponential backoff and swaps the new client into `HandlerInner`.
+//! Until then requests are answered with 503 rather than each failing on
+//! the dead connection, and `/readyz` reports the database as down.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{Notify, RwLock};
+use tokio_postgres::{Client, NoTls};
+use tracing::{event, Level};
+
+use crate::queries::Queries;
+use crate::{DynResult, HandlerInner};
+
+/// The first reconnection attempt waits this long, doubling after each
+/// failure up to `MAX_BACKOFF`.
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+pub struct Database {
+    uri: String,
+    slow_query_threshold: Duration,
+    /// Set on every connection once `limit_statements` has been called.
+    statement_timeout: Mutex<Option<Duration>>,
+    health: Mutex<Health>,
+    /// Signalled by the connection task when the connection ends.
+    lost: Notify,
+}
+
+#[derive(Default)]
+struct Health {
+    down_since: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl Database {
+    pub fn new(uri: &str, slow_query_threshold: Duration) -> Arc<Database> {
+        Arc::new(Database {
+            uri: uri.to_string(),
+            slow_query_threshold,
+            statement_timeout: Mutex::new(None),
+            health: Mutex::new(Health::default()),
+            lost: Notify::new(),
+        })
+    }
+
+    /// Opens a connection and prepares the queries on it. The connection is
+    /// driven by a spawned task that tells `supervise` when it ends.
+    pub async fn connect(self: &Arc<Database>) -> DynResult<HandlerInner> {
+        let (db, connection) = tokio_postgres::connect(&self.uri, NoTls).await?;
+        let database = self.clone();
+        tokio::spawn(async move {
+            let err = match connection.await {
+                Ok(()) => "connection closed".to_string(),
+                Err(err) => err.to_string(),
+            };
+            event!(Level::ERROR, "database connection lost: {}", err);
+            database.mark_down(err);
+            database.lost.notify_one();
+        });
+        let statement_timeout = *self.statement_timeout.lock().unwrap();
+        set_statement_timeout(&db, statement_timeout).await?;
+        let queries = Queries::prepare(&db, self.slow_query_threshold).await?;
+        Ok(HandlerInner { db, queries })
+    }
+
+    /// Sets Postgres' `statement_timeout` on the current connection and on
+    /// every one `supervise` opens after it.
+    pub async fn limit_statements(&self, inner: &HandlerInner, timeout: Option<Duration>) -> DynResult<()> {
+        *self.statement_timeout.lock().unwrap() = timeout;
+        set_statement_timeout(&inner.db, timeout).await
+    }
+
+    /// Waits for the connection to drop and reconnects, forever. Query
+    /// metrics carry over to the new connection.
+    pub async fn supervise(self: Arc<Database>, inner: Arc<RwLock<HandlerInner>>) {
+        loop {
+            self.lost.notified().await;
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                tokio::time::sleep(backoff).await;
+                match self.connect().await {
+                    Ok(mut reconnected) => {
+                        let mut locked = inner.write().await;
+                        std::mem::swap(&mut reconnected.queries.metrics, &mut locked.queries.metrics);
+                        *locked = reconnected;
+                        *self.health.lock().unwrap() = Health::default();
+                        event!(Level::WARN, "database connection restored");
+                        break;
+                    }
+                    Err(err) => {
+                        event!(Level::WARN, "reconnecting to the database: {}", err);
+                        self.mark_down(err.to_string());
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            }
+        }
+    }
+
+    fn mark_down(&self, err: String) {
+        let mut health = self.health.lock().unwrap();
+        health.down_since.get_or_insert_with(Instant::now);
+        health.last_error = Some(err);
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.health.lock().unwrap().down_since.is_none()
+    }
+
+    /// Why the database is down and for how long, `None` while it's up.
+    pub fn outage(&self) -> Option<String> {
+        let health = self.health.lock().unwrap();
+        let down_since = health.down_since?;
+        Some(format!(
+            "database down for {}s: {}",
+            down_since.elapsed().as_secs(),
+            health.last_error.as_deref().unwrap_or("unknown error")
+        ))
+    }
+}
+
+async fn set_statement_timeout(db: &Client, timeout: Option<Duration>) -> DynResult<()> {
+    if let Some(timeout) = timeout {
+        db.batch_execute(&format!("SET statement_timeout = {}", timeout.as_millis()))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Support for tests that need a database.
+#[cfg(test)]
+pub mod testing {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::schema::PROVISION_SQL;
+
+    /// Names the database tests run against, e.g. `host=/tmp user=postgres`.
+    /// Tests needing one pass without doing anything when it isn't set.
+    pub const DATABASE_VAR: &str = "WIKI_TEST_DATABASE";
+
+    /// Serialises provisioning, whose `CREATE EXTENSION IF NOT EXISTS`
+    /// fails when two run at once.
+    static PROVISIONING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    static NEXT_SCHEMA: AtomicUsize = AtomicUsize::new(0);
+
+    /// A connection to a schema of its own, freshly provisioned from
+    /// `provision_database.sql`, or `None` without `DATABASE_VAR`. Schemas
+    /// are left behind for inspection, named after the test process.
+    pub async fn provisioned() -> Option<(Arc<Database>, HandlerInner)> {
+        let uri = std::env::var(DATABASE_VAR).ok()?;
+        let schema = format!(
+            "wiki_test_{}_{}",
+            std::process::id(),
+            NEXT_SCHEMA.fetch_add(1, Ordering::Relaxed)
+        );
+        let uri = format!("{} options='-c search_path={},public'", uri, schema);
+
+        let (db, connection) = tokio_postgres::connect(&uri, NoTls)
+            .await
+            .unwrap_or_else(|err| panic!("connecting to {}: {}", DATABASE_VAR, err));
+        tokio::spawn(connection);
+        let provision: Vec<&str> = PROVISION_SQL.lines().filter(|line| !line.starts_with("DROP ")).collect();
+        {
+            let _provisioning = PROVISIONING.lock().await;
+            db.batch_execute(&format!("CREATE SCHEMA {}", schema)).await.unwrap();
+            db.batch_execute(&provision.join("\n")).await.unwrap();
+        }
+
+        let database = Database::new(&uri, Duration::from_secs(1));
+        let inner = database.connect().await.unwrap();
+        Some((database, inner))
+    }
+}
@@ -0,0 +1,190 @@
+//! Line diffs between two revisions as HTML. Only changed lines and
+//! `CONTEXT` lines around them are shown, a page of at most `PAGE_LINES`
+//! lines at a time, so diffing a huge page doesn't produce a response many
+//! times its size. Unchanged stretches in between collapse into `<details>`
+//! blocks; short ones carry their lines, longer ones are fetched by
+//! `static/diff.js` from `?lines=` when opened, see `render_lines`.
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use similar::{ChangeTag, TextDiff};
+
+/// Unchanged lines shown before and after each change.
+const CONTEXT: usize = 3;
+
+/// Changed and context lines per page.
+pub const PAGE_LINES: usize = 1000;
+
+/// Collapsed stretches up to this long are sent along with the page.
+const INLINE_LINES: usize = 50;
+
+enum Item<'t> {
+    Line {
+        tag: ChangeTag,
+        old: Option<usize>,
+        new: Option<usize>,
+        text: &'t str,
+    },
+    /// Unchanged lines of the new text not shown, and how far their line
+    /// numbers in the old text are ahead.
+    Gap { lines: Range<usize>, offset: isize },
+}
+
+pub struct Rendered {
+    pub html: String,
+    /// 1-based.
+    pub page: usize,
+    pub pages: usize,
+}
+
+fn items<'t>(diff: &TextDiff<'t, 't, 't, str>) -> Vec<Item<'t>> {
+    let mut items = Vec::new();
+    let (mut old_end, mut new_end) = (0, 0);
+    for hunk in diff.grouped_ops(CONTEXT) {
+        let first = match hunk.first() {
+            Some(first) => first,
+            None => continue,
+        };
+        let (old_start, new_start) = (first.old_range().start, first.new_range().start);
+        if new_start > new_end {
+            items.push(Item::Gap {
+                lines: new_end..new_start,
+                offset: old_start as isize - new_start as isize,
+            });
+        }
+        for op in &hunk {
+            for change in diff.iter_changes(op) {
+                items.push(Item::Line {
+                    tag: change.tag(),
+                    old: change.old_index(),
+                    new: change.new_index(),
+                    text: change.value(),
+                });
+            }
+            old_end = op.old_range().end;
+            new_end = op.new_range().end;
+        }
+    }
+    let new_len = diff.new_slices().len();
+    if !items.is_empty() && new_len > new_end {
+        items.push(Item::Gap {
+            lines: new_end..new_len,
+            offset: old_end as isize - new_end as isize,
+        });
+    }
+    items
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_line(html: &mut String, tag: ChangeTag, old: Option<usize>, new: Option<usize>, text: &str) {
+    let (class, sign) = match tag {
+        ChangeTag::Delete => ("diff-delete", '-'),
+        ChangeTag::Insert => ("diff-insert", '+'),
+        ChangeTag::Equal => ("diff-equal", ' '),
+    };
+    let number = |n: Option<usize>| n.map(|n| (n + 1).to_string()).unwrap_or_default();
+    let _ = writeln!(
+        html,
+        r#"<div class="diff-line {}"><span class="diff-number">{}</span><span class="diff-number">{}</span><span class="diff-text">{}{}</span></div>"#,
+        class,
+        number(old),
+        number(new),
+        sign,
+        escape(text.trim_end_matches(['\r', '\n']))
+    );
+}
+
+/// The unchanged lines `lines` of `new_text`, numbered in both texts, for
+/// a collapsed stretch opened on the diff page.
+pub fn render_lines(new_text: &str, lines: Range<usize>, offset: isize) -> String {
+    let mut html = String::new();
+    for (i, text) in new_text.lines().enumerate().skip(lines.start).take(lines.len()) {
+        let old = (i as isize + offset).max(0) as usize;
+        write_line(&mut html, ChangeTag::Equal, Some(old), Some(i), text);
+    }
+    html
+}
+
+/// Page `page` of the diff from `old` to `new`, clamped to the pages there
+/// are. With `expand_url`, collapsed stretches too long to send along can
+/// be fetched from it; otherwise they only say how long they are.
+pub fn render(old: &str, new: &str, page: usize, expand_url: Option<&str>) -> Rendered {
+    let diff = TextDiff::from_lines(old, new);
+    let items = items(&diff);
+    if items.is_empty() {
+        return Rendered {
+            html: "<p>No changes.</p>".to_string(),
+            page: 1,
+            pages: 1,
+        };
+    }
+
+    // pages break between lines; collapsed stretches go with the lines after
+    let mut pages: Vec<Range<usize>> = Vec::new();
+    let (mut start, mut lines) = (0, 0);
+    for (i, item) in items.iter().enumerate() {
+        if let Item::Line { .. } = item {
+            if lines == PAGE_LINES {
+                pages.push(start..i);
+                start = i;
+                lines = 0;
+            }
+            lines += 1;
+        }
+    }
+    pages.push(start..items.len());
+    let page = page.clamp(1, pages.len());
+
+    let mut html = String::from("<div class=\"diff\">\n");
+    for item in &items[pages[page - 1].clone()] {
+        match item {
+            Item::Line { tag, old, new, text } => write_line(&mut html, *tag, *old, *new, text),
+            Item::Gap { lines, offset } => {
+                let summary = match lines.len() {
+                    1 => "1 unchanged line".to_string(),
+                    n => format!("{} unchanged lines", n),
+                };
+                if lines.len() <= INLINE_LINES {
+                    let _ = writeln!(
+                        html,
+                        "<details class=\"diff-context\"><summary>{}</summary>\n{}</details>",
+                        summary,
+                        render_lines(new, lines.clone(), *offset)
+                    );
+                } else if let Some(url) = expand_url {
+                    let _ = writeln!(
+                        html,
+                        "<details class=\"diff-context\" data-src=\"{}?lines={}-{}&amp;offset={}\"><summary>{}</summary></details>",
+                        escape(url),
+                        lines.start,
+                        lines.end,
+                        offset,
+                        summary
+                    );
+                } else {
+                    let _ = writeln!(html, "<div class=\"diff-context\">{}</div>", summary);
+                }
+            }
+        }
+    }
+    html.push_str("</div>\n");
+    Rendered {
+        html,
+        page,
+        pages: pages.len(),
+    }
+}
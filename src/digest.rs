@@ -0,0 +1,257 @@
+//! The weekly digest: once a week, users who opted in at `/settings` are
+//! mailed the pages created that week, the most edited ones and the biggest
+//! changes, limited to the namespaces they chose and to what they follow,
+//! see `follow.rs`. Mail goes out through a
+//! sendmail-compatible command given with `--digest-sendmail`, and only to
+//! addresses verified through the link mailed when they're set.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use askama::Template;
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+use crate::follow::Follow;
+use crate::queries::{DigestRecipient, DigestRevision};
+use crate::routes::{Route, RouteWiki};
+use crate::snippets;
+use crate::views::digest::{Digest, DigestEntry};
+use crate::{DynResult, HandlerInner};
+
+/// Stands for pages without a namespace in `Preferences::digest_namespaces`.
+pub const MAIN_NAMESPACE: &str = "Main";
+
+/// Days between digests.
+pub const DIGEST_DAYS: i64 = 7;
+
+/// How often `run_weekly` checks whether a digest is due, so one goes out
+/// soon after a restart that spanned the due time.
+const POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Each section lists at most this many pages.
+const SECTION_LENGTH: usize = 10;
+
+/// Where digests are sent from, set by `--digest-sendmail` and friends.
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    pub program: String,
+    pub args: Vec<String>,
+    pub from: String,
+    /// Prefixed to links, without a trailing `/`.
+    pub base_url: String,
+}
+
+impl Mailer {
+    /// Pipes an HTML mail to the sendmail command, with `to` appended to
+    /// its arguments after `--`, so an address can't pass for an option.
+    pub async fn send(&self, to: &str, subject: &str, html: &str) -> DynResult<()> {
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/html; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}",
+            self.from, to, subject, html
+        );
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .arg("--")
+            .arg(to)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(message.as_bytes()).await?;
+        drop(stdin);
+        let status = child.wait().await?;
+        if !status.success() {
+            return Err(format!("{} exited with {}", self.program, status).into());
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` is in one of `namespaces`, or anywhere if there are none.
+pub fn is_wanted(namespaces: &[String], name: &str) -> bool {
+    if namespaces.is_empty() {
+        return true;
+    }
+    let ns = snippets::namespace(name).unwrap_or(MAIN_NAMESPACE);
+    namespaces.iter().any(|wanted| wanted == ns)
+}
+
+/// The digest of `revisions` for one reader, `None` if none of them are in
+/// the namespaces they asked for and pages they follow.
+fn compile(
+    revisions: &[DigestRevision],
+    namespaces: &[String],
+    follow: &Follow,
+    base_url: &str,
+) -> Option<(Vec<DigestEntry>, Vec<DigestEntry>, Vec<DigestEntry>)> {
+    let revisions: Vec<&DigestRevision> = revisions
+        .iter()
+        .filter(|revision| is_wanted(namespaces, &revision.name) && follow.covers(&revision.name))
+        .collect();
+    if revisions.is_empty() {
+        return None;
+    }
+    let link = |route: String| format!("{}{}", base_url, route);
+
+    let new_pages = revisions
+        .iter()
+        .filter(|revision| revision.previous_id.is_none())
+        .take(SECTION_LENGTH)
+        .map(|revision| DigestEntry {
+            name: revision.name.clone(),
+            link: link(RouteWiki::to(&revision.name).to_string()),
+            detail: format!("by {}", revision.modified_by),
+        })
+        .collect();
+
+    // edit count and distinct editors, in the order pages were first edited
+    let mut edits: Vec<(&str, usize, Vec<&str>)> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for revision in &revisions {
+        let i = *index.entry(&revision.name).or_insert_with(|| {
+            edits.push((&revision.name, 0, Vec::new()));
+            edits.len() - 1
+        });
+        let (_, count, editors) = &mut edits[i];
+        *count += 1;
+        if !editors.contains(&&revision.modified_by[..]) {
+            editors.push(&revision.modified_by);
+        }
+    }
+    edits.sort_by_key(|(_, count, _)| Reverse(*count));
+    let most_edited = edits
+        .iter()
+        .filter(|(_, count, _)| *count > 1)
+        .take(SECTION_LENGTH)
+        .map(|(name, count, editors)| DigestEntry {
+            name: name.to_string(),
+            link: link(RouteWiki::to_history(name).to_string()),
+            detail: format!("{} edits by {}", count, editors.join(", ")),
+        })
+        .collect();
+
+    let mut changes: Vec<&&DigestRevision> = revisions
+        .iter()
+        .filter(|revision| revision.previous_id.is_some())
+        .collect();
+    changes.sort_by_key(|revision| Reverse(revision.size_delta.abs()));
+    let notable = changes
+        .iter()
+        .take(SECTION_LENGTH)
+        .filter_map(|revision| {
+            let previous_id = revision.previous_id?;
+            Some(DigestEntry {
+                name: revision.name.clone(),
+                link: link(RouteWiki::to_diff(&revision.name, previous_id, revision.id).to_string()),
+                detail: format!("{:+} bytes by {}", revision.size_delta, revision.modified_by),
+            })
+        })
+        .collect();
+
+    Some((new_pages, most_edited, notable))
+}
+
+/// Mails the digest of changes after `since` up to `until` to everyone who
+/// asked for it, returning how many were sent. A failed mail is logged and
+/// doesn't stop the rest. The lock is only held while querying, not while
+/// mail is sent.
+pub async fn send_all(
+    inner: &RwLock<HandlerInner>,
+    mailer: &Mailer,
+    site_name: &str,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> DynResult<usize> {
+    let (revisions, recipients) = {
+        let locked = inner.read().await;
+        let revisions = locked
+            .queries
+            .fetch_digest_revisions(&locked.db, &since, &until)
+            .await?;
+        let mut recipients = Vec::new();
+        for recipient in locked.queries.fetch_digest_recipients(&locked.db).await? {
+            let follow = Follow::load(&locked, &recipient.follow).await?;
+            recipients.push((recipient, follow));
+        }
+        (revisions, recipients)
+    };
+    let subject = format!(
+        "{}: changes from {} to {}",
+        site_name,
+        since.format("%Y-%m-%d"),
+        until.format("%Y-%m-%d")
+    );
+
+    let mut sent = 0;
+    for (
+        DigestRecipient {
+            username,
+            email,
+            namespaces,
+            ..
+        },
+        follow,
+    ) in recipients
+    {
+        let (new_pages, most_edited, notable) = match compile(&revisions, &namespaces, &follow, &mailer.base_url) {
+            Some(sections) => sections,
+            None => continue,
+        };
+        let digest = Digest {
+            site_name,
+            subject: &subject,
+            username: &username,
+            namespaces: &namespaces,
+            new_pages,
+            most_edited,
+            notable,
+            settings_link: format!("{}{}", mailer.base_url, Route::Settings),
+        };
+        match mailer.send(&email, &subject, &digest.render()?).await {
+            Ok(()) => sent += 1,
+            Err(err) => event!(Level::WARN, "digest to {}: {}", username, err),
+        }
+    }
+    Ok(sent)
+}
+
+/// Sends a digest every `DIGEST_DAYS`, picking up where the last one
+/// left off. The first digest goes out a week after the server is first
+/// started with `--digest-sendmail`.
+pub async fn run_weekly(inner: Arc<RwLock<HandlerInner>>, mailer: Arc<Mailer>, site_name: String) {
+    loop {
+        if let Err(err) = send_if_due(&inner, &mailer, &site_name).await {
+            event!(Level::ERROR, "digest failed: {}", err);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn send_if_due(inner: &RwLock<HandlerInner>, mailer: &Mailer, site_name: &str) -> DynResult<()> {
+    let now = Utc::now();
+    let since = {
+        let locked = inner.read().await;
+        let since = match locked.queries.fetch_digest_sent_until(&locked.db).await? {
+            Some(since) => since,
+            None => {
+                locked.queries.store_digest_sent_until(&locked.db, &now).await?;
+                return Ok(());
+            }
+        };
+        if now - since < chrono::Duration::days(DIGEST_DAYS) {
+            return Ok(());
+        }
+        // recorded first so a crash part way through doesn't send twice
+        locked.queries.store_digest_sent_until(&locked.db, &now).await?;
+        since
+    };
+    let sent = send_all(inner, mailer, site_name, since, now).await?;
+    event!(Level::INFO, "digest: sent {} mails", sent);
+    Ok(())
+}
@@ -0,0 +1,160 @@
+//! Near-duplicate detection with MinHash. Each page is reduced to a signature
+//! of `NUM_HASHES` minimum shingle hashes; the fraction of positions where two
+//! signatures agree estimates the Jaccard similarity of their shingle sets.
+//! Locality-sensitive hashing over bands of the signature keeps the number
+//! of pairs compared close to the number of likely duplicates.
+//!
+//! `/maintenance/duplicates` keeps its last report in a `Cache` until a page
+//! is saved or moved, so opening it again doesn't read and compare every
+//! page.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::RwLock;
+
+use crate::{DynResult, HandlerInner};
+
+const NUM_HASHES: usize = 64;
+const BANDS: usize = 16;
+const ROWS: usize = NUM_HASHES / BANDS;
+
+/// Words per shingle. Pages with fewer words are skipped.
+const SHINGLE_WORDS: usize = 3;
+
+/// Pairs at or above this estimated similarity are reported.
+pub const DEFAULT_THRESHOLD: f64 = 0.6;
+
+#[derive(Debug)]
+pub struct DuplicatePair {
+    pub first: String,
+    pub second: String,
+    /// Estimated Jaccard similarity, 0 to 1.
+    pub similarity: f64,
+}
+
+/// FNV-1a, so signatures are stable across runs and builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// SplitMix64 finaliser, used to derive the `i`th hash function from one
+/// shingle hash.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn signature(text: &str) -> Option<[u64; NUM_HASHES]> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.len() < SHINGLE_WORDS {
+        return None;
+    }
+
+    let mut signature = [u64::MAX; NUM_HASHES];
+    for shingle in words.windows(SHINGLE_WORDS) {
+        let hash = fnv1a(shingle.join(" ").as_bytes());
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = (*min).min(mix(hash ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+        }
+    }
+    Some(signature)
+}
+
+fn similarity(a: &[u64; NUM_HASHES], b: &[u64; NUM_HASHES]) -> f64 {
+    let same = a.iter().zip(b.iter()).filter(|(x, y)| x == y).count();
+    same as f64 / NUM_HASHES as f64
+}
+
+/// Finds pairs of pages whose text is at least `threshold` similar, most
+/// similar first.
+pub fn find(documents: &[(String, String)], threshold: f64) -> Vec<DuplicatePair> {
+    let signatures: Vec<(&str, [u64; NUM_HASHES])> = documents
+        .iter()
+        .filter_map(|(name, text)| signature(text).map(|s| (name.as_str(), s)))
+        .collect();
+
+    let mut candidates = HashSet::new();
+    for band in 0..BANDS {
+        let mut buckets: HashMap<&[u64], Vec<usize>> = HashMap::new();
+        for (idx, (_, signature)) in signatures.iter().enumerate() {
+            let rows = &signature[band * ROWS..(band + 1) * ROWS];
+            buckets.entry(rows).or_default().push(idx);
+        }
+        for bucket in buckets.values() {
+            for (i, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[i + 1..] {
+                    candidates.insert((a, b));
+                }
+            }
+        }
+    }
+
+    let mut pairs: Vec<DuplicatePair> = candidates
+        .into_iter()
+        .filter_map(|(a, b)| {
+            let similarity = similarity(&signatures[a].1, &signatures[b].1);
+            if similarity < threshold {
+                return None;
+            }
+            let (first, second) = if signatures[a].0 <= signatures[b].0 {
+                (signatures[a].0, signatures[b].0)
+            } else {
+                (signatures[b].0, signatures[a].0)
+            };
+            Some(DuplicatePair {
+                first: first.to_string(),
+                second: second.to_string(),
+                similarity,
+            })
+        })
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.similarity
+            .partial_cmp(&a.similarity)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.first.cmp(&b.first))
+            .then_with(|| a.second.cmp(&b.second))
+    });
+    pairs
+}
+
+/// The last report and the change it was made after, see
+/// `Queries::fetch_max_change_id`.
+#[derive(Default)]
+pub struct Cache {
+    last: Mutex<Option<(i64, Arc<Vec<DuplicatePair>>)>>,
+}
+
+impl Cache {
+    /// The pairs at `DEFAULT_THRESHOLD`, found again only if a page has
+    /// changed since the last report. Finding them runs off the async
+    /// threads, without holding the lock on `inner`.
+    pub async fn report(&self, inner: &RwLock<HandlerInner>) -> DynResult<Arc<Vec<DuplicatePair>>> {
+        let (change_id, documents) = {
+            let locked = inner.read().await;
+            let change_id = locked.queries.fetch_max_change_id(&locked.db).await?;
+            if let Some((cached_at, ref pairs)) = *self.last.lock().unwrap() {
+                if cached_at == change_id {
+                    return Ok(pairs.clone());
+                }
+            }
+            let names = locked.queries.fetch_current_names(&locked.db).await?;
+            (change_id, locked.queries.fetch_current_documents(&locked.db, &names).await?)
+        };
+
+        let pairs = Arc::new(tokio::task::spawn_blocking(move || find(&documents, DEFAULT_THRESHOLD)).await?);
+        *self.last.lock().unwrap() = Some((change_id, pairs.clone()));
+        Ok(pairs)
+    }
+}
@@ -0,0 +1,628 @@
+//! Edit filters: rules admins write at `/admin/filters` that look at each
+//! save and warn, tag or block it, after MediaWiki's AbuseFilter.
+//!
+//! A rule is an expression over the edit:
+//!
+//! ```text
+//! anonymous and added_urls > 2
+//! new_page and user_age_days < 1 and text matches "(?i)casino|viagra"
+//! size_delta < -5000 and not (page contains "Sandbox")
+//! ```
+//!
+//! Variables are listed in `VARIABLES`. Operators, loosest first: `or`,
+//! `and`, `not`, then comparisons `== != < <= > >=` and the string
+//! operators `contains` and `matches`, whose right side is a regular
+//! expression. Literals are integers, `"strings"` with `\"` and `\\`
+//! escapes, `true` and `false`.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use chrono::Utc;
+use regex::Regex;
+use similar::{ChangeTag, TextDiff};
+use tokio_postgres::GenericClient;
+use tracing::{event, Level};
+
+use crate::plugins::SaveContext;
+use crate::queries::{EditFilter, Queries};
+use crate::secrets::{self, Finding};
+use crate::DynResult;
+
+/// Set on a save to go ahead despite warnings. The editor asks first.
+pub const CONFIRM_HEADER: &str = "x-confirm-filter-warnings";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// The save is refused until resent with `CONFIRM_HEADER`.
+    Warn,
+    /// The save goes ahead, marked in the page history.
+    Tag,
+    /// The save is refused.
+    Block,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[Action::Warn, Action::Tag, Action::Block];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Warn => "warn",
+            Action::Tag => "tag",
+            Action::Block => "block",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Action, String> {
+        Action::ALL
+            .iter()
+            .copied()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| format!("unknown filter action {:?}", s))
+    }
+}
+
+/// The save a rule is evaluated against.
+pub struct Edit<'a> {
+    pub page: &'a str,
+    /// `None` for a new page.
+    pub old_text: Option<&'a str>,
+    pub new_text: &'a str,
+    pub anonymous: bool,
+    /// Whole days since the account was created, 0 for anonymous edits.
+    pub user_age_days: i64,
+}
+
+/// Every variable `Vars::of` sets.
+pub const VARIABLES: &[&str] = &[
+    "page",
+    "new_page",
+    "anonymous",
+    "user_age_days",
+    "old_size",
+    "new_size",
+    "size_delta",
+    "added_urls",
+    "added_lines",
+    "text",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// The variables a rule can use, worked out from an edit once for all
+/// rules.
+pub struct Vars {
+    pub values: Vec<(&'static str, Value)>,
+}
+
+impl Vars {
+    pub fn of(edit: &Edit) -> Vars {
+        let old_text = edit.old_text.unwrap_or("");
+        let old_urls = urls(old_text);
+        let added_urls = urls(edit.new_text).difference(&old_urls).count();
+        let added_lines: Vec<&str> = TextDiff::from_lines(old_text, edit.new_text)
+            .iter_all_changes()
+            .filter(|change| change.tag() == ChangeTag::Insert)
+            .map(|change| change.value())
+            .collect();
+        let values = vec![
+            ("page", Value::Str(edit.page.to_string())),
+            ("new_page", Value::Bool(edit.old_text.is_none())),
+            ("anonymous", Value::Bool(edit.anonymous)),
+            ("user_age_days", Value::Int(edit.user_age_days)),
+            ("old_size", Value::Int(old_text.len() as i64)),
+            ("new_size", Value::Int(edit.new_text.len() as i64)),
+            ("size_delta", Value::Int(edit.new_text.len() as i64 - old_text.len() as i64)),
+            ("added_urls", Value::Int(added_urls as i64)),
+            ("added_lines", Value::Str(added_lines.concat())),
+            ("text", Value::Str(edit.new_text.to_string())),
+        ];
+        Vars { values }
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.values.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+}
+
+fn urls(text: &str) -> HashSet<&str> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    let url = URL.get_or_init(|| Regex::new(r#"https?://[^\s<>()\[\]"']+"#).unwrap());
+    url.find_iter(text).map(|m| m.as_str()).collect()
+}
+
+/// A parsed rule.
+#[derive(Debug)]
+pub struct Rule {
+    expr: Expr,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Rule { expr }),
+            Some(token) => Err(format!("unexpected {} after the end of the rule", token)),
+        }
+    }
+}
+
+impl Rule {
+    /// Whether the edit described by `vars` trips the rule.
+    pub fn matches(&self, vars: &Vars) -> Result<bool, String> {
+        match self.expr.eval(vars)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(format!("the rule comes out as {}, not true or false", other)),
+        }
+    }
+}
+
+/// Why `check` refused a save, with the message for the editor.
+pub enum Refusal {
+    Block(String),
+    /// Only warning filters matched, and the save wasn't confirmed.
+    Warn(String),
+}
+
+/// Works out the variables for saving `new_text` over the current text of
+/// `page`.
+pub async fn edit_vars<C: GenericClient>(
+    queries: &Queries,
+    db: &C,
+    page: &str,
+    new_text: &str,
+    username: Option<&str>,
+) -> DynResult<Vars> {
+    let old_text = queries
+        .fetch_current_revision(db, page)
+        .await?
+        .map(|revision| revision.document_data);
+    let user_age_days = match username {
+        Some(username) => queries
+            .fetch_user_created_at(db, username)
+            .await?
+            .map_or(0, |created_at| (Utc::now() - created_at).num_days()),
+        None => 0,
+    };
+    Ok(Vars::of(&Edit {
+        page,
+        old_text: old_text.as_deref(),
+        new_text,
+        anonymous: username.is_none(),
+        user_age_days,
+    }))
+}
+
+/// Runs each of `filters` over `vars`. A filter whose rule no longer
+/// parses, or fails on this edit, comes back as an error.
+pub fn evaluate<'f>(filters: &'f [EditFilter], vars: &Vars) -> Vec<(&'f EditFilter, Result<bool, String>)> {
+    filters
+        .iter()
+        .map(|filter| {
+            let matched = filter.rule.parse::<Rule>().and_then(|rule| rule.matches(vars));
+            (filter, matched)
+        })
+        .collect()
+}
+
+/// What `check` found in a save that may go ahead.
+#[derive(Default)]
+pub struct Matched {
+    pub filters: Vec<EditFilter>,
+    /// Likely secrets, see `secrets.rs`.
+    pub secrets: Vec<Finding>,
+}
+
+/// Runs the enabled filters, and the secret scan unless `secrets` is
+/// `Off`, over a save of `new_text`, giving what matched a save that may
+/// go ahead, to `record` once it's stored. Blocked saves are recorded in
+/// `db` straight away, so the caller should commit before answering.
+/// Warnings aren't: the save is either confirmed and recorded then, or
+/// dropped, and counting both would count the edit twice.
+pub async fn check<C: GenericClient>(
+    queries: &Queries,
+    db: &C,
+    save: &SaveContext<'_>,
+    new_text: &str,
+    confirmed: bool,
+    secrets: secrets::Policy,
+) -> DynResult<Result<Matched, Refusal>> {
+    let findings = match secrets {
+        secrets::Policy::Off => Vec::new(),
+        _ => {
+            let old_text = queries.fetch_current_revision(db, save.name).await?;
+            secrets::scan(new_text, old_text.as_ref().map_or("", |revision| &revision.document_data))
+        }
+    };
+    let filters = queries.fetch_enabled_edit_filters(db).await?;
+    if filters.is_empty() && findings.is_empty() {
+        return Ok(Ok(Matched::default()));
+    }
+
+    let mut matched = Vec::new();
+    if !filters.is_empty() {
+        let username = save.user.map(|user| &user.username[..]);
+        let vars = edit_vars(queries, db, save.name, new_text, username).await?;
+        for (filter, result) in evaluate(&filters, &vars) {
+            match result {
+                Ok(true) => matched.push(filter.clone()),
+                Ok(false) => (),
+                Err(err) => event!(Level::WARN, "edit filter {:?}: {}", filter.name, err),
+            }
+        }
+    }
+
+    let named = |action: Action| -> Vec<&str> {
+        matched
+            .iter()
+            .filter(|filter| filter.action == action.as_str())
+            .map(|filter| &filter.name[..])
+            .collect()
+    };
+    let (blocked_by, warned_by) = (named(Action::Block), named(Action::Warn));
+    let has_secrets = !findings.is_empty();
+    let refusal = if has_secrets && secrets == secrets::Policy::Block {
+        Refusal::Block(format!(
+            "This edit looks like it contains a secret ({}). Remove it and save again.",
+            secrets::describe(&findings)
+        ))
+    } else if !blocked_by.is_empty() {
+        Refusal::Block(format!("This edit was blocked by the edit filter {:?}.", blocked_by[0]))
+    } else if has_secrets && !confirmed {
+        Refusal::Warn(format!(
+            "This edit looks like it contains a secret ({}), which would stay in the page history. Save anyway?",
+            secrets::describe(&findings)
+        ))
+    } else if !warned_by.is_empty() && !confirmed {
+        Refusal::Warn(format!(
+            "This edit matches the edit filter {}. Save anyway?",
+            warned_by.iter().map(|name| format!("{:?}", name)).collect::<Vec<_>>().join(", ")
+        ))
+    } else {
+        return Ok(Ok(Matched {
+            filters: matched,
+            secrets: findings,
+        }));
+    };
+    if let Refusal::Block(_) = refusal {
+        let refused = Matched {
+            filters: matched,
+            secrets: findings,
+        };
+        insert_hits(queries, db, save, &refused, None).await?;
+    }
+    Ok(Err(refusal))
+}
+
+/// Records what matched a save once it's stored as `revision_id`.
+pub async fn record<C: GenericClient>(
+    queries: &Queries,
+    db: &C,
+    save: &SaveContext<'_>,
+    matched: &Matched,
+    revision_id: i64,
+) -> DynResult<()> {
+    insert_hits(queries, db, save, matched, Some(revision_id)).await
+}
+
+async fn insert_hits<C: GenericClient>(
+    queries: &Queries,
+    db: &C,
+    save: &SaveContext<'_>,
+    matched: &Matched,
+    revision_id: Option<i64>,
+) -> DynResult<()> {
+    for filter in &matched.filters {
+        queries
+            .insert_edit_filter_hit(db, filter, save.name, save.attribution, revision_id)
+            .await?;
+    }
+    for finding in &matched.secrets {
+        event!(
+            Level::WARN,
+            "{} on line {} of a save of {:?} by {} ({})",
+            finding.kind,
+            finding.line,
+            save.name,
+            save.attribution,
+            if revision_id.is_some() { "saved" } else { "refused" }
+        );
+        queries
+            .insert_secret_finding(db, finding, save.name, save.attribution, revision_id)
+            .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Var(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, Op, Box<Expr>),
+    Contains(Box<Expr>, Box<Expr>),
+    /// The pattern is compiled when the rule is parsed.
+    Matches(Box<Expr>, Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    const ALL: &'static [Op] = &[Op::Eq, Op::Ne, Op::Le, Op::Ge, Op::Lt, Op::Gt];
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, vars: &Vars) -> Result<Value, String> {
+        Ok(match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Var(name) => vars
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("unknown variable {}", name))?,
+            Expr::Not(e) => Value::Bool(!e.eval_bool(vars)?),
+            // both short-circuit
+            Expr::And(a, b) => Value::Bool(a.eval_bool(vars)? && b.eval_bool(vars)?),
+            Expr::Or(a, b) => Value::Bool(a.eval_bool(vars)? || b.eval_bool(vars)?),
+            Expr::Compare(a, op, b) => {
+                let ordering = match (a.eval(vars)?, b.eval(vars)?) {
+                    (Value::Int(a), Value::Int(b)) => a.cmp(&b),
+                    (Value::Str(a), Value::Str(b)) => a.cmp(&b),
+                    (Value::Bool(a), Value::Bool(b)) if matches!(op, Op::Eq | Op::Ne) => a.cmp(&b),
+                    (a, b) => return Err(format!("can't compare {} with {}", a, b)),
+                };
+                Value::Bool(match op {
+                    Op::Eq => ordering.is_eq(),
+                    Op::Ne => ordering.is_ne(),
+                    Op::Lt => ordering.is_lt(),
+                    Op::Le => ordering.is_le(),
+                    Op::Gt => ordering.is_gt(),
+                    Op::Ge => ordering.is_ge(),
+                })
+            }
+            Expr::Contains(a, b) => match (a.eval(vars)?, b.eval(vars)?) {
+                (Value::Str(a), Value::Str(b)) => Value::Bool(a.contains(&b)),
+                (a, b) => return Err(format!("contains needs two strings, got {} and {}", a, b)),
+            },
+            Expr::Matches(a, pattern) => match a.eval(vars)? {
+                Value::Str(a) => Value::Bool(pattern.is_match(&a)),
+                a => return Err(format!("matches needs a string, got {}", a)),
+            },
+        })
+    }
+
+    fn eval_bool(&self, vars: &Vars) -> Result<bool, String> {
+        match self.eval(vars)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(format!("expected true or false, got {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Str(String),
+    Word(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Int(i) => write!(f, "{}", i),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Word(w) => write!(f, "{:?}", w),
+            Token::Op(op) => write!(f, "{:?}", op.symbol()),
+            Token::Open => write!(f, "\"(\""),
+            Token::Close => write!(f, "\")\""),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+        } else if c == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                        // left alone, so regex escapes like \d work
+                        Some((_, other)) => {
+                            value.push('\\');
+                            value.push(other);
+                        }
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Token::Str(value));
+        } else if c.is_ascii_digit() || (c == '-' && s[start + 1..].starts_with(|c: char| c.is_ascii_digit())) {
+            chars.next();
+            let mut end = start + 1;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                end = i + 1;
+                chars.next();
+            }
+            let number = s[start..end]
+                .parse()
+                .map_err(|_| format!("{} is out of range", &s[start..end]))?;
+            tokens.push(Token::Int(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Word(s[start..end].to_string()));
+        } else {
+            let rest = &s[start..];
+            // two-character operators come first in `Op::ALL`
+            let op = *Op::ALL
+                .iter()
+                .find(|op| rest.starts_with(op.symbol()))
+                .ok_or_else(|| format!("unexpected {:?}", c))?;
+            for _ in 0..op.symbol().len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w == word)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek_word("or") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.peek_word("and") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.peek_word("not") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.primary()?;
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Op(op)) => {
+                self.pos += 1;
+                Ok(Expr::Compare(Box::new(left), op, Box::new(self.primary()?)))
+            }
+            Some(Token::Word(ref w)) if w == "contains" => {
+                self.pos += 1;
+                Ok(Expr::Contains(Box::new(left), Box::new(self.primary()?)))
+            }
+            Some(Token::Word(ref w)) if w == "matches" => {
+                self.pos += 1;
+                match self.next() {
+                    Some(Token::Str(pattern)) => {
+                        let pattern = Regex::new(&pattern).map_err(|e| format!("bad pattern: {}", e))?;
+                        Ok(Expr::Matches(Box::new(left), pattern))
+                    }
+                    _ => Err("matches must be followed by a \"pattern\"".to_string()),
+                }
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Int(i)) => Ok(Expr::Literal(Value::Int(i))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::Str(s))),
+            Some(Token::Word(w)) => match &w[..] {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "and" | "or" | "not" | "contains" | "matches" => Err(format!("unexpected {:?}", w)),
+                _ if VARIABLES.contains(&&w[..]) => Ok(Expr::Var(w)),
+                _ => Err(format!("unknown variable {:?}", w)),
+            },
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing \")\"".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("the rule ends too soon".to_string()),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Edit notices: guidelines or warnings shown above the editor. The page
+//! `Template:EditNotice:Namespace:Drafts` is shown when editing any page
+//! named `Drafts:...`, and `Template:EditNotice:Page:Drafts:Plan` when
+//! editing `Drafts:Plan` itself. Only admins may edit them, like namespace
+//! snippets.
+
+use crate::plugins::Plugins;
+use crate::snippets;
+use crate::{render_document, DynResult, HandlerInner};
+
+const NAMESPACE_PREFIX: &str = "Template:EditNotice:Namespace:";
+const PAGE_PREFIX: &str = "Template:EditNotice:Page:";
+
+/// Whether `name` is an edit notice.
+pub fn is_notice(name: &str) -> bool {
+    name.starts_with(NAMESPACE_PREFIX) || name.starts_with(PAGE_PREFIX)
+}
+
+/// The rendered notices for editing `name`, the namespace's first.
+pub async fn for_page(inner: &HandlerInner, plugins: &Plugins, name: &str) -> DynResult<Vec<String>> {
+    let mut names = Vec::new();
+    if let Some(ns) = snippets::namespace(name) {
+        names.push(format!("{}{}", NAMESPACE_PREFIX, ns));
+    }
+    names.push(format!("{}{}", PAGE_PREFIX, name));
+
+    let documents = inner.queries.fetch_current_documents(&inner.db, &names).await?;
+    let mut notices = Vec::new();
+    for notice_name in &names {
+        if let Some((_, body)) = documents.iter().find(|(name, _)| name == notice_name) {
+            notices.push(render_document(inner, plugins, body).await?);
+        }
+    }
+    Ok(notices)
+}
@@ -0,0 +1,204 @@
+//! `wiki export-static`: renders every current page to a directory of plain
+//! HTML files that link to each other relatively, so the snapshot can be
+//! browsed from disk or published on any static host.
+//!
+//! Layout:
+//!
+//! ```text
+//! index.html
+//! pages/<page>.html
+//! attachments/<page>/<filename>
+//! static/<asset>
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use askama::Template;
+use chrono::Utc;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::plugins::Plugins;
+use crate::routes::{Route, RouteWikiSubview};
+use crate::{assets, render_document, snippets, summary, views, DynResult, HandlerInner};
+
+/// Characters kept as-is in file names; everything else is percent-encoded
+/// so any page name makes a portable file name.
+const FILE_NAME: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub pages: usize,
+    pub attachments: usize,
+}
+
+/// Writes the snapshot into `dir`, creating it if needed. Existing files
+/// with the same paths are overwritten; nothing else is removed.
+pub async fn export(inner: &HandlerInner, plugins: &Plugins, site_name: &str, dir: &Path) -> DynResult<Summary> {
+    let mut exported = Summary::default();
+    fs::create_dir_all(dir.join("pages"))?;
+
+    let names = inner.queries.fetch_current_names(&inner.db).await?;
+
+    // attachments first, so blob permalinks in pages can point at them
+    let mut blobs = HashMap::new();
+    for name in &names {
+        for attachment in inner.queries.fetch_attachments(&inner.db, name).await? {
+            let content = match inner
+                .queries
+                .fetch_attachment(&inner.db, name, &attachment.filename)
+                .await?
+            {
+                Some(content) => content,
+                None => continue,
+            };
+            let page_dir = dir.join("attachments").join(file_name(name));
+            fs::create_dir_all(&page_dir)?;
+            fs::write(page_dir.join(file_name(&attachment.filename)), &content.data)?;
+            blobs
+                .entry(content.content_hash)
+                .or_insert_with(|| (name.clone(), attachment.filename.clone()));
+            exported.attachments += 1;
+        }
+    }
+
+    let links = Links {
+        pages: names.iter().map(|name| &name[..]).collect(),
+        blobs: &blobs,
+    };
+    for name in &names {
+        let revision = match inner.queries.fetch_current_revision(&inner.db, name).await? {
+            Some(revision) => revision,
+            None => continue,
+        };
+        let rendered = render_document(inner, plugins, &revision.document_data).await?;
+        let snippets = snippets::for_page(inner, plugins, name).await?;
+        let page = views::export::Page {
+            site_name,
+            page_title: name,
+            description: summary::description(&rendered),
+            last_modified_at: revision.created_at,
+            last_modified_by: &revision.modified_by,
+            index_link: "../index.html",
+            header: snippets.header.map(|html| links.rewrite(&html, "../")),
+            footer: snippets.footer.map(|html| links.rewrite(&html, "../")),
+            rendered: links.rewrite(&rendered, "../"),
+        };
+        fs::write(dir.join("pages").join(format!("{}.html", file_name(name))), page.render()?)?;
+        exported.pages += 1;
+    }
+
+    let index = views::export::Index {
+        site_name,
+        exported_at: Utc::now(),
+        pages: names
+            .iter()
+            .map(|name| views::export::IndexEntry {
+                name,
+                link: format!("pages/{}", href(&format!("{}.html", file_name(name)))),
+            })
+            .collect(),
+    };
+    fs::write(dir.join("index.html"), index.render()?)?;
+
+    fs::create_dir_all(dir.join("static"))?;
+    for asset in assets::names() {
+        if let Some(data) = assets::get(asset) {
+            // pages link to the hashed name
+            fs::write(dir.join("static").join(data.hashed_name), data.data)?;
+        }
+    }
+
+    Ok(exported)
+}
+
+/// Maps the wiki's absolute links onto the exported files.
+struct Links<'a> {
+    /// Exported pages. Links to any other page are left pointing at the wiki.
+    pages: HashSet<&'a str>,
+    /// Content hash to the `(page, filename)` it was exported under.
+    blobs: &'a HashMap<String, (String, String)>,
+}
+
+impl<'a> Links<'a> {
+    /// Rewrites every `href` and `src` attribute in `html` that points into
+    /// the wiki. `prefix` leads from the file being written back to the root.
+    fn rewrite(&self, html: &str, prefix: &str) -> String {
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(idx) = find_attribute(rest) {
+            let (before, after) = rest.split_at(idx);
+            out.push_str(before);
+            let value_start = after.find('"').map(|i| i + 1).unwrap_or(after.len());
+            out.push_str(&after[..value_start]);
+
+            let value_rest = &after[value_start..];
+            let value_end = value_rest.find('"').unwrap_or(value_rest.len());
+            let value = &value_rest[..value_end];
+            match self.local_path(&value.replace("&amp;", "&")) {
+                Some(path) => out.push_str(&format!("{}{}", prefix, path)),
+                None => out.push_str(value),
+            }
+            rest = &value_rest[value_end..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// The exported file a wiki URL corresponds to, relative to the root.
+    fn local_path(&self, url: &str) -> Option<String> {
+        let (path, fragment) = match url.find('#') {
+            Some(i) => (&url[..i], &url[i..]),
+            None => (url, ""),
+        };
+        let path = path.split('?').next().unwrap_or("");
+        if !path.starts_with('/') {
+            return None;
+        }
+        let local = match Route::router(path).ok()? {
+            // every page view maps to the one exported copy
+            Route::Wiki(rw)
+                if self.pages.contains(&rw.name[..])
+                    && !matches!(rw.subview, RouteWikiSubview::ExportBundle | RouteWikiSubview::ExportGit) =>
+            {
+                format!("pages/{}{}", href(&format!("{}.html", file_name(&rw.name))), fragment)
+            }
+            Route::Attachment(ra) => match ra.filename {
+                Some(filename) => format!(
+                    "attachments/{}/{}",
+                    href(&file_name(&ra.name)),
+                    href(&file_name(&filename))
+                ),
+                None => return None,
+            },
+            Route::Blob(rb) => {
+                let (page, filename) = self.blobs.get(&rb.content_hash[..])?;
+                format!("attachments/{}/{}", href(&file_name(page)), href(&file_name(filename)))
+            }
+            Route::Static(file) => format!("static/{}", file),
+            _ => return None,
+        };
+        Some(local)
+    }
+}
+
+/// Offset of the next `href="` or `src="` in `html`.
+fn find_attribute(html: &str) -> Option<usize> {
+    let href = html.find(" href=\"").map(|i| i + 1);
+    let src = html.find(" src=\"").map(|i| i + 1);
+    match (href, src) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+pub fn file_name(name: &str) -> String {
+    utf8_percent_encode(name, FILE_NAME).to_string()
+}
+
+/// A file name from `file_name` as it must appear in a URL, where its own
+/// `%` escapes need escaping again.
+fn href(file_name: &str) -> String {
+    file_name.replace('%', "%25")
+}
@@ -0,0 +1,96 @@
+//! Following namespaces and tags. `/unread` lists every page changed since
+//! the user last read it, and the weekly digest every page changed that
+//! week, unless they follow something at `/settings`: then only pages in a
+//! followed namespace, or tagged with a followed tag, are listed. A tag is
+//! a `key=value` pair from a page's data block, see `data.rs`, so following
+//! `type=project` covers project pages created later too.
+
+use std::collections::HashSet;
+
+use crate::digest;
+use crate::queries::UnreadPage;
+use crate::{DynResult, HandlerInner};
+
+/// One entry in `Preferences::follow`.
+#[derive(Debug)]
+pub enum Filter<'a> {
+    /// `digest::MAIN_NAMESPACE` stands for pages without one.
+    Namespace(&'a str),
+    Tag { key: &'a str, value: &'a str },
+}
+
+impl<'a> Filter<'a> {
+    pub fn parse(entry: &'a str) -> Result<Filter<'a>, String> {
+        match entry.split_once('=') {
+            Some((key, value)) if !key.is_empty() && !value.is_empty() => Ok(Filter::Tag { key, value }),
+            Some(_) => Err(format!("{:?} should be a tag such as type=project.", entry)),
+            None => Ok(Filter::Namespace(entry.trim_end_matches(':'))),
+        }
+    }
+}
+
+/// The entries of the follow field at `/settings`, separated by spaces or
+/// commas.
+pub fn parse_list(list: &str) -> Result<Vec<String>, String> {
+    let mut follow = Vec::new();
+    for entry in list.split(|c: char| c == ',' || c.is_whitespace()).filter(|e| !e.is_empty()) {
+        let entry = match Filter::parse(entry)? {
+            Filter::Namespace(ns) => ns.to_string(),
+            Filter::Tag { .. } => entry.to_string(),
+        };
+        if !entry.is_empty() && !follow.contains(&entry) {
+            follow.push(entry);
+        }
+    }
+    Ok(follow)
+}
+
+/// The pages a follow list covers, looked up once so each page is a cheap
+/// check.
+pub struct Follow {
+    /// Everything is covered.
+    all: bool,
+    namespaces: Vec<String>,
+    tagged: HashSet<String>,
+}
+
+impl Follow {
+    pub async fn load(inner: &HandlerInner, follow: &[String]) -> DynResult<Follow> {
+        let mut namespaces = Vec::new();
+        let (mut keys, mut values) = (Vec::new(), Vec::new());
+        for entry in follow {
+            match Filter::parse(entry)? {
+                Filter::Namespace(ns) => namespaces.push(ns.to_string()),
+                Filter::Tag { key, value } => {
+                    keys.push(key);
+                    values.push(value);
+                }
+            }
+        }
+        let tagged = if keys.is_empty() {
+            HashSet::new()
+        } else {
+            inner.queries.fetch_tagged_names(&inner.db, &keys, &values).await?.into_iter().collect()
+        };
+        Ok(Follow {
+            all: follow.is_empty(),
+            namespaces,
+            tagged,
+        })
+    }
+
+    pub fn covers(&self, name: &str) -> bool {
+        self.all
+            || (!self.namespaces.is_empty() && digest::is_wanted(&self.namespaces, name))
+            || self.tagged.contains(name)
+    }
+}
+
+/// The pages in `pages` that `follow` covers, all of them if it's empty.
+pub async fn filter(inner: &HandlerInner, follow: &[String], pages: Vec<UnreadPage>) -> DynResult<Vec<UnreadPage>> {
+    if follow.is_empty() {
+        return Ok(pages);
+    }
+    let follow = Follow::load(inner, follow).await?;
+    Ok(pages.into_iter().filter(|page| follow.covers(&page.name)).collect())
+}
@@ -0,0 +1,87 @@
+//! The site footer: links edited as the ordinary wiki page `PAGE`, followed
+//! by links to whichever of `/about`, `/terms` and `/privacy` have been
+//! written. Those are served from the wiki pages named by `page_name`, so a
+//! public instance can put up its legal pages without touching templates.
+//! Like the sidebar, only admins may change any of them, see
+//! `permissions::Action::for_request`.
+
+use std::sync::RwLock;
+
+use crate::plugins::Plugins;
+use crate::routes::LegalPage;
+use crate::sidebar::SitePage;
+use crate::{DynResult, HandlerInner};
+
+pub const PAGE: &str = "_Footer";
+
+/// The wiki page `page` is served from.
+pub fn page_name(page: LegalPage) -> &'static str {
+    match page {
+        LegalPage::About => "_About",
+        LegalPage::Terms => "_Terms",
+        LegalPage::Privacy => "_Privacy",
+    }
+}
+
+pub fn title(page: LegalPage) -> &'static str {
+    match page {
+        LegalPage::About => "About",
+        LegalPage::Terms => "Terms of use",
+        LegalPage::Privacy => "Privacy policy",
+    }
+}
+
+/// Whether `name` is the footer or one of the pages it links to.
+pub fn is_footer_page(name: &str) -> bool {
+    name == PAGE || LegalPage::ALL.iter().any(|&page| page_name(page) == name)
+}
+
+pub struct Footer {
+    links: SitePage,
+    written: RwLock<Vec<LegalPage>>,
+    /// The render generation the footer was last brought up to date at.
+    /// Every save and move bumps it, see `render_cache.rs`.
+    generation: RwLock<Option<i64>>,
+}
+
+impl Default for Footer {
+    fn default() -> Footer {
+        Footer {
+            links: SitePage::new(PAGE),
+            written: RwLock::new(Vec::new()),
+            generation: RwLock::new(None),
+        }
+    }
+}
+
+impl Footer {
+    /// Brings the links and the list of written legal pages up to date, see
+    /// `SitePage::refresh`. Nothing is looked up again until a page has been
+    /// saved or moved since the last time.
+    pub async fn refresh(&self, inner: &HandlerInner, plugins: &Plugins) -> DynResult<()> {
+        let generation = inner.queries.fetch_render_generation(&inner.db).await?;
+        if *self.generation.read().unwrap() == Some(generation) {
+            return Ok(());
+        }
+        self.links.refresh(inner, plugins).await?;
+        let names: Vec<String> = LegalPage::ALL.iter().map(|&page| page_name(page).to_string()).collect();
+        let existing = inner.queries.fetch_existing_names(&inner.db, &names).await?;
+        *self.written.write().unwrap() = LegalPage::ALL
+            .iter()
+            .copied()
+            .filter(|&page| existing.iter().any(|name| name == page_name(page)))
+            .collect();
+        *self.generation.write().unwrap() = Some(generation);
+        Ok(())
+    }
+
+    /// The rendered `PAGE`, if it exists.
+    pub fn html(&self) -> Option<String> {
+        self.links.html()
+    }
+
+    /// The legal pages that exist, in `LegalPage::ALL` order.
+    pub fn written(&self) -> Vec<LegalPage> {
+        self.written.read().unwrap().clone()
+    }
+}
@@ -0,0 +1,76 @@
+//! Page metadata in a front matter block at the very top of a page:
+//!
+//! ```markdown
+//! ---
+//! expires: 2024-06-30
+//! ---
+//! # Deploy runbook
+//! ```
+//!
+//! The block isn't rendered. Unknown keys are ignored. A page is stale from
+//! its `expires` date on: it gets a banner asking for a review and is listed
+//! on `/maintenance/stale`. `format` picks the markup the rest of the page is
+//! written in, see `markup`.
+
+use chrono::NaiveDate;
+
+use crate::markup::Format;
+
+/// Same as `Renderer::options`, so what is parsed here is what isn't shown.
+pub const DELIMITER: &str = "---";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrontMatter {
+    pub expires: Option<NaiveDate>,
+    /// Markdown unless set; unknown formats are ignored.
+    pub format: Format,
+}
+
+/// Splits `markdown` into the front matter block's lines, if it has one,
+/// and the rest of the page.
+pub fn split(markdown: &str) -> (Option<&str>, &str) {
+    let text = markdown.strip_prefix('\u{feff}').unwrap_or(markdown);
+    let after_open = match text.strip_prefix(DELIMITER).and_then(strip_line_end) {
+        Some(rest) => rest,
+        None => return (None, markdown),
+    };
+    let mut offset = 0;
+    for line in after_open.split_inclusive('\n') {
+        if line.trim_end_matches(&['\r', '\n'][..]) == DELIMITER && line.ends_with('\n') {
+            let body = &after_open[offset + line.len()..];
+            return (Some(&after_open[..offset]), body);
+        }
+        offset += line.len();
+    }
+    (None, markdown)
+}
+
+fn strip_line_end(s: &str) -> Option<&str> {
+    s.strip_prefix("\r\n").or_else(|| s.strip_prefix('\n'))
+}
+
+/// `markdown` without its front matter block.
+pub fn strip(markdown: &str) -> &str {
+    split(markdown).1
+}
+
+pub fn parse(markdown: &str) -> FrontMatter {
+    let mut front_matter = FrontMatter::default();
+    let block = match split(markdown).0 {
+        Some(block) => block,
+        None => return front_matter,
+    };
+    for line in block.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let key = key.trim();
+        if key.eq_ignore_ascii_case("expires") {
+            front_matter.expires = NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok();
+        } else if key.eq_ignore_ascii_case("format") {
+            front_matter.format = Format::from_name(value.trim()).unwrap_or_default();
+        }
+    }
+    front_matter
+}
@@ -0,0 +1,127 @@
+//! Writes a page's history as a git bundle: one commit per revision, each
+//! with a single file holding the page text. `git clone page.bundle` turns
+//! it into an ordinary repository.
+//!
+//! A bundle is a short header naming the refs followed by a packfile. The
+//! objects are stored whole, without deltas; pages are small and zlib
+//! takes care of most of the repetition.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use chrono::{DateTime, Utc};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use ring::digest;
+
+use crate::auth;
+
+const BRANCH: &str = "refs/heads/main";
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+
+pub struct Commit<'a> {
+    pub data: &'a str,
+    pub author: &'a str,
+    pub time: DateTime<Utc>,
+    pub message: String,
+}
+
+/// A bundle of `commits`, oldest first, each storing its `data` as
+/// `file_name`. Returns `None` if there are no commits.
+pub fn write(file_name: &str, commits: &[Commit<'_>]) -> io::Result<Option<Vec<u8>>> {
+    let mut pack = Pack::default();
+    let mut head: Option<[u8; 20]> = None;
+    for commit in commits {
+        let blob = pack.add(OBJ_BLOB, commit.data.as_bytes())?;
+
+        let mut tree = format!("100644 {}\0", file_name).into_bytes();
+        tree.extend_from_slice(&blob);
+        let tree = pack.add(OBJ_TREE, &tree)?;
+
+        let signature = format!("{} <> {} +0000", identity(commit.author), commit.time.timestamp());
+        let mut object = format!("tree {}\n", auth::to_hex(&tree));
+        if let Some(parent) = head {
+            object.push_str(&format!("parent {}\n", auth::to_hex(&parent)));
+        }
+        object.push_str(&format!(
+            "author {}\ncommitter {}\n\n{}\n",
+            signature, signature, commit.message
+        ));
+        head = Some(pack.add(OBJ_COMMIT, object.as_bytes())?);
+    }
+
+    let head = match head {
+        Some(head) => auth::to_hex(&head),
+        None => return Ok(None),
+    };
+    let mut bundle = format!("# v2 git bundle\n{} HEAD\n{} {}\n\n", head, head, BRANCH).into_bytes();
+    bundle.extend_from_slice(&pack.finish());
+    Ok(Some(bundle))
+}
+
+/// A name git will accept between `author` and the email.
+fn identity(name: &str) -> String {
+    let name: String = name.chars().filter(|c| !matches!(c, '<' | '>' | '\n')).collect();
+    match name.trim() {
+        "" => "unknown".to_string(),
+        name => name.to_string(),
+    }
+}
+
+#[derive(Default)]
+struct Pack {
+    objects: Vec<u8>,
+    count: u32,
+    seen: HashSet<[u8; 20]>,
+}
+
+impl Pack {
+    /// Adds an object unless an identical one is already in, returning its
+    /// id either way.
+    fn add(&mut self, kind: u8, data: &[u8]) -> io::Result<[u8; 20]> {
+        let name = match kind {
+            OBJ_COMMIT => "commit",
+            OBJ_TREE => "tree",
+            _ => "blob",
+        };
+        let mut ctx = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        ctx.update(format!("{} {}\0", name, data.len()).as_bytes());
+        ctx.update(data);
+        let mut id = [0; 20];
+        id.copy_from_slice(ctx.finish().as_ref());
+        if !self.seen.insert(id) {
+            return Ok(id);
+        }
+
+        // type and size: 3 bits of type and 4 of size in the first byte,
+        // then 7 bits of size per byte while the high bit is set
+        let mut size = data.len();
+        let mut byte = (kind << 4) | (size & 0x0f) as u8;
+        size >>= 4;
+        while size > 0 {
+            self.objects.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        self.objects.push(byte);
+
+        let mut encoder = ZlibEncoder::new(&mut self.objects, Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()?;
+        self.count += 1;
+        Ok(id)
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&self.count.to_be_bytes());
+        pack.extend_from_slice(&self.objects);
+        let checksum = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &pack);
+        pack.extend_from_slice(checksum.as_ref());
+        pack
+    }
+}
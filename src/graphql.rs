@@ -0,0 +1,342 @@
+//! A read-only GraphQL endpoint at `/graphql`, turned on with `--graphql`,
+//! for tools that want a page, its history, what links to it and search
+//! results in one request instead of one REST call each. It answers what
+//! `/api/v1` does and no more: pending revisions are hidden from those who
+//! can't review them, as on `/wiki/Page/revisions/N`.
+//!
+//! ```graphql
+//! {
+//!   page(name: "Rust") {
+//!     current { id createdAt html }
+//!     history { id createdBy sizeDelta summary }
+//!     backlinks
+//!   }
+//!   search(query: "error handling ns:projects") { page { name } lastModifiedAt }
+//! }
+//! ```
+//!
+//! Queries are limited in depth and complexity so one request can't walk
+//! the whole wiki.
+
+use std::sync::Arc;
+
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use tokio::sync::RwLock;
+
+use crate::markup;
+use crate::plugins::Plugins;
+use crate::queries;
+use crate::render_cache::RenderCache;
+use crate::search::{self, SearchQuery, SearchWeights};
+use crate::{link_graph, render_document, HandlerInner};
+
+const MAX_DEPTH: usize = 8;
+const MAX_COMPLEXITY: usize = 500;
+
+/// Most search results and backlinks returned, and the default.
+const MAX_RESULTS: i32 = 50;
+
+/// Largest request body `/graphql` reads, in bytes. Queries are short; this
+/// leaves room for long variables.
+pub const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+pub type WikiSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub fn schema() -> WikiSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// What resolvers read from, given with each request.
+pub struct Env {
+    pub inner: Arc<RwLock<HandlerInner>>,
+    pub plugins: Arc<Plugins>,
+    pub render_cache: Arc<RenderCache>,
+    pub search_weights: SearchWeights,
+    /// Whether the caller may see revisions pending review.
+    pub may_review: bool,
+}
+
+fn clamp_limit(requested: Option<i32>) -> i64 {
+    requested.unwrap_or(MAX_RESULTS).clamp(0, MAX_RESULTS).into()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The page called `name`, if it has a current revision.
+    async fn page(&self, ctx: &Context<'_>, name: String) -> async_graphql::Result<Option<Page>> {
+        let env = ctx.data::<Env>()?;
+        let locked = env.inner.read().await;
+        let current = locked.queries.fetch_current_revision(&locked.db, &name).await?;
+        Ok(current.map(|_| Page { name }))
+    }
+
+    /// Current pages matching `query`, in the syntax of `/search`.
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        limit: Option<i32>,
+    ) -> async_graphql::Result<Vec<SearchHit>> {
+        let env = ctx.data::<Env>()?;
+        let query = SearchQuery::parse(&query)?;
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let locked = env.inner.read().await;
+        let hits = search::run(&locked, &query, &env.search_weights, clamp_limit(limit)).await?;
+        Ok(hits
+            .into_iter()
+            .map(|hit| SearchHit {
+                page: Page { name: hit.name },
+                last_modified_at: hit.last_modified_at.to_rfc3339(),
+                last_modified_by: hit.last_modified_by,
+            })
+            .collect())
+    }
+
+    /// The pages within `depth` links of `root`, as on `/api/v1/graph`.
+    async fn link_graph(
+        &self,
+        ctx: &Context<'_>,
+        root: String,
+        depth: Option<i32>,
+    ) -> async_graphql::Result<LinkGraph> {
+        let env = ctx.data::<Env>()?;
+        let depth = depth
+            .map_or(link_graph::DEFAULT_DEPTH, |depth| depth.max(0) as usize)
+            .min(link_graph::MAX_DEPTH);
+        let locked = env.inner.read().await;
+        let graph = link_graph::collect(&locked, &root, depth).await?;
+        Ok(LinkGraph {
+            root,
+            depth: depth as i32,
+            truncated: graph.truncated,
+            nodes: graph
+                .nodes
+                .into_iter()
+                .map(|node| GraphNode {
+                    name: node.name,
+                    distance: node.distance as i32,
+                    exists: node.exists,
+                })
+                .collect(),
+            edges: graph
+                .edges
+                .into_iter()
+                .map(|(source, target)| GraphEdge { source, target })
+                .collect(),
+        })
+    }
+}
+
+pub struct Page {
+    name: String,
+}
+
+#[Object]
+impl Page {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn current(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Revision>> {
+        let env = ctx.data::<Env>()?;
+        let locked = env.inner.read().await;
+        let revision = locked.queries.fetch_current_revision(&locked.db, &self.name).await?;
+        Ok(revision.map(|revision| Revision::of(&self.name, revision)))
+    }
+
+    async fn revision(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<Revision>> {
+        let env = ctx.data::<Env>()?;
+        let locked = env.inner.read().await;
+        let revision = locked
+            .queries
+            .fetch_revision(&locked.db, &self.name, id, env.may_review)
+            .await?;
+        Ok(revision.map(|revision| Revision::of(&self.name, revision)))
+    }
+
+    /// The latest revisions, newest first, as on the history page.
+    async fn history(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<HistoryEntry>> {
+        let env = ctx.data::<Env>()?;
+        let locked = env.inner.read().await;
+        let history = locked
+            .queries
+            .fetch_history(&locked.db, &self.name, env.may_review)
+            .await?;
+        Ok(history
+            .into_iter()
+            .map(|entry| HistoryEntry {
+                id: entry.id,
+                created_at: entry.created_at.to_rfc3339(),
+                created_by: entry.modified_by,
+                size: entry.size,
+                size_delta: entry.size_delta,
+                status: entry.status.as_str(),
+                reviewed_by: entry.reviewed_by,
+                redacted: entry.redaction.is_some(),
+                summary: entry.summary,
+            })
+            .collect())
+    }
+
+    /// The pages the current revision links to.
+    async fn links(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        let env = ctx.data::<Env>()?;
+        let locked = env.inner.read().await;
+        Ok(locked.queries.fetch_links(&locked.db, &self.name).await?)
+    }
+
+    /// Current pages linking here.
+    async fn backlinks(&self, ctx: &Context<'_>, limit: Option<i32>) -> async_graphql::Result<Vec<String>> {
+        let env = ctx.data::<Env>()?;
+        let locked = env.inner.read().await;
+        Ok(locked
+            .queries
+            .fetch_backlinks(&locked.db, &self.name, clamp_limit(limit))
+            .await?)
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Revision {
+    id: i64,
+    created_at: String,
+    created_by: String,
+    current: bool,
+    /// The page's source, Markdown or another markup, see `markup.rs`.
+    text: String,
+    #[graphql(skip)]
+    name: String,
+}
+
+impl Revision {
+    fn of(name: &str, revision: queries::Revision) -> Revision {
+        Revision {
+            id: revision.id,
+            created_at: revision.created_at.to_rfc3339(),
+            created_by: revision.modified_by,
+            current: revision.is_current,
+            text: revision.document_data,
+            name: name.to_string(),
+        }
+    }
+}
+
+#[ComplexObject]
+impl Revision {
+    async fn size(&self) -> usize {
+        self.text.len()
+    }
+
+    /// Names of the pages the text links to.
+    async fn link_targets(&self) -> Vec<String> {
+        markup::of(&self.text).link_targets(&self.text)
+    }
+
+    #[graphql(complexity = 20)]
+    async fn html(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        let env = ctx.data::<Env>()?;
+        let locked = env.inner.read().await;
+        let html = if self.current {
+            env.render_cache
+                .render(&locked, &env.plugins, &self.name, self.id, &self.text)
+                .await?
+        } else {
+            render_document(&locked, &env.plugins, &self.text).await?
+        };
+        Ok(html)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct HistoryEntry {
+    id: i64,
+    created_at: String,
+    created_by: String,
+    size: i32,
+    size_delta: i32,
+    /// `published`, `pending`, `approved` or `rejected`.
+    status: &'static str,
+    reviewed_by: Option<String>,
+    redacted: bool,
+    summary: Option<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct SearchHit {
+    page: Page,
+    last_modified_at: String,
+    last_modified_by: String,
+}
+
+#[derive(SimpleObject)]
+pub struct LinkGraph {
+    root: String,
+    depth: i32,
+    truncated: bool,
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+#[derive(SimpleObject)]
+pub struct GraphNode {
+    name: String,
+    distance: i32,
+    exists: bool,
+}
+
+#[derive(SimpleObject)]
+pub struct GraphEdge {
+    source: String,
+    target: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::queries::DocumentIndex;
+
+    /// The statuses `history` lists for `name`, newest first.
+    async fn history_statuses(env: Env, name: &str) -> Vec<String> {
+        let query = format!("{{ page(name: {:?}) {{ history {{ status }} }} }}", name);
+        let response = schema().execute(async_graphql::Request::new(query).data(env)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        data["page"]["history"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["status"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn history_hides_held_revisions_from_readers() {
+        let (_database, inner) = match testing::provisioned().await {
+            Some(provisioned) => provisioned,
+            None => return,
+        };
+        let index = DocumentIndex::default();
+        inner.queries.store_revision(&inner.db, "Held", "alice", "first", &index).await.unwrap();
+        inner.queries.store_pending_revision(&inner.db, "Held", "192.0.2.1", "second").await.unwrap();
+
+        let inner = Arc::new(RwLock::new(inner));
+        let env = |may_review| Env {
+            inner: inner.clone(),
+            plugins: Arc::new(Plugins::compiled_in()),
+            render_cache: Arc::new(RenderCache::new(None)),
+            search_weights: SearchWeights::default(),
+            may_review,
+        };
+        assert_eq!(history_statuses(env(false), "Held").await, ["published"]);
+        assert_eq!(history_statuses(env(true), "Held").await, ["pending", "published"]);
+    }
+}
@@ -0,0 +1,313 @@
+//! Server-side highlighting of fenced code blocks.
+//!
+//! Fence languages are looked up through an alias map first (`rs` → Rust),
+//! then by syntect's own name and extension tokens. Blocks in a language
+//! nobody knows are shown as plain text under a label naming the language,
+//! rather than silently guessed at.
+//!
+//! Options in braces after the language, as in `rust {linenos hl_lines=2-3,5}`,
+//! number the lines and highlight some of them. comrak only hands the first
+//! word of the info string to `highlight`, so `join_fence_options` glues the
+//! options onto it before rendering.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use askama::{Html, MarkupDisplay};
+use comrak::adapters::SyntaxHighlighterAdapter;
+use comrak::nodes::{AstNode, NodeValue};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{
+    highlighted_html_for_string, start_highlighted_html_snippet, styled_line_to_highlighted_html,
+    IncludeBackground,
+};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::DynResult;
+
+const THEME: &str = "base16-ocean.light";
+
+/// Aliases used unless `--highlight-alias` overrides them, as
+/// `(fence language, syntax name)`.
+pub const DEFAULT_ALIASES: &[(&str, &str)] = &[
+    ("rs", "Rust"),
+    ("shell", "Bash"),
+    ("console", "Bash"),
+    ("js", "JavaScript"),
+    ("py", "Python"),
+    ("yml", "YAML"),
+];
+
+/// Longest line range `hl_lines` may cover, so a typo like `1-99999999`
+/// doesn't cost anything.
+const MAX_HIGHLIGHTED_SPAN: usize = 10_000;
+
+static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
+
+/// Installs the highlighter used by every render. Call once at startup;
+/// renders before that use the built-in syntaxes and aliases.
+pub fn init(highlighter: Highlighter) {
+    let _ = HIGHLIGHTER.set(highlighter);
+}
+
+pub fn highlighter() -> &'static Highlighter {
+    HIGHLIGHTER.get_or_init(|| {
+        Highlighter::new(&[], None).expect("the built-in syntaxes always load")
+    })
+}
+
+pub struct Highlighter {
+    syntax_set: SyntaxSet,
+    theme: Theme,
+    /// Lowercased fence language to syntax name.
+    aliases: HashMap<String, String>,
+}
+
+impl Highlighter {
+    /// `aliases` are added to, and override, `DEFAULT_ALIASES`. Every
+    /// `.sublime-syntax` file under `syntax_dir` is loaded alongside the
+    /// built-in syntaxes.
+    pub fn new(aliases: &[(String, String)], syntax_dir: Option<&Path>) -> DynResult<Highlighter> {
+        let mut syntax_set = SyntaxSet::load_defaults_newlines();
+        if let Some(dir) = syntax_dir {
+            let mut builder = syntax_set.into_builder();
+            builder
+                .add_from_folder(dir, true)
+                .map_err(|e| format!("loading syntaxes from {}: {}", dir.display(), e))?;
+            syntax_set = builder.build();
+        }
+
+        let mut alias_map = HashMap::new();
+        let defaults = DEFAULT_ALIASES.iter().map(|&(from, to)| (from, to));
+        let configured = aliases.iter().map(|(from, to)| (&from[..], &to[..]));
+        for (from, to) in defaults.chain(configured) {
+            alias_map.insert(from.to_lowercase(), to.to_string());
+        }
+        for (from, to) in &alias_map {
+            if lookup(&syntax_set, to).is_none() {
+                return Err(format!("alias {:?} names unknown syntax {:?}", from, to).into());
+            }
+        }
+
+        let mut themes = ThemeSet::load_defaults().themes;
+        Ok(Highlighter {
+            syntax_set,
+            theme: themes.remove(THEME).expect("built-in theme"),
+            aliases: alias_map,
+        })
+    }
+
+    fn syntax_for(&self, lang: &str) -> Option<&SyntaxReference> {
+        match self.aliases.get(&lang.to_lowercase()) {
+            Some(name) => lookup(&self.syntax_set, name),
+            None => lookup(&self.syntax_set, lang),
+        }
+    }
+
+    fn strip_pre(html: &str) -> &str {
+        let inner = html.find('>').map_or(html, |i| &html[i + 1..]);
+        inner.trim_end_matches('\n').trim_end_matches("</pre>")
+    }
+
+    /// Each line of `code` as highlighted HTML, without its line ending.
+    fn highlight_lines(&self, syntax: &SyntaxReference, code: &str) -> Vec<String> {
+        let background = self.theme.settings.background.unwrap_or(Color::WHITE);
+        let mut state = HighlightLines::new(syntax, &self.theme);
+        LinesWithEndings::from(code)
+            .map(|line| {
+                let regions: Vec<_> = state
+                    .highlight(line, &self.syntax_set)
+                    .into_iter()
+                    .map(|(style, text)| (style, text.trim_end_matches(&['\r', '\n'][..])))
+                    .collect();
+                styled_line_to_highlighted_html(&regions, IncludeBackground::IfDifferent(background))
+            })
+            .collect()
+    }
+}
+
+/// What the braces after a fence language ask for.
+#[derive(Debug, Default, PartialEq)]
+pub struct FenceOptions {
+    pub line_numbers: bool,
+    /// 1-based inclusive line ranges.
+    pub highlighted: Vec<(usize, usize)>,
+}
+
+impl FenceOptions {
+    /// Parses the inside of the braces: `linenos` and `hl_lines=` with
+    /// comma-separated lines and ranges, optionally quoted, separated by
+    /// spaces or semicolons. Anything else is ignored.
+    pub fn parse(options: &str) -> FenceOptions {
+        let mut parsed = FenceOptions::default();
+        for option in options.split(&[' ', '\t', ';'][..]).filter(|o| !o.is_empty()) {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            let value = value.trim_matches(&['"', '\''][..]);
+            match key {
+                "linenos" => parsed.line_numbers = !matches!(value, "false" | "0"),
+                "hl_lines" => {
+                    for part in value.split(',') {
+                        let (start, end) = part.split_once('-').unwrap_or((part, part));
+                        if let (Ok(start), Ok(end)) = (start.trim().parse(), end.trim().parse()) {
+                            if start >= 1 && start <= end && end - start < MAX_HIGHLIGHTED_SPAN {
+                                parsed.highlighted.push((start, end));
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        parsed
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.line_numbers && self.highlighted.is_empty()
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted.iter().any(|&(start, end)| (start..=end).contains(&line))
+    }
+
+    /// The options as a single word, which `parse` reads back.
+    fn to_word(&self) -> String {
+        let mut options = Vec::new();
+        if self.line_numbers {
+            options.push("linenos".to_string());
+        }
+        if !self.highlighted.is_empty() {
+            let ranges: Vec<_> = self
+                .highlighted
+                .iter()
+                .map(|&(start, end)| format!("{}-{}", start, end))
+                .collect();
+            options.push(format!("hl_lines={}", ranges.join(",")));
+        }
+        format!("{{{}}}", options.join(";"))
+    }
+
+    /// Wraps each line in a span, numbered and marked as asked.
+    fn wrap_lines(&self, lines: Vec<String>) -> String {
+        let mut out = String::new();
+        for (i, line) in lines.into_iter().enumerate() {
+            let number = i + 1;
+            out.push_str("<span class=\"code-line");
+            if self.is_highlighted(number) {
+                out.push_str(" highlighted");
+            }
+            out.push_str("\">");
+            if self.line_numbers {
+                // drawn from the attribute, so copying the code leaves it out
+                out.push_str(&format!("<span class=\"code-line-number\" data-line=\"{}\"></span>", number));
+            }
+            out.push_str(&line);
+            out.push_str("</span>\n");
+        }
+        out
+    }
+}
+
+/// Splits the word comrak passes to `highlight` into the language and the
+/// options glued onto it by `join_fence_options`.
+fn split_fence_options(lang: &str) -> (&str, FenceOptions) {
+    match lang.split_once('{') {
+        Some((lang, options)) => (lang, FenceOptions::parse(options.trim_end_matches('}'))),
+        None => (lang, FenceOptions::default()),
+    }
+}
+
+/// Rewrites each fenced code block's info string so its options are part of
+/// the first word, `rust {linenos}` becoming `rust{linenos}`, since comrak
+/// drops everything after it.
+pub fn join_fence_options<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        if let NodeValue::CodeBlock(ref mut block) = node.data.borrow_mut().value {
+            let info = String::from_utf8_lossy(&block.info).into_owned();
+            let (lang, options) = match info.find('{') {
+                Some(i) => (info[..i].trim(), &info[i + 1..]),
+                None => continue,
+            };
+            let options = FenceOptions::parse(options.trim_end().trim_end_matches('}'));
+            let lang = lang.split_whitespace().next().unwrap_or("");
+            block.info = format!("{}{}", lang, options.to_word()).into_bytes();
+        }
+    }
+}
+
+/// Finds a syntax by extension or name, ignoring case, so both `Bash` and
+/// `Bourne Again Shell (bash)` work.
+fn lookup<'a>(syntax_set: &'a SyntaxSet, name: &str) -> Option<&'a SyntaxReference> {
+    syntax_set
+        .find_syntax_by_token(name)
+        .or_else(|| syntax_set.find_syntax_by_extension(&name.to_lowercase()))
+}
+
+impl SyntaxHighlighterAdapter for Highlighter {
+    fn highlight(&self, lang: Option<&str>, code: &str) -> String {
+        let (lang, options) = match lang {
+            Some(lang) => {
+                let (lang, options) = split_fence_options(lang);
+                (Some(lang), options)
+            }
+            None => (None, FenceOptions::default()),
+        };
+        let syntax = match lang.filter(|l| !l.is_empty()) {
+            Some(lang) => match self.syntax_for(lang) {
+                Some(syntax) => syntax,
+                None => {
+                    let code = if options.is_empty() {
+                        MarkupDisplay::new_unsafe(code, Html).to_string()
+                    } else {
+                        let lines = code
+                            .lines()
+                            .map(|line| MarkupDisplay::new_unsafe(line, Html).to_string())
+                            .collect();
+                        options.wrap_lines(lines)
+                    };
+                    return format!(
+                        "<span class=\"code-language\">{}</span>\n{}",
+                        MarkupDisplay::new_unsafe(lang, Html),
+                        code
+                    );
+                }
+            },
+            None => self
+                .syntax_set
+                .find_syntax_by_first_line(code)
+                .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text()),
+        };
+
+        if !options.is_empty() {
+            return options.wrap_lines(self.highlight_lines(syntax, code));
+        }
+        let html = highlighted_html_for_string(code, &self.syntax_set, syntax, &self.theme);
+        Highlighter::strip_pre(&html).to_string()
+    }
+
+    fn build_pre_tag(&self, attributes: &HashMap<String, String>) -> String {
+        let (pre, _) = start_highlighted_html_snippet(&self.theme);
+        let mut tag = pre.trim_end().trim_end_matches('>').to_string();
+        for (name, value) in attributes {
+            tag.push_str(&format!(" {}=\"{}\"", name, MarkupDisplay::new_unsafe(value, Html)));
+        }
+        tag.push('>');
+        tag
+    }
+
+    fn build_code_tag(&self, attributes: &HashMap<String, String>) -> String {
+        let mut tag = "<code".to_string();
+        for (name, value) in attributes {
+            // `language-rust{linenos}` from `join_fence_options`
+            let value = match name.as_str() {
+                "class" => value.split('{').next().unwrap_or(""),
+                _ => value,
+            };
+            tag.push_str(&format!(" {}=\"{}\"", name, MarkupDisplay::new_unsafe(value, Html)));
+        }
+        tag.push('>');
+        tag
+    }
+}
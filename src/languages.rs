@@ -0,0 +1,83 @@
+//! Language variants of pages. With `--languages en,fr,ja`, `Page` is
+//! written in the default language, the first listed, and `Page/fr` and
+//! `Page/ja` are its translations. Only listed codes count, so a subpage
+//! such as `Notes/ab` is left alone.
+//!
+//! Views of a variant that doesn't exist fall back to the default language
+//! page, and every variant links to the others, with `hreflang` for search
+//! engines.
+
+#[derive(Debug, Clone, Default)]
+pub struct Languages {
+    /// Lowercase codes, the default first.
+    codes: Vec<String>,
+}
+
+impl Languages {
+    /// Parses `--languages`, a comma-separated list of codes such as `en`
+    /// or `pt-br`.
+    pub fn parse(list: &str) -> Result<Languages, String> {
+        let mut codes: Vec<String> = Vec::new();
+        for code in list.split(',').map(str::trim).filter(|code| !code.is_empty()) {
+            if !is_code(code) {
+                return Err(format!("expected a language code such as en or pt-br, got {:?}", code));
+            }
+            let code = code.to_ascii_lowercase();
+            if codes.contains(&code) {
+                return Err(format!("{} is listed twice", code));
+            }
+            codes.push(code);
+        }
+        Ok(Languages { codes })
+    }
+
+    /// Whether there's a language besides the default, so variants exist.
+    pub fn is_enabled(&self) -> bool {
+        self.codes.len() > 1
+    }
+
+    pub fn default_code(&self) -> Option<&str> {
+        self.codes.first().map(|code| &code[..])
+    }
+
+    /// `name` split into the page it translates and its language, which is
+    /// `None` for a page in the default language.
+    pub fn split<'n>(&self, name: &'n str) -> (&'n str, Option<&str>) {
+        if let Some((base, suffix)) = name.rsplit_once('/') {
+            // the default language has no suffix
+            if let Some(code) = self.codes.iter().skip(1).find(|code| **code == suffix) {
+                if !base.is_empty() {
+                    return (base, Some(&code[..]));
+                }
+            }
+        }
+        (name, None)
+    }
+
+    /// The name of every variant of `base` as `(code, name)` pairs, the
+    /// default language first.
+    pub fn variant_names(&self, base: &str) -> Vec<(&str, String)> {
+        self.codes
+            .iter()
+            .enumerate()
+            .map(|(i, code)| {
+                let name = if i == 0 { base.to_string() } else { format!("{}/{}", base, code) };
+                (&code[..], name)
+            })
+            .collect()
+    }
+}
+
+/// Two or three letters, optionally followed by `-` and a region or script
+/// of two to four letters or digits.
+fn is_code(code: &str) -> bool {
+    let (language, region) = match code.split_once('-') {
+        Some((language, region)) => (language, Some(region)),
+        None => (code, None),
+    };
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && region.is_none_or(|region| {
+            (2..=4).contains(&region.len()) && region.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
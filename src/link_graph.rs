@@ -0,0 +1,122 @@
+//! The neighbourhood of a page in the link graph, for `/api/v1/graph` and
+//! the map drawn on `/graph`: every page within `depth` links of the root,
+//! following links both ways, and the links between them. Only links from
+//! pages with a current revision count, but their targets needn't exist.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+
+use crate::query_params::{self, QueryError, QueryParams};
+use crate::routes::Route;
+use crate::{DynResult, HandlerInner};
+
+/// Default and largest number of links followed from the root.
+pub const DEFAULT_DEPTH: usize = 2;
+pub const MAX_DEPTH: usize = 4;
+
+/// Stop adding pages once the graph has this many.
+pub const MAX_PAGES: usize = 200;
+
+pub struct Node {
+    pub name: String,
+    /// Links between the root and this page, ignoring their direction.
+    pub distance: usize,
+    /// Whether the page has a current revision.
+    pub exists: bool,
+}
+
+pub struct LinkGraph {
+    /// The root first, then the others by distance.
+    pub nodes: Vec<Node>,
+    /// `(source, target)` pairs, both ends among `nodes`.
+    pub edges: Vec<(String, String)>,
+    /// Whether pages were left out because of `MAX_PAGES`.
+    pub truncated: bool,
+}
+
+/// Walks the link graph out from `root`, one query per step.
+pub async fn collect(inner: &HandlerInner, root: &str, depth: usize) -> DynResult<LinkGraph> {
+    let mut distances = HashMap::new();
+    distances.insert(root.to_string(), 0);
+    let mut order = vec![root.to_string()];
+    let mut frontier = vec![root.to_string()];
+    let mut truncated = false;
+
+    for distance in 1..=depth {
+        if frontier.is_empty() {
+            break;
+        }
+        let mut next = Vec::new();
+        for (source, target) in inner.queries.fetch_link_edges(&inner.db, &frontier).await? {
+            for name in [source, target] {
+                if distances.contains_key(&name) {
+                    continue;
+                }
+                if distances.len() >= MAX_PAGES {
+                    truncated = true;
+                    continue;
+                }
+                distances.insert(name.clone(), distance);
+                order.push(name.clone());
+                next.push(name);
+            }
+        }
+        frontier = next;
+    }
+
+    // the walk misses links between pages found on the last step, so ask
+    // again for the links of everything found
+    let edges = inner
+        .queries
+        .fetch_link_edges(&inner.db, &order)
+        .await?
+        .into_iter()
+        .filter(|(source, target)| distances.contains_key(source) && distances.contains_key(target))
+        .collect();
+
+    let existing: HashSet<String> = inner
+        .queries
+        .fetch_existing_names(&inner.db, &order)
+        .await?
+        .into_iter()
+        .collect();
+    let nodes = order
+        .into_iter()
+        .map(|name| Node {
+            distance: distances[&name],
+            exists: existing.contains(&name),
+            name,
+        })
+        .collect();
+
+    Ok(LinkGraph {
+        nodes,
+        edges,
+        truncated,
+    })
+}
+
+#[derive(Deserialize)]
+struct GraphParams {
+    #[serde(default, deserialize_with = "query_params::non_empty")]
+    root: Option<String>,
+    depth: Option<usize>,
+}
+
+/// `?root=` and `?depth=` from a query string, the depth clamped to
+/// `MAX_DEPTH`. The root is `None` if missing or empty.
+pub fn parse_query(query: &str) -> Result<(Option<String>, usize), QueryError> {
+    let QueryParams(params) = QueryParams::<GraphParams>::parse(query)?;
+    let depth = params.depth.unwrap_or(DEFAULT_DEPTH).min(MAX_DEPTH);
+    Ok((params.root, depth))
+}
+
+/// `route` with `?root=` and `?depth=` appended.
+pub fn link(route: Route<'_>, root: &str, depth: usize) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("root", root)
+        .append_pair("depth", &depth.to_string())
+        .finish();
+    format!("{}?{}", route, query)
+}
@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+
+use comrak::nodes::NodeValue;
+use comrak::{parse_document, Arena, ComrakOptions};
+
+use crate::names;
+use crate::routes::Route;
+
+/// Resolves a link destination to the wiki page it points at, if it is an
+/// internal link. The name is canonical, so `[x](cafe)` and `[x](Cafe)`
+/// are the same link when names ignore case.
+pub fn internal_link_target(url: &str) -> Option<String> {
+    let path = url.split(&['?', '#'][..]).next().unwrap_or("");
+    match Route::router(path) {
+        Ok(Route::Wiki(rw)) => Some(names::canonical(&rw.name, names::case())),
+        _ => None,
+    }
+}
+
+/// Every distinct wiki page linked to from `markdown`, in order of first appearance.
+pub fn internal_link_targets(markdown: &str, options: &ComrakOptions) -> Vec<String> {
+    let arena = Arena::new();
+    let root = parse_document(&arena, markdown, options);
+
+    let mut seen = HashSet::new();
+    let mut targets = Vec::new();
+    for node in root.descendants() {
+        if let NodeValue::Link(ref link) = node.data.borrow().value {
+            let target = match std::str::from_utf8(&link.url).ok().and_then(internal_link_target) {
+                Some(target) => target,
+                None => continue,
+            };
+            if seen.insert(target.clone()) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+/// Every distinct wiki page linked to from rendered `html`, in order of
+/// first appearance.
+pub fn html_link_targets(html: &str) -> Vec<String> {
+    const HREF: &str = "<a href=\"";
+
+    let mut seen = HashSet::new();
+    let mut targets = Vec::new();
+    let mut rest = html;
+    while let Some(idx) = rest.find(HREF) {
+        let href_start = &rest[idx + HREF.len()..];
+        let href = &href_start[..href_start.find('"').unwrap_or(href_start.len())];
+        if let Some(target) = internal_link_target(&href.replace("&amp;", "&")) {
+            if seen.insert(target.clone()) {
+                targets.push(target);
+            }
+        }
+        rest = href_start;
+    }
+    targets
+}
+
+/// Adds `class="missing"` to every anchor in `html` whose target page is in `missing`.
+pub fn mark_missing_links(html: &str, missing: &HashSet<String>) -> String {
+    const ANCHOR: &str = "<a href=\"";
+
+    if missing.is_empty() {
+        return html.to_string();
+    }
+
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(idx) = rest.find(ANCHOR) {
+        let (before, after) = rest.split_at(idx);
+        out.push_str(before);
+
+        let href_start = &after[ANCHOR.len()..];
+        let href = &href_start[..href_start.find('"').unwrap_or(href_start.len())];
+        let is_missing = internal_link_target(&href.replace("&amp;", "&"))
+            .map(|target| missing.contains(&target))
+            .unwrap_or(false);
+
+        if is_missing {
+            out.push_str("<a class=\"missing\" href=\"");
+        } else {
+            out.push_str(ANCHOR);
+        }
+        rest = href_start;
+    }
+    out.push_str(rest);
+    out
+}
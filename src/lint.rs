@@ -0,0 +1,140 @@
+//! Checks Markdown posted to `/api/v1/lint`, so repositories that mirror
+//! their docs into the wiki can fail a build on pages that would come out
+//! broken: links to pages that don't exist, raw HTML, which isn't rendered,
+//! and headings that skip a level.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use comrak::arena_tree::Node;
+use comrak::nodes::{Ast, NodeValue};
+use comrak::{parse_document, Arena};
+
+use crate::{front_matter, links, DynResult, HandlerInner, Renderer};
+
+/// Longest bit of raw HTML quoted in a warning.
+const MAX_QUOTE_CHARS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    BrokenLink,
+    DisallowedHtml,
+    HeadingJump,
+}
+
+impl Rule {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rule::BrokenLink => "broken-link",
+            Rule::DisallowedHtml => "disallowed-html",
+            Rule::HeadingJump => "heading-jump",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Warning {
+    pub rule: Rule,
+    /// 1-based, counting front matter.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Every warning for `markdown`, in line order.
+pub async fn lint(inner: &HandlerInner, markdown: &str) -> DynResult<Vec<Warning>> {
+    let (mut warnings, link_lines) = check_markdown(markdown);
+
+    if !link_lines.is_empty() {
+        let targets: Vec<String> = link_lines
+            .iter()
+            .map(|(target, _)| target.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let existing: HashSet<String> = inner
+            .queries
+            .fetch_existing_names(&inner.db, &targets)
+            .await?
+            .into_iter()
+            .collect();
+        for (target, line) in link_lines {
+            if !existing.contains(&target) {
+                warnings.push(Warning {
+                    rule: Rule::BrokenLink,
+                    line,
+                    message: format!("links to {:?}, which doesn't exist", target),
+                });
+            }
+        }
+    }
+
+    warnings.sort_by_key(|w| w.line);
+    Ok(warnings)
+}
+
+/// The warnings that need nothing but the text, and the internal links in
+/// it with the line each is on.
+fn check_markdown(markdown: &str) -> (Vec<Warning>, Vec<(String, usize)>) {
+    let front_matter_lines = markdown[..markdown.len() - front_matter::strip(markdown).len()]
+        .matches('\n')
+        .count();
+    let arena = Arena::new();
+    let root = parse_document(&arena, markdown, &Renderer.options());
+
+    let mut warnings = Vec::new();
+    let mut link_lines: Vec<(String, usize)> = Vec::new();
+    let mut previous_level = None;
+    for node in root.descendants() {
+        let line = block_line(node) + front_matter_lines;
+        match node.data.borrow().value {
+            NodeValue::Link(ref link) => {
+                let target = std::str::from_utf8(&link.url).ok().and_then(links::internal_link_target);
+                if let Some(target) = target {
+                    link_lines.push((target, line));
+                }
+            }
+            NodeValue::HtmlBlock(ref html) => warnings.push(disallowed_html(&html.literal, line)),
+            NodeValue::HtmlInline(ref html) => warnings.push(disallowed_html(html, line)),
+            NodeValue::Heading(ref heading) => {
+                let level = heading.level;
+                if let Some(previous) = previous_level {
+                    if level > previous + 1 {
+                        warnings.push(Warning {
+                            rule: Rule::HeadingJump,
+                            line,
+                            message: format!("level {} heading follows a level {} heading", level, previous),
+                        });
+                    }
+                }
+                previous_level = Some(level);
+            }
+            _ => (),
+        }
+    }
+
+    (warnings, link_lines)
+}
+
+/// The line `node` starts on. Inline nodes don't record one, so they're
+/// placed on the first line of the block holding them.
+fn block_line<'a>(node: &'a Node<'a, RefCell<Ast>>) -> usize {
+    node.ancestors()
+        .map(|n| n.data.borrow().start_line)
+        .find(|&line| line > 0)
+        .unwrap_or(1) as usize
+}
+
+fn disallowed_html(html: &[u8], line: usize) -> Warning {
+    let html = String::from_utf8_lossy(html);
+    let html = html.trim();
+    let first_line = html.lines().next().unwrap_or("");
+    let mut quote: String = first_line.chars().take(MAX_QUOTE_CHARS).collect();
+    if quote.len() < html.len() {
+        quote.push('…');
+    }
+    Warning {
+        rule: Rule::DisallowedHtml,
+        line,
+        message: format!("raw HTML isn't rendered: {}", quote),
+    }
+}
@@ -0,0 +1,231 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request};
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, ServerConfig};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::TlsAcceptor;
+use tracing::{event, Level};
+
+use crate::{DynResult, Handler};
+
+/// Used when neither `--listen` nor socket activation provide a socket.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:3000";
+
+/// Unix socket peers have no IP address. They are reported as loopback, so
+/// a proxy in front of the socket can be trusted with `--trusted-proxy 127.0.0.1`.
+const UNIX_PEER: ([u8; 4], u16) = ([127, 0, 0, 1], 0);
+
+#[derive(Debug, Clone)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// A socket passed in by the service manager, see `systemd.rs`.
+    #[cfg(feature = "systemd")]
+    Inherited(String),
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(feature = "systemd")]
+            ListenAddr::Inherited(addr) => write!(f, "{} (inherited)", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// One `--listen` value: `ADDR[,tls-cert=PATH,tls-key=PATH]`, where `ADDR`
+/// is `HOST:PORT` (`[::]:3000` for IPv6) or `unix:PATH`.
+#[derive(Debug, Clone)]
+pub struct ListenSpec {
+    pub addr: ListenAddr,
+    pub tls: Option<TlsFiles>,
+}
+
+impl std::str::FromStr for ListenSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let addr = parts.next().unwrap_or("");
+        let addr = match addr.strip_prefix("unix:") {
+            Some(path) if !path.is_empty() => ListenAddr::Unix(PathBuf::from(path)),
+            Some(_) => return Err(format!("--listen {:?}: missing socket path", s)),
+            None => ListenAddr::Tcp(
+                addr.parse()
+                    .map_err(|_| format!("--listen {:?}: expected HOST:PORT or unix:PATH", s))?,
+            ),
+        };
+
+        let (mut cert, mut key) = (None, None);
+        for option in parts {
+            match option.split_once('=') {
+                Some(("tls-cert", path)) => cert = Some(PathBuf::from(path)),
+                Some(("tls-key", path)) => key = Some(PathBuf::from(path)),
+                _ => return Err(format!("--listen {:?}: unknown option {:?}", s, option)),
+            }
+        }
+        let tls = match (cert, key) {
+            (Some(cert), Some(key)) => Some(TlsFiles { cert, key }),
+            (None, None) => None,
+            _ => return Err(format!("--listen {:?}: tls-cert and tls-key go together", s)),
+        };
+        Ok(ListenSpec { addr, tls })
+    }
+}
+
+impl ListenSpec {
+    /// Binds the socket and loads any certificate, so configuration problems
+    /// stop the server before it starts accepting on other listeners.
+    pub async fn bind(&self) -> DynResult<Listener> {
+        let tls = match self.tls {
+            Some(ref files) => Some(TlsAcceptor::from(Arc::new(server_config(files)?))),
+            None => None,
+        };
+        let socket = match self.addr {
+            ListenAddr::Tcp(addr) => Socket::Tcp(TcpListener::bind(addr).await?),
+            ListenAddr::Unix(ref path) => {
+                remove_stale_socket(path)?;
+                Socket::Unix(UnixListener::bind(path)?)
+            }
+            #[cfg(feature = "systemd")]
+            ListenAddr::Inherited(..) => return Err("inherited sockets can't be bound".into()),
+        };
+        Ok(Listener {
+            addr: self.addr.clone(),
+            socket,
+            tls,
+        })
+    }
+}
+
+enum Socket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub struct Listener {
+    pub addr: ListenAddr,
+    socket: Socket,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Listener {
+    /// Wraps a listening socket inherited from the service manager. The
+    /// socket must already be non-blocking.
+    #[cfg(feature = "systemd")]
+    pub fn inherited_tcp(listener: std::net::TcpListener) -> io::Result<Listener> {
+        Ok(Listener {
+            addr: ListenAddr::Inherited(listener.local_addr()?.to_string()),
+            socket: Socket::Tcp(TcpListener::from_std(listener)?),
+            tls: None,
+        })
+    }
+
+    #[cfg(feature = "systemd")]
+    pub fn inherited_unix(listener: std::os::unix::net::UnixListener) -> io::Result<Listener> {
+        let addr = listener.local_addr()?;
+        let addr = match addr.as_pathname() {
+            Some(path) => format!("unix:{}", path.display()),
+            None => "unix socket".to_string(),
+        };
+        Ok(Listener {
+            addr: ListenAddr::Inherited(addr),
+            socket: Socket::Unix(UnixListener::from_std(listener)?),
+            tls: None,
+        })
+    }
+
+    /// Accepts connections forever, serving each on its own task.
+    pub async fn serve(self, handler: Handler) {
+        loop {
+            let accepted = match self.socket {
+                Socket::Tcp(ref listener) => listener.accept().await.map(|(stream, addr)| {
+                    tokio::spawn(connection(handler.clone(), self.tls.clone(), stream, addr));
+                }),
+                Socket::Unix(ref listener) => listener.accept().await.map(|(stream, _)| {
+                    let addr = SocketAddr::from(UNIX_PEER);
+                    tokio::spawn(connection(handler.clone(), self.tls.clone(), stream, addr));
+                }),
+            };
+            if let Err(err) = accepted {
+                // usually out of file descriptors; back off rather than spin
+                event!(Level::ERROR, "accept on {}: {}", self.addr, err);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn connection<S>(handler: Handler, tls: Option<TlsAcceptor>, stream: S, remote_addr: SocketAddr)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |req: Request<Body>| {
+        let handler = handler.clone();
+        async move { Ok::<_, Infallible>(handler.respond(remote_addr, req).await) }
+    });
+    let served = match tls {
+        Some(acceptor) => match acceptor.accept(stream).await {
+            Ok(stream) => Http::new().serve_connection(stream, service).await,
+            Err(err) => {
+                event!(Level::DEBUG, "TLS handshake with {}: {}", remote_addr, err);
+                return;
+            }
+        },
+        None => Http::new().serve_connection(stream, service).await,
+    };
+    if let Err(err) = served {
+        event!(Level::DEBUG, "connection from {}: {}", remote_addr, err);
+    }
+}
+
+fn server_config(files: &TlsFiles) -> DynResult<ServerConfig> {
+    let bad = |what: &str, path: &Path| format!("no {} found in {}", what, path.display());
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(&files.cert)?))
+        .map_err(|_| bad("certificates", &files.cert))?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(&files.key)?))
+        .map_err(|_| bad("private key", &files.key))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(&files.key)?))
+            .map_err(|_| bad("private key", &files.key))?;
+    }
+    let key = keys.into_iter().next().ok_or_else(|| bad("private key", &files.key))?;
+    if certs.is_empty() {
+        return Err(bad("certificates", &files.cert).into());
+    }
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key)?;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+/// A socket file left by a previous run would make `bind` fail. Anything
+/// that isn't a socket is left alone.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+
+/// Expands save-time macros: `~~~~` becomes a signature with the editor and
+/// the time, `{{date}}` the current date and `{{now}}` the current date and
+/// time. The result is stored, so the expansions never change afterwards.
+///
+/// Fenced code blocks and inline code spans are left alone. `{{date}}` and
+/// `{{now}}` take precedence over templates of the same name.
+pub fn expand(markdown: &str, signer: &str, now: DateTime<Utc>) -> String {
+    let date = now.format("%Y-%m-%d").to_string();
+    let time = now.format("%Y-%m-%d %H:%M UTC").to_string();
+    let signature = format!("\u{2014} {} ({})", signer, time);
+
+    let mut out = String::with_capacity(markdown.len());
+    let mut in_fence = false;
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            out.push_str(line);
+            continue;
+        }
+        if in_fence {
+            out.push_str(line);
+            continue;
+        }
+
+        for (text, is_code) in code_spans(line) {
+            if is_code {
+                out.push_str(text);
+            } else {
+                let text = text.replace("{{date}}", &date).replace("{{now}}", &time);
+                out.push_str(&replace_signatures(&text, &signature));
+            }
+        }
+    }
+    out
+}
+
+/// Replaces runs of exactly four tildes; longer runs are left as they are.
+fn replace_signatures(text: &str, signature: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('~') {
+        out.push_str(&rest[..start]);
+        let run = run_length(&rest[start..], '~');
+        if run == 4 {
+            out.push_str(signature);
+        } else {
+            out.push_str(&rest[start..start + run]);
+        }
+        rest = &rest[start + run..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Splits a line into text and inline code spans, returning `(text, is_code)`
+/// pairs. A span opened by a run of backticks is closed by a run of the same
+/// length; an unclosed run is plain text.
+fn code_spans(line: &str) -> Vec<(&str, bool)> {
+    let mut out = Vec::new();
+    let mut text_start = 0;
+    let mut pos = 0;
+    while let Some(idx) = line[pos..].find('`') {
+        let open = pos + idx;
+        let run = run_length(&line[open..], '`');
+
+        let mut close = None;
+        let mut search = open + run;
+        while let Some(i) = line[search..].find('`') {
+            let at = search + i;
+            let len = run_length(&line[at..], '`');
+            if len == run {
+                close = Some(at);
+                break;
+            }
+            search = at + len;
+        }
+
+        match close {
+            Some(close) => {
+                out.push((&line[text_start..open], false));
+                out.push((&line[open..close + run], true));
+                text_start = close + run;
+                pos = text_start;
+            }
+            None => pos = open + run,
+        }
+    }
+    out.push((&line[text_start..], false));
+    out
+}
+
+fn run_length(s: &str, c: char) -> usize {
+    s.len() - s.trim_start_matches(c).len()
+}
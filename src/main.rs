@@ -1,24 +1,21 @@
-use std::convert::Infallible;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
-use std::io::Write;
 
-use similar::{ChangeTag, TextDiff};
 use askama::Template;
-use chrono::{SubsecRound, DateTime, Utc};
-use clap::{App, Arg};
-use comrak::plugins::syntect::SyntectAdapter;
+use chrono::{SubsecRound, Utc};
+use clap::{App, Arg, SubCommand};
+use futures::FutureExt;
 use comrak::{
     format_html_with_plugins, parse_document, Arena, ComrakOptions, ComrakPlugins,
     ComrakRenderPlugins,
 };
-use hyper::server::conn::AddrStream;
-use hyper::service::{make_service_fn, service_fn};
 use hyper::Method;
 use hyper::{header, Body, Response};
-use hyper::{Request, Server, StatusCode};
+use hyper::{Request, StatusCode, Uri};
+use serde::Deserialize;
 use tokio::sync::RwLock;
-use tokio_postgres::NoTls;
 use tracing::{event, Level};
 use tracing_subscriber::filter::LevelFilter as TracingLevelFilter;
 use tracing_subscriber::FmtSubscriber;
@@ -26,28 +23,131 @@ use tracing_subscriber::FmtSubscriber;
 const CARGO_PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 const CARGO_PKG_NAME: &str = env!("CARGO_PKG_NAME");
 
+/// Hand-maintained; update it alongside any change to the `/api/v1` routes.
+const OPENAPI_DOCUMENT: &str = include_str!("openapi.json");
+
+/// What a redacted revision's text is replaced with.
+const REDACTED_TOMBSTONE: &str = "This revision was redacted by an administrator.";
+
 type DynResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+mod accounts;
+mod append;
+mod appearance;
+mod asciidoc;
+mod assets;
+mod attachments;
+mod auth;
+mod backup;
+mod bots;
+mod bundle;
+mod challenge;
+mod check;
+mod conditional;
+mod config;
+mod cors;
+mod data;
+mod database;
+mod diff;
+mod digest;
+mod duplicates;
+mod edit_filter;
+mod edit_notices;
+mod export;
+mod follow;
+mod footer;
+mod front_matter;
+mod git_bundle;
+mod graphql;
+mod highlight;
+mod languages;
+mod link_graph;
+mod links;
+mod lint;
+mod listen;
+mod macros;
+mod markup;
+mod metrics;
+mod names;
+mod negotiate;
+mod permissions;
+mod plugins;
+mod preferences;
+mod previews;
+mod protection;
+mod proxy;
+mod queries;
+mod query_params;
+mod render_cache;
+mod rename_links;
+mod replace;
+mod resize;
+mod revision_graph;
 mod routes;
+mod schema;
+mod search;
+mod secrets;
+mod sidebar;
+mod site_token;
+mod snippets;
+mod special;
+mod stats;
+mod summary;
+mod sync;
+#[cfg(feature = "systemd")]
+mod systemd;
+mod timeouts;
+mod titles;
+mod transclusion;
+mod trust;
+mod two_factor;
 pub mod views;
 
+use self::accounts::Accounts;
+use self::auth::CurrentUser;
+use self::challenge::{Challenger, IssuedChallenge};
+use self::config::Config;
+use self::languages::Languages;
+use self::markup::Markup;
+use self::permissions::{Action, NewPageRequest};
+use self::plugins::{PluginError, Plugins, SaveContext};
+use self::preferences::{Editor, Preferences};
+use self::proxy::ClientInfo;
+use self::queries::{PageBundle, Queries};
+use self::query_params::{QueryError, QueryParams};
 use self::routes::*;
+use self::search::SearchQuery;
+use self::timeouts::RequestClass;
+use self::views::error::ErrorKind;
+use self::trust::TrustLevel;
 
 struct Renderer;
 
 impl Renderer {
-    fn render(&self, markdown: &str) -> DynResult<String> {
-        let arena = Arena::new();
-
+    fn options(&self) -> ComrakOptions {
         let mut options = ComrakOptions::default();
         options.extension.strikethrough = true;
         options.extension.footnotes = true;
+        // data blocks and query results render as tables
+        options.extension.table = true;
+        options.extension.autolink = true;
+        options.extension.tasklist = true;
+        options.extension.front_matter_delimiter = Some(front_matter::DELIMITER.to_string());
+        options
+    }
+}
+
+impl Markup for Renderer {
+    fn render(&self, markdown: &str) -> DynResult<String> {
+        let arena = Arena::new();
+        let options = self.options();
 
         let root = parse_document(&arena, markdown, &options);
-        let adapter = SyntectAdapter::new("base16-ocean.light");
+        highlight::join_fence_options(root);
+        resize::apply_sizes(root);
         let plugins = ComrakPlugins {
             render: ComrakRenderPlugins {
-                codefence_syntax_highlighter: Some(&adapter),
+                codefence_syntax_highlighter: Some(highlight::highlighter()),
             },
         };
 
@@ -56,18 +156,111 @@ impl Renderer {
         format_html_with_plugins(root, &options, &mut html, &plugins)?;
         Ok(String::from_utf8(html)?)
     }
+
+    fn link_targets(&self, markdown: &str) -> Vec<String> {
+        links::internal_link_targets(markdown, &self.options())
+    }
+
+    fn expands_markdown(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Clone)]
 struct Handler {
+    config: Arc<Config>,
+    challenger: Option<Arc<Challenger>>,
+    /// `None` without a mailer, see `accounts.rs`.
+    accounts: Option<Arc<Accounts>>,
+    pending_logins: Arc<two_factor::PendingLogins>,
+    plugins: Arc<Plugins>,
     inner: Arc<RwLock<HandlerInner>>,
+    sidebar: Arc<sidebar::SitePage>,
+    footer: Arc<footer::Footer>,
+    appearance: Arc<appearance::SiteAppearance>,
+    protection: Arc<protection::SiteProtection>,
+    render_cache: Arc<render_cache::RenderCache>,
+    database: Arc<database::Database>,
+    /// `None` without `--graphql`.
+    graphql: Option<Arc<graphql::WikiSchema>>,
+    duplicates: Arc<duplicates::Cache>,
 }
 
 struct HandlerInner {
     db: tokio_postgres::Client,
+    queries: Queries,
 }
 
 impl Handler {
+    /// A handler serving with `config`, with the site settings kept in the
+    /// database loaded and the connection supervised.
+    async fn start(config: Config, database: Arc<database::Database>, inner: HandlerInner) -> DynResult<Handler> {
+        let plugins = Plugins::compiled_in();
+        event!(Level::INFO, "plugins: {:?}", plugins.names());
+
+        let challenger = match config.anonymous_challenge {
+            Some(kind) => Some(Arc::new(Challenger::new(kind)?)),
+            None => None,
+        };
+
+        let accounts = match config.digest {
+            Some(ref mailer) => Some(Arc::new(
+                Accounts::load(&inner, mailer.clone(), config.site_name.clone()).await?,
+            )),
+            None => None,
+        };
+        let pending_logins = Arc::new(two_factor::PendingLogins::load(&inner).await?);
+
+        let render_stale = config.render_stale;
+        let graphql = if config.graphql { Some(Arc::new(graphql::schema())) } else { None };
+        let handler = Handler {
+            config: Arc::new(config),
+            challenger,
+            accounts,
+            pending_logins,
+            plugins: Arc::new(plugins),
+            inner: Arc::new(RwLock::new(inner)),
+            sidebar: Arc::new(sidebar::SitePage::new(sidebar::PAGE)),
+            footer: Arc::new(footer::Footer::default()),
+            appearance: Arc::new(appearance::SiteAppearance::default()),
+            protection: Arc::new(protection::SiteProtection::default()),
+            render_cache: Arc::new(render_cache::RenderCache::new(render_stale)),
+            database,
+            graphql,
+            duplicates: Arc::new(duplicates::Cache::default()),
+        };
+        tokio::spawn(handler.database.clone().supervise(handler.inner.clone()));
+        handler.appearance.load(&*handler.inner.read().await).await?;
+        handler.protection.load(&*handler.inner.read().await).await?;
+
+        Ok(handler)
+    }
+
+    fn page_context(&self, req: &Request<Body>) -> views::PageContext {
+        let flash = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+            .find(|(key, _)| key == "flash")
+            .and_then(|(_, value)| views::flash_message(&value));
+        let user = CurrentUser::of(req);
+
+        views::PageContext {
+            site_name: self.config.site_name.clone(),
+            base_url: ClientInfo::of(req).map(|c| c.base_url()).unwrap_or_default(),
+            current_user: user.map(|u| u.display_name().to_string()),
+            theme: user
+                .and_then(|u| u.preferences.theme.clone())
+                .unwrap_or_else(|| self.config.theme.clone()),
+            utc_offset: user.map(|u| u.preferences.utc_offset).unwrap_or(0),
+            flash,
+            moderation: self.config.moderation,
+            sidebar: self.sidebar.html(),
+            footer: self.footer.html(),
+            legal_pages: self.footer.written(),
+            appearance: self.appearance.get(),
+            is_admin: user.map(|u| u.is_admin).unwrap_or(false),
+            can_review: CurrentUser::has_trust(req, TrustLevel::Trusted),
+        }
+    }
+
     async fn serve_wiki_page(
         &self,
         req: Request<Body>,
@@ -79,6 +272,9 @@ impl Handler {
         if req.method() == Method::PUT {
             return self.serve_wiki_page_put(req, rw).await;
         }
+        if req.method() == Method::POST {
+            return self.serve_wiki_page_post(req, rw).await;
+        }
 
         let response = Response::builder()
             .header("Content-Type", "text/html; charset=utf8")
@@ -90,7 +286,7 @@ impl Handler {
 
     async fn serve_wiki_page_history_get(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         rw: &RouteWiki<'_>,
     ) -> DynResult<Response<Body>> {
         if let RouteWikiSubview::History = rw.subview {
@@ -99,38 +295,88 @@ impl Handler {
             return Err(RouteError::NotFound.into());
         }
 
+        // the compare form picks two revisions, older or newer first
+        let QueryParams(compare) = QueryParams::<CompareParams>::from_request(&req)?;
+        if let (Some(from), Some(to)) = (compare.from, compare.to) {
+            let location = if from == to {
+                RouteWiki::to_revision(&rw.name, from)
+            } else {
+                RouteWiki::to_diff(&rw.name, from.min(to), from.max(to))
+            };
+            let res = Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, location.to_string())
+                .body(Body::empty())
+                .expect("unable to build response");
+            return Ok(res);
+        }
+
         let locked = self.inner.read().await;
-        let rows = locked
-            .db
-            .query(
-                r#"
-                    SELECT created_at, document_history.id, modified_by FROM document_history
-                    INNER JOIN document ON document.id = document_history.document_id
-                    WHERE document.name = $1
-                    LIMIT 50
-                "#,
-                &[&rw.name],
-            )
+        let history = locked
+            .queries
+            .fetch_history(&locked.db, &rw.name, self.may_review(&req))
             .await?;
-
-        if rows.len() == 0 {
+        if history.is_empty() {
             return Err(RouteError::NotFound.into());
         }
 
-        let mut history_records = Vec::new();
-        for row in rows {
-            let document_history_id: i64 = row.try_get(1)?;
-            let created_at: DateTime<Utc> = row.try_get(0)?;
-            history_records.push(views::wiki::HistoryRecord {
-                created_at: created_at.trunc_subsecs(0),
-                document_history_id,
-                created_by: row.try_get(2)?,
-                link: RouteWiki::to_revision(&rw.name, document_history_id).to_owned(),
-            });
-        }
+        let tags = locked.queries.fetch_tags(&locked.db, &rw.name).await?;
+        let filter_tags = locked.queries.fetch_edit_filter_tags(&locked.db, &rw.name).await?;
+        let history_records = history
+            .into_iter()
+            .map(|entry| views::wiki::HistoryRecord {
+                tags: tags
+                    .iter()
+                    .filter(|tag| tag.revision_id == entry.id)
+                    .map(|tag| views::wiki::TagLink {
+                        label: tag.label.clone(),
+                        link: RouteTag::to(&rw.name, &tag.label).to_owned(),
+                        created_by: tag.created_by.clone(),
+                        created_at: tag.created_at.trunc_subsecs(0),
+                    })
+                    .collect(),
+                filter_tags: filter_tags
+                    .iter()
+                    .filter(|(revision_id, _)| *revision_id == entry.id)
+                    .map(|(_, name)| name.clone())
+                    .collect(),
+                tag_link: RouteWiki::to_tag_revision(&rw.name, entry.id).to_owned(),
+                created_at: entry.created_at.trunc_subsecs(0),
+                document_history_id: entry.id,
+                created_by: entry.modified_by,
+                size: entry.size,
+                size_delta: entry.size_delta,
+                link: RouteWiki::to_revision(&rw.name, entry.id).to_owned(),
+                status: entry.status,
+                reviewed_by: entry.reviewed_by,
+                redaction: entry.redaction,
+                summary: entry.summary,
+                redact_link: RouteWiki::to_redact_revision(&rw.name, entry.id).to_owned(),
+            })
+            .collect();
+        let moves = locked
+            .queries
+            .fetch_moves(&locked.db, &rw.name)
+            .await?
+            .into_iter()
+            .map(move_record)
+            .collect();
+
         let hist = views::wiki::History {
+            ctx: self.page_context(&req),
             page_title: &rw.name,
             history_records,
+            moves,
+            can_edit: permissions::is_allowed(
+                self.config.site_policy,
+                CurrentUser::of(&req),
+                Action::Edit,
+            ) && self.may_edit(Some(&rw.name), CurrentUser::of(&req)),
+            can_admin: permissions::is_allowed(
+                self.config.site_policy,
+                CurrentUser::of(&req),
+                Action::Admin,
+            ),
         };
 
         let response = Response::builder()
@@ -141,101 +387,119 @@ impl Handler {
         Ok(response)
     }
 
-    async fn serve_wiki_page_diff_get(
+    async fn serve_wiki_page_graph_get(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         rw: &RouteWiki<'_>,
     ) -> DynResult<Response<Body>> {
-        let first;
-        let second;
-        if let RouteWikiSubview::Diff(first_tmp, second_tmp) = rw.subview {
-            first = first_tmp;
-            second = second_tmp;
-        } else {
+        let locked = self.inner.read().await;
+        let revisions = locked
+            .queries
+            .fetch_revision_graph(&locked.db, &rw.name, revision_graph::LIMIT, self.may_review(&req))
+            .await?;
+        if revisions.is_empty() {
             return Err(RouteError::NotFound.into());
         }
 
+        let graph = views::wiki::Graph {
+            ctx: self.page_context(&req),
+            page_title: &rw.name,
+            truncated: revisions.len() as i64 == revision_graph::LIMIT,
+            layout: revision_graph::layout(&revisions),
+        };
+
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(graph.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn serve_wiki_page_diff_get(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let (first, second) = match rw.subview {
+            RouteWikiSubview::Diff(first, second) => (first, second),
+            _ => return Err(RouteError::NotFound.into()),
+        };
+        if first == second {
+            let response = Response::builder()
+                .header("Content-Type", "text/plain; charset=utf8")
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!(
+                    "Both sides of the diff are revision {}; compare two different revisions.",
+                    first
+                )))?;
+            return Ok(response);
+        }
+        // older revision first, so additions always show as additions
+        if first > second {
+            let response = Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, RouteWiki::to_diff(&rw.name, second, first).to_string())
+                .body(Body::empty())?;
+            return Ok(response);
+        }
+
         let locked = self.inner.read().await;
-        let first_row = locked
-            .db
-            .query_opt(
-                r#"
-                    SELECT created_at, document_history.id, modified_by, document_data FROM document_history
-                    INNER JOIN document ON document.id = document_history.document_id
-                    WHERE document.name = $1 AND document_history.id = $2
-                    LIMIT 50
-                "#,
-                &[&rw.name, &first],
-            )
+        let first = locked
+            .queries
+            .fetch_revision(&locked.db, &rw.name, first, self.may_review(&req))
             .await?
-            .ok_or_else(|| RouteError::NotFound)?;
-
-        let document_history_id = first_row.try_get(1)?;
-        let created_at: DateTime<Utc> = first_row.try_get(0)?;
-        let first_document: String = first_row.try_get(3)?;
-        let first_spec = views::wiki::RevisionSpec {
-            document_history_id,
-            created_at: created_at.trunc_subsecs(0),
-            created_by: first_row.try_get(2)?,
-            history_link: RouteWiki::to_revision(&rw.name, document_history_id).to_owned(),
-        };
-
-        drop(first_row);
-
-        let second_row = locked
-            .db
-            .query_opt(
-                r#"
-                    SELECT created_at, document_history.id, modified_by, document_data FROM document_history
-                    INNER JOIN document ON document.id = document_history.document_id
-                    WHERE document.name = $1 AND document_history.id = $2
-                    LIMIT 50
-                "#,
-                &[&rw.name, &second],
-            )
+            .ok_or(RouteError::NotFound)?;
+        let second = locked
+            .queries
+            .fetch_revision(&locked.db, &rw.name, second, self.may_review(&req))
             .await?
-            .ok_or_else(|| RouteError::NotFound)?;
-
-        let document_history_id = second_row.try_get(1)?;
-        let created_at: DateTime<Utc> = second_row.try_get(0)?;
-        let second_document: String = second_row.try_get(3)?;
-        let second_spec = views::wiki::RevisionSpec {
-            document_history_id,
-            created_at: created_at.trunc_subsecs(0),
-            created_by: second_row.try_get(2)?,
-            history_link: RouteWiki::to_revision(&rw.name, document_history_id).to_owned(),
-        };
-
-        drop(second_row);
-
-        // #[derive(Template)]
-        // #[template(path = "wiki/diff.html")]
-        // struct Diff {
-        //     pub page_title: &'a str,
-        //     pub first: RevisionSpec,
-        //     pub second: RevisionSpec,
-        //     pub rendered: String,
-        // }
-
-        let mut diffed_data = Vec::new();
-        writeln!(&mut diffed_data, "````diff").unwrap();
-        let diff = TextDiff::from_lines(&first_document, &second_document);
-        for change in diff.iter_all_changes() {
-            let sign = match change.tag() {
-                ChangeTag::Delete => "-",
-                ChangeTag::Insert => "+",
-                ChangeTag::Equal => " ",
-            };
-            write!(&mut diffed_data, "{}{}", sign, change).unwrap();
+            .ok_or(RouteError::NotFound)?;
+
+        let revision_spec = |revision: &queries::Revision| views::wiki::RevisionSpec {
+            document_history_id: revision.id,
+            created_at: revision.created_at.trunc_subsecs(0),
+            created_by: revision.modified_by.clone(),
+            history_link: RouteWiki::to_revision(&rw.name, revision.id).to_owned(),
+        };
+        let first_spec = revision_spec(&first);
+        let second_spec = revision_spec(&second);
+        let first_document = first.document_data;
+        let second_document = second.document_data;
+
+        let QueryParams(params) = QueryParams::<DiffParams>::from_request(&req)?;
+
+        // a collapsed stretch of unchanged lines being opened, see diff.rs
+        if let Some(ref lines) = params.lines {
+            let (start, end) = lines
+                .split_once('-')
+                .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)))
+                .filter(|(start, end)| start <= end)
+                .ok_or(RouteError::NotFound)?;
+            let offset = params.offset.unwrap_or(0);
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::OK)
+                .body(Body::from(diff::render_lines(&second_document, start..end, offset)))?;
+            return Ok(response);
         }
-        writeln!(&mut diffed_data, "````").unwrap();
 
-        let diffed_data = String::from_utf8_lossy(&diffed_data);
+        let page = params.page.unwrap_or(1);
+        let diff_link = RouteWiki::to_diff(&rw.name, first_spec.document_history_id, second_spec.document_history_id)
+            .to_string();
+        let rendered = diff::render(&first_document, &second_document, page, Some(&diff_link));
+        let page_link = |page: usize| format!("{}?page={}", diff_link, page);
+
         let diff = views::wiki::Diff {
+            ctx: self.page_context(&req),
             page_title: &rw.name,
             first: first_spec,
             second: second_spec,
-            rendered: Renderer.render(&diffed_data)?,
+            previous_link: (rendered.page > 1).then(|| page_link(rendered.page - 1)),
+            next_link: (rendered.page < rendered.pages).then(|| page_link(rendered.page + 1)),
+            page: rendered.page,
+            pages: rendered.pages,
+            rendered: rendered.html,
         };
 
         let response = Response::builder()
@@ -255,45 +519,103 @@ impl Handler {
         if let RouteWikiSubview::History = rw.subview {
             return self.serve_wiki_page_history_get(req, rw).await;
         }
+        if let RouteWikiSubview::Graph = rw.subview {
+            return self.serve_wiki_page_graph_get(req, rw).await;
+        }
         if let RouteWikiSubview::Diff(..) = rw.subview {
             return self.serve_wiki_page_diff_get(req, rw).await;
         }
+        if let RouteWikiSubview::Annotations(..)
+        | RouteWikiSubview::TagRevision(..)
+        | RouteWikiSubview::RedactRevision(..) = rw.subview
+        {
+            return Err(RouteError::NotFound.into());
+        }
+        if let RouteWikiSubview::Move = rw.subview {
+            return self.serve_wiki_page_move_get(req, rw).await;
+        }
+        if let RouteWikiSubview::ExportBundle = rw.subview {
+            return self.serve_wiki_page_export_bundle_get(req, rw).await;
+        }
+        if let RouteWikiSubview::Merge = rw.subview {
+            return self.serve_wiki_page_merge_get(req, rw).await;
+        }
+        if let RouteWikiSubview::ExportGit = rw.subview {
+            return self.serve_wiki_page_export_git_get(rw).await;
+        }
 
         let locked = self.inner.read().await;
 
-        let row = match rw.subview {
-            RouteWikiSubview::History => unreachable!(),
-            RouteWikiSubview::Revision(r) => locked
-                    .db
-                    .query_opt(
-                        r#"
-                            SELECT
-                                document_data,
-                                document_history.created_at,
-                                document_history.modified_by
-                            FROM document_history
-                            INNER JOIN document ON document.id = document_history.document_id
-                            WHERE document.name = $1 AND document_history.id = $2
-                        "#,
-                        &[&rw.name, &r],
-                    ).await?,
-            _ => locked
-                    .db
-                    .query_opt(
-                        r#"
-                            SELECT
-                                document_data,
-                                document_history.created_at,
-                                document_history.modified_by
-                            FROM document_history
-                            INNER JOIN document ON document.current_revision_id = document_history.id
-                            WHERE document.name = $1
-                        "#,
-                        &[&rw.name],
-                    ).await?
-        }.ok_or_else(|| RouteError::NotFound)?;
-
-        let document_data: String = row.try_get(0)?;
+        let revision_id = match rw.subview {
+            RouteWikiSubview::Revision(r) => Some(r),
+            _ => None,
+        };
+        let bundle = locked
+            .queries
+            .fetch_page_bundle(&locked.db, &rw.name, revision_id, self.may_review(&req))
+            .await?;
+        let PageBundle {
+            revision,
+            tags,
+            backlink_count,
+        } = match bundle {
+            Some(bundle) => bundle,
+            None => {
+                // pages merged into another point readers at it
+                if let RouteWikiSubview::View = rw.subview {
+                    if let Some(target) = locked.queries.fetch_redirect(&locked.db, &rw.name).await? {
+                        let res = Response::builder()
+                            .status(StatusCode::FOUND)
+                            .header(
+                                header::LOCATION,
+                                format!("{}?flash=redirected", RouteWiki::to(&target)),
+                            )
+                            .body(Body::empty())
+                            .expect("unable to build response");
+                        return Ok(res);
+                    }
+                    // untranslated variants show the default language
+                    if let (base, Some(_)) = self.config.languages.split(&rw.name) {
+                        let base = vec![base.to_string()];
+                        if !locked.queries.fetch_existing_names(&locked.db, &base).await?.is_empty() {
+                            let res = Response::builder()
+                                .status(StatusCode::FOUND)
+                                .header(
+                                    header::LOCATION,
+                                    format!("{}?flash=untranslated", RouteWiki::to(&base[0])),
+                                )
+                                .body(Body::empty())
+                                .expect("unable to build response");
+                            return Ok(res);
+                        }
+                    }
+                    return self.missing_page(&req, &locked, &rw.name).await;
+                }
+                // wanted pages link here to start them
+                if let RouteWikiSubview::Edit = rw.subview {
+                    return self.serve_editor(&req, &locked, rw, String::new()).await;
+                }
+                // permalinks keep working after the page is moved
+                if let RouteWikiSubview::Revision(revision_id) = rw.subview {
+                    if let Some(name) = locked.queries.fetch_revision_page(&locked.db, revision_id).await? {
+                        if name != rw.name {
+                            let res = Response::builder()
+                                .status(StatusCode::FOUND)
+                                .header(
+                                    header::LOCATION,
+                                    RouteWiki::to_revision(&name, revision_id).to_string(),
+                                )
+                                .body(Body::empty())
+                                .expect("unable to build response");
+                            return Ok(res);
+                        }
+                    }
+                }
+                return Err(RouteError::NotFound.into());
+            }
+        };
+
+        let document_data = revision.document_data;
         match rw.subview {
             // #[derive(Template)]
             // #[template(path = "wiki/view.html")]
@@ -306,215 +628,5521 @@ impl Handler {
             //     pub rendered: String,
             // }
             RouteWikiSubview::View | RouteWikiSubview::Revision(..) => {
-                let last_modified_at: DateTime<Utc> = row.try_get(1)?;
-                let rendered = Renderer.render(&document_data)?;
+                // curl and external editors can ask for the source rather
+                // than the page; browsers always prefer HTML
+                let accept = req.headers().get(header::ACCEPT).and_then(|v| v.to_str().ok());
+                if negotiate::preferred(accept, &["text/html", "text/markdown"]) == Some("text/markdown") {
+                    let response = Response::builder()
+                        .header("Content-Type", "text/markdown; charset=utf8")
+                        .header(header::VARY, "Accept")
+                        .status(StatusCode::OK)
+                        .body(Body::from(document_data))?;
+                    return Ok(response);
+                }
+
+                let document_history_id = revision.id;
+                let old_revision = if revision.is_current {
+                    None
+                } else {
+                    Some(document_history_id)
+                };
+                let rendered = if revision.is_current {
+                    let (rendered, refresh) = self
+                        .render_cache
+                        .render_or_stale(&locked, &self.plugins, &rw.name, document_history_id, &document_data)
+                        .await?;
+                    if refresh {
+                        self.refresh_render_cache(&rw.name);
+                    }
+                    rendered
+                } else {
+                    render_document(&locked, &self.plugins, &document_data).await?
+                };
+                let snippets = snippets::for_page(&locked, &self.plugins, &rw.name).await?;
+                let attachments = attachment_records(&locked, &rw.name).await?;
+                let (language, variants) = language_variants(&locked, &self.config.languages, &rw.name).await?;
+                if let (Some(user), None) = (CurrentUser::of(&req), old_revision) {
+                    locked
+                        .queries
+                        .mark_read(&locked.db, &user.username, &rw.name, document_history_id)
+                        .await?;
+                }
+
+                let annotations = locked
+                    .queries
+                    .fetch_annotations(&locked.db, document_history_id)
+                    .await?
+                    .into_iter()
+                    .map(|a| views::wiki::Annotation {
+                        created_at: a.created_at.trunc_subsecs(0),
+                        created_by: a.created_by,
+                        start_offset: a.start_offset,
+                        end_offset: a.end_offset,
+                        quote: a.quote,
+                        body: a.body,
+                    })
+                    .collect();
+
+                let ctx = self.page_context(&req);
+                // link previews need absolute image URLs
+                let image = summary::first_image(&rendered).and_then(|src| summary::absolute_url(src, &ctx.base_url));
                 let view = views::wiki::View {
+                    ctx,
                     page_title: &rw.name,
-                    last_modified_at: last_modified_at.trunc_subsecs(0),
-                    last_modified_by: row.try_get(2)?,
+                    description: summary::description(&rendered),
+                    image,
+                    last_modified_at: revision.created_at.trunc_subsecs(0),
+                    last_modified_by: revision.modified_by,
                     history_link: RouteWiki::to_history(&rw.name).to_owned(),
                     edit_link: RouteWiki::to_edit(&rw.name).to_owned(),
-                    rendered: rendered,
+                    move_link: RouteWiki::to_move(&rw.name).to_owned(),
+                    merge_link: RouteWiki::to_merge(&rw.name).to_owned(),
+                    export_link: RouteWiki::to_export_bundle(&rw.name).to_owned(),
+                    link_graph_link: link_graph::link(Route::LinkGraph, &rw.name, link_graph::DEFAULT_DEPTH),
+                    attachments_link: RouteAttachment::to_list(&rw.name).to_owned(),
+                    canonical_link: RouteWiki::to(&rw.name).to_owned(),
+                    permalink: RouteWiki::to_revision(&rw.name, document_history_id).to_owned(),
+                    old_revision,
+                    expired_on: front_matter::parse(&document_data)
+                        .expires
+                        .filter(|&date| date <= Utc::now().date_naive()),
+                    annotate_link: RouteWiki::to_annotations(&rw.name, document_history_id)
+                        .to_owned(),
+                    annotate_challenge: self.issue_challenge(&req)?,
+                    can_edit: permissions::is_allowed(
+                        self.config.site_policy,
+                        CurrentUser::of(&req),
+                        Action::Edit,
+                    ) && self.may_edit(Some(&rw.name), CurrentUser::of(&req)),
+                    can_annotate: permissions::is_allowed(
+                        self.config.site_policy,
+                        CurrentUser::of(&req),
+                        Action::Annotate,
+                    ),
+                    can_admin: permissions::is_allowed(
+                        self.config.site_policy,
+                        CurrentUser::of(&req),
+                        Action::Admin,
+                    ),
+                    annotations,
+                    tags,
+                    backlink_count,
+                    header: snippets.header,
+                    footer: snippets.footer,
+                    rendered,
+                    attachments,
+                    language,
+                    variants,
                 };
 
                 let response = Response::builder()
                     .header("Content-Type", "text/html; charset=utf8")
+                    .header(header::VARY, "Accept")
                     .status(StatusCode::OK)
                     .body(Body::from(view.render()?))?;
 
                 Ok(response)
             }
-            RouteWikiSubview::Edit => {
-                let xx = format!("<textarea>{}</textarea>", document_data);
-                let response = Response::builder()
-                    .header("Content-Type", "text/html; charset=utf8")
-                    .status(StatusCode::OK)
-                    .body(Body::from(xx))?;
+            RouteWikiSubview::Edit => self.serve_editor(&req, &locked, rw, document_data).await,
+            RouteWikiSubview::History
+            | RouteWikiSubview::Graph
+            | RouteWikiSubview::Diff(..)
+            | RouteWikiSubview::Annotations(..)
+            | RouteWikiSubview::TagRevision(..)
+            | RouteWikiSubview::RedactRevision(..)
+            | RouteWikiSubview::Move
+            | RouteWikiSubview::Merge
+            | RouteWikiSubview::ExportBundle
+            | RouteWikiSubview::ExportGit
+            | RouteWikiSubview::PreviewDiff => unreachable!(),
+        }
+    }
 
-                Ok(response)
+    /// The editor for `rw`, starting from `document_data`: the current text,
+    /// or nothing for a page that doesn't exist yet.
+    async fn serve_editor(
+        &self,
+        req: &Request<Body>,
+        locked: &HandlerInner,
+        rw: &RouteWiki<'_>,
+        mut document_data: String,
+    ) -> DynResult<Response<Body>> {
+        let challenge = self.issue_challenge(req)?;
+        // `?merge=<page>` comes from the duplicates report: the other
+        // page's text is appended so it can be tidied up and saved
+        let QueryParams(params) = QueryParams::<EditParams>::from_request(req)?;
+        if let Some(other) = params.merge {
+            if let Some(merged) =
+                locked.queries.fetch_current_revision(&locked.db, &other).await?
+            {
+                document_data = merged_text(&document_data, &other, &merged.document_data);
             }
-            RouteWikiSubview::History | RouteWikiSubview::Diff(..) => unreachable!(),
         }
+        let notices = edit_notices::for_page(locked, &self.plugins, &rw.name).await?;
+        let edit = views::wiki::Edit {
+            ctx: self.page_context(req),
+            page_title: &rw.name,
+            document_data,
+            notices,
+            view_link: RouteWiki::to(&rw.name).to_owned(),
+            preview_diff_link: RouteWiki::to_preview_diff(&rw.name).to_owned(),
+            challenge,
+            toolbar: match CurrentUser::of(req) {
+                Some(user) => user.preferences.editor == Editor::Toolbar,
+                None => auth::cookie(req, views::wiki::EDITOR_COOKIE) == Some("toolbar"),
+            },
+            logged_in: CurrentUser::of(req).is_some(),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(edit.render()?))?;
+
+        Ok(response)
     }
 
-    async fn serve_wiki_page_put(
+    async fn serve_wiki_page_post(
         &self,
         req: Request<Body>,
         rw: &RouteWiki<'_>,
-        // document_data: &str,
     ) -> DynResult<Response<Body>> {
-        let user_id = "Anonymous";
+        if let RouteWikiSubview::Annotations(..) = rw.subview {
+            return self.serve_wiki_page_annotations_post(req, rw).await;
+        }
+        if let RouteWikiSubview::Move = rw.subview {
+            return self.serve_wiki_page_move_post(req, rw).await;
+        }
+        if let RouteWikiSubview::TagRevision(..) = rw.subview {
+            return self.serve_wiki_page_tag_post(req, rw).await;
+        }
+        if let RouteWikiSubview::RedactRevision(..) = rw.subview {
+            return self.serve_wiki_page_redact_post(req, rw).await;
+        }
+        if let RouteWikiSubview::Merge = rw.subview {
+            return self.serve_wiki_page_merge_post(req, rw).await;
+        }
+        if let RouteWikiSubview::PreviewDiff = rw.subview {
+            return self.serve_wiki_page_preview_diff_post(req, rw).await;
+        }
 
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("Method Not Allowed"))?;
+
+        Ok(response)
+    }
+
+    /// Renders the changes a draft posted from the editor would make to the
+    /// current revision, as an HTML fragment for the editor to show.
+    async fn serve_wiki_page_preview_diff_post(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
         let body_bytes = hyper::body::to_bytes(req).await?;
-        let document_data = String::from_utf8_lossy(&body_bytes);
+        let draft = String::from_utf8(body_bytes.to_vec())?;
 
-        let mut locked = self.inner.write().await;
-        let tx = locked.db.transaction().await?;
-
-        let now = chrono::offset::Utc::now();
-        let row = tx
-            .query_opt(
-                r#"
-                    INSERT INTO document
-                    (name, last_modified) VALUES ($1, $2)
-                    ON CONFLICT (name) DO UPDATE SET last_modified = EXCLUDED.last_modified
-                    RETURNING id
-                "#,
-                &[&rw.name, &now],
-            )
-            .await?
-            .ok_or_else(|| RouteError::NotFound)?;
-
-        let document_id: i64 = row.try_get(0).ok().ok_or_else(|| RouteError::NotFound)?;
-
-        let row = tx
-            .query_one(
-                r#"
-                    INSERT INTO document_history (created_at, document_id, modified_by, document_data)
-                    VALUES (NOW(), $1, $2, $3)
-                    RETURNING id
-                "#,
-                &[&document_id, &user_id, &document_data],
+        let current = {
+            let locked = self.inner.read().await;
+            locked.queries.fetch_current_revision(&locked.db, &rw.name).await?
+        };
+        let current = current.map(|revision| revision.document_data).unwrap_or_default();
+
+        let rendered = diff::render(&current, &draft, 1, None);
+        let rendered = if rendered.pages > 1 {
+            format!(
+                "{}<p>Only the first {} changed lines are shown.</p>",
+                rendered.html,
+                diff::PAGE_LINES
             )
-            .await?;
+        } else {
+            rendered.html
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(rendered))?;
 
-        let document_history_id: i64 = row.try_get(0).ok().ok_or_else(|| RouteError::NotFound)?;
+        Ok(response)
+    }
 
-        tx.execute(
-            r#"
-                INSERT INTO document
-                (name, last_modified, current_revision_id) VALUES ($1, $2, $3)
-                ON CONFLICT (name) DO UPDATE SET
-                    current_revision_id = EXCLUDED.current_revision_id,
-                    last_modified = EXCLUDED.last_modified
-                RETURNING id
-            "#,
-            &[&rw.name, &now, &document_history_id],
-        )
-        .await?;
+    async fn serve_wiki_page_annotations_post(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let document_history_id;
+        if let RouteWikiSubview::Annotations(r) = rw.subview {
+            document_history_id = r;
+        } else {
+            return Err(RouteError::NotFound.into());
+        }
 
-        tx.commit().await?;
+        let user_id = CurrentUser::attribution(&req);
 
-        let res = Response::builder()
-            .status(StatusCode::FOUND)
-            .header(header::LOCATION, RouteWiki::to(&rw.name).to_string())
-            .body(Body::empty())
-            .expect("unable to build response");
-        Ok(res)
-    }
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut start_offset: Option<i32> = None;
+        let mut end_offset: Option<i32> = None;
+        let mut quote = String::new();
+        let mut body = String::new();
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "start" => start_offset = value.parse().ok(),
+                "end" => end_offset = value.parse().ok(),
+                "quote" => quote = value.into_owned(),
+                "body" => body = value.into_owned(),
+                _ => (),
+            }
+        }
+
+        let (start_offset, end_offset) = match (start_offset, end_offset) {
+            (Some(s), Some(e)) if 0 <= s && s < e && !body.trim().is_empty() => (s, e),
+            _ => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Bad Request"))?;
+                return Ok(response);
+            }
+        };
+
+        let annotation = queries::NewAnnotation {
+            created_by: &user_id,
+            start_offset,
+            end_offset,
+            quote: &quote,
+            body: &body,
+        };
+        let locked = self.inner.read().await;
+        let inserted = locked
+            .queries
+            .insert_annotation(&locked.db, &rw.name, document_history_id, &annotation)
+            .await?;
+
+        if !inserted {
+            return Err(RouteError::NotFound.into());
+        }
 
-    async fn login_page(&self, _req: Request<Body>) -> DynResult<Response<Body>> {
         let res = Response::builder()
             .status(StatusCode::FOUND)
-            .header(header::LOCATION, RouteWiki::to("sample_doc").to_string())
+            .header(
+                header::LOCATION,
+                RouteWiki::to_revision(&rw.name, document_history_id).to_string(),
+            )
             .body(Body::empty())
             .expect("unable to build response");
         Ok(res)
     }
 
-    async fn handle(
+    /// A `.tar.gz` of the page, every page it links to up to `?depth=` links
+    /// away, their attachments, and a `manifest.json` describing them.
+    async fn serve_wiki_page_export_bundle_get(
         &self,
-        _remote_addr: SocketAddr,
         req: Request<Body>,
+        rw: &RouteWiki<'_>,
     ) -> DynResult<Response<Body>> {
-        let decoded = decode_percents(req.uri().path())?;
-        let route = Route::router(&decoded)?.to_owned();
+        let QueryParams(params) = QueryParams::<BundleParams>::from_request(&req)?;
+        let depth = params.depth.unwrap_or(bundle::DEFAULT_DEPTH).min(bundle::MAX_DEPTH);
 
-        match route {
-            Route::Root => {
-                let res = Response::builder()
-                    .status(StatusCode::FOUND)
-                    .header(header::LOCATION, "/login")
-                    .body(Body::empty())
-                    .expect("unable to build response");
-                Ok(res)
+        let locked = self.inner.read().await;
+
+        let mut seen = HashSet::new();
+        seen.insert(rw.name.to_string());
+        let mut queue = std::collections::VecDeque::new();
+        queue.push_back((rw.name.to_string(), 0));
+        let mut pages = Vec::new();
+        while let Some((name, distance)) = queue.pop_front() {
+            let revision = match locked.queries.fetch_current_revision(&locked.db, &name).await? {
+                Some(revision) => revision,
+                None if pages.is_empty() => return Err(RouteError::NotFound.into()),
+                None => continue,
+            };
+            if distance < depth {
+                let targets = markup::of(&revision.document_data).link_targets(&revision.document_data);
+                for target in targets {
+                    if seen.len() < bundle::MAX_PAGES && seen.insert(target.clone()) {
+                        queue.push_back((target, distance + 1));
+                    }
+                }
             }
-            Route::Login => self.login_page(req).await,
-            Route::Wiki(ref article) => self.serve_wiki_page(req, article).await,
+            pages.push((name, revision));
         }
-    }
-}
-
-fn decode_percents<'a>(string: &'a str) -> Result<std::borrow::Cow<'a, str>, std::str::Utf8Error> {
-    percent_encoding::percent_decode_str(string).decode_utf8()
-}
-
-fn main() {
-    let runtime = tokio::runtime::Runtime::new().unwrap();
-    runtime.block_on(main2()).unwrap();
-}
 
-async fn main2() -> DynResult<()> {
-    let mut my_subscriber_builder = FmtSubscriber::builder();
-
-    let app = App::new(CARGO_PKG_NAME)
-        .version(CARGO_PKG_VERSION)
-        .author("Stacey Ell <software@e.staceyell.com>")
-        .arg(
-            Arg::with_name("v")
-                .short("v")
-                .multiple(true)
-                .help("Sets the level of verbosity"),
-        );
+        let root = &rw.name;
+        let mut archive = bundle::TarWriter::new(Vec::new());
+        let mut manifest_pages = Vec::new();
+        for (name, revision) in &pages {
+            let mtime = revision.created_at.timestamp();
+            let path = format!("{}/pages/{}.md", root, name);
+            archive.append(&path, revision.document_data.as_bytes(), mtime)?;
 
-    let matches = app.get_matches();
+            let mut manifest_attachments = Vec::new();
+            for attachment in locked.queries.fetch_attachments(&locked.db, name).await? {
+                let data = match locked
+                    .queries
+                    .fetch_attachment(&locked.db, name, &attachment.filename)
+                    .await?
+                {
+                    Some(content) => content.data,
+                    None => continue,
+                };
+                let attachment_path = format!("{}/attachments/{}/{}", root, name, attachment.filename);
+                archive.append(&attachment_path, &data, attachment.uploaded_at.timestamp())?;
+                manifest_attachments.push(serde_json::json!({
+                    "filename": attachment.filename,
+                    "content_type": attachment.content_type,
+                    "file": attachment_path,
+                }));
+            }
 
-    let verbosity = matches.occurrences_of("v");
-    let should_print_test_logging = 4 < verbosity;
+            manifest_pages.push(serde_json::json!({
+                "name": name,
+                "revision": revision.id,
+                "modified_at": revision.created_at.to_rfc3339(),
+                "modified_by": revision.modified_by,
+                "file": path,
+                "attachments": manifest_attachments,
+            }));
+        }
 
-    my_subscriber_builder = my_subscriber_builder.with_max_level(match verbosity {
-        0 => TracingLevelFilter::ERROR,
-        1 => TracingLevelFilter::WARN,
-        2 => TracingLevelFilter::INFO,
-        3 => TracingLevelFilter::DEBUG,
-        _ => TracingLevelFilter::TRACE,
-    });
+        let manifest = serde_json::json!({
+            "root": root,
+            "depth": depth,
+            "exported_at": Utc::now().to_rfc3339(),
+            "pages": manifest_pages,
+        });
+        let manifest = serde_json::to_vec_pretty(&manifest)?;
+        archive.append(&format!("{}/manifest.json", root), &manifest, Utc::now().timestamp())?;
 
-    tracing::subscriber::set_global_default(my_subscriber_builder.finish())
-        .expect("setting tracing default failed");
+        let filename = percent_encoding::utf8_percent_encode(root, percent_encoding::NON_ALPHANUMERIC);
+        let response = Response::builder()
+            .header("Content-Type", "application/gzip")
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename*=UTF-8''{}.tar.gz", filename),
+            )
+            .status(StatusCode::OK)
+            .body(Body::from(archive.finish()?))?;
 
-    if should_print_test_logging {
-        print_test_logging();
+        Ok(response)
     }
 
-    let db_uri = "postgresql://quassel@localhost/quassel";
-
-    let (db_client, connection) = tokio_postgres::connect(db_uri, NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
+    async fn serve_wiki_page_export_git_get(&self, rw: &RouteWiki<'_>) -> DynResult<Response<Body>> {
+        let locked = self.inner.read().await;
+        let revisions = locked
+            .queries
+            .fetch_published_revisions(&locked.db, &rw.name)
+            .await?;
+        let commits: Vec<_> = revisions
+            .iter()
+            .map(|revision| git_bundle::Commit {
+                data: &revision.document_data,
+                author: &revision.modified_by,
+                time: revision.created_at,
+                message: format!("{}: revision {}", rw.name, revision.id),
+            })
+            .collect();
+        let file_name = format!("{}.md", export::file_name(&rw.name));
+        let bundle = git_bundle::write(&file_name, &commits)?.ok_or(RouteError::NotFound)?;
 
-    let handler = Handler {
-        inner: Arc::new(RwLock::new(HandlerInner { db: db_client })),
-    };
+        let filename = percent_encoding::utf8_percent_encode(&rw.name, percent_encoding::NON_ALPHANUMERIC);
+        let response = Response::builder()
+            .header("Content-Type", "application/x-git-bundle")
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename*=UTF-8''{}.bundle", filename),
+            )
+            .status(StatusCode::OK)
+            .body(Body::from(bundle))?;
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+        Ok(response)
+    }
 
-    // And a MakeService to handle each connection...
-    let make_service = make_service_fn(move |conn: &AddrStream| {
-        let handler = handler.clone();
-        let addr: SocketAddr = conn.remote_addr();
+    async fn serve_wiki_page_move_get(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let locked = self.inner.read().await;
+        locked
+            .queries
+            .fetch_document_id(&locked.db, &rw.name)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+        let backlinks = locked
+            .queries
+            .fetch_backlinks(&locked.db, &rw.name, rename_links::MAX_PAGES)
+            .await?;
 
-        let service = service_fn(move |req| {
-            let handler = handler.clone();
-            let addr = addr.clone();
+        let page = views::wiki::Move {
+            ctx: self.page_context(&req),
+            page_title: &rw.name,
+            view_link: RouteWiki::to(&rw.name).to_owned(),
+            move_link: RouteWiki::to_move(&rw.name).to_owned(),
+            new_name: &rw.name,
+            reason: "",
+            update_links: !backlinks.is_empty(),
+            backlinks,
+            always_update_links: self.config.update_links_on_move,
+            error: None,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
 
-            async move { handler.handle(addr, req).await }
-        });
+        Ok(response)
+    }
 
-        // Return the service to hyper.
-        async move { Ok::<_, Infallible>(service) }
-    });
+    async fn serve_wiki_page_tag_post(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let may_review = self.may_review(&req);
+        let document_history_id;
+        if let RouteWikiSubview::TagRevision(r) = rw.subview {
+            document_history_id = r;
+        } else {
+            return Err(RouteError::NotFound.into());
+        }
+
+        let user_id = CurrentUser::attribution(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let label = form_urlencoded::parse(&body_bytes)
+            .find(|(key, _)| key == "label")
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default();
+
+        let rejected = |status, message: String| -> DynResult<Response<Body>> {
+            let response = Response::builder()
+                .header("Content-Type", "text/plain; charset=utf8")
+                .status(status)
+                .body(Body::from(message))?;
+            Ok(response)
+        };
+
+        if !routes::is_valid_tag_label(&label) {
+            return rejected(
+                StatusCode::BAD_REQUEST,
+                format!("{:?} is not a valid tag.", label),
+            );
+        }
+
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+
+        if queries
+            .fetch_revision(&tx, &rw.name, document_history_id, may_review)
+            .await?
+            .is_none()
+        {
+            return Err(RouteError::NotFound.into());
+        }
+        let inserted = queries
+            .insert_tag(&tx, &rw.name, document_history_id, &label, &user_id)
+            .await?;
+        if !inserted {
+            return rejected(
+                StatusCode::CONFLICT,
+                format!("This page already has a tag named {:?}.", label),
+            );
+        }
+        tx.commit().await?;
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(
+                header::LOCATION,
+                format!("{}?flash=tagged", RouteWiki::to_history(&rw.name)),
+            )
+            .body(Body::empty())
+            .expect("unable to build response");
+
+        Ok(res)
+    }
+
+    /// Replaces the text of an old revision with a tombstone, for when
+    /// secrets or personal data were saved by mistake. The history row, its
+    /// author and date stay. The current revision can't be redacted; the
+    /// page has to be edited first so the text is gone from it too.
+    async fn serve_wiki_page_redact_post(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let document_history_id = match rw.subview {
+            RouteWikiSubview::RedactRevision(r) => r,
+            _ => return Err(RouteError::NotFound.into()),
+        };
+
+        let user_id = CurrentUser::attribution(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let reason = form_urlencoded::parse(&body_bytes)
+            .find(|(key, _)| key == "reason")
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default();
+
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+
+        // only admins redact
+        let revision = queries
+            .fetch_revision(&tx, &rw.name, document_history_id, true)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+        if revision.is_current {
+            let response = Response::builder()
+                .header("Content-Type", "text/plain; charset=utf8")
+                .status(StatusCode::CONFLICT)
+                .body(Body::from(
+                    "This is the current revision. Edit the page to remove the text first, then redact this revision.",
+                ))?;
+            return Ok(response);
+        }
+        queries
+            .redact_revision(&tx, document_history_id, REDACTED_TOMBSTONE, &user_id, &reason)
+            .await?;
+        tx.commit().await?;
+        event!(Level::WARN, "{} redacted revision {} of {:?}", user_id, document_history_id, rw.name);
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(
+                header::LOCATION,
+                format!("{}?flash=redacted", RouteWiki::to_history(&rw.name)),
+            )
+            .body(Body::empty())
+            .expect("unable to build response");
+
+        Ok(res)
+    }
+
+    /// Redirects a tag to the revision it points at.
+    async fn serve_tag(&self, req: Request<Body>, rt: &RouteTag<'_>) -> DynResult<Response<Body>> {
+        if req.method() != Method::GET {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        let locked = self.inner.read().await;
+        let revision_id = locked
+            .queries
+            .fetch_tagged_revision_id(&locked.db, &rt.name, &rt.label)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(
+                header::LOCATION,
+                RouteWiki::to_revision(&rt.name, revision_id).to_string(),
+            )
+            .body(Body::empty())
+            .expect("unable to build response");
+
+        Ok(res)
+    }
+
+    async fn serve_wiki_page_move_post(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let user_id = CurrentUser::attribution(&req);
+        let user = CurrentUser::of(&req).cloned();
+        let held = self.held_for_review(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut new_name = String::new();
+        let mut reason = String::new();
+        let mut update_links = self.config.update_links_on_move;
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "new_name" => new_name = names::canonical(&value, self.config.page_name_case),
+                "reason" => reason = value.trim().to_string(),
+                "update_links" => update_links = true,
+                _ => (),
+            }
+        }
+
+        let rejected = |ctx, status, error: String| -> DynResult<Response<Body>> {
+            let page = views::wiki::Move {
+                ctx,
+                page_title: &rw.name,
+                view_link: RouteWiki::to(&rw.name).to_owned(),
+                move_link: RouteWiki::to_move(&rw.name).to_owned(),
+                new_name: &new_name,
+                reason: &reason,
+                backlinks: Vec::new(),
+                update_links,
+                always_update_links: self.config.update_links_on_move,
+                error: Some(error),
+            };
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(status)
+                .body(Body::from(page.render()?))?;
+            Ok(response)
+        };
+
+        if !routes::is_valid_page_name(&new_name) {
+            return rejected(
+                ctx,
+                StatusCode::BAD_REQUEST,
+                format!("{:?} is not a valid page name.", new_name),
+            );
+        }
+        if new_name == rw.name {
+            return rejected(
+                ctx,
+                StatusCode::BAD_REQUEST,
+                "The new name is the same as the old one.".to_string(),
+            );
+        }
+        if !self.may_edit(Some(&new_name), user.as_ref()) {
+            return rejected(
+                ctx,
+                StatusCode::FORBIDDEN,
+                format!("Pages named like {:?} are protected.", new_name),
+            );
+        }
+        // as when saving one, see `Action::for_request`
+        if permissions::is_site_page(&new_name)
+            && !permissions::is_allowed(self.config.site_policy, user.as_ref(), Action::Admin)
+        {
+            return rejected(
+                ctx,
+                StatusCode::FORBIDDEN,
+                format!("Only admins may change {:?}.", new_name),
+            );
+        }
+
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+
+        // moving over an existing page would orphan its history
+        if queries.fetch_document_id(&tx, &new_name).await?.is_some() {
+            return rejected(
+                ctx,
+                StatusCode::CONFLICT,
+                format!("A page named {:?} already exists.", new_name),
+            );
+        }
+        let moved = queries
+            .move_document(&tx, &rw.name, &new_name, &user_id, &reason)
+            .await?;
+        if !moved {
+            return Err(RouteError::NotFound.into());
+        }
+        tx.commit().await?;
+        self.warm_render_cache(&new_name);
+
+        if update_links {
+            let (changes, over_limit) = rename_links::plan(&locked, &rw.name, &new_name).await?;
+            let author = rename_links::author(&user_id);
+            let may_write = |name: &str| self.may_write(name, user.as_ref());
+            let editor = replace::Editor {
+                author: &author,
+                user: user.as_ref(),
+                may_write: &may_write,
+                held,
+                secrets: self.config.secrets_policy,
+            };
+            let summary = rename_links::summary(&rw.name, &new_name);
+            let outcomes = replace::apply(&mut locked, &self.plugins, &changes, &editor, Some(&summary)).await?;
+            drop(locked);
+
+            let mut not_updated = Vec::new();
+            for (name, outcome) in outcomes {
+                match outcome.problem() {
+                    None => self.warm_render_cache(&name),
+                    Some(problem) => not_updated.push(views::wiki::LinkUpdate {
+                        link: RouteWiki::to(&name).to_owned(),
+                        name,
+                        problem,
+                    }),
+                }
+            }
+            not_updated.extend(over_limit.into_iter().map(|name| views::wiki::LinkUpdate {
+                link: RouteWiki::to(&name).to_owned(),
+                name,
+                problem: format!("past the first {} pages", rename_links::MAX_PAGES),
+            }));
+            if !not_updated.is_empty() {
+                let page = views::wiki::Moved {
+                    ctx,
+                    old_name: &rw.name,
+                    new_link: RouteWiki::to(&new_name).to_owned(),
+                    new_name: &new_name,
+                    not_updated,
+                };
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::OK)
+                    .body(Body::from(page.render()?))?;
+                return Ok(response);
+            }
+        }
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(
+                header::LOCATION,
+                format!("{}?flash=moved", RouteWiki::to(&new_name)),
+            )
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    async fn serve_wiki_page_merge_get(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let QueryParams(params) = QueryParams::<MergeParams>::from_request(&req)?;
+        let into = params.into.trim().to_string();
+
+        let locked = self.inner.read().await;
+        let source = locked
+            .queries
+            .fetch_current_revision(&locked.db, &rw.name)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let (status, preview, error) = if into.is_empty() {
+            (StatusCode::OK, None, None)
+        } else {
+            match self
+                .merge_preview(&locked, &rw.name, &source.document_data, &into, None)
+                .await?
+            {
+                Ok(preview) => (StatusCode::OK, Some(preview), None),
+                Err((status, error)) => (status, None, Some(error)),
+            }
+        };
+
+        let page = views::wiki::Merge {
+            ctx: self.page_context(&req),
+            page_title: &rw.name,
+            view_link: RouteWiki::to(&rw.name).to_owned(),
+            merge_link: RouteWiki::to_merge(&rw.name).to_owned(),
+            into: &into,
+            reason: "",
+            preview,
+            error,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(status)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    /// Checks that `source` can be merged into `into` and describes the
+    /// result. `document_data` overrides the default combined text.
+    async fn merge_preview(
+        &self,
+        inner: &HandlerInner,
+        source: &str,
+        source_text: &str,
+        into: &str,
+        document_data: Option<String>,
+    ) -> DynResult<Result<views::wiki::MergePreview, (StatusCode, String)>> {
+        if into == source {
+            return Ok(Err((
+                StatusCode::BAD_REQUEST,
+                "A page can't be merged into itself.".to_string(),
+            )));
+        }
+        let target = match inner.queries.fetch_current_revision(&inner.db, into).await? {
+            Some(target) => target,
+            None => {
+                return Ok(Err((
+                    StatusCode::NOT_FOUND,
+                    format!("There is no page named {:?}.", into),
+                )))
+            }
+        };
+        let counts = inner
+            .queries
+            .fetch_merge_preview(&inner.db, source, into)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let document_data = document_data
+            .unwrap_or_else(|| merged_text(&target.document_data, source, source_text));
+        Ok(Ok(views::wiki::MergePreview {
+            into_link: RouteWiki::to(into).to_owned(),
+            rendered: render_document(inner, &self.plugins, &document_data).await?,
+            document_data,
+            revisions: counts.revisions,
+            conflicting_tags: counts.conflicting_tags,
+            conflicting_attachments: counts.conflicting_attachments,
+        }))
+    }
+
+    /// Merges the page into `into`: its history, tags and attachments move
+    /// over, the combined text becomes a new revision of `into`, and the old
+    /// name redirects there.
+    async fn serve_wiki_page_merge_post(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let user = CurrentUser::of(&req).cloned();
+        let user_id = CurrentUser::attribution(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut into = String::new();
+        let mut document_data = None;
+        let mut reason = String::new();
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "into" => into = value.trim().to_string(),
+                "document" => document_data = Some(value.replace("\r\n", "\n")),
+                "reason" => reason = value.trim().to_string(),
+                _ => (),
+            }
+        }
+
+        let mut locked = self.inner.write().await;
+        let source = locked
+            .queries
+            .fetch_current_revision(&locked.db, &rw.name)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+        let preview = self
+            .merge_preview(&locked, &rw.name, &source.document_data, &into, document_data)
+            .await?;
+        let document_data = match preview {
+            Ok(preview) => preview.document_data,
+            Err((status, error)) => {
+                let page = views::wiki::Merge {
+                    ctx,
+                    page_title: &rw.name,
+                    view_link: RouteWiki::to(&rw.name).to_owned(),
+                    merge_link: RouteWiki::to_merge(&rw.name).to_owned(),
+                    into: &into,
+                    reason: &reason,
+                    preview: None,
+                    error: Some(error),
+                };
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(status)
+                    .body(Body::from(page.render()?))?;
+                return Ok(response);
+            }
+        };
+
+        let reason = if reason.is_empty() {
+            "Merged".to_string()
+        } else {
+            format!("Merged: {}", reason)
+        };
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+        let merged = queries
+            .merge_document(&tx, &rw.name, &into, &user_id, &reason)
+            .await?;
+        if !merged {
+            return Err(RouteError::NotFound.into());
+        }
+        let index = index_document(&document_data);
+        let document_history_id = queries
+            .store_revision(&tx, &into, &user_id, &document_data, &index)
+            .await?;
+        tx.commit().await?;
+
+        let save = SaveContext {
+            name: &into,
+            user: user.as_ref(),
+            attribution: &user_id,
+        };
+        self.plugins.post_save(&save, document_history_id);
+        self.warm_render_cache(save.name);
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(
+                header::LOCATION,
+                format!("{}?flash=merged", RouteWiki::to(&into)),
+            )
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    async fn serve_wiki_page_put(
+        &self,
+        req: Request<Body>,
+        rw: &RouteWiki<'_>,
+        // document_data: &str,
+    ) -> DynResult<Response<Body>> {
+        let user = CurrentUser::of(&req).cloned();
+        let user_id = CurrentUser::attribution(&req);
+        let pending = self.held_for_review(&req);
+        let challenged = self.challenger.is_some() && !CurrentUser::has_trust(&req, TrustLevel::Autoconfirmed);
+        let confirmed = req.headers().contains_key(edit_filter::CONFIRM_HEADER);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut document_data =
+            macros::expand(&String::from_utf8_lossy(&body_bytes), &user_id, Utc::now());
+
+        let save = SaveContext {
+            name: &rw.name,
+            user: user.as_ref(),
+            attribution: &user_id,
+        };
+        if let Err(err) = self.plugins.pre_save(&save, &mut document_data) {
+            return plugin_error_response(err);
+        }
+
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+        // saving the text as it is would only clutter the history
+        if queries
+            .fetch_current_revision_if_unchanged(&tx, &rw.name, &document_data)
+            .await?
+            .is_some()
+        {
+            let res = Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(
+                    header::LOCATION,
+                    format!("{}?flash=unchanged", RouteWiki::to(&rw.name)),
+                )
+                .body(Body::empty())
+                .expect("unable to build response");
+            return Ok(res);
+        }
+        let checked = edit_filter::check(
+            queries,
+            &tx,
+            &save,
+            &document_data,
+            confirmed,
+            self.config.secrets_policy,
+        );
+        let matched = match checked.await? {
+            Ok(matched) => matched,
+            Err(refusal) => {
+                tx.commit().await?;
+                return self.edit_filter_refusal(refusal, challenged);
+            }
+        };
+        if pending {
+            let document_history_id = queries
+                .store_pending_revision(&tx, &rw.name, &user_id, &document_data)
+                .await?;
+            edit_filter::record(queries, &tx, &save, &matched, document_history_id).await?;
+            tx.commit().await?;
+
+            let res = Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .header(
+                    header::LOCATION,
+                    format!("{}?flash=pending", RouteWiki::to_history(&rw.name)),
+                )
+                .body(Body::empty())
+                .expect("unable to build response");
+            return Ok(res);
+        }
+        let index = index_document(&document_data);
+        let document_history_id = queries
+            .store_revision(&tx, &rw.name, &user_id, &document_data, &index)
+            .await?;
+        edit_filter::record(queries, &tx, &save, &matched, document_history_id).await?;
+        tx.commit().await?;
+        self.plugins.post_save(&save, document_history_id);
+        self.warm_render_cache(save.name);
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, RouteWiki::to(&rw.name).to_string())
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    async fn serve_attachment(
+        &self,
+        req: Request<Body>,
+        ra: &RouteAttachment<'_>,
+    ) -> DynResult<Response<Body>> {
+        match (req.method(), ra.filename.is_some()) {
+            (&Method::GET, false) => self.serve_attachment_list_get(req, ra).await,
+            (&Method::GET, true) => self.serve_attachment_get(req, ra).await,
+            (&Method::PUT, true) => self.serve_attachment_put(req, ra).await,
+            _ => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .body(Body::from("Method Not Allowed"))?;
+
+                Ok(response)
+            }
+        }
+    }
+
+    async fn serve_attachment_list_get(
+        &self,
+        req: Request<Body>,
+        ra: &RouteAttachment<'_>,
+    ) -> DynResult<Response<Body>> {
+        let locked = self.inner.read().await;
+        locked
+            .queries
+            .fetch_document_id(&locked.db, &ra.name)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let attachments = attachment_records(&locked, &ra.name).await?;
+        let page = views::wiki::Attachments {
+            ctx: self.page_context(&req),
+            page_title: &ra.name,
+            view_link: RouteWiki::to(&ra.name).to_owned(),
+            attachments,
+        };
+
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn serve_attachment_get(
+        &self,
+        req: Request<Body>,
+        ra: &RouteAttachment<'_>,
+    ) -> DynResult<Response<Body>> {
+        let filename = ra.filename.as_deref().unwrap_or("");
+        let QueryParams(size) = QueryParams::<resize::SizeParams>::from_request(&req)?;
+        let locked = self.inner.read().await;
+        let content = locked
+            .queries
+            .fetch_attachment(&locked.db, &ra.name, filename)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+        let content = resize::resized(&locked, content, resize::Size::of(&size)).await?;
+
+        // the named URL can be replaced by a new upload, so clients revalidate
+        attachment_response(&req, content, "no-cache")
+    }
+
+    async fn serve_blob(&self, req: Request<Body>, rb: &RouteBlob<'_>) -> DynResult<Response<Body>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+        if !attachments::is_valid_content_hash(&rb.content_hash) {
+            return Err(RouteError::NotFound.into());
+        }
+
+        let QueryParams(size) = QueryParams::<resize::SizeParams>::from_request(&req)?;
+        let locked = self.inner.read().await;
+        let content = locked
+            .queries
+            .fetch_attachment_by_hash(&locked.db, &rb.content_hash, &rb.filename)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+        let content = resize::resized(&locked, content, resize::Size::of(&size)).await?;
+
+        attachment_response(&req, content, attachments::IMMUTABLE_CACHE_CONTROL)
+    }
+
+    async fn serve_blob_preview(&self, req: Request<Body>, rb: &RouteBlob<'_>) -> DynResult<Response<Body>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+        if !attachments::is_valid_content_hash(&rb.content_hash) {
+            return Err(RouteError::NotFound.into());
+        }
+
+        let locked = self.inner.read().await;
+        let png = previews::picture(&locked, &rb.content_hash, &rb.filename)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let response = Response::builder()
+            .header("Content-Type", "image/png")
+            .header(header::CACHE_CONTROL, attachments::IMMUTABLE_CACHE_CONTROL)
+            .header("X-Content-Type-Options", "nosniff")
+            .status(StatusCode::OK)
+            .body(Body::from(png))?;
+        Ok(response)
+    }
+
+    async fn serve_attachment_put(
+        &self,
+        req: Request<Body>,
+        ra: &RouteAttachment<'_>,
+    ) -> DynResult<Response<Body>> {
+        let filename = ra.filename.as_deref().unwrap_or("");
+        let user_id = CurrentUser::attribution(&req);
+        let limits = self.config.upload_limits;
+
+        let declared_size = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        if let Some(size) = declared_size {
+            if let Err(err) = limits.check(size, 0, 0) {
+                return attachment_error_response(err);
+            }
+        }
+
+        let data = match read_upload(req.into_body(), &limits).await? {
+            Ok(data) => data,
+            Err(err) => return attachment_error_response(err),
+        };
+        let content_type = match attachments::check_type(filename, &data) {
+            Ok(content_type) => content_type,
+            Err(err) => return attachment_error_response(err),
+        };
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+
+        let document_id = queries
+            .fetch_document_id(&tx, &ra.name)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let (user_used, site_used) = queries
+            .fetch_attachment_usage(&tx, document_id, filename, &user_id)
+            .await?;
+        if let Err(err) = limits.check(data.len() as u64, user_used as u64, site_used as u64) {
+            return attachment_error_response(err);
+        }
+
+        let content_hash = attachments::content_hash(&data);
+        let attachment = queries::NewAttachment {
+            filename,
+            content_type,
+            data: &data,
+            content_hash: &content_hash,
+            uploaded_by: &user_id,
+        };
+        queries.upsert_attachment(&tx, document_id, &attachment).await?;
+
+        tx.commit().await?;
+
+        let res = Response::builder()
+            .status(StatusCode::CREATED)
+            .header(
+                header::LOCATION,
+                RouteAttachment::to_file(&ra.name, filename).to_string(),
+            )
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    async fn serve_api_wiki(
+        &self,
+        req: Request<Body>,
+        ra: &RouteApiWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        match ra.action {
+            RouteApiWikiAction::Page if req.method() == Method::GET => {
+                self.serve_api_wiki_revision_get(req, ra, None).await
+            }
+            RouteApiWikiAction::Page if req.method() == Method::PUT => {
+                self.serve_api_wiki_page_put(req, ra).await
+            }
+            RouteApiWikiAction::Append if req.method() == Method::POST => {
+                self.serve_api_wiki_append_post(req, ra).await
+            }
+            RouteApiWikiAction::Page | RouteApiWikiAction::Append => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .body(Body::from("Method Not Allowed"))?;
+
+                Ok(response)
+            }
+            RouteApiWikiAction::Revision(revision) if req.method() == Method::GET => {
+                self.serve_api_wiki_revision_get(req, ra, Some(revision)).await
+            }
+            RouteApiWikiAction::Summary if req.method() == Method::GET => {
+                self.serve_api_wiki_summary_get(req, ra).await
+            }
+            RouteApiWikiAction::Permalink if req.method() == Method::GET => {
+                self.serve_api_wiki_permalink_get(req, ra).await
+            }
+            RouteApiWikiAction::Revision(..) | RouteApiWikiAction::Summary | RouteApiWikiAction::Permalink => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .body(Body::from("Method Not Allowed"))?;
+
+                Ok(response)
+            }
+        }
+    }
+
+    /// Serves a revision, or the current one if `revision` is `None`, as JSON
+    /// metadata, raw Markdown or rendered HTML, whichever the `Accept` header
+    /// prefers.
+    async fn serve_api_wiki_revision_get(
+        &self,
+        req: Request<Body>,
+        ra: &RouteApiWiki<'_>,
+        revision: Option<i64>,
+    ) -> DynResult<Response<Body>> {
+        const JSON: &str = "application/json";
+        const MARKDOWN: &str = "text/markdown";
+        const HTML: &str = "text/html";
+
+        let accept = req.headers().get(header::ACCEPT).and_then(|v| v.to_str().ok());
+        let media_type = match negotiate::preferred(accept, &[JSON, MARKDOWN, HTML]) {
+            Some(media_type) => media_type,
+            None => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/plain; charset=utf8")
+                    .status(StatusCode::NOT_ACCEPTABLE)
+                    .body(Body::from("Available as application/json, text/markdown or text/html"))?;
+                return Ok(response);
+            }
+        };
+
+        let locked = self.inner.read().await;
+        let revision = match revision {
+            Some(revision) => {
+                locked
+                    .queries
+                    .fetch_revision(&locked.db, &ra.name, revision, self.may_review(&req))
+                    .await?
+            }
+            None => locked.queries.fetch_current_revision(&locked.db, &ra.name).await?,
+        };
+        let revision = revision.ok_or(RouteError::NotFound)?;
+
+        let body = match media_type {
+            MARKDOWN => revision.document_data,
+            HTML if revision.is_current => {
+                self.render_cache
+                    .render(&locked, &self.plugins, &ra.name, revision.id, &revision.document_data)
+                    .await?
+            }
+            HTML => render_document(&locked, &self.plugins, &revision.document_data).await?,
+            _ => serde_json::json!({
+                "name": ra.name,
+                "revision": revision.id,
+                "created_at": revision.created_at.to_rfc3339(),
+                "created_by": revision.modified_by,
+                "current": revision.is_current,
+                "size": revision.document_data.len(),
+                "links": markup::of(&revision.document_data).link_targets(&revision.document_data),
+            })
+            .to_string(),
+        };
+        let content_type = match media_type {
+            JSON => JSON.to_string(),
+            other => format!("{}; charset=utf8", other),
+        };
+        let response = Response::builder()
+            .header("Content-Type", content_type)
+            .header(header::VARY, "Accept")
+            .header(header::ETAG, revision_etag(revision.id))
+            .status(StatusCode::OK)
+            .body(Body::from(body))?;
+
+        Ok(response)
+    }
+
+    /// The current revision's first paragraph and image, for hovercards and
+    /// other link previews.
+    async fn serve_api_wiki_summary_get(
+        &self,
+        req: Request<Body>,
+        ra: &RouteApiWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let base_url = ClientInfo::of(&req).map(|c| c.base_url()).unwrap_or_default();
+        let locked = self.inner.read().await;
+        let revision = locked
+            .queries
+            .fetch_current_revision(&locked.db, &ra.name)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+        let rendered = self
+            .render_cache
+            .render(&locked, &self.plugins, &ra.name, revision.id, &revision.document_data)
+            .await?;
+
+        let body = serde_json::json!({
+            "name": ra.name,
+            "revision": revision.id,
+            "extract": summary::description(&rendered),
+            "thumbnail": summary::first_image(&rendered).and_then(|src| summary::absolute_url(src, &base_url)),
+            "link": RouteWiki::to(&ra.name).to_string(),
+        });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .header(header::ETAG, revision_etag(revision.id))
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// The address of the current revision, which keeps showing the same
+    /// text however the page changes, for citations.
+    async fn serve_api_wiki_permalink_get(
+        &self,
+        req: Request<Body>,
+        ra: &RouteApiWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let base_url = ClientInfo::of(&req).map(|c| c.base_url()).unwrap_or_default();
+        let locked = self.inner.read().await;
+        let revision = locked
+            .queries
+            .fetch_current_revision(&locked.db, &ra.name)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let body = serde_json::json!({
+            "name": ra.name,
+            "revision": revision.id,
+            "created_at": revision.created_at.to_rfc3339(),
+            "permalink": format!("{}{}", base_url, RouteWiki::to_revision(&ra.name, revision.id)),
+        });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .header(header::ETAG, revision_etag(revision.id))
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// Replaces the page's text with the request body, storing a new revision.
+    async fn serve_api_wiki_page_put(
+        &self,
+        req: Request<Body>,
+        ra: &RouteApiWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        if !is_valid_page_name(&ra.name) {
+            let response = Response::builder()
+                .header("Content-Type", "text/plain; charset=utf8")
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Invalid page name"))?;
+            return Ok(response);
+        }
+
+        let user = CurrentUser::of(&req).cloned();
+        let user_id = CurrentUser::attribution(&req);
+        let if_match = req
+            .headers()
+            .get(header::IF_MATCH)
+            .map(|v| v.to_str().unwrap_or("").to_string());
+        let pending = self.held_for_review(&req);
+        let confirmed = req.headers().contains_key(edit_filter::CONFIRM_HEADER);
+        let QueryParams(params) = QueryParams::<SaveParams>::from_request(&req)?;
+        let dry_run = params.dry_run;
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut document_data = String::from_utf8(body_bytes.to_vec())?;
+
+        let save = SaveContext {
+            name: &ra.name,
+            user: user.as_ref(),
+            attribution: &user_id,
+        };
+        if let Err(err) = self.plugins.pre_save(&save, &mut document_data) {
+            return plugin_error_response(err);
+        }
+
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+
+        if let Some(if_match) = if_match {
+            // checked against the locked row, so a concurrent save can't
+            // slip in between the check and the store
+            let current = queries.fetch_current_revision_id_for_update(&tx, &ra.name).await?;
+            if !if_match_allows(&if_match, current) {
+                let mut response = Response::builder()
+                    .header("Content-Type", "text/plain; charset=utf8")
+                    .status(StatusCode::PRECONDITION_FAILED);
+                if let Some(current) = current {
+                    response = response.header(header::ETAG, revision_etag(current));
+                }
+                let response = response.body(Body::from("The page has changed since it was fetched"))?;
+                return Ok(response);
+            }
+        }
+
+        if let Some(current) = queries
+            .fetch_current_revision_if_unchanged(&tx, &ra.name, &document_data)
+            .await?
+        {
+            if dry_run {
+                tx.rollback().await?;
+                drop(locked);
+                let matched = edit_filter::Matched::default();
+                return self.dry_run_response(&ra.name, &document_data, false, &matched, true).await;
+            }
+            let body = serde_json::json!({
+                "name": ra.name,
+                "revision": current,
+                "pending": false,
+                "unchanged": true,
+            });
+            let response = Response::builder()
+                .header("Content-Type", "application/json")
+                .header(header::ETAG, revision_etag(current))
+                .status(StatusCode::OK)
+                .body(Body::from(body.to_string()))?;
+            return Ok(response);
+        }
+
+        let checked = edit_filter::check(
+            queries,
+            &tx,
+            &save,
+            &document_data,
+            confirmed,
+            self.config.secrets_policy,
+        );
+        let matched = match checked.await? {
+            Ok(matched) => matched,
+            Err(refusal) => {
+                // a dry run leaves no trace of the refusal either
+                if dry_run {
+                    tx.rollback().await?;
+                } else {
+                    tx.commit().await?;
+                }
+                return self.edit_filter_refusal(refusal, false);
+            }
+        };
+        if dry_run {
+            tx.rollback().await?;
+            drop(locked);
+            return self.dry_run_response(&ra.name, &document_data, pending, &matched, false).await;
+        }
+        let document_history_id = if pending {
+            queries
+                .store_pending_revision(&tx, &ra.name, &user_id, &document_data)
+                .await?
+        } else {
+            let index = index_document(&document_data);
+            queries
+                .store_revision(&tx, &ra.name, &user_id, &document_data, &index)
+                .await?
+        };
+        edit_filter::record(queries, &tx, &save, &matched, document_history_id).await?;
+
+        tx.commit().await?;
+        if !pending {
+            self.plugins.post_save(&save, document_history_id);
+            self.warm_render_cache(save.name);
+        }
+
+        let body = serde_json::json!({
+            "name": ra.name,
+            "revision": document_history_id,
+            "pending": pending,
+            "unchanged": false,
+        });
+        let mut response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(if pending { StatusCode::ACCEPTED } else { StatusCode::OK });
+        if !pending {
+            response = response.header(header::ETAG, revision_etag(document_history_id));
+        }
+        let response = response.body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// Lists pages whose current revision is newer than `?after=`, oldest
+    /// first, so a client can page through everything that changed.
+    async fn serve_api_changes(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() != Method::GET {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        let QueryParams(params) = QueryParams::<ChangesParams>::from_request(&req)?;
+        let after = params.after.unwrap_or(0);
+        let limit = params.limit.unwrap_or(100).clamp(1, 500);
+
+        let changes = {
+            let locked = self.inner.read().await;
+            locked.queries.fetch_changes_after(&locked.db, after, limit).await?
+        };
+        let pages: Vec<_> = changes
+            .into_iter()
+            .map(|page| {
+                serde_json::json!({
+                    "name": page.name,
+                    "revision": page.revision_id,
+                    "created_at": page.created_at.to_rfc3339(),
+                    "created_by": page.created_by,
+                })
+            })
+            .collect();
+
+        let body = serde_json::json!({ "pages": pages });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// Lints the Markdown in the request body. Warnings don't make the
+    /// request fail, so a clean page and a broken one both get a 200.
+    async fn serve_api_lint(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() != Method::POST {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let markdown = String::from_utf8(body_bytes.to_vec())?;
+
+        let warnings = {
+            let locked = self.inner.read().await;
+            lint::lint(&locked, &markdown).await?
+        };
+        let warnings: Vec<_> = warnings
+            .into_iter()
+            .map(|warning| {
+                serde_json::json!({
+                    "rule": warning.rule.as_str(),
+                    "line": warning.line,
+                    "message": warning.message,
+                })
+            })
+            .collect();
+
+        let body = serde_json::json!({ "warnings": warnings });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// The pages within `?depth=` links of `?root=` and the links between
+    /// them, see `link_graph.rs`.
+    async fn serve_api_graph(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() != Method::GET {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        let (root, depth) = link_graph::parse_query(req.uri().query().unwrap_or(""))?;
+        let root = match root {
+            Some(root) => root,
+            None => {
+                let body = serde_json::json!({ "error": "root is required" });
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(body.to_string()))?;
+                return Ok(response);
+            }
+        };
+
+        let graph = {
+            let locked = self.inner.read().await;
+            link_graph::collect(&locked, &root, depth).await?
+        };
+        if !graph.nodes[0].exists {
+            return Err(RouteError::NotFound.into());
+        }
+
+        let nodes: Vec<_> = graph
+            .nodes
+            .iter()
+            .map(|node| {
+                serde_json::json!({
+                    "name": node.name,
+                    "distance": node.distance,
+                    "exists": node.exists,
+                    "link": RouteWiki::to(&node.name).to_string(),
+                })
+            })
+            .collect();
+        let edges: Vec<_> = graph
+            .edges
+            .iter()
+            .map(|(source, target)| serde_json::json!({ "source": source, "target": target }))
+            .collect();
+
+        let body = serde_json::json!({
+            "root": root,
+            "depth": depth,
+            "truncated": graph.truncated,
+            "nodes": nodes,
+            "edges": edges,
+        });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// Runs a GraphQL query posted as JSON, see `graphql.rs`. Errors in the
+    /// query, including going past the schema's depth and complexity
+    /// limits, come back in the response's `errors`, with a 200, as GraphQL
+    /// clients expect.
+    async fn serve_graphql(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let schema = self.graphql.as_ref().ok_or(RouteError::NotFound)?;
+        if req.method() != Method::POST {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        let env = graphql::Env {
+            inner: self.inner.clone(),
+            plugins: self.plugins.clone(),
+            render_cache: self.render_cache.clone(),
+            search_weights: self.config.search_weights,
+            may_review: self.may_review(&req),
+        };
+        let body_bytes = match read_body(req.into_body(), graphql::MAX_REQUEST_SIZE).await? {
+            Some(body_bytes) => body_bytes,
+            None => {
+                let message = format!("the request is over {} bytes", graphql::MAX_REQUEST_SIZE);
+                let body = serde_json::json!({ "error": message });
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::from(body.to_string()))?;
+                return Ok(response);
+            }
+        };
+        let request: async_graphql::Request = match serde_json::from_slice(&body_bytes) {
+            Ok(request) => request,
+            Err(err) => {
+                let body = serde_json::json!({ "error": err.to_string() });
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(body.to_string()))?;
+                return Ok(response);
+            }
+        };
+
+        let result = schema.execute(request.data(env)).await;
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(serde_json::to_string(&result)?))?;
+
+        Ok(response)
+    }
+
+    async fn serve_api_titles(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() != Method::GET {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        let QueryParams(params) = QueryParams::<TitlesParams>::from_request(&req)?;
+        let query = match params.q {
+            Some(query) => query,
+            None => {
+                let body = serde_json::json!({ "error": "q is required" });
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(body.to_string()))?;
+                return Ok(response);
+            }
+        };
+
+        let names = {
+            let locked = self.inner.read().await;
+            titles::similar(&locked, &query, titles::SWITCHER_RESULTS).await?
+        };
+        let titles: Vec<_> = names
+            .iter()
+            .map(|name| serde_json::json!({ "name": name, "link": RouteWiki::to(name).to_string() }))
+            .collect();
+
+        let body = serde_json::json!({ "query": query, "titles": titles });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    async fn serve_api_data(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() != Method::GET {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        let directives = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes());
+        let query = match data::Query::from_directives(directives) {
+            Ok(query) => query,
+            Err(err) => {
+                let body = serde_json::json!({ "error": err });
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(body.to_string()))?;
+                return Ok(response);
+            }
+        };
+
+        let locked = self.inner.read().await;
+        let rows: Vec<_> = query
+            .run(&locked)
+            .await?
+            .into_iter()
+            .map(|(name, data)| serde_json::json!({ "name": name, "data": data }))
+            .collect();
+
+        let body = serde_json::json!({ "pages": rows });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    async fn serve_api_wiki_append_post(
+        &self,
+        req: Request<Body>,
+        ra: &RouteApiWiki<'_>,
+    ) -> DynResult<Response<Body>> {
+        let user = CurrentUser::of(&req).cloned();
+        let user_id = CurrentUser::attribution(&req);
+        let pending = self.held_for_review(&req);
+        let confirmed = req.headers().contains_key(edit_filter::CONFIRM_HEADER);
+        let QueryParams(SaveParams { dry_run, heading }) = QueryParams::from_request(&req)?;
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let block = macros::expand(&String::from_utf8_lossy(&body_bytes), &user_id, Utc::now());
+
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+
+        let current = queries.fetch_current_text_for_update(&tx, &ra.name).await?;
+        let mut document_data =
+            append::append_block(current.as_deref().unwrap_or(""), &block, heading.as_deref());
+
+        let save = SaveContext {
+            name: &ra.name,
+            user: user.as_ref(),
+            attribution: &user_id,
+        };
+        if let Err(err) = self.plugins.pre_save(&save, &mut document_data) {
+            return plugin_error_response(err);
+        }
+
+        let checked = edit_filter::check(
+            queries,
+            &tx,
+            &save,
+            &document_data,
+            confirmed,
+            self.config.secrets_policy,
+        );
+        let matched = match checked.await? {
+            Ok(matched) => matched,
+            Err(refusal) => {
+                // a dry run leaves no trace of the refusal either
+                if dry_run {
+                    tx.rollback().await?;
+                } else {
+                    tx.commit().await?;
+                }
+                return self.edit_filter_refusal(refusal, false);
+            }
+        };
+        if dry_run {
+            tx.rollback().await?;
+            drop(locked);
+            return self.dry_run_response(&ra.name, &document_data, pending, &matched, false).await;
+        }
+        let document_history_id = if pending {
+            queries
+                .store_pending_revision(&tx, &ra.name, &user_id, &document_data)
+                .await?
+        } else {
+            let index = index_document(&document_data);
+            queries
+                .store_revision(&tx, &ra.name, &user_id, &document_data, &index)
+                .await?
+        };
+        edit_filter::record(queries, &tx, &save, &matched, document_history_id).await?;
+
+        tx.commit().await?;
+        if !pending {
+            self.plugins.post_save(&save, document_history_id);
+            self.warm_render_cache(save.name);
+        }
+
+        let body = serde_json::json!({
+            "name": ra.name,
+            "revision": document_history_id,
+            "pending": pending,
+            "unchanged": false,
+        });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(if pending { StatusCode::ACCEPTED } else { StatusCode::OK })
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// What a save with `?dry_run=true` would have done: the edit filters
+    /// that would tag it, secrets found, lint warnings and the page as it
+    /// would render, or that it would change nothing. The caller has rolled
+    /// its transaction back and let go of the write lock, so linting and
+    /// rendering don't hold up saves.
+    async fn dry_run_response(
+        &self,
+        name: &str,
+        document_data: &str,
+        pending: bool,
+        matched: &edit_filter::Matched,
+        unchanged: bool,
+    ) -> DynResult<Response<Body>> {
+        let inner = &*self.inner.read().await;
+        let warnings: Vec<_> = lint::lint(inner, document_data)
+            .await?
+            .into_iter()
+            .map(|warning| {
+                serde_json::json!({
+                    "rule": warning.rule.as_str(),
+                    "line": warning.line,
+                    "message": warning.message,
+                })
+            })
+            .collect();
+        let filters: Vec<_> = matched.filters.iter().map(|filter| &filter.name).collect();
+        let secrets: Vec<_> = matched
+            .secrets
+            .iter()
+            .map(|finding| serde_json::json!({ "kind": finding.kind, "line": finding.line }))
+            .collect();
+        let html = render_document(inner, &self.plugins, document_data).await?;
+
+        let body = serde_json::json!({
+            "name": name,
+            "dry_run": true,
+            "pending": pending,
+            "unchanged": unchanged,
+            "edit_filters": filters,
+            "secrets": secrets,
+            "warnings": warnings,
+            "text": document_data,
+            "html": html,
+        });
+        let response = Response::builder()
+            .header("Content-Type", "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))?;
+
+        Ok(response)
+    }
+
+    /// A challenge for the page to solve before saving, if the request
+    /// will have to pass one.
+    fn issue_challenge(&self, req: &Request<Body>) -> DynResult<Option<IssuedChallenge>> {
+        match self.challenger {
+            Some(ref challenger) if !CurrentUser::has_trust(req, TrustLevel::Autoconfirmed) => {
+                Ok(Some(challenger.issue()?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Refuses a write by an editor below `TrustLevel::Autoconfirmed`
+    /// without a solved challenge. The refusal carries a new challenge, so
+    /// API clients can solve it and retry.
+    fn unsolved_challenge(&self, req: &Request<Body>) -> DynResult<Option<Response<Body>>> {
+        let challenger = match self.challenger {
+            Some(ref challenger) if !CurrentUser::has_trust(req, TrustLevel::Autoconfirmed) => challenger,
+            _ => return Ok(None),
+        };
+        match challenger.verify(req.headers()) {
+            Ok(()) => Ok(None),
+            Err(err) => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/plain; charset=utf8")
+                    .header(challenge::CHALLENGE_HEADER, challenger.issue()?.token)
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::from(err.to_string()))?;
+                Ok(Some(response))
+            }
+        }
+    }
+
+    /// Whether a save by the request's user waits at `/review`: under
+    /// `--moderation` unless they're autoconfirmed, and always for an
+    /// anonymous visitor's new page under `--anonymous-new-pages`.
+    fn held_for_review(&self, req: &Request<Body>) -> bool {
+        (self.config.moderation && !CurrentUser::has_trust(req, TrustLevel::Autoconfirmed))
+            || NewPageRequest::of(req)
+    }
+
+    /// Answers a save an edit filter refused. A warned save can be resent
+    /// with `edit_filter::CONFIRM_HEADER`; an anonymous editor's challenge
+    /// has been spent by then, so the editor page gets a new one with the
+    /// warning.
+    fn edit_filter_refusal(
+        &self,
+        refusal: edit_filter::Refusal,
+        challenged: bool,
+    ) -> DynResult<Response<Body>> {
+        let (status, message) = match refusal {
+            edit_filter::Refusal::Block(message) => (StatusCode::FORBIDDEN, message),
+            edit_filter::Refusal::Warn(message) => (StatusCode::PRECONDITION_REQUIRED, message),
+        };
+        let mut response = Response::builder()
+            .header("Content-Type", "text/plain; charset=utf8")
+            .status(status);
+        if let (StatusCode::PRECONDITION_REQUIRED, true, Some(challenger)) =
+            (status, challenged, &self.challenger)
+        {
+            response = response.header(challenge::CHALLENGE_HEADER, challenger.issue()?.token);
+        }
+        Ok(response.body(Body::from(message))?)
+    }
+
+    /// Renders `name` and the pages showing it in the background, so the
+    /// first reader after a save gets a cached page.
+    fn warm_render_cache(&self, name: &str) {
+        let handler = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let warmed = handler
+                .render_cache
+                .warm(&handler.inner, &handler.plugins, &name)
+                .await;
+            if let Err(err) = warmed {
+                event!(Level::WARN, "warming the render cache for {:?}: {}", name, err);
+            }
+        });
+    }
+
+    /// Renders `name` again in the background after a stale rendering was
+    /// served, see `--render-stale-secs`.
+    fn refresh_render_cache(&self, name: &str) {
+        let handler = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let refreshed = handler
+                .render_cache
+                .refresh(&handler.inner, &handler.plugins, &name)
+                .await;
+            if let Err(err) = refreshed {
+                event!(Level::WARN, "refreshing the render cache for {:?}: {}", name, err);
+            }
+        });
+    }
+
+    async fn current_user(&self, req: &Request<Body>) -> DynResult<Option<auth::User>> {
+        let token = match auth::cookie(req, auth::SESSION_COOKIE) {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        let locked = self.inner.read().await;
+        let session = locked
+            .queries
+            .fetch_session_user(&locked.db, token)
+            .await?;
+        if session.as_ref().is_some_and(|session| session.seen_stale) {
+            let client = auth::SessionClient::of(req);
+            locked
+                .queries
+                .touch_session(&locked.db, token, client.addr.as_deref(), client.user_agent.as_deref())
+                .await?;
+        }
+
+        Ok(session.map(|session| {
+            let is_admin = self.config.admins.contains(&session.username)
+                && (session.two_factor || !self.config.require_admin_2fa);
+            auth::User {
+                trust: self.config.trust.level(session.edit_count, session.age_days, is_admin),
+                is_admin,
+                username: session.username,
+                preferences: session.preferences,
+                edit_count: session.edit_count,
+            }
+        }))
+    }
+
+    async fn login_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() == Method::POST {
+            return self.login_page_post(req).await;
+        }
+
+        let login = self.login_form(self.page_context(&req), None);
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(login.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn login_page_post(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let client = auth::SessionClient::of(&req);
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut username = String::new();
+        let mut password = String::new();
+        let mut pending = String::new();
+        let mut code = String::new();
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "username" => username = value.into_owned(),
+                "password" => password = value.into_owned(),
+                "pending" => pending = value.into_owned(),
+                "code" => code = value.into_owned(),
+                _ => (),
+            }
+        }
+        if !pending.is_empty() {
+            return self.login_code_post(ctx, &client, &pending, &code).await;
+        }
+
+        let locked = self.inner.read().await;
+        let credentials = locked.queries.fetch_user_credentials(&locked.db, &username).await?;
+        if credentials.is_none() {
+            auth::verify_no_password(&password);
+        }
+        let credentials =
+            credentials.filter(|credentials| auth::verify_password(&password, &credentials.password_hash));
+
+        let credentials = match credentials {
+            Some(credentials) if credentials.awaiting_verification => {
+                // the password was right, so it's safe to say why and to
+                // mail the link again
+                let account = locked
+                    .queries
+                    .fetch_account_by_id(&locked.db, credentials.user_id)
+                    .await?;
+                drop(locked);
+                if let (Some(accounts), Some(account)) = (&self.accounts, account) {
+                    if let Some(ref email) = account.email {
+                        accounts.send(accounts::Purpose::Verify, &account, email).await?;
+                    }
+                }
+                let login = self.login_form(
+                    ctx,
+                    Some("Follow the link mailed to you to confirm your address before logging in. It has been sent again, unless it already was several times this hour."),
+                );
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::from(login.render()?))?;
+                return Ok(response);
+            }
+            Some(credentials) => credentials,
+            None => {
+                let login = self.login_form(ctx, Some("Incorrect username or password."));
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::from(login.render()?))?;
+                return Ok(response);
+            }
+        };
+
+        if credentials.two_factor {
+            let mut login = self.login_form(ctx, None);
+            login.pending = Some(
+                self.pending_logins
+                    .issue(credentials.user_id, &credentials.password_hash),
+            );
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::OK)
+                .body(Body::from(login.render()?))?;
+            return Ok(response);
+        }
+
+        self.start_session(&locked, credentials.user_id, &client, false).await
+    }
+
+    /// The second step of logging in with two-factor authentication: a code
+    /// from the authenticator app, or a recovery code.
+    async fn login_code_post(
+        &self,
+        ctx: views::PageContext,
+        client: &auth::SessionClient,
+        pending: &str,
+        code: &str,
+    ) -> DynResult<Response<Body>> {
+        let locked = self.inner.read().await;
+        let state = match two_factor::PendingLogins::user_of(pending) {
+            Some(user_id) => locked
+                .queries
+                .fetch_two_factor(&locked.db, user_id)
+                .await?
+                .filter(|state| self.pending_logins.verify(pending, user_id, &state.password_hash))
+                .map(|state| (user_id, state)),
+            None => None,
+        };
+        let (user_id, state) = match state {
+            Some(state) => state,
+            None => {
+                let login = self.login_form(ctx, Some("That took too long; log in again."));
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::from(login.render()?))?;
+                return Ok(response);
+            }
+        };
+
+        let (status, error) = if self.pending_logins.is_locked_out(user_id) {
+            (StatusCode::TOO_MANY_REQUESTS, "Too many wrong codes; wait a few minutes and try again.")
+        } else if self.check_second_factor(&locked, user_id, &state, code).await? {
+            return self.start_session(&locked, user_id, client, true).await;
+        } else {
+            (StatusCode::UNAUTHORIZED, "That code isn't right.")
+        };
+        let mut login = self.login_form(ctx, Some(error));
+        login.pending = Some(pending.to_string());
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(status)
+            .body(Body::from(login.render()?))?;
+        Ok(response)
+    }
+
+    /// Whether `code` is the current code for `user_id` or one of their
+    /// recovery codes, using it up either way. Wrong codes count towards
+    /// a lockout.
+    async fn check_second_factor(
+        &self,
+        inner: &HandlerInner,
+        user_id: i64,
+        state: &queries::TwoFactorState,
+        code: &str,
+    ) -> DynResult<bool> {
+        let passed = match state.secret {
+            // turned off since the password was checked
+            None => true,
+            Some(ref secret) => match two_factor::check_code(secret, code, state.last_step) {
+                Some(step) => inner.queries.advance_totp_step(&inner.db, user_id, step).await?,
+                None => {
+                    inner
+                        .queries
+                        .use_recovery_code(&inner.db, user_id, &two_factor::hash_recovery_code(code))
+                        .await?
+                }
+            },
+        };
+        if !passed {
+            self.pending_logins.record_failure(user_id);
+        }
+        Ok(passed)
+    }
+
+    async fn start_session(
+        &self,
+        inner: &HandlerInner,
+        user_id: i64,
+        client: &auth::SessionClient,
+        two_factor: bool,
+    ) -> DynResult<Response<Body>> {
+        let token = auth::new_session_token()?;
+        inner
+            .queries
+            .insert_session(
+                &inner.db,
+                &token,
+                user_id,
+                client.addr.as_deref(),
+                client.user_agent.as_deref(),
+                two_factor,
+            )
+            .await?;
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(
+                header::LOCATION,
+                format!("{}?flash=logged-in", RouteWiki::to("sample_doc")),
+            )
+            .header(header::SET_COOKIE, auth::session_cookie(&token))
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    fn login_form<'e>(&self, ctx: views::PageContext, error: Option<&'e str>) -> views::login::Login<'e> {
+        views::login::Login {
+            ctx,
+            error,
+            can_register: self.config.open_registration,
+            can_reset: self.accounts.is_some(),
+            pending: None,
+        }
+    }
+
+    /// Mailing links for accounts, see `accounts.rs`; not found without a
+    /// mailer.
+    fn accounts(&self) -> Result<&Accounts, RouteError> {
+        self.accounts.as_deref().ok_or(RouteError::NotFound)
+    }
+
+    async fn register_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if !self.config.open_registration {
+            return Err(RouteError::NotFound.into());
+        }
+        if req.method() == Method::POST {
+            return self.register_page_post(req).await;
+        }
+
+        let page = views::account::Register {
+            ctx: self.page_context(&req),
+            username: "",
+            email: "",
+            error: None,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn register_page_post(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let accounts = self.accounts()?;
+        let ctx = self.page_context(&req);
+        let addr = ClientInfo::of(&req).map(|client| client.addr);
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut username = String::new();
+        let mut email = String::new();
+        let mut password = String::new();
+        let mut confirm = String::new();
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "username" => username = value.trim().to_string(),
+                "email" => email = value.trim().to_string(),
+                "password" => password = value.into_owned(),
+                "confirm" => confirm = value.into_owned(),
+                _ => (),
+            }
+        }
+
+        let mut error = accounts::username_problem(&username)
+            .or_else(|| {
+                Some("That doesn't look like an email address.")
+                    .filter(|_| !preferences::is_valid_email(&email))
+            })
+            .or_else(|| accounts::password_problem(&password, &confirm))
+            .map(|message| (message, StatusCode::BAD_REQUEST));
+        if error.is_none() && !accounts.allow_client(addr) {
+            error = Some((
+                "Too many accounts were asked for from this address in the last hour; try again later.",
+                StatusCode::TOO_MANY_REQUESTS,
+            ));
+        }
+
+        if error.is_none() {
+            let locked = self.inner.read().await;
+            let password_hash = auth::hash_password(&password)?;
+            match locked
+                .queries
+                .register_user(&locked.db, &username, &password_hash)
+                .await?
+            {
+                Some(user_id) => {
+                    let preferences = Preferences {
+                        email: Some(email.clone()),
+                        ..Preferences::default()
+                    };
+                    locked
+                        .queries
+                        .upsert_preferences(&locked.db, &username, &preferences)
+                        .await?;
+                    let account = locked
+                        .queries
+                        .fetch_account_by_id(&locked.db, user_id)
+                        .await?
+                        .ok_or(RouteError::NotFound)?;
+                    drop(locked);
+                    accounts.send(accounts::Purpose::Verify, &account, &email).await?;
+
+                    let res = Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header(header::LOCATION, format!("{}?flash=verify-sent", Route::Login))
+                        .body(Body::empty())
+                        .expect("unable to build response");
+                    return Ok(res);
+                }
+                None => error = Some(("That username is taken.", StatusCode::CONFLICT)),
+            }
+        }
+
+        let (message, status) = error.expect("only errors get this far");
+        let page = views::account::Register {
+            ctx,
+            username: &username,
+            email: &email,
+            error: Some(message),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(status)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    fn bad_link(&self, ctx: views::PageContext, err: accounts::TokenError) -> DynResult<Response<Body>> {
+        let page = views::account::BadLink {
+            ctx,
+            message: err.to_string(),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn verify_email_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let accounts = self.accounts()?;
+        let ctx = self.page_context(&req);
+        let token = accounts::parse_token(req.uri().query())?;
+
+        let locked = self.inner.read().await;
+        let account = match Accounts::user_of(&token) {
+            Some(user_id) => locked.queries.fetch_account_by_id(&locked.db, user_id).await?,
+            None => None,
+        };
+        // bound to the current address, so changing it voids older links
+        let email = account.as_ref().and_then(|account| account.email.as_deref());
+        let (account, email) = match (account.as_ref(), email) {
+            (Some(account), Some(email)) => match accounts.verify(accounts::Purpose::Verify, &token, email) {
+                Ok(()) => (account, email),
+                Err(err) => return self.bad_link(ctx, err),
+            },
+            _ => return self.bad_link(ctx, accounts::TokenError::Invalid),
+        };
+        locked
+            .queries
+            .set_verified_email(&locked.db, account.id, email)
+            .await?;
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash=email-verified", Route::Login))
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    async fn forgot_password_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let accounts = self.accounts()?;
+        if req.method() != Method::POST {
+            let page = views::account::Forgot {
+                ctx: self.page_context(&req),
+            };
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::OK)
+                .body(Body::from(page.render()?))?;
+            return Ok(response);
+        }
+
+        if !accounts.allow_client(ClientInfo::of(&req).map(|client| client.addr)) {
+            let response = Response::builder()
+                .header("Content-Type", "text/plain; charset=utf8")
+                .header(header::RETRY_AFTER, "3600")
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body(Body::from("Too many password resets were asked for from this address in the last hour; try again later."))?;
+            return Ok(response);
+        }
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let name = form_urlencoded::parse(&body_bytes)
+            .find(|(key, _)| key == "account")
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default();
+
+        let account = {
+            let locked = self.inner.read().await;
+            match locked.queries.fetch_account_by_username(&locked.db, &name).await? {
+                Some(account) => Some(account),
+                None => {
+                    locked
+                        .queries
+                        .fetch_account_by_verified_email(&locked.db, &name)
+                        .await?
+                }
+            }
+        };
+        if let Some(account) = account {
+            if let Some(to) = account.reset_address() {
+                accounts.send(accounts::Purpose::Reset, &account, to).await?;
+            }
+        }
+
+        // the same answer whether or not there's such an account
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash=reset-sent", Route::Login))
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    async fn reset_password_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let accounts = self.accounts()?;
+        let ctx = self.page_context(&req);
+        let is_post = req.method() == Method::POST;
+        let mut token = accounts::parse_token(req.uri().query())?;
+        let mut password = String::new();
+        let mut confirm = String::new();
+        if is_post {
+            let body_bytes = hyper::body::to_bytes(req).await?;
+            for (key, value) in form_urlencoded::parse(&body_bytes) {
+                match &key[..] {
+                    "token" => token = value.into_owned(),
+                    "password" => password = value.into_owned(),
+                    "confirm" => confirm = value.into_owned(),
+                    _ => (),
+                }
+            }
+        }
+
+        let locked = self.inner.read().await;
+        let account = match Accounts::user_of(&token) {
+            Some(user_id) => locked.queries.fetch_account_by_id(&locked.db, user_id).await?,
+            None => None,
+        };
+        // bound to the password hash, so the link stops working once used
+        let account = match account {
+            Some(account) => match accounts.verify(accounts::Purpose::Reset, &token, &account.password_hash) {
+                Ok(()) => account,
+                Err(err) => return self.bad_link(ctx, err),
+            },
+            None => return self.bad_link(ctx, accounts::TokenError::Invalid),
+        };
+
+        let error = if is_post {
+            accounts::password_problem(&password, &confirm)
+        } else {
+            None
+        };
+        if !is_post || error.is_some() {
+            let page = views::account::Reset {
+                ctx,
+                token: &token,
+                error,
+            };
+            let status = if error.is_some() { StatusCode::BAD_REQUEST } else { StatusCode::OK };
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(status)
+                .body(Body::from(page.render()?))?;
+            return Ok(response);
+        }
+
+        let password_hash = auth::hash_password(&password)?;
+        locked
+            .queries
+            .reset_password(&locked.db, account.id, &password_hash)
+            .await?;
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash=password-reset", Route::Login))
+            .header(header::SET_COOKIE, auth::expired_session_cookie())
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    /// Ends the session. Only a POST does, so a link or an image on another
+    /// site can't log anyone out.
+    async fn logout_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() != Method::POST {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))?;
+            return Ok(response);
+        }
+
+        if let Some(token) = auth::cookie(&req, auth::SESSION_COOKIE) {
+            let locked = self.inner.read().await;
+            locked.queries.delete_session(&locked.db, token).await?;
+        }
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash=logged-out", Route::Login))
+            .header(header::SET_COOKIE, auth::expired_session_cookie())
+            .body(Body::empty())
+            .expect("unable to build response");
+        Ok(res)
+    }
+
+    /// Whether `user` may edit `name` as far as page protection goes, see
+    /// `protection.rs`.
+    fn may_edit(&self, name: Option<&str>, user: Option<&auth::User>) -> bool {
+        let level = name.and_then(|name| self.protection.level_for(name));
+        permissions::is_allowed_on_page(level, user)
+    }
+
+    /// Starts a transaction whose statements Postgres cancels once the
+    /// request would have timed out anyway: dropping the transaction rolls
+    /// it back, but doesn't stop a statement still running on the shared
+    /// connection. Outside transactions `--statement-timeout-ms` applies.
+    async fn transaction<'a>(&self, db: &'a mut tokio_postgres::Client) -> DynResult<tokio_postgres::Transaction<'a>> {
+        let tx = db.transaction().await?;
+        let limit = self.config.timeouts.limit(RequestClass::Write);
+        tx.batch_execute(&format!("SET LOCAL statement_timeout = {}", limit.as_millis()))
+            .await?;
+        Ok(tx)
+    }
+
+    /// Whether the request may see held and rejected revisions.
+    fn may_review(&self, req: &Request<Body>) -> bool {
+        permissions::is_allowed(self.config.site_policy, CurrentUser::of(req), Action::Review)
+    }
+
+    /// Whether `user` may change the page `name` on top of being allowed
+    /// to edit at all: its protection level, and site pages only as an
+    /// admin, as `Action::for_request` has it for saves.
+    fn may_write(&self, name: &str, user: Option<&auth::User>) -> bool {
+        self.may_edit(Some(name), user)
+            && (!permissions::is_site_page(name)
+                || permissions::is_allowed(self.config.site_policy, user, Action::Admin))
+    }
+
+    fn forbidden(&self) -> DynResult<Response<Body>> {
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(format!(
+                r#"Forbidden &mdash; <a href="{}">log in</a> to edit."#,
+                Route::Login
+            )))?;
+
+        Ok(response)
+    }
+
+    /// A 403 for a crawler trying to edit, see `bots.rs`. No login link,
+    /// since there's nobody to follow it.
+    fn bot_forbidden(&self) -> DynResult<Response<Body>> {
+        let response = Response::builder()
+            .header("Content-Type", "text/plain; charset=utf8")
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from("Automated clients may not edit this wiki without logging in."))?;
+
+        Ok(response)
+    }
+
+    async fn changes_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let locked = self.inner.read().await;
+        let unread = unread_markers(&locked, &req).await?;
+        let changes = locked
+            .queries
+            .fetch_recent_changes(&locked.db, 100)
+            .await?
+            .into_iter()
+            .map(|change| match change {
+                queries::Change::Edit {
+                    name,
+                    revision_id,
+                    modified_by,
+                    created_at,
+                } => views::changes::ChangeRecord::Edit(views::changes::EditRecord {
+                    unread: matches!(unread.get(&name), Some(&read) if revision_id > read),
+                    link: RouteWiki::to(&name).to_owned(),
+                    revision_link: RouteWiki::to_revision(&name, revision_id).to_owned(),
+                    name,
+                    revision_id,
+                    modified_by,
+                    created_at: created_at.trunc_subsecs(0),
+                }),
+                queries::Change::Move(entry) => views::changes::ChangeRecord::Move(move_record(entry)),
+            })
+            .collect();
+
+        let page = views::changes::Changes {
+            ctx: self.page_context(&req),
+            changes,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    /// `/about`, `/terms` and `/privacy`. Until the page behind one is
+    /// written, admins get a link to write it and everyone else a 404.
+    async fn legal_page(&self, req: Request<Body>, page: LegalPage) -> DynResult<Response<Body>> {
+        let name = footer::page_name(page);
+        let html = {
+            let locked = self.inner.read().await;
+            match locked.queries.fetch_current_revision(&locked.db, name).await? {
+                Some(revision) => Some(render_document(&locked, &self.plugins, &revision.document_data).await?),
+                None => None,
+            }
+        };
+        let ctx = self.page_context(&req);
+        if html.is_none() && !ctx.is_admin {
+            return Err(RouteError::NotFound.into());
+        }
+
+        let page = views::legal::Legal {
+            ctx,
+            title: footer::title(page),
+            html,
+            edit_link: RouteWiki::to_edit(name).to_owned(),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    /// A form for picking a page, and the map of pages around it drawn by
+    /// `static/graph.js` from `/api/v1/graph`.
+    async fn link_graph_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let (root, depth) = link_graph::parse_query(req.uri().query().unwrap_or(""))?;
+        let page = views::link_graph::LinkGraph {
+            ctx: self.page_context(&req),
+            api_link: root.as_ref().map(|root| link_graph::link(Route::ApiGraph, root, depth)),
+            root,
+            depth,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn unread_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        // the permission check guarantees a user
+        let user = CurrentUser::of(&req).ok_or(RouteError::NotFound)?;
+        let (username, follow) = (user.username.clone(), user.preferences.follow.clone());
+        let locked = self.inner.read().await;
+
+        if req.method() == Method::POST {
+            locked.queries.mark_all_read(&locked.db, &username).await?;
+            let res = Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, format!("{}?flash=marked-read", Route::Unread))
+                .body(Body::empty())
+                .expect("unable to build response");
+            return Ok(res);
+        }
+
+        let pages = locked.queries.fetch_unread_pages(&locked.db, &username).await?;
+        let pages = follow::filter(&locked, &follow, pages)
+            .await?
+            .into_iter()
+            .map(|p| views::unread::UnreadRecord {
+                link: RouteWiki::to(&p.name).to_owned(),
+                diff_link: p
+                    .read_revision_id
+                    .map(|read| RouteWiki::to_diff(&p.name, read, p.current_revision_id).to_owned()),
+                name: p.name,
+                modified_by: p.modified_by,
+                modified_at: p.modified_at.trunc_subsecs(0),
+            })
+            .collect();
+
+        let page = views::unread::Unread {
+            ctx: self.page_context(&req),
+            pages,
+            follow,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn review_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() == Method::POST {
+            return self.review_page_post(req).await;
+        }
+
+        let locked = self.inner.read().await;
+        let pending = locked
+            .queries
+            .fetch_pending_revisions(&locked.db)
+            .await?
+            .into_iter()
+            .map(|p| views::review::PendingRecord {
+                link: RouteWiki::to(&p.name).to_owned(),
+                revision_link: RouteWiki::to_revision(&p.name, p.id).to_owned(),
+                diff_link: p
+                    .current_revision_id
+                    .map(|current| RouteWiki::to_diff(&p.name, current, p.id).to_owned()),
+                name: p.name,
+                revision_id: p.id,
+                modified_by: p.modified_by,
+                created_at: p.created_at.trunc_subsecs(0),
+                size: p.size,
+            })
+            .collect();
+
+        let page = views::review::Review {
+            ctx: self.page_context(&req),
+            pending,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn review_page_post(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let reviewer = CurrentUser::attribution(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut revision_id: Option<i64> = None;
+        let mut decision = None;
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "revision" => revision_id = value.parse().ok(),
+                "decision" => match &value[..] {
+                    "approve" => decision = Some(queries::RevisionStatus::Approved),
+                    "reject" => decision = Some(queries::RevisionStatus::Rejected),
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+
+        let (revision_id, decision) = match (revision_id, decision) {
+            (Some(r), Some(d)) => (r, d),
+            _ => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Bad Request"))?;
+                return Ok(response);
+            }
+        };
+
+        let mut locked = self.inner.write().await;
+        let HandlerInner { db, queries } = &mut *locked;
+        let tx = self.transaction(db).await?;
+        let reviewed = queries
+            .review_revision(&tx, revision_id, decision, &reviewer)
+            .await?;
+        let flash = match (&reviewed, decision) {
+            (None, _) => "already-reviewed",
+            // rolled back, so it stays pending until someone rejects it
+            (Some(revision), queries::RevisionStatus::Approved) if !revision.up_to_date => {
+                let res = Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, format!("{}?flash=review-outdated", Route::Review))
+                    .body(Body::empty())
+                    .expect("unable to build response");
+                return Ok(res);
+            }
+            (Some(revision), queries::RevisionStatus::Approved) => {
+                let index = index_document(&revision.document_data);
+                queries.publish_revision(&tx, revision, &index).await?;
+                "approved"
+            }
+            (Some(..), _) => "rejected",
+        };
+        tx.commit().await?;
+
+        if let (Some(revision), queries::RevisionStatus::Approved) = (&reviewed, decision) {
+            // only anonymous edits are held, so there is no user to pass on
+            let save = SaveContext {
+                name: &revision.name,
+                user: None,
+                attribution: &revision.modified_by,
+            };
+            self.plugins.post_save(&save, revision.id);
+            self.warm_render_cache(save.name);
+        }
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash={}", Route::Review, flash))
+            .body(Body::empty())
+            .expect("unable to build response");
+
+        Ok(res)
+    }
+
+    async fn maintenance_page(
+        &self,
+        req: Request<Body>,
+        report: MaintenanceReport,
+    ) -> DynResult<Response<Body>> {
+        match report {
+            MaintenanceReport::Duplicates => self.duplicates_page(req).await,
+            MaintenanceReport::Wanted => self.wanted_pages_page(req).await,
+            MaintenanceReport::Stale => self.stale_pages_page(req).await,
+        }
+    }
+
+    async fn duplicates_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let pairs = self
+            .duplicates
+            .report(&self.inner)
+            .await?
+            .iter()
+            .map(|pair| views::maintenance::DuplicateRecord {
+                first_link: RouteWiki::to(&pair.first).to_string(),
+                second_link: RouteWiki::to(&pair.second).to_string(),
+                merge_link: format!(
+                    "{}?merge={}",
+                    RouteWiki::to_edit(&pair.first),
+                    form_urlencoded::byte_serialize(pair.second.as_bytes()).collect::<String>()
+                ),
+                similarity: (pair.similarity * 100.0).round() as u32,
+                first: pair.first.clone(),
+                second: pair.second.clone(),
+            })
+            .collect();
+
+        let page = views::maintenance::Duplicates {
+            ctx: self.page_context(&req),
+            pairs,
+            threshold: (duplicates::DEFAULT_THRESHOLD * 100.0).round() as u32,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn stale_pages_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let stale = {
+            let locked = self.inner.read().await;
+            locked.queries.fetch_stale_pages(&locked.db, Utc::now().date_naive()).await?
+        };
+
+        let pages = stale
+            .into_iter()
+            .map(|p| views::maintenance::StaleRecord {
+                link: RouteWiki::to(&p.name).to_owned(),
+                edit_link: RouteWiki::to_edit(&p.name).to_owned(),
+                name: p.name,
+                expires_on: p.expires_on,
+                modified_at: p.modified_at.trunc_subsecs(0),
+                modified_by: p.modified_by,
+            })
+            .collect();
+
+        let page = views::maintenance::Stale {
+            ctx: self.page_context(&req),
+            pages,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    /// Synchronous because it lists `special::REGISTRY`, which in turn holds
+    /// this handler.
+    fn special_pages_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let pages = special::REGISTRY
+            .iter()
+            .map(|page| views::special::SpecialRecord {
+                name: page.name,
+                link: Route::Special(page.name.into()),
+                description: page.description,
+            })
+            .collect();
+
+        let page = views::special::SpecialPages {
+            ctx: self.page_context(&req),
+            pages,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn all_pages_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let (names, unread) = {
+            let locked = self.inner.read().await;
+            let names = locked.queries.fetch_current_names(&locked.db).await?;
+            (names, unread_markers(&locked, &req).await?)
+        };
+
+        let page = views::special::PageList {
+            ctx: self.page_context(&req),
+            title: "All pages",
+            intro: "Every page on the wiki, alphabetically.",
+            empty: "There are no pages yet.",
+            count_heading: None,
+            pages: names
+                .into_iter()
+                .map(|name| views::special::PageRecord {
+                    unread: unread.contains_key(&name),
+                    ..views::special::PageRecord::existing(name)
+                })
+                .collect(),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn orphans_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let names = {
+            let locked = self.inner.read().await;
+            locked.queries.fetch_orphans(&locked.db).await?
+        };
+
+        let page = views::special::PageList {
+            ctx: self.page_context(&req),
+            title: "Orphaned pages",
+            intro: "Pages that no other page links to.",
+            empty: "Every page is linked from somewhere.",
+            count_heading: None,
+            pages: names.into_iter().map(views::special::PageRecord::existing).collect(),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn wanted_pages_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let wanted = {
+            let locked = self.inner.read().await;
+            locked.queries.fetch_wanted(&locked.db).await?
+        };
+
+        let pages = wanted
+            .into_iter()
+            .map(|(name, count)| views::special::PageRecord {
+                link: RouteWiki::to_edit(&name).to_owned(),
+                missing: true,
+                count,
+                unread: false,
+                name,
+            })
+            .collect();
+
+        let page = views::special::PageList {
+            ctx: self.page_context(&req),
+            title: "Wanted pages",
+            intro: "Pages that are linked to but don't exist yet.",
+            empty: "Every link leads to an existing page.",
+            count_heading: Some("Links"),
+            pages,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn random_page(&self, _req: Request<Body>) -> DynResult<Response<Body>> {
+        let name = {
+            let locked = self.inner.read().await;
+            locked.queries.fetch_random_name(&locked.db).await?
+        };
+
+        let location = match name {
+            Some(name) => RouteWiki::to(&name).to_string(),
+            None => Route::Special("AllPages".into()).to_string(),
+        };
+        let response = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, location)
+            .body(Body::empty())?;
+
+        Ok(response)
+    }
+
+    async fn settings_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() == Method::POST {
+            return self.settings_page_post(req).await;
+        }
+
+        // the permission check guarantees a user
+        let user = CurrentUser::of(&req).ok_or(RouteError::NotFound)?;
+        let page = views::settings::Settings {
+            ctx: self.page_context(&req),
+            username: &user.username,
+            preferences: &user.preferences,
+            edit_count: user.edit_count,
+            trust: user.trust,
+            errors: Vec::new(),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn settings_page_post(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let user = CurrentUser::of(&req).cloned().ok_or(RouteError::NotFound)?;
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut preferences = Preferences::default();
+        let mut errors = Vec::new();
+        let non_empty = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "display_name" => preferences.display_name = non_empty(&value),
+                "timezone" => preferences.timezone = value.trim().to_string(),
+                "theme" => preferences.theme = non_empty(&value),
+                "editor" => match value.parse() {
+                    Ok(editor) => preferences.editor = editor,
+                    Err(err) => errors.push(err),
+                },
+                "email" => preferences.email = non_empty(&value),
+                "digest" => preferences.digest = true,
+                "digest_namespaces" => {
+                    preferences.digest_namespaces = value
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .map(|ns| ns.trim_end_matches(':'))
+                        .filter(|ns| !ns.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                "follow" => match follow::parse_list(&value) {
+                    Ok(follow) => preferences.follow = follow,
+                    Err(err) => errors.push(err),
+                },
+                _ => (),
+            }
+        }
+
+        let locked = self.inner.read().await;
+        if !locked
+            .queries
+            .timezone_exists(&locked.db, &preferences.timezone)
+            .await?
+        {
+            errors.push(format!("{:?} is not a known timezone.", preferences.timezone));
+        }
+        if let Some(ref theme) = preferences.theme {
+            if !preferences::THEMES.contains(&&theme[..]) {
+                errors.push(format!("{:?} is not a known theme.", theme));
+            }
+        }
+        if let Some(ref email) = preferences.email {
+            if !preferences::is_valid_email(email) {
+                errors.push(format!("{:?} doesn't look like an email address.", email));
+            }
+        }
+        if preferences.digest && preferences.email.is_none() {
+            errors.push("The weekly digest needs an email address.".to_string());
+        }
+
+        if !errors.is_empty() {
+            let page = views::settings::Settings {
+                ctx,
+                username: &user.username,
+                preferences: &preferences,
+                edit_count: user.edit_count,
+                trust: user.trust,
+                errors,
+            };
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(page.render()?))?;
+            return Ok(response);
+        }
+
+        locked
+            .queries
+            .upsert_preferences(&locked.db, &user.username, &preferences)
+            .await?;
+
+        // a new address has to be confirmed before password resets go to it
+        let mut flash = "settings-saved";
+        if let (Some(accounts), Some(email)) = (&self.accounts, &preferences.email) {
+            if user.preferences.email.as_ref() != Some(email) {
+                let account = locked
+                    .queries
+                    .fetch_account_by_username(&locked.db, &user.username)
+                    .await?;
+                drop(locked);
+                if let Some(account) = account.filter(|account| account.reset_address().is_none()) {
+                    if accounts.send(accounts::Purpose::Verify, &account, email).await? {
+                        flash = "verify-sent-settings";
+                    }
+                }
+            }
+        }
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash={}", Route::Settings, flash))
+            .body(Body::empty())
+            .expect("unable to build response");
+
+        Ok(res)
+    }
+
+    async fn sessions_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let user = CurrentUser::of(&req).cloned().ok_or(RouteError::NotFound)?;
+        let current = auth::cookie(&req, auth::SESSION_COOKIE).unwrap_or("").to_string();
+
+        if req.method() == Method::POST {
+            let body_bytes = hyper::body::to_bytes(req).await?;
+            let session = form_urlencoded::parse(&body_bytes)
+                .find(|(key, _)| key == "session")
+                .map(|(_, value)| value.into_owned())
+                .unwrap_or_default();
+
+            let locked = self.inner.read().await;
+            let res = if session == "all" {
+                locked
+                    .queries
+                    .revoke_all_sessions(&locked.db, &user.username)
+                    .await?;
+                Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, format!("{}?flash=logged-out-everywhere", Route::Login))
+                    .header(header::SET_COOKIE, auth::expired_session_cookie())
+                    .body(Body::empty())
+                    .expect("unable to build response")
+            } else {
+                let id = session.parse().map_err(|_| RouteError::NotFound)?;
+                if !locked
+                    .queries
+                    .revoke_session(&locked.db, &user.username, id)
+                    .await?
+                {
+                    return Err(RouteError::NotFound.into());
+                }
+                Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, format!("{}?flash=session-revoked", Route::Sessions))
+                    .body(Body::empty())
+                    .expect("unable to build response")
+            };
+            return Ok(res);
+        }
+
+        let locked = self.inner.read().await;
+        let page = views::settings::Sessions {
+            ctx: self.page_context(&req),
+            sessions: locked
+                .queries
+                .fetch_user_sessions(&locked.db, &user.username)
+                .await?,
+            current,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn two_factor_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let user = CurrentUser::of(&req).cloned().ok_or(RouteError::NotFound)?;
+        let token = auth::cookie(&req, auth::SESSION_COOKIE).unwrap_or("").to_string();
+        let is_post = req.method() == Method::POST;
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut action = String::new();
+        let mut secret = String::new();
+        let mut code = String::new();
+        let mut password = String::new();
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "action" => action = value.into_owned(),
+                "secret" => secret = value.into_owned(),
+                "code" => code = value.into_owned(),
+                "password" => password = value.into_owned(),
+                _ => (),
+            }
+        }
+
+        let locked = self.inner.read().await;
+        let user_id = locked
+            .queries
+            .fetch_account_by_username(&locked.db, &user.username)
+            .await?
+            .ok_or(RouteError::NotFound)?
+            .id;
+        let mut state = locked
+            .queries
+            .fetch_two_factor(&locked.db, user_id)
+            .await?
+            .ok_or(RouteError::NotFound)?;
+
+        let mut error = None;
+        let mut new_recovery_codes = Vec::new();
+        if is_post && self.pending_logins.is_locked_out(user_id) {
+            error = Some("Too many wrong codes; wait a few minutes and try again.");
+        } else if is_post && state.secret.is_none() && action == "enable" {
+            // a session left open on someone else's computer mustn't be
+            // enough to tie the account to their device
+            if !auth::verify_password(&password, &state.password_hash) {
+                self.pending_logins.record_failure(user_id);
+                error = Some("That password isn't right.");
+            } else if let Some(step) = two_factor::check_code(&secret, &code, None) {
+                new_recovery_codes = two_factor::new_recovery_codes()?;
+                let hashes: Vec<String> =
+                    new_recovery_codes.iter().map(|code| two_factor::hash_recovery_code(code)).collect();
+                locked
+                    .queries
+                    .set_two_factor(&locked.db, user_id, Some((&secret, step)), &hashes)
+                    .await?;
+                // sessions logged in with the password alone end; this one
+                // has just shown both
+                locked.queries.keep_only_session(&locked.db, user_id, &token).await?;
+                state.secret = Some(secret.clone());
+            } else {
+                self.pending_logins.record_failure(user_id);
+                error = Some("That code isn't right. Check that your device's clock is correct.");
+            }
+        } else if is_post && state.secret.is_some() {
+            // changes need a current code, not just the session
+            if !self.check_second_factor(&locked, user_id, &state, &code).await? {
+                error = Some("That code isn't right.");
+            } else if action == "disable" {
+                locked
+                    .queries
+                    .set_two_factor(&locked.db, user_id, None, &[])
+                    .await?;
+                state.secret = None;
+            } else if action == "recovery-codes" {
+                new_recovery_codes = two_factor::new_recovery_codes()?;
+                let hashes: Vec<String> =
+                    new_recovery_codes.iter().map(|code| two_factor::hash_recovery_code(code)).collect();
+                locked
+                    .queries
+                    .replace_recovery_codes(&locked.db, user_id, &hashes)
+                    .await?;
+            }
+        }
+
+        let enrollment = match state.secret {
+            Some(_) => None,
+            None => {
+                // keep the secret the code was tried against
+                if secret.is_empty() || error.is_none() {
+                    secret = two_factor::new_secret()?;
+                }
+                let uri = two_factor::provisioning_uri(&self.config.site_name, &user.username, &secret);
+                Some(views::settings::Enrollment {
+                    qr_svg: two_factor::qr_svg(&uri)?,
+                    secret,
+                })
+            }
+        };
+        let page = views::settings::TwoFactor {
+            ctx,
+            enabled: state.secret.is_some(),
+            recovery_codes_left: state.recovery_codes_left,
+            new_recovery_codes,
+            enrollment,
+            admin_required: self.config.require_admin_2fa && self.config.admins.contains(&user.username),
+            error,
+        };
+        let status = if error.is_some() { StatusCode::BAD_REQUEST } else { StatusCode::OK };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(status)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn admin_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() == Method::POST {
+            return self.admin_page_post(req).await;
+        }
+
+        let appearance = self.appearance.get();
+        let page = views::admin::Admin {
+            ctx: self.page_context(&req),
+            appearance: &appearance,
+            errors: Vec::new(),
+            upload_limits: self.config.upload_limits,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn admin_page_post(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let updated_by = CurrentUser::attribution(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut appearance = (*self.appearance.get()).clone();
+        let (mut set_accent, mut set_link, mut remove_logo) = (false, false, false);
+        let (mut accent_color, mut link_color, mut custom_css) = (None, None, None);
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "set_accent_color" => set_accent = true,
+                "set_link_color" => set_link = true,
+                "accent_color" => accent_color = Some(value.trim().to_ascii_lowercase()),
+                "link_color" => link_color = Some(value.trim().to_ascii_lowercase()),
+                "custom_css" => custom_css = Some(value.replace("\r\n", "\n")),
+                "remove_logo" => remove_logo = true,
+                _ => (),
+            }
+        }
+
+        let locked = self.inner.read().await;
+        if remove_logo {
+            locked.queries.upsert_logo(&locked.db, None, &updated_by).await?;
+            appearance.logo_hash = None;
+            self.appearance.set(appearance);
+            let res = Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, format!("{}?flash=appearance-saved", Route::Admin))
+                .body(Body::empty())
+                .expect("unable to build response");
+            return Ok(res);
+        }
+
+        let mut errors = Vec::new();
+        appearance.accent_color = accent_color.filter(|_| set_accent);
+        appearance.link_color = link_color.filter(|_| set_link);
+        for color in appearance.accent_color.iter().chain(&appearance.link_color) {
+            if !appearance::is_valid_color(color) {
+                errors.push(format!("{:?} is not a colour like #1a2b3c.", color));
+            }
+        }
+        appearance.custom_css = custom_css.unwrap_or_default();
+        if let Err(err) = appearance::check_custom_css(&appearance.custom_css) {
+            errors.push(err);
+        }
+
+        if !errors.is_empty() {
+            let page = views::admin::Admin {
+                ctx,
+                appearance: &appearance,
+                errors,
+                upload_limits: self.config.upload_limits,
+            };
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(page.render()?))?;
+            return Ok(response);
+        }
+
+        locked
+            .queries
+            .upsert_appearance(&locked.db, &appearance, &updated_by)
+            .await?;
+        self.appearance.set(appearance);
+
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash=appearance-saved", Route::Admin))
+            .body(Body::empty())
+            .expect("unable to build response");
+
+        Ok(res)
+    }
+
+    async fn edit_filters_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() == Method::POST {
+            return self.edit_filters_page_post(req).await;
+        }
+
+        let page = self
+            .edit_filters_view(self.page_context(&req), Vec::new(), Default::default(), None)
+            .await?;
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn edit_filters_view(
+        &self,
+        ctx: views::PageContext,
+        errors: Vec<String>,
+        test: views::admin::FilterTest,
+        rejected: Option<queries::EditFilter>,
+    ) -> DynResult<views::admin::EditFilters> {
+        let locked = self.inner.read().await;
+        let mut filters = locked.queries.fetch_edit_filters(&locked.db).await?;
+        if let Some(ref rejected) = rejected {
+            // shown as submitted, so the fix starts from what was typed
+            if let Some(filter) = filters.iter_mut().find(|filter| filter.id == rejected.id) {
+                filter.name = rejected.name.clone();
+                filter.rule = rejected.rule.clone();
+                filter.action = rejected.action.clone();
+                filter.enabled = rejected.enabled;
+            }
+        }
+        let hits = locked.queries.fetch_recent_edit_filter_hits(&locked.db, 50).await?;
+        let secret_findings = locked.queries.fetch_recent_secret_findings(&locked.db, 50).await?;
+        Ok(views::admin::EditFilters {
+            ctx,
+            filters,
+            hits,
+            secret_findings,
+            secrets_policy: self.config.secrets_policy.as_str(),
+            errors,
+            test,
+            rejected,
+        })
+    }
+
+    async fn edit_filters_page_post(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let updated_by = CurrentUser::attribution(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let (mut op, mut id, mut name, mut action, mut enabled) = (None, None, None, None, false);
+        let mut test = views::admin::FilterTest::default();
+        let mut rejected = None;
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "op" => op = Some(value.into_owned()),
+                "id" => id = value.parse::<i64>().ok(),
+                "name" => name = Some(value.trim().to_string()),
+                "action" => action = Some(value.into_owned()),
+                "enabled" => enabled = true,
+                "rule" => test.rule = value.trim().to_string(),
+                "page" => test.page = value.trim().to_string(),
+                "user" => test.user = value.trim().to_string(),
+                "old_text" => test.old_text = value.replace("\r\n", "\n"),
+                "new_text" => test.new_text = value.replace("\r\n", "\n"),
+                _ => (),
+            }
+        }
+
+        let mut errors = Vec::new();
+        match op.as_deref() {
+            Some("save") => {
+                let name = name.unwrap_or_default();
+                if name.is_empty() {
+                    errors.push("A filter needs a name.".to_string());
+                }
+                if let Err(err) = test.rule.parse::<edit_filter::Rule>() {
+                    errors.push(format!("The rule doesn't parse: {}", err));
+                }
+                let action = action.unwrap_or_default();
+                if let Err(err) = action.parse::<edit_filter::Action>() {
+                    errors.push(err);
+                }
+                if errors.is_empty() {
+                    let filter = queries::NewEditFilter {
+                        name: &name,
+                        rule: &test.rule,
+                        action: &action,
+                        enabled,
+                    };
+                    let locked = self.inner.read().await;
+                    locked
+                        .queries
+                        .upsert_edit_filter(&locked.db, id, &filter, &updated_by)
+                        .await?;
+                    let res = Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header(header::LOCATION, format!("{}?flash=filter-saved", Route::EditFilters))
+                        .body(Body::empty())
+                        .expect("unable to build response");
+                    return Ok(res);
+                }
+                // the test console isn't what was submitted, the filter is
+                rejected = Some(queries::EditFilter {
+                    id: id.unwrap_or(0),
+                    name,
+                    rule: std::mem::take(&mut test.rule),
+                    action,
+                    enabled,
+                    updated_by,
+                    updated_at: Utc::now(),
+                });
+                test = Default::default();
+            }
+            Some("delete") => {
+                let id = id.ok_or(RouteError::NotFound)?;
+                let locked = self.inner.read().await;
+                locked.queries.delete_edit_filter(&locked.db, id).await?;
+                let res = Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, format!("{}?flash=filter-deleted", Route::EditFilters))
+                    .body(Body::empty())
+                    .expect("unable to build response");
+                return Ok(res);
+            }
+            Some("test") => {
+                let locked = self.inner.read().await;
+                let user_age_days = match test.user.is_empty() {
+                    true => 0,
+                    false => locked
+                        .queries
+                        .fetch_user_created_at(&locked.db, &test.user)
+                        .await?
+                        .map_or(0, |created_at| (Utc::now() - created_at).num_days()),
+                };
+                let vars = edit_filter::Vars::of(&edit_filter::Edit {
+                    page: &test.page,
+                    old_text: Some(&test.old_text[..]).filter(|text| !text.is_empty()),
+                    new_text: &test.new_text,
+                    anonymous: test.user.is_empty(),
+                    user_age_days,
+                });
+                let filters = if test.rule.is_empty() {
+                    locked.queries.fetch_enabled_edit_filters(&locked.db).await?
+                } else {
+                    vec![queries::EditFilter {
+                        id: 0,
+                        name: "(this rule)".to_string(),
+                        rule: test.rule.clone(),
+                        action: "-".to_string(),
+                        enabled: true,
+                        updated_by: updated_by.clone(),
+                        updated_at: Utc::now(),
+                    }]
+                };
+                test.results = edit_filter::evaluate(&filters, &vars)
+                    .into_iter()
+                    .map(|(filter, result)| views::admin::FilterTestResult {
+                        name: filter.name.clone(),
+                        action: filter.action.clone(),
+                        outcome: match result {
+                            Ok(true) => "matches".to_string(),
+                            Ok(false) => "doesn't match".to_string(),
+                            Err(err) => format!("error: {}", err),
+                        },
+                    })
+                    .collect();
+                if filters.is_empty() {
+                    errors.push("No filters are enabled; enter a rule to test.".to_string());
+                }
+                test.variables = vars
+                    .values
+                    .iter()
+                    .map(|(name, value)| (*name, value.to_string()))
+                    .collect();
+            }
+            _ => errors.push("Unknown request.".to_string()),
+        }
+
+        let status = match op.as_deref() {
+            Some("test") => StatusCode::OK,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let page = self.edit_filters_view(ctx, errors, test, rejected).await?;
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(status)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn protection_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        if req.method() == Method::POST {
+            return self.protection_page_post(req).await;
+        }
+
+        let page = self
+            .protection_view(self.page_context(&req), Vec::new(), String::new(), String::new())
+            .await?;
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn stats_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let today = Utc::now().date_naive();
+        let since = today.and_hms_opt(0, 0, 0).expect("midnight always exists").and_utc()
+            - chrono::Duration::days(stats::DAYS - 1);
+        let (totals, edits, top_editors, queries) = {
+            let locked = self.inner.read().await;
+            (
+                locked.queries.fetch_site_totals(&locked.db).await?,
+                locked.queries.fetch_edits_per_day(&locked.db, &since).await?,
+                locked
+                    .queries
+                    .fetch_top_editors(&locked.db, &since, stats::TOP_EDITORS)
+                    .await?,
+                locked.queries.metrics.summary(5),
+            )
+        };
+        let edits_per_day = stats::daily_counts(&edits, today);
+
+        let page = views::admin::Stats {
+            ctx: self.page_context(&req),
+            sparkline: stats::sparkline(&edits_per_day),
+            recent_edits: edits_per_day.iter().map(|&(_, count)| count).sum(),
+            totals,
+            top_editors: top_editors
+                .into_iter()
+                .map(|(name, edits)| views::admin::EditorRecord { name, edits })
+                .collect(),
+            cache: self.render_cache.stats(),
+            queries,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    /// Find and replace across every page, see `replace.rs`. A preview
+    /// lists the pages that would change; only those still checked when
+    /// it's confirmed are saved, each through the checks a save from the
+    /// editor goes through.
+    async fn replace_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let mut page = views::admin::Replace {
+            ctx: self.page_context(&req),
+            errors: Vec::new(),
+            find: String::new(),
+            replacement: String::new(),
+            regex: false,
+            summary: String::new(),
+            preview: None,
+            outcomes: Vec::new(),
+        };
+        if req.method() != Method::POST {
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::OK)
+                .body(Body::from(page.render()?))?;
+            return Ok(response);
+        }
+
+        let author = CurrentUser::attribution(&req);
+        let user = CurrentUser::of(&req).cloned();
+        let held = self.held_for_review(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let mut op = String::new();
+        let mut checked = HashSet::new();
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "op" => op = value.into_owned(),
+                "find" => page.find = value.into_owned(),
+                "replacement" => page.replacement = value.into_owned(),
+                "regex" => page.regex = true,
+                "summary" => page.summary = value.trim().to_string(),
+                "page" => {
+                    checked.insert(value.into_owned());
+                }
+                _ => (),
+            }
+        }
+
+        let pattern = match replace::Pattern::new(&page.find, page.regex) {
+            Ok(pattern) => pattern,
+            Err(err) => {
+                page.errors.push(err.to_string());
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(page.render()?))?;
+                return Ok(response);
+            }
+        };
+
+        if op == "apply" {
+            let mut locked = self.inner.write().await;
+            let changes: Vec<_> = replace::plan(&locked, &pattern, &page.replacement)
+                .await?
+                .into_iter()
+                .filter(|change| checked.contains(&change.name))
+                .collect();
+            let may_write = |name: &str| self.may_write(name, user.as_ref());
+            let editor = replace::Editor {
+                author: &author,
+                user: user.as_ref(),
+                may_write: &may_write,
+                held,
+                secrets: self.config.secrets_policy,
+            };
+            let summary = Some(&page.summary[..]).filter(|summary| !summary.is_empty());
+            let outcomes = replace::apply(&mut locked, &self.plugins, &changes, &editor, summary).await?;
+            drop(locked);
+
+            for (name, outcome) in outcomes {
+                checked.remove(&name);
+                let problem = outcome.problem();
+                if problem.is_none() {
+                    self.warm_render_cache(&name);
+                }
+                page.outcomes.push(views::admin::ReplaceOutcome {
+                    link: RouteWiki::to(&name).to_owned(),
+                    name,
+                    problem,
+                });
+            }
+            // checked in the preview, but changed since so nothing matches
+            let mut unmatched: Vec<_> = checked.into_iter().collect();
+            unmatched.sort();
+            page.outcomes.extend(unmatched.into_iter().map(|name| views::admin::ReplaceOutcome {
+                link: RouteWiki::to(&name).to_owned(),
+                name,
+                problem: Some("no longer matches".to_string()),
+            }));
+        } else {
+            let changes = replace::plan(&*self.inner.read().await, &pattern, &page.replacement).await?;
+            page.preview = Some(
+                changes
+                    .iter()
+                    .map(|change| views::admin::ReplacePreview {
+                        link: RouteWiki::to(&change.name).to_owned(),
+                        name: change.name.clone(),
+                        diff: change.unified_diff(),
+                    })
+                    .collect(),
+            );
+        }
+
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    async fn protection_view(
+        &self,
+        ctx: views::PageContext,
+        errors: Vec<String>,
+        patterns: String,
+        reason: String,
+    ) -> DynResult<views::admin::Protection> {
+        let locked = self.inner.read().await;
+        let names = locked.queries.fetch_current_names(&locked.db).await?;
+        let rules = self
+            .protection
+            .rules()
+            .iter()
+            .map(|rule| views::admin::ProtectedPattern {
+                pages: names
+                    .iter()
+                    .filter(|name| protection::matches(&rule.pattern, name))
+                    .count(),
+                rule: rule.clone(),
+            })
+            .collect();
+        Ok(views::admin::Protection {
+            ctx,
+            rules,
+            errors,
+            patterns,
+            reason,
+        })
+    }
+
+    async fn protection_page_post(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let ctx = self.page_context(&req);
+        let created_by = CurrentUser::attribution(&req);
+
+        let body_bytes = hyper::body::to_bytes(req).await?;
+        let (mut op, mut id, mut level) = (None, None, None);
+        let (mut patterns, mut reason) = (String::new(), String::new());
+        for (key, value) in form_urlencoded::parse(&body_bytes) {
+            match &key[..] {
+                "op" => op = Some(value.into_owned()),
+                "id" => id = value.parse::<i64>().ok(),
+                "level" => level = Some(value.into_owned()),
+                "patterns" => patterns = value.replace("\r\n", "\n"),
+                "reason" => reason = value.trim().to_string(),
+                _ => (),
+            }
+        }
+
+        let mut errors = Vec::new();
+        let flash = match op.as_deref() {
+            Some("protect") => {
+                let level = level.unwrap_or_default();
+                if let Err(err) = level.parse::<protection::Level>() {
+                    errors.push(err);
+                }
+                let lines: Vec<String> = patterns
+                    .lines()
+                    .map(|line| names::canonical(line.trim(), self.config.page_name_case))
+                    .filter(|line| !line.is_empty())
+                    .collect();
+                if lines.is_empty() {
+                    errors.push("Enter at least one pattern.".to_string());
+                }
+                errors.extend(lines.iter().filter_map(|line| protection::check_pattern(line).err()));
+                if errors.is_empty() {
+                    let mut locked = self.inner.write().await;
+                    let HandlerInner { db, queries } = &mut *locked;
+                    let tx = self.transaction(db).await?;
+                    for line in &lines {
+                        queries
+                            .upsert_protection_rule(&tx, line, &level, &reason, &created_by)
+                            .await?;
+                    }
+                    tx.commit().await?;
+                }
+                "protection-saved"
+            }
+            Some("remove") => {
+                let id = id.ok_or(RouteError::NotFound)?;
+                let locked = self.inner.read().await;
+                locked.queries.delete_protection_rule(&locked.db, id).await?;
+                "protection-removed"
+            }
+            _ => {
+                errors.push("Unknown request.".to_string());
+                ""
+            }
+        };
+
+        if !errors.is_empty() {
+            let page = self.protection_view(ctx, errors, patterns, reason).await?;
+            let response = Response::builder()
+                .header("Content-Type", "text/html; charset=utf8")
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(page.render()?))?;
+            return Ok(response);
+        }
+
+        self.protection.load(&*self.inner.read().await).await?;
+        let res = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("{}?flash={}", Route::Protection, flash))
+            .body(Body::empty())
+            .expect("unable to build response");
+
+        Ok(res)
+    }
+
+    async fn serve_logo(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        match *req.method() {
+            Method::GET | Method::HEAD => {
+                let locked = self.inner.read().await;
+                let logo = locked.queries.fetch_logo(&locked.db).await?.ok_or(RouteError::NotFound)?;
+                attachment_response(&req, logo, "no-cache")
+            }
+            Method::PUT => {
+                let updated_by = CurrentUser::attribution(&req);
+                let data = hyper::body::to_bytes(req).await?;
+                if data.len() > appearance::MAX_LOGO_SIZE {
+                    return logo_error_response(format!(
+                        "The logo is {} bytes; the limit is {}.",
+                        data.len(),
+                        appearance::MAX_LOGO_SIZE
+                    ));
+                }
+                let content_type = match attachments::sniff_image(&data) {
+                    Some(content_type) => content_type,
+                    None => {
+                        return logo_error_response("The logo must be a PNG, JPEG, GIF or WebP image.".to_string())
+                    }
+                };
+                let logo = queries::AttachmentContent {
+                    content_type: content_type.to_string(),
+                    content_hash: attachments::content_hash(&data),
+                    data: data.to_vec(),
+                };
+
+                let locked = self.inner.read().await;
+                locked.queries.upsert_logo(&locked.db, Some(&logo), &updated_by).await?;
+                let mut appearance = (*self.appearance.get()).clone();
+                appearance.logo_hash = Some(logo.content_hash);
+                self.appearance.set(appearance);
+
+                let response = Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty())?;
+                Ok(response)
+            }
+            _ => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .body(Body::from("Method Not Allowed"))?;
+                Ok(response)
+            }
+        }
+    }
+
+    async fn search_page(&self, req: Request<Body>) -> DynResult<Response<Body>> {
+        let QueryParams(params) = QueryParams::<SearchParams>::from_request(&req)?;
+        let query_text = params.q.clone().unwrap_or_default();
+        let parsed = SearchQuery::parse(&query_text).and_then(|mut q| {
+            for (key, value) in params.filters() {
+                q.apply_filter(key, value)?;
+            }
+            Ok(q)
+        });
+        let query = match parsed {
+            Ok(query) => query,
+            Err(err) => {
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Bad Request: {}", err)))?;
+                return Ok(response);
+            }
+        };
+
+        let mut results = Vec::new();
+        let mut did_you_mean = Vec::new();
+        if !query.is_empty() {
+            let locked = self.inner.read().await;
+            for hit in search::run(&locked, &query, &self.config.search_weights, 50).await? {
+                results.push(views::search::SearchResult {
+                    link: RouteWiki::to(&hit.name).to_owned(),
+                    name: hit.name,
+                    last_modified_at: hit.last_modified_at.trunc_subsecs(0),
+                    last_modified_by: hit.last_modified_by,
+                });
+            }
+            did_you_mean = titles::did_you_mean(&locked, &query.all_text()).await?;
+        }
+
+        let page = views::search::Results {
+            ctx: self.page_context(&req),
+            query: &query_text,
+            namespace: params.ns.as_deref().unwrap_or(""),
+            tag: params.tag.as_deref().unwrap_or(""),
+            author: params.author.as_deref().unwrap_or(""),
+            before: params.before.as_deref().unwrap_or(""),
+            after: params.after.as_deref().unwrap_or(""),
+            results,
+            did_you_mean,
+        };
+
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::OK)
+            .body(Body::from(page.render()?))?;
+
+        Ok(response)
+    }
+
+    /// Serves `req`, answering with an error page when handling it fails or
+    /// panics rather than dropping the connection. The panic hook set up in
+    /// `main` has already logged a panic's backtrace by the time it's caught
+    /// here.
+    async fn respond(&self, remote_addr: SocketAddr, req: Request<Body>) -> Response<Body> {
+        let (method, uri) = (req.method().clone(), req.uri().clone());
+        let kind = match AssertUnwindSafe(self.handle(remote_addr, req)).catch_unwind().await {
+            Ok(Ok(response)) => return response,
+            Ok(Err(err)) if matches!(err.downcast_ref(), Some(RouteError::NotFound)) => ErrorKind::NotFound,
+            Ok(Err(err)) if err.downcast_ref::<QueryError>().is_some() => {
+                return bad_query(&uri, &err.to_string());
+            }
+            Ok(Err(err)) => {
+                event!(Level::ERROR, "{} {}: {}", method, uri.path(), err);
+                ErrorKind::Failed
+            }
+            Err(..) => {
+                event!(Level::ERROR, "{} {}: handler panicked", method, uri.path());
+                ErrorKind::Failed
+            }
+        };
+        self.error_page(kind)
+    }
+
+    /// The not found page for a wiki page that doesn't exist, suggesting
+    /// pages with similar names in case of a typo.
+    async fn missing_page(&self, req: &Request<Body>, inner: &HandlerInner, name: &str) -> DynResult<Response<Body>> {
+        let page = views::error::Error {
+            ctx: self.page_context(req),
+            kind: ErrorKind::NotFound,
+            suggestions: titles::similar(inner, name, titles::SUGGESTIONS).await?,
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/html; charset=utf8")
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from(page.render()?))?;
+        Ok(response)
+    }
+
+    fn error_page(&self, kind: ErrorKind) -> Response<Body> {
+        // the page is as an anonymous visitor sees it, since the request
+        // may be gone or its user unknown
+        let page = views::error::Error {
+            ctx: self.page_context(&Request::new(Body::empty())),
+            kind,
+            suggestions: Vec::new(),
+        };
+        let body = page.render().unwrap_or_else(|err| {
+            event!(Level::ERROR, "rendering the error page: {}", err);
+            String::new()
+        });
+        let mut response = Response::builder().header("Content-Type", "text/html; charset=utf8");
+        response = match kind {
+            ErrorKind::NotFound => response.status(StatusCode::NOT_FOUND),
+            ErrorKind::Failed => response.status(StatusCode::INTERNAL_SERVER_ERROR),
+            ErrorKind::Unavailable => response
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(header::RETRY_AFTER, "5"),
+        };
+        response.body(Body::from(body)).expect("unable to build response")
+    }
+
+    fn readyz(&self) -> DynResult<Response<Body>> {
+        let (status, body) = match self.database.outage() {
+            None => (StatusCode::OK, "ok\n".to_string()),
+            Some(outage) => (StatusCode::SERVICE_UNAVAILABLE, format!("{}\n", outage)),
+        };
+        let response = Response::builder()
+            .header("Content-Type", "text/plain; charset=utf8")
+            .header(header::CACHE_CONTROL, "no-store")
+            .status(status)
+            .body(Body::from(body))?;
+        Ok(response)
+    }
+
+    async fn handle(
+        &self,
+        remote_addr: SocketAddr,
+        mut req: Request<Body>,
+    ) -> DynResult<Response<Body>> {
+        let client = ClientInfo::resolve(remote_addr, req.headers(), &self.config.trusted_proxies);
+        // the path alone: query strings carry reset links' tokens
+        event!(Level::INFO, "{} {} {}", client.addr, req.method(), req.uri().path());
+        req.extensions_mut().insert(client);
+
+        // probes carry no site token, and the answer gives nothing away
+        if let Ok(Route::Readyz) = Route::router(req.uri().path()) {
+            return self.readyz();
+        }
+
+        if let Some(ref token) = self.config.site_token {
+            // preflights carry no credentials and answer nothing about the site
+            if !cors::is_preflight(&req) {
+                match site_token::check(&req, token) {
+                    site_token::Check::Allowed => (),
+                    site_token::Check::Unlock { location } => {
+                        return site_token::unlock_response(token, &location);
+                    }
+                    site_token::Check::Denied => return site_token::denied_response(),
+                }
+            }
+        }
+
+        let route = {
+            let path = req.uri().path();
+            match Route::router(path) {
+                Ok(route) => route.to_owned(),
+                Err(err) => {
+                    let decoded = decode_percents(path)?;
+                    match self.plugins.route_owner(&decoded) {
+                        Some(_) => Route::Plugin(decoded.into_owned().into()),
+                        None => match routes::moved(path) {
+                            Some(moved) if req.method() == Method::GET || req.method() == Method::HEAD => {
+                                let location = match req.uri().query() {
+                                    Some(query) => format!("{}?{}", moved, query),
+                                    None => moved.to_string(),
+                                };
+                                let response = Response::builder()
+                                    .status(StatusCode::MOVED_PERMANENTLY)
+                                    .header(header::LOCATION, location)
+                                    .body(Body::empty())?;
+                                return Ok(response);
+                            }
+                            _ => return Err(err.into()),
+                        },
+                    }
+                }
+            }
+        };
+        let case = self.config.page_name_case;
+        let route = match route.with_page_name(|name| names::canonical(name, case)) {
+            // writes go straight to the canonical page, reads are sent there
+            // so the address bar and links settle on one form
+            Some(canonical) if req.method() == Method::GET || req.method() == Method::HEAD => {
+                let location = match req.uri().query() {
+                    Some(query) => format!("{}?{}", canonical, query),
+                    None => canonical.to_string(),
+                };
+                let response = Response::builder()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(header::LOCATION, location)
+                    .body(Body::empty())?;
+                return Ok(response);
+            }
+            Some(canonical) => canonical,
+            None => route,
+        };
+
+        let cors = match self.config.cors {
+            Some(ref cors) if route.is_api() => {
+                cors.allowed_origin(req.headers()).map(|origin| (cors, origin))
+            }
+            _ => None,
+        };
+        match cors {
+            // preflights are answered before permission checks since the
+            // browser never sends credentials with them
+            Some((cors, origin)) if cors::is_preflight(&req) => Ok(cors.preflight(&req, origin)),
+            Some((cors, origin)) => {
+                let mut response = self.handle_route(req, route).await?;
+                cors.apply(&mut response, origin);
+                Ok(response)
+            }
+            None => self.handle_route(req, route).await,
+        }
+    }
+
+    async fn handle_route(
+        &self,
+        mut req: Request<Body>,
+        route: Route<'static>,
+    ) -> DynResult<Response<Body>> {
+        // everything past here may need the database, so while it's down
+        // there's no point trying
+        if !self.database.is_up() && !matches!(route, Route::Static(..) | Route::ApiOpenApi | Route::Metrics) {
+            return Ok(self.error_page(ErrorKind::Unavailable));
+        }
+        let user = self.current_user(&req).await?;
+        let action = Action::for_request(&route, req.method());
+        if action == Action::Edit && user.is_none() && self.is_bot(&req) {
+            return self.bot_forbidden();
+        }
+        let new_page_request = user.is_none() && self.is_new_page_request(&route, &req).await?;
+        if !new_page_request && !permissions::is_allowed(self.config.site_policy, user.as_ref(), action) {
+            return self.forbidden();
+        }
+        if action == Action::Edit && !self.may_edit(permissions::page_of(&route), user.as_ref()) {
+            return self.forbidden();
+        }
+        req.extensions_mut().insert(CurrentUser(user));
+        if permissions::writes_page_text(&route, req.method()) {
+            if let Some(response) = self.unsolved_challenge(&req)? {
+                return Ok(response);
+            }
+        }
+        if new_page_request {
+            if let Some(response) = self.new_page_rate_limit(&req).await? {
+                return Ok(response);
+            }
+            req.extensions_mut().insert(NewPageRequest);
+        }
+
+        if req.method() == Method::GET && route.is_html_page() {
+            assets::warn_stale_templates();
+            let inner = self.inner.read().await;
+            self.sidebar.refresh(&inner, &self.plugins).await?;
+            self.footer.refresh(&inner, &self.plugins).await?;
+        }
+
+        let class = RequestClass::for_request(&route, req.method());
+        let limit = self.config.timeouts.limit(class);
+        let ctx = self.page_context(&req);
+        match tokio::time::timeout(limit, self.dispatch(req, route)).await {
+            Ok(result) => result,
+            Err(..) => {
+                event!(Level::WARN, "request timed out after {:?}", limit);
+                let page = views::timeout::Timeout {
+                    ctx,
+                    write: class == RequestClass::Write,
+                };
+                let response = Response::builder()
+                    .header("Content-Type", "text/html; charset=utf8")
+                    .header(header::RETRY_AFTER, "30")
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from(page.render()?))?;
+                Ok(response)
+            }
+        }
+    }
+
+    fn is_bot(&self, req: &Request<Body>) -> bool {
+        match self.config.bots {
+            Some(ref bots) => bots.is_bot(req.headers()),
+            None => false,
+        }
+    }
+
+    /// Whether `req` is an anonymous visitor creating a page that
+    /// `--anonymous-new-pages` lets them submit for review.
+    async fn is_new_page_request(&self, route: &Route<'_>, req: &Request<Body>) -> DynResult<bool> {
+        if self.config.anonymous_new_pages.is_none() {
+            return Ok(false);
+        }
+        let name = match permissions::page_created_by(route, req.method()) {
+            Some(name) => name.to_string(),
+            None => return Ok(false),
+        };
+        let locked = self.inner.read().await;
+        let existing = locked.queries.fetch_existing_names(&locked.db, &[name]).await?;
+        Ok(existing.is_empty())
+    }
+
+    /// A 429 once the visitor has submitted their hourly allowance of new
+    /// pages. Pages already approved or rejected no longer count.
+    async fn new_page_rate_limit(&self, req: &Request<Body>) -> DynResult<Option<Response<Body>>> {
+        let per_hour = match self.config.anonymous_new_pages {
+            Some(per_hour) => per_hour,
+            None => return Ok(None),
+        };
+        let since = Utc::now() - chrono::Duration::hours(1);
+        let locked = self.inner.read().await;
+        let submitted = locked
+            .queries
+            .fetch_pending_creation_count_by(&locked.db, &CurrentUser::attribution(req), &since)
+            .await?;
+        if submitted < i64::from(per_hour) {
+            return Ok(None);
+        }
+        let response = Response::builder()
+            .header("Content-Type", "text/plain; charset=utf8")
+            .header(header::RETRY_AFTER, "3600")
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .body(Body::from("Too many new pages from this address in the last hour; try again later."))?;
+        Ok(Some(response))
+    }
+
+    async fn dispatch(&self, req: Request<Body>, route: Route<'static>) -> DynResult<Response<Body>> {
+        match route {
+            Route::Root => {
+                let res = Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, "/login")
+                    .body(Body::empty())
+                    .expect("unable to build response");
+                Ok(res)
+            }
+            Route::Login => self.login_page(req).await,
+            Route::Logout => self.logout_page(req).await,
+            Route::Register => self.register_page(req).await,
+            Route::VerifyEmail => self.verify_email_page(req).await,
+            Route::ForgotPassword => self.forgot_password_page(req).await,
+            Route::ResetPassword => self.reset_password_page(req).await,
+            Route::Search => self.search_page(req).await,
+            Route::Changes => self.changes_page(req).await,
+            Route::Review => self.review_page(req).await,
+            Route::Unread => self.unread_page(req).await,
+            Route::Settings => self.settings_page(req).await,
+            Route::TwoFactor => self.two_factor_page(req).await,
+            Route::Sessions => self.sessions_page(req).await,
+            Route::Admin => self.admin_page(req).await,
+            Route::EditFilters => self.edit_filters_page(req).await,
+            Route::Protection => self.protection_page(req).await,
+            Route::Stats => self.stats_page(req).await,
+            Route::Replace => self.replace_page(req).await,
+            Route::Logo => self.serve_logo(req).await,
+            Route::Maintenance(report) => self.maintenance_page(req, report).await,
+            Route::Legal(page) => self.legal_page(req, page).await,
+            Route::LinkGraph => self.link_graph_page(req).await,
+            Route::Special(ref name) => match special::find(name) {
+                Some(page) => (page.handler)(self, req).await,
+                None => Err(RouteError::NotFound.into()),
+            },
+            Route::Plugin(ref path) => match self.plugins.route_owner(path) {
+                Some(plugin) => plugin.handle(req).await,
+                None => Err(RouteError::NotFound.into()),
+            },
+            Route::Wiki(ref article) => self.serve_wiki_page(req, article).await,
+            Route::Attachment(ref ra) => self.serve_attachment(req, ra).await,
+            Route::Blob(ref rb) => self.serve_blob(req, rb).await,
+            Route::BlobPreview(ref rb) => self.serve_blob_preview(req, rb).await,
+            Route::Tag(ref rt) => self.serve_tag(req, rt).await,
+            Route::ApiWiki(ref ra) => self.serve_api_wiki(req, ra).await,
+            Route::ApiData => self.serve_api_data(req).await,
+            Route::ApiChanges => self.serve_api_changes(req).await,
+            Route::ApiLint => self.serve_api_lint(req).await,
+            Route::ApiGraph => self.serve_api_graph(req).await,
+            Route::ApiTitles => self.serve_api_titles(req).await,
+            Route::GraphQl => self.serve_graphql(req).await,
+            Route::Readyz => self.readyz(),
+            Route::Metrics => {
+                let metrics = self.inner.read().await.queries.metrics.render();
+                let response = Response::builder()
+                    .header("Content-Type", "text/plain; version=0.0.4")
+                    .status(StatusCode::OK)
+                    .body(Body::from(metrics))?;
+                Ok(response)
+            }
+            Route::ApiOpenApi => {
+                let response = Response::builder()
+                    .header("Content-Type", "application/json")
+                    .status(StatusCode::OK)
+                    .body(Body::from(OPENAPI_DOCUMENT))?;
+                Ok(response)
+            }
+            Route::Static(ref file) => {
+                if let Some((content_type, data)) = assets::from_disk(file) {
+                    let response = Response::builder()
+                        .header("Content-Type", content_type)
+                        .header(header::CACHE_CONTROL, assets::DEV_CACHE_CONTROL)
+                        .status(StatusCode::OK)
+                        .body(Body::from(data))?;
+                    return Ok(response);
+                }
+                let asset = assets::get(file).ok_or(RouteError::NotFound)?;
+                let accept_encoding = req.headers().get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok());
+                let coding = assets::CODINGS
+                    .iter()
+                    .copied()
+                    .find(|coding| negotiate::accepts_encoding(accept_encoding, coding));
+                let etag = asset.etag(coding);
+                let mut response = Response::builder()
+                    .header(header::ETAG, &etag)
+                    .header(header::CACHE_CONTROL, asset.cache_control())
+                    .header(header::VARY, "Accept-Encoding");
+                let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok());
+                if conditional::none_match(if_none_match, &etag) {
+                    return Ok(response.status(StatusCode::NOT_MODIFIED).body(Body::empty())?);
+                }
+                response = response.header("Content-Type", asset.content_type).status(StatusCode::OK);
+                if let Some(coding) = coding {
+                    response = response.header(header::CONTENT_ENCODING, coding);
+                }
+                Ok(response.body(Body::from(asset.body(coding)))?)
+            }
+        }
+    }
+}
+
+/// Renders a page in its markup, expanding templates and marking links to
+/// pages that don't exist yet.
+async fn render_document(
+    inner: &HandlerInner,
+    plugins: &Plugins,
+    text: &str,
+) -> DynResult<String> {
+    let mut text = text.to_string();
+    plugins.pre_render(&mut text);
+    let markup = markup::of(&text);
+    if markup.expands_markdown() {
+        text = transclusion::expand(inner, &text).await?;
+        text = data::expand(inner, &text).await?;
+    }
+    let targets = markup.link_targets(&text);
+    let rendered = markup.render(&text)?;
+    if targets.is_empty() {
+        return Ok(rendered);
+    }
+
+    let existing = inner.queries.fetch_existing_names(&inner.db, &targets).await?;
+
+    let mut missing: HashSet<String> = targets.into_iter().collect();
+    for name in existing {
+        missing.remove(&name);
+    }
+
+    Ok(links::mark_missing_links(&rendered, &missing))
+}
+
+/// Derives the link graph entries and page data of a revision about to
+/// become current.
+fn index_document(text: &str) -> queries::DocumentIndex {
+    let markup = markup::of(text);
+    queries::DocumentIndex {
+        links: markup.link_targets(text),
+        data: if markup.expands_markdown() { data::extract(text) } else { Vec::new() },
+        expires: front_matter::parse(text).expires,
+    }
+}
+
+/// A line diff from `first` to `second`, rendered as a `diff` code block.
+/// `target` with `source` appended under a heading linking back to it.
+fn merged_text(target: &str, source_name: &str, source: &str) -> String {
+    format!(
+        "{}\n\n## Merged from [{}](<{}>)\n\n{}",
+        target.trim_end(),
+        source_name,
+        RouteWiki::to(source_name),
+        source
+    )
+}
+
+/// The API's entity tag for a revision: its id, quoted.
+fn revision_etag(revision_id: i64) -> String {
+    format!("\"{}\"", revision_id)
+}
+
+/// `?from=` and `?to=` from the history page's compare form.
+#[derive(Deserialize)]
+struct CompareParams {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct DiffParams {
+    page: Option<usize>,
+    /// `start-end` of a collapsed stretch being opened, see `diff.rs`.
+    lines: Option<String>,
+    offset: Option<isize>,
+}
+
+#[derive(Deserialize)]
+struct EditParams {
+    merge: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MergeParams {
+    #[serde(default)]
+    into: String,
+}
+
+#[derive(Deserialize)]
+struct BundleParams {
+    depth: Option<usize>,
+}
+
+/// For API saves and appends; only appends take a heading.
+#[derive(Deserialize)]
+struct SaveParams {
+    #[serde(default, deserialize_with = "query_params::flag")]
+    dry_run: bool,
+    heading: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChangesParams {
+    after: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct TitlesParams {
+    q: Option<String>,
+}
+
+/// `?q=` and the filters from the advanced search form, which sends blank
+/// fields for filters left unset.
+#[derive(Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+    #[serde(default, deserialize_with = "query_params::non_empty")]
+    ns: Option<String>,
+    #[serde(default, deserialize_with = "query_params::non_empty")]
+    tag: Option<String>,
+    #[serde(default, deserialize_with = "query_params::non_empty")]
+    author: Option<String>,
+    #[serde(default, deserialize_with = "query_params::non_empty")]
+    before: Option<String>,
+    #[serde(default, deserialize_with = "query_params::non_empty")]
+    after: Option<String>,
+}
+
+impl SearchParams {
+    /// The filters given, as `SearchQuery::apply_filter` takes them.
+    fn filters(&self) -> Vec<(&'static str, &str)> {
+        let filters = [
+            ("ns", &self.ns),
+            ("tag", &self.tag),
+            ("author", &self.author),
+            ("before", &self.before),
+            ("after", &self.after),
+        ];
+        filters
+            .iter()
+            .filter_map(|(key, value)| Some((*key, value.as_deref()?)))
+            .collect()
+    }
+}
+
+/// Whether an `If-Match` header lets a save replace a page whose current
+/// revision is `current`. `*` matches any existing page; weak tags never
+/// match, as RFC 7232 asks.
+fn if_match_allows(if_match: &str, current: Option<i64>) -> bool {
+    let current = match current {
+        Some(current) => current,
+        None => return false,
+    };
+    let etag = revision_etag(current);
+    if_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Serves attachment contents with the content hash as the ETag, answering
+/// a matching `If-None-Match` with 304 and a `Range` with 206 or 416, see
+/// `conditional.rs`.
+fn attachment_response(
+    req: &Request<Body>,
+    content: queries::AttachmentContent,
+    cache_control: &str,
+) -> DynResult<Response<Body>> {
+    let etag = format!("\"{}\"", content.content_hash);
+    let header_value = |name: header::HeaderName| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    let builder = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header("X-Content-Type-Options", "nosniff");
+    if conditional::none_match(header_value(header::IF_NONE_MATCH), &etag) {
+        return Ok(builder.status(StatusCode::NOT_MODIFIED).body(Body::empty())?);
+    }
+
+    // sliced without copying for a range
+    let data = hyper::body::Bytes::from(content.data);
+    let len = data.len() as u64;
+    let range = if conditional::if_range_allows(header_value(header::IF_RANGE), &etag) {
+        header_value(header::RANGE)
+    } else {
+        None
+    };
+    let response = match conditional::byte_range(range, len) {
+        Ok(None) => builder
+            .header("Content-Type", content.content_type)
+            .status(StatusCode::OK)
+            .body(Body::from(data))?,
+        Ok(Some((first, last))) => builder
+            .header("Content-Type", content.content_type)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", first, last, len))
+            .status(StatusCode::PARTIAL_CONTENT)
+            .body(Body::from(data.slice(first as usize..=last as usize)))?,
+        Err(conditional::Unsatisfiable) => builder
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .body(Body::empty())?,
+    };
+    Ok(response)
+}
+
+fn move_record(entry: queries::MoveEntry) -> views::wiki::MoveRecord {
+    views::wiki::MoveRecord {
+        old_link: RouteWiki::to(&entry.old_name).to_owned(),
+        new_link: RouteWiki::to(&entry.new_name).to_owned(),
+        old_name: entry.old_name,
+        new_name: entry.new_name,
+        moved_by: entry.moved_by,
+        moved_at: entry.moved_at.trunc_subsecs(0),
+        reason: entry.reason,
+    }
+}
+
+/// For a logged-in viewer, each page with changes they haven't read and the
+/// newest revision of it they have, 0 if none. Empty for anonymous viewers.
+async fn unread_markers(inner: &HandlerInner, req: &Request<Body>) -> DynResult<HashMap<String, i64>> {
+    let user = match CurrentUser::of(req) {
+        Some(user) => user,
+        None => return Ok(HashMap::new()),
+    };
+    let pages = inner.queries.fetch_unread_pages(&inner.db, &user.username).await?;
+    Ok(pages
+        .into_iter()
+        .map(|p| (p.name, p.read_revision_id.unwrap_or(0)))
+        .collect())
+}
+
+/// The language of page `name` and its variants that exist, see
+/// `languages.rs`. Nothing unless `--languages` names more than one.
+async fn language_variants(
+    inner: &HandlerInner,
+    languages: &Languages,
+    name: &str,
+) -> DynResult<(Option<String>, Vec<views::wiki::LanguageVariant>)> {
+    if !languages.is_enabled() {
+        return Ok((None, Vec::new()));
+    }
+    let (base, code) = languages.split(name);
+    let language = code.or_else(|| languages.default_code()).map(str::to_string);
+    let variant_names = languages.variant_names(base);
+    let names: Vec<String> = variant_names.iter().map(|(_, name)| name.clone()).collect();
+    let existing = inner.queries.fetch_existing_names(&inner.db, &names).await?;
+    let variants = variant_names
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (_, variant))| existing.contains(variant))
+        .map(|(i, (code, variant))| views::wiki::LanguageVariant {
+            code: code.to_string(),
+            link: RouteWiki::to(&variant).to_owned(),
+            current: variant == name,
+            is_default: i == 0,
+        })
+        .collect();
+    Ok((language, variants))
+}
+
+/// The attachments of page `name` with their previews, for the page view
+/// and the attachments list.
+async fn attachment_records(inner: &HandlerInner, name: &str) -> DynResult<Vec<views::wiki::AttachmentRecord>> {
+    let mut records = Vec::new();
+    for a in inner.queries.fetch_attachments(&inner.db, name).await? {
+        let preview = previews::for_attachment(inner, &a).await?;
+        records.push(views::wiki::AttachmentRecord {
+            link: RouteAttachment::to_file(name, &a.filename).to_owned(),
+            permalink: RouteBlob::to(&a.content_hash, &a.filename).to_owned(),
+            content_hash: a.content_hash,
+            filename: a.filename,
+            content_type: a.content_type,
+            size: a.size_bytes,
+            uploaded_by: a.uploaded_by,
+            uploaded_at: a.uploaded_at.trunc_subsecs(0),
+            preview,
+        });
+    }
+    Ok(records)
+}
+
+/// The 400 for a malformed query string: `{"error": ...}` from the API,
+/// like its other bad requests, and text elsewhere.
+fn bad_query(uri: &Uri, message: &str) -> Response<Body> {
+    let is_api = Route::router(uri.path()).is_ok_and(|route| route.is_api());
+    let (content_type, body) = if is_api {
+        ("application/json", serde_json::json!({ "error": message }).to_string())
+    } else {
+        ("text/plain; charset=utf8", format!("Bad Request: {}", message))
+    };
+    Response::builder()
+        .header("Content-Type", content_type)
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(body))
+        .expect("unable to build response")
+}
+
+fn plugin_error_response(err: PluginError) -> DynResult<Response<Body>> {
+    let response = Response::builder()
+        .header("Content-Type", "text/plain; charset=utf8")
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .body(Body::from(err.to_string()))?;
+
+    Ok(response)
+}
+
+fn logo_error_response(message: String) -> DynResult<Response<Body>> {
+    let response = Response::builder()
+        .header("Content-Type", "text/plain; charset=utf8")
+        .status(StatusCode::UNPROCESSABLE_ENTITY)
+        .body(Body::from(message))?;
+
+    Ok(response)
+}
+
+/// Reads an upload body, stopping as soon as it's past the upload limits:
+/// a chunked body has no Content-Length to check beforehand.
+async fn read_upload(
+    mut body: Body,
+    limits: &attachments::UploadLimits,
+) -> DynResult<Result<Vec<u8>, attachments::AttachmentError>> {
+    use hyper::body::HttpBody;
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk?);
+        if let Err(err) = limits.check(data.len() as u64, 0, 0) {
+            return Ok(Err(err));
+        }
+    }
+    Ok(Ok(data))
+}
+
+/// The whole of `body`, or `None` as soon as it's over `limit` bytes.
+async fn read_body(mut body: Body, limit: usize) -> DynResult<Option<Vec<u8>>> {
+    use hyper::body::HttpBody;
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk?);
+        if data.len() > limit {
+            return Ok(None);
+        }
+    }
+    Ok(Some(data))
+}
+
+fn attachment_error_response(err: attachments::AttachmentError) -> DynResult<Response<Body>> {
+    use attachments::AttachmentError;
+
+    let status = match err {
+        AttachmentError::BadFilename => StatusCode::BAD_REQUEST,
+        AttachmentError::DisallowedType(..) | AttachmentError::ContentMismatch(..) => {
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        }
+        AttachmentError::TooLarge { .. }
+        | AttachmentError::UserQuotaExceeded { .. }
+        | AttachmentError::SiteQuotaExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+    };
+
+    let response = Response::builder()
+        .header("Content-Type", "text/plain; charset=utf8")
+        .status(status)
+        .body(Body::from(err.to_string()))?;
+
+    Ok(response)
+}
+
+fn decode_percents<'a>(string: &'a str) -> Result<std::borrow::Cow<'a, str>, std::str::Utf8Error> {
+    percent_encoding::percent_decode_str(string).decode_utf8()
+}
+
+/// The command line: server flags, and subcommands for maintenance.
+fn app() -> App<'static, 'static> {
+    App::new(CARGO_PKG_NAME)
+        .version(CARGO_PKG_VERSION)
+        .author("Stacey Ell <software@e.staceyell.com>")
+        .arg(
+            Arg::with_name("v")
+                .short("v")
+                .multiple(true)
+                .help("Sets the level of verbosity"),
+        )
+        .arg(
+            Arg::with_name("site-policy")
+                .long("site-policy")
+                .takes_value(true)
+                .possible_values(&["open", "read-only-public"])
+                .default_value("open")
+                .help("Who may edit: everyone, or only logged-in users"),
+        )
+        .arg(
+            Arg::with_name("site-name")
+                .long("site-name")
+                .takes_value(true)
+                .default_value(CARGO_PKG_NAME)
+                .help("Name shown in page titles and the header"),
+        )
+        .arg(
+            Arg::with_name("theme")
+                .long("theme")
+                .takes_value(true)
+                .possible_values(preferences::THEMES)
+                .default_value("light")
+                .help("Colour theme for all pages"),
+        )
+        .arg(
+            Arg::with_name("trusted-proxy")
+                .long("trusted-proxy")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Address range (CIDR) of a reverse proxy whose X-Forwarded-* headers are honoured"),
+        )
+        .arg(
+            Arg::with_name("max-upload-size")
+                .long("max-upload-size")
+                .takes_value(true)
+                .default_value("10485760")
+                .help("Largest attachment that may be uploaded, in bytes"),
+        )
+        .arg(
+            Arg::with_name("upload-quota-user")
+                .long("upload-quota-user")
+                .takes_value(true)
+                .help("Total attachment bytes each user may store"),
+        )
+        .arg(
+            Arg::with_name("upload-quota-site")
+                .long("upload-quota-site")
+                .takes_value(true)
+                .help("Total attachment bytes the whole site may store"),
+        )
+        .arg(
+            Arg::with_name("anonymous-challenge")
+                .long("anonymous-challenge")
+                .takes_value(true)
+                .possible_values(&["none", "proof-of-work"])
+                .default_value("none")
+                .help("Challenge anonymous editors must pass before saving a page"),
+        )
+        .arg(
+            Arg::with_name("challenge-difficulty")
+                .long("challenge-difficulty")
+                .takes_value(true)
+                .default_value("16")
+                .help("Leading zero bits required by the proof-of-work challenge"),
+        )
+        .arg(
+            Arg::with_name("dev")
+                .long("dev")
+                .help("Serve static/ from the source tree on every request, so edits to scripts and the theme stylesheet show on reload; templates are compiled in, and edits to them are only logged until a rebuild"),
+        )
+        .arg(
+            Arg::with_name("graphql")
+                .long("graphql")
+                .help("Answer read-only GraphQL queries for pages, history, search and links at /graphql"),
+        )
+        .arg(
+            Arg::with_name("moderation")
+                .long("moderation")
+                .help("Hold edits by anonymous users for review at /review before they are published"),
+        )
+        .arg(
+            Arg::with_name("anonymous-new-pages")
+                .long("anonymous-new-pages")
+                .takes_value(true)
+                .value_name("PER_HOUR")
+                .help("Let anonymous visitors submit up to this many new pages an hour for review, even under read-only-public"),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Address to serve on, repeatable: HOST:PORT, [::]:PORT or unix:PATH, optionally followed by ,tls-cert=PATH,tls-key=PATH [default: 127.0.0.1:3000, or sockets passed by systemd]"),
+        )
+        .arg(
+            Arg::with_name("cors-origin")
+                .long("cors-origin")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Origin allowed to call /api/v1 from a browser, or * for any; CORS is off unless given"),
+        )
+        .arg(
+            Arg::with_name("cors-methods")
+                .long("cors-methods")
+                .takes_value(true)
+                .default_value("GET, HEAD")
+                .help("Comma-separated methods cross-origin API requests may use"),
+        )
+        .arg(
+            Arg::with_name("cors-headers")
+                .long("cors-headers")
+                .takes_value(true)
+                .default_value("Content-Type,If-Match")
+                .help("Comma-separated request headers cross-origin API requests may send"),
+        )
+        .arg(
+            Arg::with_name("cors-max-age")
+                .long("cors-max-age")
+                .takes_value(true)
+                .default_value("600")
+                .help("Seconds browsers may cache a CORS preflight response"),
+        )
+        .arg(
+            Arg::with_name("timeout-render")
+                .long("timeout-render")
+                .takes_value(true)
+                .default_value("15")
+                .help("Seconds a page view or other read may take before giving up with a 503"),
+        )
+        .arg(
+            Arg::with_name("secrets-policy")
+                .long("secrets-policy")
+                .takes_value(true)
+                .possible_values(&["off", "warn", "block"])
+                .help("What to do with saves that look like they contain credentials such as AWS keys or private keys: ask the editor to confirm, refuse them or not scan at all [default: warn]"),
+        )
+        .arg(
+            Arg::with_name("bot-user-agent")
+                .long("bot-user-agent")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Refuse anonymous edits from user agents containing PATTERN, ignoring case, repeatable; adds to built-in patterns such as bot, crawl and spider"),
+        )
+        .arg(
+            Arg::with_name("allow-bot-edits")
+                .long("allow-bot-edits")
+                .help("Let anonymous clients that look like crawlers open editors and save pages"),
+        )
+        .arg(
+            Arg::with_name("update-links-on-move")
+                .long("update-links-on-move")
+                .help("Always update links to a moved page on the pages linking to it, instead of offering to"),
+        )
+        .arg(
+            Arg::with_name("languages")
+                .long("languages")
+                .takes_value(true)
+                .value_name("CODES")
+                .help("Languages pages may be translated into, comma-separated with the default first, e.g. en,fr,ja; Page/fr is then the French variant of Page"),
+        )
+        .arg(
+            Arg::with_name("search-weights")
+                .long("search-weights")
+                .takes_value(true)
+                .value_name("WEIGHTS")
+                .help("How search ranks matches, as title=W,body=W,recency=W; recency boosts recently edited pages [default: title=1,body=0.1,recency=0.5]"),
+        )
+        .arg(
+            Arg::with_name("render-stale-secs")
+                .long("render-stale-secs")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Keep serving a cached page for up to this long after it goes out of date, even right after an edit, while it is rendered again in the background"),
+        )
+        .arg(
+            Arg::with_name("timeout-write")
+                .long("timeout-write")
+                .takes_value(true)
+                .default_value("30")
+                .help("Seconds a save, upload or other write may take before giving up with a 503"),
+        )
+        .arg(
+            Arg::with_name("timeout-export")
+                .long("timeout-export")
+                .takes_value(true)
+                .default_value("120")
+                .help("Seconds an export bundle may take before giving up with a 503"),
+        )
+        .arg(
+            Arg::with_name("site-token-file")
+                .long("site-token-file")
+                .takes_value(true)
+                .help("File holding a shared secret every request must present, making the whole site private"),
+        )
+        .arg(
+            Arg::with_name("page-name-case")
+                .long("page-name-case")
+                .takes_value(true)
+                .possible_values(&["sensitive", "first-upper", "lower"])
+                .default_value("sensitive")
+                .help("Whether page names differing only in case are the same page"),
+        )
+        .arg(
+            Arg::with_name("slow-query-ms")
+                .long("slow-query-ms")
+                .takes_value(true)
+                .default_value("200")
+                .help("Log database queries taking longer than this many milliseconds as warnings"),
+        )
+        .arg(
+            Arg::with_name("statement-timeout-ms")
+                .long("statement-timeout-ms")
+                .takes_value(true)
+                .default_value("10000")
+                .help("Cancel any database statement the server runs outside a transaction that takes longer than this many milliseconds; 0 for no limit. Statements in a transaction get --timeout-write"),
+        )
+        .arg(
+            Arg::with_name("highlight-alias")
+                .long("highlight-alias")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Highlight code fences in LANG as SYNTAX, repeatable, e.g. rs=Rust; adds to the built-in aliases"),
+        )
+        .arg(
+            Arg::with_name("syntax-dir")
+                .long("syntax-dir")
+                .takes_value(true)
+                .help("Directory of extra .sublime-syntax definitions for code highlighting"),
+        )
+        .arg(
+            Arg::with_name("admin")
+                .long("admin")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Username allowed to perform administrative actions such as merging pages"),
+        )
+        .arg(
+            Arg::with_name("require-admin-2fa")
+                .long("require-admin-2fa")
+                .help("Only give --admin users admin rights once they've turned on two-factor authentication at /settings/two-factor"),
+        )
+        .arg(
+            Arg::with_name("digest-sendmail")
+                .long("digest-sendmail")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Email users who opted in at /settings a weekly digest of changes, piping each mail to this command with the recipient appended, such as \"/usr/sbin/sendmail -i\""),
+        )
+        .arg(
+            Arg::with_name("digest-from")
+                .long("digest-from")
+                .takes_value(true)
+                .help("From address of the weekly digest"),
+        )
+        .arg(
+            Arg::with_name("digest-base-url")
+                .long("digest-base-url")
+                .takes_value(true)
+                .help("Address the wiki is reached at, such as https://wiki.example.com, for links in the weekly digest"),
+        )
+        .arg(
+            Arg::with_name("open-registration")
+                .long("open-registration")
+                .help("Let anyone create an account at /register, after confirming their email address; needs --digest-sendmail"),
+        )
+        .arg(
+            Arg::with_name("autoconfirmed-after")
+                .long("autoconfirmed-after")
+                .takes_value(true)
+                .value_name("EDITS,DAYS")
+                .help("Published edits and account age in days after which users skip the anonymous challenge and --moderation [default: 10,4]"),
+        )
+        .arg(
+            Arg::with_name("trusted-after")
+                .long("trusted-after")
+                .takes_value(true)
+                .value_name("EDITS,DAYS")
+                .help("Published edits and account age in days after which users may review held edits; admins always may [default: 100,30]"),
+        )
+        .arg(
+            Arg::with_name("nightly-check")
+                .long("nightly-check")
+                .help("Re-render every page once a day, logging any that fail or have a stale link graph"),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Re-renders every page, reporting any that fail or have a stale link graph")
+                .arg(
+                    Arg::with_name("fix")
+                        .long("fix")
+                        .help("Rewrite stale link graph entries"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replace")
+                .about("Finds and replaces text in the current revision of every page, showing a diff of each affected page")
+                .arg(Arg::with_name("find").required(true))
+                .arg(Arg::with_name("replacement").required(true))
+                .arg(
+                    Arg::with_name("regex")
+                        .long("regex")
+                        .help("Treat FIND as a regular expression; REPLACEMENT may use $1 or ${name}"),
+                )
+                .arg(
+                    Arg::with_name("apply")
+                        .long("apply")
+                        .help("Store the changes as new revisions instead of only previewing them"),
+                )
+                .arg(
+                    Arg::with_name("author")
+                        .long("author")
+                        .takes_value(true)
+                        .default_value("find-and-replace")
+                        .help("Name the new revisions are attributed to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("canonicalize-names")
+                .about("Moves pages with names that aren't canonical under --page-name-case to their canonical names, and updates the link graph to match"),
+        )
+        .subcommand(
+            SubCommand::with_name("gc-attachments")
+                .about("Deletes stored attachment contents that no attachment refers to any more"),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Pulls and pushes pages changed since the last sync with another instance")
+                .arg(
+                    Arg::with_name("remote")
+                        .required(true)
+                        .help("Base URL of the other instance, e.g. https://wiki.example.com"),
+                )
+                .arg(
+                    Arg::with_name("session")
+                        .long("session")
+                        .takes_value(true)
+                        .help("Session cookie value to authenticate to the remote with"),
+                )
+                .arg(
+                    Arg::with_name("remote-token-file")
+                        .long("remote-token-file")
+                        .takes_value(true)
+                        .help("File holding the remote's --site-token, if it is private"),
+                )
+                .arg(
+                    Arg::with_name("on-conflict")
+                        .long("on-conflict")
+                        .takes_value(true)
+                        .possible_values(&["newest", "queue"])
+                        .default_value("newest")
+                        .help("For pages edited on both sides: keep the newest edit, or queue them until resolved"),
+                )
+                .arg(
+                    Arg::with_name("keep-local")
+                        .long("keep-local")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Resolve a queued conflict by pushing the local page, repeatable"),
+                )
+                .arg(
+                    Arg::with_name("keep-remote")
+                        .long("keep-remote")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Resolve a queued conflict by pulling the remote page, repeatable"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-static")
+                .about("Renders every current page to static HTML files that link to each other")
+                .arg(
+                    Arg::with_name("dir")
+                        .required(true)
+                        .help("Directory to write into, created if missing"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Writes page history to a gzipped archive, all of it or only what changed since a checkpoint")
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("File to write the archive to"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("Only include changes after this change id, as printed by the previous backup, or RFC 3339 time"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Applies backup archives, a full backup first and then its increments in order")
+                .arg(Arg::with_name("archive").required(true).multiple(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("add-user")
+                .about("Creates a user, reading the password from stdin")
+                .arg(Arg::with_name("username").required(true)),
+        )
+}
+
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(main2()).unwrap();
+}
+
+async fn main2() -> DynResult<()> {
+    let mut my_subscriber_builder = FmtSubscriber::builder();
+
+    let matches = app().get_matches();
+
+    let verbosity = matches.occurrences_of("v");
+    let should_print_test_logging = 4 < verbosity;
+
+    my_subscriber_builder = my_subscriber_builder.with_max_level(match verbosity {
+        0 => TracingLevelFilter::ERROR,
+        1 => TracingLevelFilter::WARN,
+        2 => TracingLevelFilter::INFO,
+        3 => TracingLevelFilter::DEBUG,
+        _ => TracingLevelFilter::TRACE,
+    });
+
+    tracing::subscriber::set_global_default(my_subscriber_builder.finish())
+        .expect("setting tracing default failed");
+
+    // panics in handlers are caught by `Handler::respond`, which can't see
+    // where they came from, so the backtrace is logged here
+    panic::set_hook(Box::new(|info| {
+        event!(Level::ERROR, "{}\n{}", info, std::backtrace::Backtrace::force_capture());
+    }));
+
+    if should_print_test_logging {
+        print_test_logging();
+    }
+
+    let config = Config::from_matches(&matches)?;
+    names::init(config.page_name_case);
+    if config.dev {
+        // where this binary was built from, which is where it's being worked on
+        let source = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        assets::serve_from_disk(source.join("static"));
+        assets::watch_templates(source.join("templates"));
+    }
+    highlight::init(highlight::Highlighter::new(
+        &config.highlight_aliases,
+        config.syntax_dir.as_deref(),
+    )?);
+
+    let db_uri = "postgresql://quassel@localhost/quassel";
+
+    let database = database::Database::new(db_uri, config.slow_query_threshold);
+    let inner = database.connect().await?;
+    schema::check(&inner.db).await?;
+
+    match matches.subcommand() {
+        ("add-user", Some(sub)) => {
+            return add_user(&inner, sub.value_of("username").unwrap()).await;
+        }
+        ("canonicalize-names", Some(_)) => {
+            let mut inner = inner;
+            let renamed = names::canonicalize_all(&mut inner, config.page_name_case).await?;
+            for renamed in &renamed {
+                println!("{}", renamed);
+            }
+            println!("{} page(s) had a non-canonical name", renamed.len());
+            return Ok(());
+        }
+        ("gc-attachments", Some(_)) => {
+            let mut inner = inner;
+            let tx = inner.db.transaction().await?;
+            let (deleted, freed) = inner.queries.collect_unused_blobs(&tx).await?;
+            tx.commit().await?;
+            println!("deleted {} unused attachment blob(s), {} bytes", deleted, freed);
+            return Ok(());
+        }
+        ("check", Some(sub)) => {
+            let fix = sub.is_present("fix");
+            let plugins = Plugins::compiled_in();
+            let findings = check::check_all(&RwLock::new(inner), &plugins, fix).await?;
+            for finding in &findings {
+                println!("{}", finding);
+            }
+            // a non-zero exit lets cron or CI flag the problems
+            if !findings.is_empty() && !fix {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        ("replace", Some(sub)) => {
+            let pattern = replace::Pattern::new(sub.value_of("find").unwrap(), sub.is_present("regex"))?;
+            let replacement = sub.value_of("replacement").unwrap();
+            let changes = replace::plan(&inner, &pattern, replacement).await?;
+            for change in &changes {
+                print!("{}", change.unified_diff());
+            }
+            if !sub.is_present("apply") {
+                println!("{} page(s) would change; rerun with --apply to save them", changes.len());
+                return Ok(());
+            }
+
+            let mut inner = inner;
+            let plugins = Plugins::compiled_in();
+            // run by whoever runs the server, so every page may be changed
+            let editor = replace::Editor {
+                author: sub.value_of("author").unwrap(),
+                user: None,
+                may_write: &|_| true,
+                held: false,
+                secrets: config.secrets_policy,
+            };
+            for (name, outcome) in replace::apply(&mut inner, &plugins, &changes, &editor, None).await? {
+                match outcome {
+                    replace::Outcome::Saved(id) => println!("{}: saved revision {}", name, id),
+                    outcome => println!("{}: skipped, {}", name, outcome.problem().unwrap_or_default()),
+                }
+            }
+            return Ok(());
+        }
+        ("sync", Some(sub)) => {
+            let site_token = match sub.value_of("remote-token-file") {
+                Some(path) => Some(std::fs::read_to_string(path)?.trim().to_string()),
+                None => None,
+            };
+            let remote = sync::Remote::new(
+                sub.value_of("remote").unwrap(),
+                sub.value_of("session").map(str::to_string),
+                site_token,
+            )?;
+            let values = |name| -> Vec<String> {
+                sub.values_of(name).into_iter().flatten().map(str::to_string).collect()
+            };
+            let mut inner = inner;
+            let plugins = Plugins::compiled_in();
+            let mut sync = sync::Sync {
+                inner: &mut inner,
+                plugins: &plugins,
+                remote: &remote,
+                policy: sub.value_of("on-conflict").unwrap().parse()?,
+            };
+            let outcomes = sync.run(&values("keep-local"), &values("keep-remote")).await?;
+            for (name, outcome) in &outcomes {
+                println!("{}: {}", name, outcome);
+            }
+            let conflicts = outcomes
+                .iter()
+                .filter(|(_, outcome)| matches!(outcome, sync::Outcome::Conflict))
+                .count();
+            if conflicts > 0 {
+                println!("{} conflict(s); resolve with --keep-local or --keep-remote", conflicts);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        ("export-static", Some(sub)) => {
+            let plugins = Plugins::compiled_in();
+            let dir = std::path::Path::new(sub.value_of("dir").unwrap());
+            let exported = export::export(&inner, &plugins, &config.site_name, dir).await?;
+            println!(
+                "exported {} page(s) and {} attachment(s) to {}",
+                exported.pages,
+                exported.attachments,
+                dir.display()
+            );
+            return Ok(());
+        }
+        ("backup", Some(sub)) => {
+            let since = sub.value_of("since").map(str::parse::<backup::Since>).transpose()?;
+            let path = std::path::Path::new(sub.value_of("output").unwrap());
+            let mut inner = inner;
+            let backed_up = backup::backup(&mut inner, since, path).await?;
+            match since {
+                Some(since) => print!("backed up changes since {}: ", since),
+                None => print!("backed up everything: "),
+            }
+            println!(
+                "{} revision(s) and {} move(s), until change {}",
+                backed_up.revisions, backed_up.moves, backed_up.until
+            );
+            return Ok(());
+        }
+        ("restore", Some(sub)) => {
+            let mut inner = inner;
+            for archive in sub.values_of("archive").unwrap() {
+                let restored = backup::restore(&mut inner, std::path::Path::new(archive)).await?;
+                println!(
+                    "{}: restored {} revision(s) and {} move(s), until change {}",
+                    archive, restored.revisions, restored.moves, restored.until
+                );
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    // only for the server: the commands above run long queries on purpose.
+    // A handler that times out stops waiting, but its query would carry on
+    // holding the one connection every request shares
+    database.limit_statements(&inner, config.statement_timeout).await?;
 
-    let server = Server::bind(&addr).serve(make_service);
+    let handler = Handler::start(config, database, inner).await?;
+
+    if let Some(ref mailer) = handler.config.digest {
+        tokio::spawn(digest::run_weekly(
+            handler.inner.clone(),
+            Arc::new(mailer.clone()),
+            handler.config.site_name.clone(),
+        ));
+    }
+
+    if matches.is_present("nightly-check") {
+        tokio::spawn(check::run_nightly(
+            handler.inner.clone(),
+            handler.plugins.clone(),
+        ));
+    }
+
+    // bind everything up front so a bad --listen fails before serving starts
+    let mut listeners = Vec::new();
+    #[cfg(feature = "systemd")]
+    listeners.extend(systemd::inherited_listeners()?);
+    let default_listen = [listen::DEFAULT_LISTEN.parse()?];
+    let specs = if handler.config.listen.is_empty() && listeners.is_empty() {
+        &default_listen[..]
+    } else {
+        &handler.config.listen[..]
+    };
+    for spec in specs {
+        let listener = spec
+            .bind()
+            .await
+            .map_err(|err| format!("unable to listen on {}: {}", spec.addr, err))?;
+        listeners.push(listener);
+    }
+    for listener in &listeners {
+        event!(Level::INFO, "listening on {}", listener.addr);
+    }
+    #[cfg(feature = "systemd")]
+    systemd::notify_ready();
 
     // And run forever...
-    if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+    futures::future::join_all(listeners.into_iter().map(|l| l.serve(handler.clone()))).await;
+
+    Ok(())
+}
+
+async fn add_user(inner: &HandlerInner, username: &str) -> DynResult<()> {
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.is_empty() {
+        return Err("refusing to create a user with an empty password".into());
     }
 
+    let password_hash = auth::hash_password(password)?;
+    inner
+        .queries
+        .insert_user(&inner.db, username, &password_hash)
+        .await?;
+
+    println!("created user {}", username);
     Ok(())
 }
 
@@ -526,3 +6154,94 @@ fn print_test_logging() {
     event!(Level::WARN, "logger initialized - warn check");
     event!(Level::ERROR, "logger initialized - error check");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_query_is_a_400() {
+        let message = QueryParams::<CompareParams>::parse("from=latest").err().unwrap().to_string();
+
+        let uri: Uri = "/wiki/Home/history?from=latest".parse().unwrap();
+        let response = bad_query(&uri, &message);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-Type"], "text/plain; charset=utf8");
+
+        // API clients get the message as JSON
+        let uri: Uri = "/api/v1/wiki/Home?dry_run=maybe".parse().unwrap();
+        let response = bad_query(&uri, &message);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-Type"], "application/json");
+    }
+
+    /// A handler over a freshly provisioned database, started with `args`,
+    /// or `None` without `database::testing::DATABASE_VAR`.
+    async fn test_handler(args: &[&str]) -> Option<Handler> {
+        let (database, inner) = database::testing::provisioned().await?;
+        let matches = app().get_matches_from(std::iter::once("wiki").chain(args.iter().copied()));
+        let config = Config::from_matches(&matches).unwrap();
+        Some(Handler::start(config, database, inner).await.unwrap())
+    }
+
+    async fn request(handler: &Handler, req: Request<Body>) -> Response<Body> {
+        handler.handle("192.0.2.1:1234".parse().unwrap(), req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn missing_pages_open_an_empty_editor() {
+        let handler = match test_handler(&[]).await {
+            Some(handler) => handler,
+            None => return,
+        };
+
+        let req = Request::get(RouteWiki::to_edit("Wanted").to_string()).body(Body::empty()).unwrap();
+        let response = request(&handler, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<textarea"));
+
+        // reading it is still a 404
+        let req = Request::get(RouteWiki::to("Wanted").to_string()).body(Body::empty()).unwrap();
+        assert_eq!(request(&handler, req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn unchanged_saves_store_nothing() {
+        let handler = match test_handler(&[]).await {
+            Some(handler) => handler,
+            None => return,
+        };
+        let put = || Request::put(RouteWiki::to("Home").to_string()).body(Body::from("same text")).unwrap();
+
+        assert_eq!(request(&handler, put()).await.status(), StatusCode::FOUND);
+        let response = request(&handler, put()).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/wiki/Home?flash=unchanged");
+
+        let locked = handler.inner.read().await;
+        let history = locked.queries.fetch_history(&locked.db, "Home", true).await.unwrap();
+        assert_eq!(history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_pending_new_pages_count_towards_the_hourly_allowance() {
+        let handler = match test_handler(&["--moderation", "--anonymous-new-pages", "1"]).await {
+            Some(handler) => handler,
+            None => return,
+        };
+        {
+            let locked = handler.inner.read().await;
+            let index = index_document("home");
+            locked.queries.store_revision(&locked.db, "Home", "alice", "home", &index).await.unwrap();
+        }
+        let put = |name: &str, text: &'static str| {
+            Request::put(RouteWiki::to(name).to_string()).body(Body::from(text)).unwrap()
+        };
+
+        // an edit held by --moderation isn't a new page
+        assert_eq!(request(&handler, put("Home", "edited")).await.status(), StatusCode::ACCEPTED);
+        assert_eq!(request(&handler, put("First", "new")).await.status(), StatusCode::ACCEPTED);
+        assert_eq!(request(&handler, put("Second", "new")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
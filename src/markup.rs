@@ -0,0 +1,57 @@
+//! Source formats pages can be written in. Pages are Markdown unless their
+//! front matter names another format:
+//!
+//! ```text
+//! ---
+//! format: asciidoc
+//! ---
+//! = Deploy runbook
+//! ```
+
+use crate::asciidoc::AsciiDoc;
+use crate::{front_matter, DynResult, Renderer};
+
+pub trait Markup: Sync {
+    /// `text` as HTML. Front matter isn't shown.
+    fn render(&self, text: &str) -> DynResult<String>;
+
+    /// Every distinct wiki page linked to from `text`, in order of first
+    /// appearance.
+    fn link_targets(&self, text: &str) -> Vec<String>;
+
+    /// Whether templates and data blocks are expanded before rendering.
+    /// They expand to Markdown, so other formats leave them as written.
+    fn expands_markdown(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Markdown,
+    AsciiDoc,
+}
+
+impl Format {
+    /// The format a front matter `format:` value names, ignoring case.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match &name.to_ascii_lowercase()[..] {
+            "markdown" | "md" => Some(Format::Markdown),
+            "asciidoc" | "adoc" => Some(Format::AsciiDoc),
+            _ => None,
+        }
+    }
+
+    pub fn markup(self) -> &'static dyn Markup {
+        match self {
+            Format::Markdown => &Renderer,
+            Format::AsciiDoc => &AsciiDoc,
+        }
+    }
+}
+
+/// The markup `text` is written in, going by its front matter.
+pub fn of(text: &str) -> &'static dyn Markup {
+    front_matter::parse(text).format.markup()
+}
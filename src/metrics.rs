@@ -0,0 +1,140 @@
+//! Per-query latency for the database calls in `queries.rs`, logged when
+//! slow and served from `/metrics` in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{event, Level};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Latency {
+    count: u64,
+    total: Duration,
+    max: Duration,
+    /// Runs that took longer than the slow query threshold.
+    slow: u64,
+}
+
+/// See `QueryMetrics::summary`.
+pub struct QuerySummary {
+    pub runs: u64,
+    pub slow_runs: u64,
+    /// `(query, mean time, runs)`, slowest first.
+    pub slowest: Vec<(&'static str, Duration, u64)>,
+}
+
+pub struct QueryMetrics {
+    slow_threshold: Duration,
+    /// Keyed by query name, sorted so the output is stable between scrapes.
+    latencies: Mutex<BTreeMap<&'static str, Latency>>,
+}
+
+impl QueryMetrics {
+    /// Queries taking longer than `slow_threshold` are logged at WARN.
+    pub fn new(slow_threshold: Duration) -> QueryMetrics {
+        QueryMetrics {
+            slow_threshold,
+            latencies: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Runs `query` and records how long it took under `name`, whether or
+    /// not it succeeded.
+    pub async fn time<T, E, F>(&self, name: &'static str, query: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let result = query.await;
+        let elapsed = started.elapsed();
+
+        let slow = elapsed > self.slow_threshold;
+        if slow {
+            event!(
+                Level::WARN,
+                query = name,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "slow query"
+            );
+        } else {
+            event!(
+                Level::TRACE,
+                query = name,
+                elapsed_us = elapsed.as_micros() as u64,
+                "query"
+            );
+        }
+
+        let mut latencies = self.latencies.lock().unwrap();
+        let latency = latencies.entry(name).or_default();
+        latency.count += 1;
+        latency.total += elapsed;
+        latency.max = latency.max.max(elapsed);
+        latency.slow += u64::from(slow);
+        result
+    }
+
+    /// Runs and slow runs of every query since startup, and the `n` queries
+    /// with the longest mean time, for `/admin/stats`.
+    pub fn summary(&self, n: usize) -> QuerySummary {
+        let latencies = self.latencies.lock().unwrap().clone();
+        let mut slowest: Vec<_> = latencies
+            .iter()
+            .filter(|(_, latency)| latency.count > 0)
+            .map(|(&name, latency)| (name, latency.total.div_f64(latency.count as f64), latency.count))
+            .collect();
+        slowest.sort_by_key(|&(_, average, _)| std::cmp::Reverse(average));
+        slowest.truncate(n);
+        QuerySummary {
+            runs: latencies.values().map(|latency| latency.count).sum(),
+            slow_runs: latencies.values().map(|latency| latency.slow).sum(),
+            slowest,
+        }
+    }
+
+    /// Everything recorded since startup, in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let latencies = self.latencies.lock().unwrap().clone();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP wiki_query_duration_seconds Time spent running each database query.");
+        let _ = writeln!(out, "# TYPE wiki_query_duration_seconds summary");
+        for (name, latency) in &latencies {
+            let _ = writeln!(
+                out,
+                "wiki_query_duration_seconds_sum{{query=\"{}\"}} {}",
+                name,
+                latency.total.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "wiki_query_duration_seconds_count{{query=\"{}\"}} {}",
+                name, latency.count
+            );
+        }
+
+        let _ = writeln!(out, "# HELP wiki_query_duration_max_seconds Longest single run of each database query.");
+        let _ = writeln!(out, "# TYPE wiki_query_duration_max_seconds gauge");
+        for (name, latency) in &latencies {
+            let _ = writeln!(
+                out,
+                "wiki_query_duration_max_seconds{{query=\"{}\"}} {}",
+                name,
+                latency.max.as_secs_f64()
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP wiki_slow_queries_total Runs of each database query over the slow query threshold."
+        );
+        let _ = writeln!(out, "# TYPE wiki_slow_queries_total counter");
+        for (name, latency) in &latencies {
+            let _ = writeln!(out, "wiki_slow_queries_total{{query=\"{}\"}} {}", name, latency.slow);
+        }
+        out
+    }
+}
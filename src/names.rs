@@ -0,0 +1,125 @@
+//! Canonical forms of page names, so that names that look the same to a
+//! reader lead to the same page. Requests for any other form are redirected,
+//! and saves and moves go to the canonical name. Link targets are kept in
+//! the same form, and `canonicalize_all` brings pages and links stored
+//! before, or under another policy, in line.
+
+use std::sync::OnceLock;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{DynResult, HandlerInner};
+
+/// Recorded as the mover of pages renamed by `canonicalize_all`.
+const MOVED_BY: &str = "canonicalize-names";
+
+static CASE: OnceLock<NameCase> = OnceLock::new();
+
+/// How letter case is treated when canonicalising, see `--page-name-case`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameCase {
+    /// `cafe` and `Cafe` are different pages.
+    Sensitive,
+    /// The first letter is capitalised, so `cafe` is `Cafe`.
+    FirstUpper,
+    /// Names are lowercased, so `Cafe` and `CAFE` are `cafe`.
+    Lower,
+}
+
+impl std::str::FromStr for NameCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sensitive" => Ok(NameCase::Sensitive),
+            "first-upper" => Ok(NameCase::FirstUpper),
+            "lower" => Ok(NameCase::Lower),
+            _ => Err(format!("unknown page name case policy {:?}", s)),
+        }
+    }
+}
+
+/// Installs the policy link targets are canonicalised with. Call once at
+/// startup; until then names are case sensitive.
+pub fn init(case: NameCase) {
+    let _ = CASE.set(case);
+}
+
+/// The policy installed with `init`.
+pub fn case() -> NameCase {
+    CASE.get().copied().unwrap_or(NameCase::Sensitive)
+}
+
+/// `name` in NFC, with surrounding whitespace trimmed, inner runs of
+/// whitespace collapsed to one space and `case` applied.
+pub fn canonical(name: &str, case: NameCase) -> String {
+    let name: String = name.nfc().collect();
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    match case {
+        NameCase::Sensitive => name,
+        NameCase::FirstUpper => {
+            let mut chars = name.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => name,
+            }
+        }
+        NameCase::Lower => name.to_lowercase(),
+    }
+}
+
+/// What `canonicalize_all` did to a page with a non-canonical name.
+pub enum Renamed {
+    Moved {
+        from: String,
+        to: String,
+    },
+    /// Another page already has the canonical name, so this one was left
+    /// for someone to merge or move by hand.
+    Taken {
+        from: String,
+        to: String,
+    },
+}
+
+impl std::fmt::Display for Renamed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Renamed::Moved { from, to } => write!(f, "{}: moved to {}", from, to),
+            Renamed::Taken { from, to } => write!(f, "{}: not moved, {} already exists", from, to),
+        }
+    }
+}
+
+/// Moves every page whose name isn't canonical under `case` to its canonical
+/// name, leaving a redirect as any move does, and points the link graph at
+/// canonical names. Run once after upgrading or changing `--page-name-case`.
+pub async fn canonicalize_all(inner: &mut HandlerInner, case: NameCase) -> DynResult<Vec<Renamed>> {
+    let HandlerInner { db, queries } = inner;
+    let tx = db.transaction().await?;
+
+    let mut renamed = Vec::new();
+    for from in queries.fetch_document_names(&tx).await? {
+        let to = canonical(&from, case);
+        if to == from {
+            continue;
+        }
+        if queries.fetch_document_id(&tx, &to).await?.is_some() {
+            renamed.push(Renamed::Taken { from, to });
+            continue;
+        }
+        queries
+            .move_document(&tx, &from, &to, MOVED_BY, "canonical page name")
+            .await?;
+        renamed.push(Renamed::Moved { from, to });
+    }
+
+    for target in queries.fetch_link_target_names(&tx).await? {
+        let canonical = canonical(&target, case);
+        if canonical != target {
+            queries.rename_link_target(&tx, &target, &canonical).await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(renamed)
+}
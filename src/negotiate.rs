@@ -0,0 +1,83 @@
+//! `Accept` and `Accept-Encoding` header content negotiation.
+
+/// The entry of `offered` the client prefers according to its `Accept`
+/// header, or `None` if it accepts none of them. Ties, and requests without
+/// an `Accept` header, go to the earliest entry in `offered`.
+pub fn preferred<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return offered.first().copied(),
+    };
+    let ranges: Vec<MediaRange> = accept.split(',').filter_map(MediaRange::parse).collect();
+
+    let mut best: Option<(&str, f32)> = None;
+    for &media_type in offered {
+        let q = quality(&ranges, media_type);
+        if q > best.map_or(0.0, |(_, best_q)| best_q) {
+            best = Some((media_type, q));
+        }
+    }
+    best.map(|(media_type, _)| media_type)
+}
+
+struct MediaRange<'a> {
+    main: &'a str,
+    sub: &'a str,
+    q: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(range: &'a str) -> Option<MediaRange<'a>> {
+        let mut params = range.split(';').map(str::trim);
+        let (main, sub) = params.next()?.split_once('/')?;
+        let q = params
+            .filter_map(|param| param.strip_prefix("q="))
+            .filter_map(|q| q.parse().ok())
+            .next()
+            .unwrap_or(1.0);
+        Some(MediaRange { main, sub, q })
+    }
+
+    /// How closely this range matches `main/sub`: 2 for exact, 1 for
+    /// `main/*`, 0 for `*/*`.
+    fn specificity(&self, main: &str, sub: &str) -> Option<u8> {
+        match (self.main, self.sub) {
+            ("*", "*") => Some(0),
+            (m, "*") if m.eq_ignore_ascii_case(main) => Some(1),
+            (m, s) if m.eq_ignore_ascii_case(main) && s.eq_ignore_ascii_case(sub) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// The q-value of the most specific range matching `media_type`.
+fn quality(ranges: &[MediaRange], media_type: &str) -> f32 {
+    let (main, sub) = media_type.split_once('/').unwrap_or((media_type, ""));
+    ranges
+        .iter()
+        .filter_map(|range| range.specificity(main, sub).map(|s| (s, range.q)))
+        .max_by_key(|&(specificity, _)| specificity)
+        .map_or(0.0, |(_, q)| q)
+}
+
+/// Whether an `Accept-Encoding` header allows `coding`, either by name or
+/// through `*`, with a non-zero q-value.
+pub fn accepts_encoding(accept_encoding: Option<&str>, coding: &str) -> bool {
+    let mut named = None;
+    let mut wildcard = None;
+    for entry in accept_encoding.unwrap_or("").split(',') {
+        let mut params = entry.split(';').map(str::trim);
+        let name = params.next().unwrap_or("");
+        let q: f32 = params
+            .filter_map(|param| param.strip_prefix("q="))
+            .filter_map(|q| q.parse().ok())
+            .next()
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            named = Some(q);
+        } else if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    matches!(named.or(wildcard), Some(q) if q > 0.0)
+}
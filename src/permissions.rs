@@ -0,0 +1,174 @@
+use hyper::{Body, Method, Request};
+
+use crate::auth::User;
+use crate::protection::Level;
+use crate::routes::{Route, RouteApiWikiAction, RouteWikiSubview};
+use crate::trust::TrustLevel;
+use crate::{edit_notices, footer, sidebar, snippets};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SitePolicy {
+    /// Anyone may read and edit.
+    Open,
+    /// Anyone may read, only logged-in users may edit.
+    ReadOnlyPublic,
+}
+
+impl std::str::FromStr for SitePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(SitePolicy::Open),
+            "read-only-public" => Ok(SitePolicy::ReadOnlyPublic),
+            _ => Err(format!("unknown site policy {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    Edit,
+    /// Approving or rejecting held edits, limited to trusted users.
+    Review,
+    /// Changing the logged-in user's own settings and read markers.
+    Settings,
+    /// Commenting on a page without editing it, limited to logged-in users
+    /// so notes are signed.
+    Annotate,
+    /// Site maintenance such as merging pages, limited to `--admin` users.
+    Admin,
+}
+
+impl Action {
+    pub fn for_request(route: &Route<'_>, method: &Method) -> Action {
+        let action = Action::for_route(route, method);
+        // the sidebar, footer, namespace snippets and edit notices show up on
+        // many pages, so changing them is up to admins
+        if action == Action::Edit && edits_site_page(route) {
+            return Action::Admin;
+        }
+        action
+    }
+
+    fn for_route(route: &Route<'_>, method: &Method) -> Action {
+        match route {
+            // logging in must always be possible, and so must getting an
+            // account to log in with
+            Route::Login
+            | Route::Logout
+            | Route::Register
+            | Route::VerifyEmail
+            | Route::ForgotPassword
+            | Route::ResetPassword => Action::Read,
+            Route::Review => Action::Review,
+            Route::Settings | Route::TwoFactor | Route::Sessions | Route::Unread => Action::Settings,
+            Route::Admin | Route::EditFilters | Route::Protection | Route::Stats | Route::Replace => Action::Admin,
+            // post a draft or a query but change nothing
+            Route::ApiLint | Route::GraphQl => Action::Read,
+            Route::Logo if method != Method::GET && method != Method::HEAD => Action::Admin,
+            Route::Wiki(ref rw) if matches!(rw.subview, RouteWikiSubview::Annotations(..)) => Action::Annotate,
+            Route::Wiki(ref rw) if matches!(rw.subview, RouteWikiSubview::Merge | RouteWikiSubview::RedactRevision(..)) => {
+                Action::Admin
+            }
+            Route::Wiki(ref rw)
+                if matches!(
+                    rw.subview,
+                    RouteWikiSubview::Edit | RouteWikiSubview::Move | RouteWikiSubview::PreviewDiff
+                ) =>
+            {
+                Action::Edit
+            }
+            _ if method == Method::GET || method == Method::HEAD => Action::Read,
+            _ => Action::Edit,
+        }
+    }
+}
+
+/// The page a request reads or changes, if it's about one.
+pub fn page_of<'r>(route: &'r Route<'_>) -> Option<&'r str> {
+    match route {
+        Route::Wiki(ref rw) => Some(&rw.name),
+        Route::Attachment(ref ra) => Some(&ra.name),
+        Route::ApiWiki(ref ra) => Some(&ra.name),
+        _ => None,
+    }
+}
+
+fn edits_site_page(route: &Route<'_>) -> bool {
+    page_of(route).is_some_and(is_site_page)
+}
+
+/// Whether `name` is the sidebar, a footer page, a namespace snippet or an
+/// edit notice, which only admins may change.
+pub fn is_site_page(name: &str) -> bool {
+    name == sidebar::PAGE || footer::is_footer_page(name) || snippets::is_snippet(name) || edit_notices::is_notice(name)
+}
+
+/// Marks a request by an anonymous visitor creating a page under
+/// `--anonymous-new-pages`: allowed whatever the site policy says, but the
+/// page is held for review. Inserted into the request extensions by
+/// `Handler::handle_route`.
+#[derive(Debug, Clone, Copy)]
+pub struct NewPageRequest;
+
+impl NewPageRequest {
+    pub fn of(req: &Request<Body>) -> bool {
+        req.extensions().get::<NewPageRequest>().is_some()
+    }
+}
+
+/// Whether a request stores text from whoever sent it: a save from the
+/// editor, an API page write or append, or an annotation. Anonymous ones
+/// must pass the `--anonymous-challenge`.
+pub fn writes_page_text(route: &Route<'_>, method: &Method) -> bool {
+    match route {
+        Route::Wiki(ref rw) => match rw.subview {
+            RouteWikiSubview::View => method == Method::PUT,
+            RouteWikiSubview::Annotations(..) => method == Method::POST,
+            _ => false,
+        },
+        Route::ApiWiki(ref ra) => match ra.action {
+            RouteApiWikiAction::Page => method == Method::PUT,
+            RouteApiWikiAction::Append => method == Method::POST,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// The page a request would create if it doesn't exist yet: a save from
+/// the editor or an API `PUT`. Site pages such as the sidebar don't count.
+pub fn page_created_by<'r>(route: &'r Route<'_>, method: &Method) -> Option<&'r str> {
+    if method != Method::PUT || edits_site_page(route) {
+        return None;
+    }
+    match route {
+        Route::Wiki(ref rw) if matches!(rw.subview, RouteWikiSubview::View) => Some(&rw.name),
+        Route::ApiWiki(ref ra) if matches!(ra.action, RouteApiWikiAction::Page) => Some(&ra.name),
+        _ => None,
+    }
+}
+
+pub fn is_allowed(policy: SitePolicy, user: Option<&User>, action: Action) -> bool {
+    match (policy, action) {
+        (_, Action::Read) => true,
+        (_, Action::Review) => matches!(user, Some(u) if u.trust >= TrustLevel::Trusted),
+        (_, Action::Settings) => user.is_some(),
+        (_, Action::Annotate) => user.is_some(),
+        (_, Action::Admin) => matches!(user, Some(u) if u.is_admin),
+        (SitePolicy::Open, Action::Edit) => true,
+        (SitePolicy::ReadOnlyPublic, Action::Edit) => user.is_some(),
+    }
+}
+
+/// Whether `user` may edit a page protected at `level`, see
+/// `protection.rs`. Checked on top of `is_allowed`.
+pub fn is_allowed_on_page(level: Option<Level>, user: Option<&User>) -> bool {
+    match level {
+        None => true,
+        Some(Level::LoggedIn) => user.is_some(),
+        Some(Level::Admin) => matches!(user, Some(u) if u.is_admin),
+    }
+}
@@ -0,0 +1,33 @@
+use super::{Plugin, PluginError, SaveContext};
+
+/// Refuses anonymous saves containing more external links than `max_links`,
+/// a cheap guard against link spam.
+pub struct LinkLimit {
+    pub max_links: usize,
+}
+
+impl Default for LinkLimit {
+    fn default() -> LinkLimit {
+        LinkLimit { max_links: 10 }
+    }
+}
+
+impl Plugin for LinkLimit {
+    fn name(&self) -> &'static str {
+        "link-limit"
+    }
+
+    fn pre_save(&self, save: &SaveContext<'_>, document: &mut String) -> Result<(), PluginError> {
+        if save.user.is_some() {
+            return Ok(());
+        }
+        let links = document.matches("http://").count() + document.matches("https://").count();
+        if links > self.max_links {
+            return Err(PluginError::Rejected(format!(
+                "anonymous edits may contain at most {} external links, log in to add more",
+                self.max_links
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use futures::future::BoxFuture;
+use hyper::{Body, Request, Response};
+
+use crate::auth::User;
+use crate::DynResult;
+
+#[cfg(feature = "plugin-link-limit")]
+mod link_limit;
+
+/// The save a `pre_save` or `post_save` hook is looking at.
+#[allow(dead_code)] // only read by plugins, which may all be disabled
+pub struct SaveContext<'a> {
+    pub name: &'a str,
+    pub user: Option<&'a User>,
+    /// Who the revision is attributed to, see `CurrentUser::attribution`.
+    pub attribution: &'a str,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)] // only raised by plugins, which may all be disabled
+pub enum PluginError {
+    /// The save is refused; the message is shown to the editor.
+    Rejected(String),
+}
+
+impl std::fmt::Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PluginError::Rejected(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// A compiled-in extension. Every hook has a do-nothing default, so a plugin
+/// only implements the ones it needs.
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Runs before a revision is stored. May rewrite `document` or refuse the
+    /// save.
+    fn pre_save(&self, _save: &SaveContext<'_>, _document: &mut String) -> Result<(), PluginError> {
+        Ok(())
+    }
+
+    /// Runs after a revision has been committed. Slow work such as sending
+    /// notifications should be spawned rather than done inline.
+    fn post_save(&self, _save: &SaveContext<'_>, _revision_id: i64) {}
+
+    /// Runs on a page's Markdown before templates are expanded and it is
+    /// rendered.
+    fn pre_render(&self, _markdown: &mut String) {}
+
+    /// Paths this plugin serves. An entry ending in `/` matches every path
+    /// under it. Only paths the built-in router doesn't know are offered.
+    fn routes(&self) -> &[&'static str] {
+        &[]
+    }
+
+    fn handle(&self, _req: Request<Body>) -> BoxFuture<'static, DynResult<Response<Body>>> {
+        Box::pin(async { Err(crate::routes::RouteError::NotFound.into()) })
+    }
+}
+
+/// The plugins enabled by cargo features, in the order their hooks run.
+pub struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Plugins {
+    #[allow(unused_mut, clippy::vec_init_then_push)]
+    pub fn compiled_in() -> Plugins {
+        let mut plugins: Vec<Box<dyn Plugin>> = Vec::new();
+        #[cfg(feature = "plugin-link-limit")]
+        plugins.push(Box::new(link_limit::LinkLimit::default()));
+        Plugins { plugins }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.plugins.iter().map(|p| p.name()).collect()
+    }
+
+    pub fn pre_save(&self, save: &SaveContext<'_>, document: &mut String) -> Result<(), PluginError> {
+        for plugin in &self.plugins {
+            plugin.pre_save(save, document)?;
+        }
+        Ok(())
+    }
+
+    pub fn post_save(&self, save: &SaveContext<'_>, revision_id: i64) {
+        for plugin in &self.plugins {
+            plugin.post_save(save, revision_id);
+        }
+    }
+
+    pub fn pre_render(&self, markdown: &mut String) {
+        for plugin in &self.plugins {
+            plugin.pre_render(markdown);
+        }
+    }
+
+    /// The plugin serving `path`, if any.
+    pub fn route_owner(&self, path: &str) -> Option<&dyn Plugin> {
+        self.plugins
+            .iter()
+            .find(|p| {
+                p.routes().iter().any(|r| match r.strip_suffix('/') {
+                    Some(_) => path.starts_with(r),
+                    None => path == *r,
+                })
+            })
+            .map(|p| &**p)
+    }
+}
@@ -0,0 +1,102 @@
+use std::str::FromStr;
+
+/// Colour themes `base.html` has styles for.
+pub const THEMES: &[&str] = &["light", "dark"];
+
+/// Settings a logged-in user chose at `/settings`. Users who never saved the
+/// form get the defaults.
+#[derive(Debug, Clone)]
+pub struct Preferences {
+    /// Shown in the page header instead of the username. Edits are still
+    /// attributed to the username.
+    pub display_name: Option<String>,
+    /// An IANA zone name such as `Europe/Berlin`.
+    pub timezone: String,
+    /// Offset of `timezone` from UTC when the request started, in seconds.
+    pub utc_offset: i32,
+    /// Overrides the site theme when set.
+    pub theme: Option<String>,
+    pub editor: Editor,
+    pub email: Option<String>,
+    /// Mail `email` the weekly digest, see `digest.rs`.
+    pub digest: bool,
+    /// Namespaces the digest is limited to, all of them if empty.
+    /// `digest::MAIN_NAMESPACE` stands for pages without one.
+    pub digest_namespaces: Vec<String>,
+    /// Namespaces and `key=value` tags `/unread` and the digest are limited
+    /// to, everything if empty, see `follow.rs`.
+    pub follow: Vec<String>,
+}
+
+impl Default for Preferences {
+    fn default() -> Preferences {
+        Preferences {
+            display_name: None,
+            timezone: "UTC".to_string(),
+            utc_offset: 0,
+            theme: None,
+            editor: Editor::Plain,
+            email: None,
+            digest: false,
+            digest_namespaces: Vec::new(),
+            follow: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Editor {
+    Plain,
+    /// The Markdown toolbar from `static/editor.js`.
+    Toolbar,
+}
+
+impl Editor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Editor::Plain => "plain",
+            Editor::Toolbar => "toolbar",
+        }
+    }
+}
+
+impl FromStr for Editor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Editor, String> {
+        match s {
+            "plain" => Ok(Editor::Plain),
+            "toolbar" => Ok(Editor::Toolbar),
+            _ => Err(format!("unknown editor {:?}", s)),
+        }
+    }
+}
+
+/// Whether `email` is a plain `local@example.com` address: dot-separated
+/// atoms of ASCII letters, digits and the usual symbols before the `@`, and
+/// a domain of at least two labels after it. Quoted local parts, comments
+/// and address literals are refused. It's passed to sendmail and put in a
+/// `To:` header, so nothing that could be taken for an option or break a
+/// header line gets through; whether mail arrives is another matter.
+pub fn is_valid_email(email: &str) -> bool {
+    const MAX_LEN: usize = 254;
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let is_atom_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c);
+    let local_ok = !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('-')
+        && local.split('.').all(|atom| !atom.is_empty() && atom.chars().all(is_atom_char));
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_ok = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    email.len() <= MAX_LEN && local_ok && domain_ok
+}
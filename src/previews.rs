@@ -0,0 +1,172 @@
+//! Previews of attachments for the page view and the attachments list:
+//! images as thumbnails, PDFs as a picture of their first page and text
+//! files as an excerpt. Previews are made the first time they're asked for
+//! and kept by content hash, so each is only made once.
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use image::ImageFormat;
+use tokio::process::Command;
+use tracing::{event, Level};
+
+use crate::queries::AttachmentInfo;
+use crate::routes::{Route, RouteBlob};
+use crate::{DynResult, HandlerInner};
+
+/// Renders a PDF page to PNG. Part of poppler-utils; without it PDFs just
+/// don't get a preview.
+const PDFTOPPM: &str = "pdftoppm";
+const PDF_PREVIEW_PIXELS: &str = "320";
+const PDF_PREVIEW_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longer side of an image thumbnail.
+const THUMBNAIL_PIXELS: u32 = 320;
+
+const EXCERPT_LINES: usize = 12;
+const EXCERPT_CHARS: usize = 800;
+
+const KIND_PDF_PAGE: &str = "pdf-page";
+const KIND_THUMBNAIL: &str = "thumbnail";
+const KIND_EXCERPT: &str = "excerpt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Image,
+    Pdf,
+    Text,
+}
+
+impl Kind {
+    fn of(content_type: &str) -> Option<Kind> {
+        match content_type {
+            t if t.starts_with("image/") => Some(Kind::Image),
+            "application/pdf" => Some(Kind::Pdf),
+            t if t.starts_with("text/") => Some(Kind::Text),
+            _ => None,
+        }
+    }
+}
+
+/// What to show in place of an attachment: a picture, an excerpt, or, for
+/// types without a preview, neither.
+#[derive(Debug, Default)]
+pub struct Preview {
+    pub image: Option<Route<'static>>,
+    pub excerpt: Option<String>,
+}
+
+/// The preview of `attachment`. Thumbnails and PDF pages are only linked
+/// here, so the pages listing them don't wait on making them.
+pub async fn for_attachment(inner: &HandlerInner, attachment: &AttachmentInfo) -> DynResult<Preview> {
+    let hash = &attachment.content_hash;
+    let filename = &attachment.filename;
+    let preview = match Kind::of(&attachment.content_type) {
+        Some(Kind::Image) | Some(Kind::Pdf) => Preview {
+            image: Some(RouteBlob::to_preview(hash, filename).to_owned()),
+            excerpt: None,
+        },
+        Some(Kind::Text) => Preview {
+            image: None,
+            excerpt: excerpt(inner, hash, filename).await?,
+        },
+        None => Preview::default(),
+    };
+    Ok(preview)
+}
+
+/// The start of a text attachment.
+async fn excerpt(inner: &HandlerInner, content_hash: &str, filename: &str) -> DynResult<Option<String>> {
+    let queries = &inner.queries;
+    if let Some(data) = queries.fetch_attachment_preview(&inner.db, content_hash, KIND_EXCERPT).await? {
+        return Ok(Some(String::from_utf8(data)?));
+    }
+    let content = match queries.fetch_attachment_by_hash(&inner.db, content_hash, filename).await? {
+        Some(content) => content,
+        None => return Ok(None),
+    };
+
+    let text = String::from_utf8_lossy(&content.data);
+    let lines: Vec<&str> = text.trim_end().lines().collect();
+    let mut excerpt: String = lines[..lines.len().min(EXCERPT_LINES)]
+        .join("\n")
+        .chars()
+        .take(EXCERPT_CHARS)
+        .collect();
+    if lines.len() > EXCERPT_LINES || excerpt.chars().count() == EXCERPT_CHARS {
+        excerpt.push('…');
+    }
+    queries
+        .store_attachment_preview(&inner.db, content_hash, KIND_EXCERPT, excerpt.as_bytes())
+        .await?;
+    Ok(Some(excerpt))
+}
+
+/// The thumbnail of an image attachment or the first page of a PDF one, as
+/// a PNG, or `None` if there's no such attachment or it couldn't be made.
+pub async fn picture(inner: &HandlerInner, content_hash: &str, filename: &str) -> DynResult<Option<Vec<u8>>> {
+    let queries = &inner.queries;
+    for kind in &[KIND_THUMBNAIL, KIND_PDF_PAGE] {
+        if let Some(png) = queries.fetch_attachment_preview(&inner.db, content_hash, kind).await? {
+            return Ok(Some(png));
+        }
+    }
+    let content = match queries.fetch_attachment_by_hash(&inner.db, content_hash, filename).await? {
+        Some(content) => content,
+        None => return Ok(None),
+    };
+
+    let (kind, made) = match Kind::of(&content.content_type) {
+        Some(Kind::Image) => {
+            let data = content.data;
+            let made = tokio::task::spawn_blocking(move || thumbnail(&data)).await?;
+            (KIND_THUMBNAIL, made)
+        }
+        Some(Kind::Pdf) => (KIND_PDF_PAGE, render_first_page(&content.data).await),
+        _ => return Ok(None),
+    };
+    let png = match made {
+        Ok(png) => png,
+        Err(err) => {
+            // not stored, so it's tried again once pdftoppm is installed
+            event!(Level::WARN, "no preview for {}: {}", content_hash, err);
+            return Ok(None);
+        }
+    };
+    queries
+        .store_attachment_preview(&inner.db, content_hash, kind, &png)
+        .await?;
+    Ok(Some(png))
+}
+
+/// `image` scaled down to fit `THUMBNAIL_PIXELS`, keeping its aspect ratio.
+fn thumbnail(image: &[u8]) -> DynResult<Vec<u8>> {
+    let image = image::load_from_memory(image)?;
+    let mut png = Vec::new();
+    image
+        .thumbnail(THUMBNAIL_PIXELS, THUMBNAIL_PIXELS)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+async fn render_first_page(pdf: &[u8]) -> DynResult<Vec<u8>> {
+    // removed with everything in it when dropped
+    let dir = tempfile::Builder::new().prefix("wiki-preview").tempdir()?;
+    let input = dir.path().join("input.pdf");
+    let output = dir.path().join("page");
+    tokio::fs::write(&input, pdf).await?;
+
+    let run = Command::new(PDFTOPPM)
+        .args(["-png", "-singlefile", "-f", "1", "-l", "1", "-scale-to", PDF_PREVIEW_PIXELS])
+        .arg(&input)
+        .arg(&output)
+        .kill_on_drop(true)
+        .status();
+    let status = tokio::time::timeout(PDF_PREVIEW_TIMEOUT, run)
+        .await
+        .map_err(|_| format!("{} timed out", PDFTOPPM))??;
+    if !status.success() {
+        return Err(format!("{} exited with {}", PDFTOPPM, status).into());
+    }
+    Ok(tokio::fs::read(output.with_extension("png")).await?)
+}
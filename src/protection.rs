@@ -0,0 +1,111 @@
+//! Page protection set by admins at `/admin/protection`: rules that limit
+//! who may edit every page whose name matches a pattern, so a whole
+//! namespace can be locked in one go. `permissions::is_allowed_on_page`
+//! applies them.
+//!
+//! A pattern is a page name where `*` stands for any run of characters:
+//! `Policy:*` covers the `Policy` namespace, `*/Archive` every archive
+//! subpage and `Main Page` just that page.
+
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use crate::queries::ProtectionRule;
+use crate::{DynResult, HandlerInner};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Only logged-in users may edit.
+    LoggedIn,
+    /// Only admins may edit.
+    Admin,
+}
+
+impl Level {
+    pub const ALL: &'static [Level] = &[Level::LoggedIn, Level::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::LoggedIn => "logged-in",
+            Level::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        Level::ALL
+            .iter()
+            .copied()
+            .find(|level| level.as_str() == s)
+            .ok_or_else(|| format!("unknown protection level {:?}", s))
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of
+/// characters, including none.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        // no `*` at all
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Problems with a pattern an admin entered, if any.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    if pattern.is_empty() {
+        return Err("A pattern can't be empty.".to_string());
+    }
+    if pattern.chars().all(|c| c == '*') {
+        return Err(format!(
+            "{:?} would protect every page; use --site-policy instead.",
+            pattern
+        ));
+    }
+    Ok(())
+}
+
+/// The current rules, read at startup and replaced whenever
+/// `/admin/protection` saves a change.
+#[derive(Default)]
+pub struct SiteProtection {
+    rules: RwLock<Arc<Vec<ProtectionRule>>>,
+}
+
+impl SiteProtection {
+    pub async fn load(&self, inner: &HandlerInner) -> DynResult<()> {
+        let rules = inner.queries.fetch_protection_rules(&inner.db).await?;
+        *self.rules.write().unwrap() = Arc::new(rules);
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Arc<Vec<ProtectionRule>> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// The strictest level of the rules matching `name`.
+    pub fn level_for(&self, name: &str) -> Option<Level> {
+        self.rules()
+            .iter()
+            .filter(|rule| matches(&rule.pattern, name))
+            .filter_map(|rule| rule.level.parse().ok())
+            .max()
+    }
+}
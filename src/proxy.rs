@@ -0,0 +1,121 @@
+use std::net::{IpAddr, SocketAddr};
+
+use hyper::header::{self, HeaderMap};
+use hyper::{Body, Request};
+
+/// An address range in CIDR notation, e.g. `10.0.0.0/8` or `::1/128`.
+#[derive(Debug, Clone, Copy)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl std::str::FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("invalid address range {:?}", s);
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| bad())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse().map_err(|_| bad())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(bad());
+        }
+        Ok(IpRange { addr, prefix_len })
+    }
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Where a request really came from, after accounting for trusted proxies.
+/// Inserted into the request extensions by `Handler::handle`.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub addr: IpAddr,
+    pub scheme: String,
+    pub host: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn of(req: &Request<Body>) -> Option<&ClientInfo> {
+        req.extensions().get::<ClientInfo>()
+    }
+
+    /// Resolves the client address. Forwarding headers are only honoured when
+    /// the connection itself comes from a trusted proxy.
+    pub fn resolve(remote_addr: SocketAddr, headers: &HeaderMap, trusted: &[IpRange]) -> ClientInfo {
+        let host = headers
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_string());
+        let is_trusted = |ip: IpAddr| trusted.iter().any(|r| r.contains(ip));
+
+        if !is_trusted(remote_addr.ip()) {
+            return ClientInfo {
+                addr: remote_addr.ip(),
+                scheme: "http".to_string(),
+                host,
+            };
+        }
+
+        let mut addr = remote_addr.ip();
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        // walk back from the nearest hop until we leave the trusted proxies
+        for hop in forwarded.into_iter().rev() {
+            addr = hop;
+            if !is_trusted(hop) {
+                break;
+            }
+        }
+
+        let scheme = match headers
+            .get("x-forwarded-proto")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split(',').next())
+            .map(|h| h.trim())
+        {
+            Some("https") => "https",
+            _ => "http",
+        };
+
+        ClientInfo {
+            addr,
+            scheme: scheme.to_string(),
+            host,
+        }
+    }
+
+    pub fn base_url(&self) -> String {
+        match self.host {
+            Some(ref host) => format!("{}://{}", self.scheme, host),
+            None => String::new(),
+        }
+    }
+}
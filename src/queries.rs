@@ -0,0 +1,3925 @@
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio_postgres::{GenericClient, Row, Statement};
+
+use crate::appearance::Appearance;
+use crate::attachments::content_hash;
+use crate::metrics::QueryMetrics;
+use crate::preferences::Preferences;
+use crate::secrets;
+use crate::DynResult;
+
+/// Runs one of the prepared statements through `QueryMetrics::time`, named
+/// after its field: `timed!(self, db.query(history, &[&name]))`.
+macro_rules! timed {
+    ($queries:ident, $db:ident.$method:ident($statement:ident, $($params:tt)*)) => {
+        $queries
+            .metrics
+            .time(stringify!($statement), $db.$method(&$queries.$statement, $($params)*))
+    };
+}
+
+/// A single stored revision of a document.
+#[derive(Debug)]
+pub struct Revision {
+    pub id: i64,
+    pub document_data: String,
+    pub created_at: DateTime<Utc>,
+    pub modified_by: String,
+    pub is_current: bool,
+}
+
+impl Revision {
+    fn from_row(row: &Row) -> DynResult<Revision> {
+        let current_revision_id: Option<i64> = row.try_get(4)?;
+        let id = row.try_get(0)?;
+        Ok(Revision {
+            id,
+            document_data: row.try_get(1)?,
+            created_at: row.try_get(2)?,
+            modified_by: row.try_get(3)?,
+            is_current: current_revision_id == Some(id),
+        })
+    }
+}
+
+/// What the page view needs from the database, fetched together: a
+/// revision of the document, the tags on it and how many pages link to the
+/// document. Protection levels are kept in memory, see `protection.rs`.
+#[derive(Debug)]
+pub struct PageBundle {
+    pub revision: Revision,
+    /// Labels on this revision, oldest first.
+    pub tags: Vec<String>,
+    /// Pages with a current revision linking here.
+    pub backlink_count: i64,
+}
+
+impl PageBundle {
+    fn from_row(row: &Row) -> DynResult<PageBundle> {
+        Ok(PageBundle {
+            revision: Revision::from_row(row)?,
+            tags: row.try_get(5)?,
+            backlink_count: row.try_get(6)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub modified_by: String,
+    pub size: i32,
+    pub size_delta: i32,
+    pub status: RevisionStatus,
+    pub reviewed_by: Option<String>,
+    pub redaction: Option<Redaction>,
+    pub summary: Option<String>,
+}
+
+/// A revision as drawn on a page's revision graph.
+#[derive(Debug, Clone)]
+pub struct GraphRevision {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub modified_by: String,
+    pub status: RevisionStatus,
+    pub content_hash: String,
+    pub redacted: bool,
+    /// The page it was written on, if it was merged in from another.
+    pub merged_from: Option<String>,
+}
+
+/// Who replaced a revision's text with a tombstone, and why.
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    pub redacted_by: String,
+    pub redacted_at: DateTime<Utc>,
+    pub reason: String,
+}
+
+/// Where a revision is in the review workflow. Revisions saved without
+/// moderation are `Published` straight away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevisionStatus {
+    Published,
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl RevisionStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RevisionStatus::Published => "published",
+            RevisionStatus::Pending => "pending",
+            RevisionStatus::Approved => "approved",
+            RevisionStatus::Rejected => "rejected",
+        }
+    }
+
+    fn parse(s: &str) -> DynResult<RevisionStatus> {
+        match s {
+            "published" => Ok(RevisionStatus::Published),
+            "pending" => Ok(RevisionStatus::Pending),
+            "approved" => Ok(RevisionStatus::Approved),
+            "rejected" => Ok(RevisionStatus::Rejected),
+            _ => Err(format!("unknown revision status {:?}", s).into()),
+        }
+    }
+}
+
+/// A revision waiting in the review queue.
+#[derive(Debug)]
+pub struct PendingRevision {
+    pub id: i64,
+    pub name: String,
+    pub modified_by: String,
+    pub created_at: DateTime<Utc>,
+    pub size: i32,
+    pub current_revision_id: Option<i64>,
+}
+
+/// A pending revision that has just been approved or rejected.
+#[derive(Debug)]
+pub struct ReviewedRevision {
+    pub id: i64,
+    pub document_id: i64,
+    pub name: String,
+    pub modified_by: String,
+    pub document_data: String,
+    /// Whether the page is still at the revision this one was written on.
+    /// If not, approving it would undo the edits saved since.
+    pub up_to_date: bool,
+}
+
+#[derive(Debug)]
+pub struct Annotation {
+    pub created_at: DateTime<Utc>,
+    pub created_by: String,
+    pub start_offset: i32,
+    pub end_offset: i32,
+    pub quote: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct NewAnnotation<'a> {
+    pub created_by: &'a str,
+    pub start_offset: i32,
+    pub end_offset: i32,
+    pub quote: &'a str,
+    pub body: &'a str,
+}
+
+#[derive(Debug)]
+pub struct AttachmentInfo {
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub content_hash: String,
+    pub uploaded_by: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct NewAttachment<'a> {
+    pub filename: &'a str,
+    pub content_type: &'a str,
+    pub data: &'a [u8],
+    /// See `attachments::content_hash`.
+    pub content_hash: &'a str,
+    pub uploaded_by: &'a str,
+}
+
+#[derive(Debug)]
+pub struct AttachmentContent {
+    pub content_type: String,
+    pub content_hash: String,
+    pub data: Vec<u8>,
+}
+
+impl AttachmentContent {
+    fn from_row(row: &Row) -> DynResult<AttachmentContent> {
+        Ok(AttachmentContent {
+            content_type: row.try_get(0)?,
+            content_hash: row.try_get(1)?,
+            data: row.try_get(2)?,
+        })
+    }
+}
+
+/// See `Queries::fetch_stale_pages`.
+#[derive(Debug)]
+pub struct StalePage {
+    pub name: String,
+    pub expires_on: NaiveDate,
+    pub modified_at: DateTime<Utc>,
+    pub modified_by: String,
+}
+
+/// Site-wide counts for `/admin/stats`, see `Queries::fetch_site_totals`.
+#[derive(Debug)]
+pub struct SiteTotals {
+    /// Pages with a current revision.
+    pub pages: i64,
+    pub revisions: i64,
+    pub users: i64,
+    /// Space every revision takes on disk, with indexes, in bytes.
+    pub revision_bytes: i64,
+    /// Attachment contents, each distinct file counted once.
+    pub attachment_bytes: i64,
+}
+
+/// See `Queries::fetch_unread_pages`.
+#[derive(Debug)]
+pub struct UnreadPage {
+    pub name: String,
+    pub current_revision_id: i64,
+    /// The newest revision the user has seen, if they ever opened the page.
+    pub read_revision_id: Option<i64>,
+    pub modified_at: DateTime<Utc>,
+    pub modified_by: String,
+}
+
+/// See `Queries::fetch_merge_preview`.
+#[derive(Debug)]
+pub struct MergePreview {
+    /// Revisions, including pending ones, that will join the target's history.
+    pub revisions: i64,
+    /// Tags the target already uses; the source's copies are dropped.
+    pub conflicting_tags: Vec<String>,
+    /// Attachment names the target already uses; these stay with the source.
+    pub conflicting_attachments: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct MoveEntry {
+    pub old_name: String,
+    pub new_name: String,
+    pub moved_by: String,
+    pub moved_at: DateTime<Utc>,
+    pub reason: String,
+}
+
+impl MoveEntry {
+    fn from_row(row: &Row) -> DynResult<MoveEntry> {
+        Ok(MoveEntry {
+            old_name: row.try_get(0)?,
+            new_name: row.try_get(1)?,
+            moved_by: row.try_get(2)?,
+            moved_at: row.try_get(3)?,
+            reason: row.try_get(4)?,
+        })
+    }
+}
+
+/// A page's current revision, as listed for `wiki sync`.
+#[derive(Debug, Clone)]
+pub struct ChangedPage {
+    pub name: String,
+    pub revision_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub created_by: String,
+}
+
+impl ChangedPage {
+    fn from_row(row: &Row) -> DynResult<ChangedPage> {
+        Ok(ChangedPage {
+            name: row.try_get(0)?,
+            revision_id: row.try_get(1)?,
+            created_at: row.try_get(2)?,
+            created_by: row.try_get(3)?,
+        })
+    }
+}
+
+/// A revision as written to a backup archive, see `backup.rs`.
+#[derive(Debug)]
+pub struct BackupRevision {
+    pub id: i64,
+    /// The page's name when the backup was taken.
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub modified_by: String,
+    pub document_data: String,
+    pub status: String,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    /// Whether this is the page's current revision.
+    pub current: bool,
+}
+
+/// A move as written to a backup archive.
+#[derive(Debug)]
+pub struct BackupMove {
+    pub entry: MoveEntry,
+    /// The page's name when the backup was taken.
+    pub name: String,
+}
+
+/// A rule from `/admin/filters`, see `edit_filter.rs`.
+#[derive(Debug, Clone)]
+pub struct EditFilter {
+    pub id: i64,
+    pub name: String,
+    pub rule: String,
+    pub action: String,
+    pub enabled: bool,
+    pub updated_by: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl EditFilter {
+    fn from_row(row: &Row) -> DynResult<EditFilter> {
+        Ok(EditFilter {
+            id: row.try_get(0)?,
+            name: row.try_get(1)?,
+            rule: row.try_get(2)?,
+            action: row.try_get(3)?,
+            enabled: row.try_get(4)?,
+            updated_by: row.try_get(5)?,
+            updated_at: row.try_get(6)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct NewEditFilter<'a> {
+    pub name: &'a str,
+    /// Already checked to parse.
+    pub rule: &'a str,
+    pub action: &'a str,
+    pub enabled: bool,
+}
+
+/// The user behind a session, see `Queries::fetch_session_user`.
+#[derive(Debug)]
+pub struct SessionUser {
+    pub username: String,
+    pub preferences: Preferences,
+    /// Published revisions attributed to the user.
+    pub edit_count: i64,
+    /// Whole days since the account was created.
+    pub age_days: i64,
+    /// `last_seen_at` is old enough to be worth updating.
+    pub seen_stale: bool,
+    /// Two-factor authentication is on and this session passed it, see
+    /// `two_factor.rs`.
+    pub two_factor: bool,
+}
+
+/// A pattern of pages protected at `/admin/protection`, see
+/// `protection.rs`.
+#[derive(Debug, Clone)]
+pub struct ProtectionRule {
+    pub id: i64,
+    pub pattern: String,
+    pub level: String,
+    pub reason: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A published revision for the weekly digest, see `digest.rs`.
+#[derive(Debug)]
+pub struct DigestRevision {
+    pub name: String,
+    pub id: i64,
+    /// The revision before it, `None` if it created the page.
+    pub previous_id: Option<i64>,
+    pub modified_by: String,
+    pub size_delta: i32,
+}
+
+/// Someone who asked for the weekly digest at `/settings`, with a verified
+/// address.
+#[derive(Debug)]
+pub struct DigestRecipient {
+    pub username: String,
+    pub email: String,
+    pub namespaces: Vec<String>,
+    /// `Preferences::follow`.
+    pub follow: Vec<String>,
+}
+
+/// A logged-in device, listed at `/settings/sessions`.
+#[derive(Debug)]
+pub struct SessionRecord {
+    pub id: i64,
+    /// Only compared with the request's cookie, never shown.
+    pub token: String,
+    pub addr: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// What logging in checks, see `Handler::login_page_post`.
+#[derive(Debug)]
+pub struct Credentials {
+    pub user_id: i64,
+    pub password_hash: String,
+    /// Signed up and yet to verify their address, see `accounts.rs`.
+    pub awaiting_verification: bool,
+    /// A code is needed after the password, see `two_factor.rs`.
+    pub two_factor: bool,
+}
+
+/// A user's two-factor authentication, see `two_factor.rs`.
+#[derive(Debug)]
+pub struct TwoFactorState {
+    pub password_hash: String,
+    /// `None` while it's off.
+    pub secret: Option<String>,
+    pub last_step: Option<i64>,
+    pub recovery_codes_left: i64,
+}
+
+/// A user as far as signing up and resetting passwords go, see
+/// `accounts.rs`.
+#[derive(Debug)]
+pub struct Account {
+    pub id: i64,
+    pub username: String,
+    pub password_hash: String,
+    /// The address from `/settings`, verified or not.
+    pub email: Option<String>,
+    pub verified_email: Option<String>,
+}
+
+impl Account {
+    fn from_row(row: &Row) -> DynResult<Account> {
+        Ok(Account {
+            id: row.try_get(0)?,
+            username: row.try_get(1)?,
+            password_hash: row.try_get(2)?,
+            email: row.try_get(3)?,
+            verified_email: row.try_get(4)?,
+        })
+    }
+
+    /// Where a password reset link may go: the current address, if it has
+    /// been verified.
+    pub fn reset_address(&self) -> Option<&str> {
+        self.email
+            .as_deref()
+            .filter(|email| self.verified_email.as_deref() == Some(*email))
+    }
+}
+
+/// A save an edit filter matched.
+#[derive(Debug)]
+pub struct EditFilterHit {
+    pub filter_name: String,
+    pub page_name: String,
+    pub attribution: String,
+    pub action: String,
+    /// `None` if the save was refused.
+    pub revision_id: Option<i64>,
+    pub hit_at: DateTime<Utc>,
+}
+
+pub struct SecretFinding {
+    pub kind: String,
+    pub line: i32,
+    pub page_name: String,
+    pub attribution: String,
+    /// `None` if the save was refused.
+    pub revision_id: Option<i64>,
+    pub found_at: DateTime<Utc>,
+}
+
+/// A label pointing at one revision of a document.
+#[derive(Debug)]
+pub struct RevisionTag {
+    pub label: String,
+    pub revision_id: i64,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a revision contributes to the link graph and `page_data`, derived
+/// from its Markdown when it becomes current.
+#[derive(Debug, Default)]
+pub struct DocumentIndex {
+    pub links: Vec<String>,
+    pub data: Vec<(String, String)>,
+    pub expires: Option<NaiveDate>,
+}
+
+/// An entry in the site-wide change log.
+#[derive(Debug)]
+pub enum Change {
+    Edit {
+        name: String,
+        revision_id: i64,
+        modified_by: String,
+        created_at: DateTime<Utc>,
+    },
+    Move(MoveEntry),
+}
+
+/// Every statement the server runs, prepared once when the connection is
+/// opened. Preparing up front means a schema that has drifted from the code
+/// fails at startup rather than on the first request that touches it.
+///
+/// Statements belong to the connection they were prepared on, so a `Queries`
+/// must only be used with that connection or transactions opened on it.
+pub struct Queries {
+    pub metrics: QueryMetrics,
+    document_id: Statement,
+    existing_names: Statement,
+    current_documents: Statement,
+    current_revision: Statement,
+    current_revision_for_update: Statement,
+    current_revision_id_for_update: Statement,
+    revision: Statement,
+    page_bundle: Statement,
+    history: Statement,
+    revision_graph: Statement,
+    published_revisions: Statement,
+    current_names: Statement,
+    document_names: Statement,
+    link_target_names: Statement,
+    rename_link_target: Statement,
+    links: Statement,
+    backlinks: Statement,
+    link_edges: Statement,
+    similar_names: Statement,
+    transcluders: Statement,
+    orphans: Statement,
+    wanted: Statement,
+    random_name: Statement,
+    delete_links: Statement,
+    insert_links: Statement,
+    delete_page_data: Statement,
+    insert_page_data: Statement,
+    matching_page_data: Statement,
+    tagged_names: Statement,
+    upsert_document: Statement,
+    rename_document: Statement,
+    insert_move: Statement,
+    moves: Statement,
+    merge_conflicts: Statement,
+    merge_history: Statement,
+    merge_tags: Statement,
+    delete_tags: Statement,
+    merge_attachments: Statement,
+    merge_moves: Statement,
+    set_redirect: Statement,
+    redirect: Statement,
+    revision_page: Statement,
+    recent_changes: Statement,
+    changes_after: Statement,
+    sync_cursor: Statement,
+    upsert_sync_cursor: Statement,
+    sync_page: Statement,
+    upsert_sync_page: Statement,
+    sync_conflicts: Statement,
+    insert_sync_conflict: Statement,
+    delete_sync_conflict: Statement,
+    insert_revision: Statement,
+    set_current_revision: Statement,
+    set_revision_summary: Statement,
+    ensure_document: Statement,
+    pending_revisions: Statement,
+    review_revision: Statement,
+    annotations: Statement,
+    insert_annotation: Statement,
+    tags: Statement,
+    tagged_revision: Statement,
+    insert_tag: Statement,
+    redact_revision: Statement,
+    clear_annotation_quotes: Statement,
+    attachments: Statement,
+    attachment: Statement,
+    attachment_by_hash: Statement,
+    attachment_usage: Statement,
+    attachment_preview: Statement,
+    insert_attachment_preview: Statement,
+    insert_blob: Statement,
+    replaced_attachment_hash: Statement,
+    adjust_blob_refs: Statement,
+    upsert_attachment: Statement,
+    recount_blob_refs: Statement,
+    delete_unused_blobs: Statement,
+    session_user: Statement,
+    upsert_preferences: Statement,
+    timezone_exists: Statement,
+    unread_pages: Statement,
+    set_expiry: Statement,
+    stale_pages: Statement,
+    mark_read: Statement,
+    mark_all_read: Statement,
+    appearance: Statement,
+    upsert_appearance: Statement,
+    logo: Statement,
+    upsert_logo: Statement,
+    render_generation: Statement,
+    bump_render_generation: Statement,
+    max_change_id: Statement,
+    pending_creation_count_by: Statement,
+    edit_filters: Statement,
+    protection_rules: Statement,
+    digest_revisions: Statement,
+    digest_recipients: Statement,
+    digest_sent_until: Statement,
+    store_digest_sent_until: Statement,
+    upsert_protection_rule: Statement,
+    delete_protection_rule: Statement,
+    enabled_edit_filters: Statement,
+    insert_edit_filter: Statement,
+    update_edit_filter: Statement,
+    delete_edit_filter: Statement,
+    insert_edit_filter_hit: Statement,
+    recent_edit_filter_hits: Statement,
+    insert_secret_finding: Statement,
+    recent_secret_findings: Statement,
+    edit_filter_tags: Statement,
+    user_created_at: Statement,
+    current_revision_with_hash: Statement,
+    revisions_after: Statement,
+    moves_after: Statement,
+    insert_restored_revision: Statement,
+    insert_restored_move: Statement,
+    insert_restored_move_log: Statement,
+    reset_revision_ids: Statement,
+    restore_checkpoint: Statement,
+    upsert_restore_checkpoint: Statement,
+    insert_session: Statement,
+    delete_session: Statement,
+    touch_session: Statement,
+    user_sessions: Statement,
+    revoke_session: Statement,
+    revoke_all_sessions: Statement,
+    revoke_other_sessions: Statement,
+    set_session_two_factor: Statement,
+    user_credentials: Statement,
+    insert_user: Statement,
+    account_by_id: Statement,
+    account_by_username: Statement,
+    account_by_verified_email: Statement,
+    register_user: Statement,
+    set_verified_email: Statement,
+    set_password_hash: Statement,
+    delete_user_sessions: Statement,
+    signing_key: Statement,
+    insert_signing_key: Statement,
+    two_factor: Statement,
+    set_totp: Statement,
+    advance_totp_step: Statement,
+    delete_recovery_codes: Statement,
+    insert_recovery_codes: Statement,
+    use_recovery_code: Statement,
+    site_totals: Statement,
+    edits_per_day: Statement,
+    top_editors: Statement,
+}
+
+impl Queries {
+    /// Queries slower than `slow_query_threshold` are logged, see
+    /// `QueryMetrics`.
+    pub async fn prepare(db: &tokio_postgres::Client, slow_query_threshold: Duration) -> DynResult<Queries> {
+        Ok(Queries {
+            metrics: QueryMetrics::new(slow_query_threshold),
+            document_id: db.prepare("SELECT id FROM document WHERE name = $1").await?,
+            existing_names: db
+                .prepare(
+                    r#"
+                        SELECT name FROM document
+                        WHERE name = ANY($1) AND current_revision_id IS NOT NULL
+                    "#,
+                )
+                .await?,
+            current_documents: db
+                .prepare(
+                    r#"
+                        SELECT document.name, document_history.document_data FROM document
+                        INNER JOIN document_history ON document_history.id = document.current_revision_id
+                        WHERE document.name = ANY($1)
+                    "#,
+                )
+                .await?,
+            current_revision: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document_history.id,
+                            document_data,
+                            document_history.created_at,
+                            document_history.modified_by,
+                            document.current_revision_id
+                        FROM document_history
+                        INNER JOIN document ON document.current_revision_id = document_history.id
+                        WHERE document.name = $1
+                    "#,
+                )
+                .await?,
+            current_revision_for_update: db
+                .prepare(
+                    r#"
+                        SELECT document_history.document_data FROM document
+                        LEFT JOIN document_history ON document_history.id = document.current_revision_id
+                        WHERE document.name = $1
+                        FOR UPDATE OF document
+                    "#,
+                )
+                .await?,
+            current_revision_id_for_update: db
+                .prepare(
+                    r#"
+                        SELECT current_revision_id FROM document
+                        WHERE name = $1
+                        FOR UPDATE
+                    "#,
+                )
+                .await?,
+            revision: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document_history.id,
+                            document_data,
+                            document_history.created_at,
+                            document_history.modified_by,
+                            document.current_revision_id
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document.name = $1 AND document_history.id = $2
+                            AND (document_history.status IN ('published', 'approved') OR $3)
+                    "#,
+                )
+                .await?,
+            page_bundle: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document_history.id,
+                            document_data,
+                            document_history.created_at,
+                            document_history.modified_by,
+                            document.current_revision_id,
+                            ARRAY(
+                                SELECT label FROM revision_tags
+                                WHERE revision_tags.document_history_id = document_history.id
+                                ORDER BY revision_tags.created_at
+                            ),
+                            (
+                                SELECT COUNT(DISTINCT source.id) FROM document_link
+                                INNER JOIN document AS source ON source.id = document_link.source_document_id
+                                WHERE document_link.target_name = document.name
+                                    AND source.current_revision_id IS NOT NULL
+                            )
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document.name = $1
+                            AND document_history.id = COALESCE($2::BIGINT, document.current_revision_id)
+                            AND (document_history.status IN ('published', 'approved') OR $3)
+                    "#,
+                )
+                .await?,
+            history: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document_history.id,
+                            created_at,
+                            modified_by,
+                            octet_length(document_data),
+                            octet_length(document_data) - COALESCE(
+                                LAG(octet_length(document_data)) OVER (ORDER BY document_history.id),
+                                0
+                            ),
+                            status,
+                            reviewed_by,
+                            redacted_by,
+                            redacted_at,
+                            redaction_reason,
+                            summary
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document.name = $1
+                            AND (status IN ('published', 'approved') OR $2)
+                        ORDER BY document_history.id DESC
+                        LIMIT 50
+                    "#,
+                )
+                .await?,
+            revision_graph: db
+                .prepare(
+                    r#"
+                        SELECT * FROM (
+                            SELECT
+                                document_history.id,
+                                created_at,
+                                modified_by,
+                                status,
+                                content_hash,
+                                redacted_by IS NOT NULL,
+                                merged_from
+                            FROM document_history
+                            INNER JOIN document ON document.id = document_history.document_id
+                            WHERE document.name = $1
+                                AND (status IN ('published', 'approved') OR $3)
+                            ORDER BY document_history.id DESC
+                            LIMIT $2
+                        ) AS recent
+                        ORDER BY id
+                    "#,
+                )
+                .await?,
+            published_revisions: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document_history.id,
+                            document_data,
+                            document_history.created_at,
+                            document_history.modified_by,
+                            document.current_revision_id
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document.name = $1 AND status IN ('published', 'approved')
+                        ORDER BY document_history.id
+                    "#,
+                )
+                .await?,
+            current_names: db
+                .prepare(
+                    r#"
+                        SELECT name FROM document
+                        WHERE current_revision_id IS NOT NULL
+                        ORDER BY name
+                    "#,
+                )
+                .await?,
+            document_names: db
+                .prepare(
+                    r#"
+                        SELECT name FROM document
+                        ORDER BY name
+                    "#,
+                )
+                .await?,
+            link_target_names: db
+                .prepare(
+                    r#"
+                        SELECT DISTINCT target_name FROM document_link
+                        ORDER BY target_name
+                    "#,
+                )
+                .await?,
+            rename_link_target: db
+                .prepare(
+                    r#"
+                        WITH renamed AS (
+                            DELETE FROM document_link WHERE target_name = $1
+                            RETURNING source_document_id
+                        )
+                        INSERT INTO document_link (source_document_id, target_name)
+                        SELECT source_document_id, $2 FROM renamed
+                        ON CONFLICT DO NOTHING
+                    "#,
+                )
+                .await?,
+            links: db
+                .prepare(
+                    r#"
+                        SELECT target_name FROM document_link
+                        INNER JOIN document ON document.id = document_link.source_document_id
+                        WHERE document.name = $1
+                        ORDER BY target_name
+                    "#,
+                )
+                .await?,
+            backlinks: db
+                .prepare(
+                    r#"
+                        SELECT document.name FROM document_link
+                        INNER JOIN document ON document.id = document_link.source_document_id
+                        WHERE document_link.target_name = $1 AND document.current_revision_id IS NOT NULL
+                        ORDER BY document.name
+                        LIMIT $2
+                    "#,
+                )
+                .await?,
+            link_edges: db
+                .prepare(
+                    r#"
+                        SELECT document.name, document_link.target_name FROM document_link
+                        INNER JOIN document ON document.id = document_link.source_document_id
+                        WHERE document.current_revision_id IS NOT NULL
+                            AND (document.name = ANY($1) OR document_link.target_name = ANY($1))
+                        ORDER BY document.name, document_link.target_name
+                    "#,
+                )
+                .await?,
+            // `%` compares whole names, `<%` finds the query inside a longer
+            // name; both can use the trigram index on document.name
+            similar_names: db
+                .prepare(
+                    r#"
+                        SELECT name FROM document
+                        WHERE current_revision_id IS NOT NULL AND (name % $1 OR $1 <% name)
+                        ORDER BY greatest(similarity(name, $1), word_similarity($1, name)) DESC, name
+                        LIMIT $2
+                    "#,
+                )
+                .await?,
+            transcluders: db
+                .prepare(
+                    r#"
+                        SELECT document.name FROM document
+                        INNER JOIN document_history ON document_history.id = document.current_revision_id
+                        WHERE strpos(document_history.document_data, $1) > 0
+                        ORDER BY document.name
+                        LIMIT $2
+                    "#,
+                )
+                .await?,
+            orphans: db
+                .prepare(
+                    r#"
+                        SELECT name FROM document
+                        WHERE current_revision_id IS NOT NULL
+                            AND NOT EXISTS (
+                                SELECT 1 FROM document_link
+                                INNER JOIN document source ON source.id = document_link.source_document_id
+                                WHERE document_link.target_name = document.name
+                                    AND source.id <> document.id
+                                    AND source.current_revision_id IS NOT NULL
+                            )
+                        ORDER BY name
+                    "#,
+                )
+                .await?,
+            wanted: db
+                .prepare(
+                    r#"
+                        SELECT document_link.target_name, COUNT(*) FROM document_link
+                        INNER JOIN document source ON source.id = document_link.source_document_id
+                        LEFT JOIN document target ON target.name = document_link.target_name
+                        WHERE source.current_revision_id IS NOT NULL
+                            AND target.current_revision_id IS NULL
+                        GROUP BY document_link.target_name
+                        ORDER BY COUNT(*) DESC, document_link.target_name
+                    "#,
+                )
+                .await?,
+            random_name: db
+                .prepare(
+                    r#"
+                        SELECT name FROM document
+                        WHERE current_revision_id IS NOT NULL
+                        ORDER BY random()
+                        LIMIT 1
+                    "#,
+                )
+                .await?,
+            delete_links: db
+                .prepare(
+                    r#"
+                        DELETE FROM document_link USING document
+                        WHERE document.id = document_link.source_document_id AND document.name = $1
+                    "#,
+                )
+                .await?,
+            insert_links: db
+                .prepare(
+                    r#"
+                        INSERT INTO document_link (source_document_id, target_name)
+                        SELECT document.id, target FROM document, unnest($2::TEXT[]) AS target
+                        WHERE document.name = $1
+                        ON CONFLICT DO NOTHING
+                    "#,
+                )
+                .await?,
+            delete_page_data: db
+                .prepare(
+                    r#"
+                        DELETE FROM page_data
+                        WHERE document_id = (SELECT id FROM document WHERE name = $1)
+                    "#,
+                )
+                .await?,
+            insert_page_data: db
+                .prepare(
+                    r#"
+                        INSERT INTO page_data (document_id, key, value)
+                        SELECT document.id, pairs.key, pairs.value
+                        FROM document, UNNEST($2::VARCHAR[], $3::TEXT[]) AS pairs(key, value)
+                        WHERE document.name = $1
+                    "#,
+                )
+                .await?,
+            matching_page_data: db
+                .prepare(
+                    r#"
+                        SELECT document.name, page_data.key, page_data.value FROM page_data
+                        INNER JOIN document ON document.id = page_data.document_id
+                        WHERE document.current_revision_id IS NOT NULL
+                            AND page_data.document_id IN (
+                                SELECT document_id FROM page_data
+                                WHERE (key, value) IN (SELECT * FROM UNNEST($1::VARCHAR[], $2::TEXT[]))
+                                GROUP BY document_id
+                                HAVING COUNT(*) = CARDINALITY($1::VARCHAR[])
+                            )
+                        ORDER BY document.name
+                    "#,
+                )
+                .await?,
+            tagged_names: db
+                .prepare(
+                    r#"
+                        SELECT DISTINCT document.name FROM page_data
+                        INNER JOIN document ON document.id = page_data.document_id
+                        WHERE (key, value) IN (SELECT * FROM UNNEST($1::VARCHAR[], $2::TEXT[]))
+                    "#,
+                )
+                .await?,
+            upsert_document: db
+                .prepare(
+                    r#"
+                        INSERT INTO document
+                        (name, last_modified) VALUES ($1, $2)
+                        ON CONFLICT (name) DO UPDATE SET last_modified = EXCLUDED.last_modified
+                        RETURNING id
+                    "#,
+                )
+                .await?,
+            rename_document: db
+                .prepare("UPDATE document SET name = $2 WHERE name = $1 RETURNING id")
+                .await?,
+            insert_move: db
+                .prepare(
+                    r#"
+                        INSERT INTO move_log (document_id, old_name, new_name, moved_by, moved_at, reason)
+                        VALUES ($1, $2, $3, $4, NOW(), $5)
+                    "#,
+                )
+                .await?,
+            merge_conflicts: db
+                .prepare(
+                    r#"
+                        SELECT
+                            (SELECT COUNT(*) FROM document_history WHERE document_id = $1),
+                            ARRAY(
+                                SELECT label FROM revision_tags WHERE document_id = $1
+                                AND label IN (SELECT label FROM revision_tags WHERE document_id = $2)
+                                ORDER BY label
+                            ),
+                            ARRAY(
+                                SELECT filename FROM attachment WHERE document_id = $1
+                                AND filename IN (SELECT filename FROM attachment WHERE document_id = $2)
+                                ORDER BY filename
+                            )
+                    "#,
+                )
+                .await?,
+            merge_history: db
+                .prepare(
+                    r#"
+                        UPDATE document_history SET
+                            document_id = $2,
+                            merged_from = COALESCE(merged_from, (SELECT name FROM document WHERE id = $1))
+                        WHERE document_id = $1
+                    "#,
+                )
+                .await?,
+            merge_tags: db
+                .prepare(
+                    r#"
+                        UPDATE revision_tags SET document_id = $2
+                        WHERE document_id = $1
+                        AND label NOT IN (SELECT label FROM revision_tags WHERE document_id = $2)
+                    "#,
+                )
+                .await?,
+            delete_tags: db
+                .prepare("DELETE FROM revision_tags WHERE document_id = $1")
+                .await?,
+            merge_attachments: db
+                .prepare(
+                    r#"
+                        UPDATE attachment SET document_id = $2
+                        WHERE document_id = $1
+                        AND filename NOT IN (SELECT filename FROM attachment WHERE document_id = $2)
+                    "#,
+                )
+                .await?,
+            merge_moves: db
+                .prepare("UPDATE move_log SET document_id = $2 WHERE document_id = $1")
+                .await?,
+            set_redirect: db
+                .prepare(
+                    r#"
+                        UPDATE document SET current_revision_id = NULL, redirect_to = $2, last_modified = NOW()
+                        WHERE id = $1
+                    "#,
+                )
+                .await?,
+            redirect: db
+                .prepare(
+                    r#"
+                        SELECT redirect_to FROM document
+                        WHERE name = $1 AND current_revision_id IS NULL AND redirect_to IS NOT NULL
+                    "#,
+                )
+                .await?,
+            revision_page: db
+                .prepare(
+                    r#"
+                        SELECT document.name FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document_history.id = $1
+                    "#,
+                )
+                .await?,
+            moves: db
+                .prepare(
+                    r#"
+                        SELECT old_name, new_name, moved_by, moved_at, reason FROM move_log
+                        INNER JOIN document ON document.id = move_log.document_id
+                        WHERE document.name = $1
+                        ORDER BY move_log.id
+                    "#,
+                )
+                .await?,
+            recent_changes: db
+                .prepare(
+                    r#"
+                        SELECT * FROM (
+                            SELECT
+                                NULL::VARCHAR AS old_name,
+                                document.name AS new_name,
+                                document_history.modified_by,
+                                document_history.created_at,
+                                NULL::TEXT AS reason,
+                                document_history.id AS revision_id
+                            FROM document_history
+                            INNER JOIN document ON document.id = document_history.document_id
+                            UNION ALL
+                            SELECT old_name, new_name, moved_by, moved_at, reason, NULL
+                            FROM move_log
+                        ) changes
+                        ORDER BY created_at DESC
+                        LIMIT $1
+                    "#,
+                )
+                .await?,
+            changes_after: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document.name,
+                            document_history.id,
+                            document_history.created_at,
+                            document_history.modified_by
+                        FROM document
+                        INNER JOIN document_history ON document_history.id = document.current_revision_id
+                        WHERE document.current_revision_id > $1
+                        ORDER BY document.current_revision_id
+                        LIMIT $2
+                    "#,
+                )
+                .await?,
+            sync_cursor: db
+                .prepare("SELECT local_after, remote_after FROM sync_cursor WHERE remote = $1")
+                .await?,
+            upsert_sync_cursor: db
+                .prepare(
+                    r#"
+                        INSERT INTO sync_cursor (remote, local_after, remote_after)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT (remote) DO UPDATE
+                        SET local_after = EXCLUDED.local_after, remote_after = EXCLUDED.remote_after
+                    "#,
+                )
+                .await?,
+            sync_page: db
+                .prepare(
+                    r#"
+                        SELECT local_revision_id, remote_revision_id FROM sync_page
+                        WHERE remote = $1 AND name = $2
+                    "#,
+                )
+                .await?,
+            upsert_sync_page: db
+                .prepare(
+                    r#"
+                        INSERT INTO sync_page (remote, name, local_revision_id, remote_revision_id)
+                        VALUES ($1, $2, $3, $4)
+                        ON CONFLICT (remote, name) DO UPDATE
+                        SET local_revision_id = EXCLUDED.local_revision_id,
+                            remote_revision_id = EXCLUDED.remote_revision_id
+                    "#,
+                )
+                .await?,
+            sync_conflicts: db
+                .prepare(
+                    r#"
+                        SELECT name, local_revision_id, remote_revision_id, detected_at FROM sync_conflict
+                        WHERE remote = $1
+                        ORDER BY name
+                    "#,
+                )
+                .await?,
+            insert_sync_conflict: db
+                .prepare(
+                    r#"
+                        INSERT INTO sync_conflict (remote, name, local_revision_id, remote_revision_id, detected_at)
+                        VALUES ($1, $2, $3, $4, NOW())
+                        ON CONFLICT (remote, name) DO UPDATE
+                        SET local_revision_id = EXCLUDED.local_revision_id,
+                            remote_revision_id = EXCLUDED.remote_revision_id,
+                            detected_at = EXCLUDED.detected_at
+                    "#,
+                )
+                .await?,
+            delete_sync_conflict: db
+                .prepare("DELETE FROM sync_conflict WHERE remote = $1 AND name = $2")
+                .await?,
+            insert_revision: db
+                .prepare(
+                    r#"
+                        INSERT INTO document_history (created_at, document_id, modified_by, document_data, status, content_hash, base_revision_id)
+                        VALUES (NOW(), $1, $2, $3, $4, $5, (SELECT current_revision_id FROM document WHERE id = $1))
+                        RETURNING id
+                    "#,
+                )
+                .await?,
+            set_current_revision: db
+                .prepare(
+                    r#"
+                        UPDATE document SET current_revision_id = $2, last_modified = $3
+                        WHERE id = $1
+                    "#,
+                )
+                .await?,
+            set_revision_summary: db
+                .prepare("UPDATE document_history SET summary = $2 WHERE id = $1")
+                .await?,
+            ensure_document: db
+                .prepare(
+                    r#"
+                        INSERT INTO document (name, last_modified) VALUES ($1, NOW())
+                        ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+                        RETURNING id
+                    "#,
+                )
+                .await?,
+            pending_revisions: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document_history.id,
+                            document.name,
+                            document_history.modified_by,
+                            document_history.created_at,
+                            octet_length(document_history.document_data),
+                            document.current_revision_id
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document_history.status = 'pending'
+                        ORDER BY document_history.id
+                    "#,
+                )
+                .await?,
+            review_revision: db
+                .prepare(
+                    r#"
+                        UPDATE document_history SET status = $2, reviewed_by = $3, reviewed_at = NOW()
+                        FROM document
+                        WHERE document.id = document_history.document_id
+                            AND document_history.id = $1
+                            AND document_history.status = 'pending'
+                        RETURNING document.id, document.name, document_history.modified_by, document_history.document_data,
+                            document_history.base_revision_id IS NOT DISTINCT FROM document.current_revision_id
+                    "#,
+                )
+                .await?,
+            annotations: db
+                .prepare(
+                    r#"
+                        SELECT created_at, created_by, start_offset, end_offset, quote, body
+                        FROM document_annotation
+                        WHERE document_history_id = $1
+                        ORDER BY start_offset, id
+                    "#,
+                )
+                .await?,
+            insert_annotation: db
+                .prepare(
+                    r#"
+                        INSERT INTO document_annotation
+                        (document_history_id, created_at, created_by, start_offset, end_offset, quote, body)
+                        SELECT document_history.id, NOW(), $3, $4, $5, $6, $7
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document.name = $1 AND document_history.id = $2
+                    "#,
+                )
+                .await?,
+            tags: db
+                .prepare(
+                    r#"
+                        SELECT label, document_history_id, created_by, created_at FROM revision_tags
+                        INNER JOIN document ON document.id = revision_tags.document_id
+                        WHERE document.name = $1
+                        ORDER BY revision_tags.created_at
+                    "#,
+                )
+                .await?,
+            tagged_revision: db
+                .prepare(
+                    r#"
+                        SELECT document_history_id FROM revision_tags
+                        INNER JOIN document ON document.id = revision_tags.document_id
+                        WHERE document.name = $1 AND revision_tags.label = $2
+                    "#,
+                )
+                .await?,
+            redact_revision: db
+                .prepare(
+                    r#"
+                        UPDATE document_history
+                        SET document_data = $2, content_hash = $3, redacted_by = $4, redacted_at = NOW(), redaction_reason = $5
+                        WHERE id = $1
+                    "#,
+                )
+                .await?,
+            clear_annotation_quotes: db
+                .prepare("UPDATE document_annotation SET quote = '' WHERE document_history_id = $1")
+                .await?,
+            insert_tag: db
+                .prepare(
+                    r#"
+                        INSERT INTO revision_tags
+                        (document_id, label, document_history_id, created_by, created_at)
+                        SELECT document.id, $3, document_history.id, $4, NOW()
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document.name = $1 AND document_history.id = $2
+                        ON CONFLICT (document_id, label) DO NOTHING
+                    "#,
+                )
+                .await?,
+            attachments: db
+                .prepare(
+                    r#"
+                        SELECT filename, content_type, size_bytes, uploaded_by, uploaded_at, content_hash
+                        FROM attachment
+                        INNER JOIN document ON document.id = attachment.document_id
+                        WHERE document.name = $1
+                        ORDER BY filename
+                    "#,
+                )
+                .await?,
+            attachment: db
+                .prepare(
+                    r#"
+                        SELECT attachment.content_type, attachment.content_hash, attachment_blob.data
+                        FROM attachment
+                        INNER JOIN document ON document.id = attachment.document_id
+                        INNER JOIN attachment_blob ON attachment_blob.content_hash = attachment.content_hash
+                        WHERE document.name = $1 AND attachment.filename = $2
+                    "#,
+                )
+                .await?,
+            attachment_by_hash: db
+                .prepare(
+                    r#"
+                        SELECT attachment.content_type, attachment.content_hash, attachment_blob.data
+                        FROM attachment_blob
+                        INNER JOIN attachment ON attachment.content_hash = attachment_blob.content_hash
+                        WHERE attachment_blob.content_hash = $1 AND attachment.filename = $2
+                        LIMIT 1
+                    "#,
+                )
+                .await?,
+            attachment_preview: db
+                .prepare("SELECT data FROM attachment_preview WHERE content_hash = $1 AND kind = $2")
+                .await?,
+            insert_attachment_preview: db
+                .prepare(
+                    r#"
+                        INSERT INTO attachment_preview (content_hash, kind, data)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT (content_hash, kind) DO NOTHING
+                    "#,
+                )
+                .await?,
+            attachment_usage: db
+                .prepare(
+                    r#"
+                        SELECT
+                            COALESCE(SUM(size_bytes) FILTER (WHERE uploaded_by = $3), 0)::BIGINT,
+                            COALESCE(SUM(size_bytes), 0)::BIGINT
+                        FROM attachment
+                        WHERE NOT (document_id = $1 AND filename = $2)
+                    "#,
+                )
+                .await?,
+            insert_blob: db
+                .prepare(
+                    r#"
+                        INSERT INTO attachment_blob (content_hash, data, size_bytes, ref_count, created_at)
+                        VALUES ($1, $2, $3, 0, NOW())
+                        ON CONFLICT (content_hash) DO NOTHING
+                    "#,
+                )
+                .await?,
+            replaced_attachment_hash: db
+                .prepare(
+                    r#"
+                        SELECT content_hash FROM attachment
+                        WHERE document_id = $1 AND filename = $2
+                        FOR UPDATE
+                    "#,
+                )
+                .await?,
+            adjust_blob_refs: db
+                .prepare("UPDATE attachment_blob SET ref_count = ref_count + $2 WHERE content_hash = $1")
+                .await?,
+            recount_blob_refs: db
+                .prepare(
+                    r#"
+                        UPDATE attachment_blob SET ref_count = (
+                            SELECT COUNT(*) FROM attachment
+                            WHERE attachment.content_hash = attachment_blob.content_hash
+                        )
+                    "#,
+                )
+                .await?,
+            delete_unused_blobs: db
+                .prepare(
+                    r#"
+                        DELETE FROM attachment_blob WHERE ref_count = 0
+                        RETURNING size_bytes
+                    "#,
+                )
+                .await?,
+            upsert_attachment: db
+                .prepare(
+                    r#"
+                        INSERT INTO attachment
+                        (document_id, filename, content_type, size_bytes, content_hash, uploaded_by, uploaded_at)
+                        VALUES ($1, $2, $3, $4, $5, $6, NOW())
+                        ON CONFLICT (document_id, filename) DO UPDATE SET
+                            content_type = EXCLUDED.content_type,
+                            size_bytes = EXCLUDED.size_bytes,
+                            content_hash = EXCLUDED.content_hash,
+                            uploaded_by = EXCLUDED.uploaded_by,
+                            uploaded_at = EXCLUDED.uploaded_at
+                    "#,
+                )
+                .await?,
+            session_user: db
+                .prepare(
+                    r#"
+                        SELECT
+                            wiki_user.username,
+                            user_preferences.display_name,
+                            COALESCE(user_preferences.timezone, 'UTC'),
+                            EXTRACT(EPOCH FROM
+                                (NOW() AT TIME ZONE COALESCE(user_preferences.timezone, 'UTC'))
+                                - (NOW() AT TIME ZONE 'UTC')
+                            )::INTEGER,
+                            user_preferences.theme,
+                            COALESCE(user_preferences.editor, 'plain'),
+                            user_preferences.email,
+                            COALESCE(user_preferences.digest, FALSE),
+                            COALESCE(user_preferences.digest_namespaces, ''),
+                            (
+                                SELECT COUNT(*) FROM document_history
+                                WHERE modified_by = wiki_user.username AND status = 'published'
+                            ),
+                            EXTRACT(DAY FROM NOW() - wiki_user.created_at)::BIGINT,
+                            user_session.two_factor AND wiki_user.totp_secret IS NOT NULL,
+                            user_session.last_seen_at < NOW() - INTERVAL '5 minutes',
+                            COALESCE(user_preferences.follow, '')
+                        FROM user_session
+                        INNER JOIN wiki_user ON wiki_user.id = user_session.user_id
+                        LEFT JOIN user_preferences ON user_preferences.user_id = wiki_user.id
+                        WHERE user_session.token = $1
+                    "#,
+                )
+                .await?,
+            upsert_preferences: db
+                .prepare(
+                    r#"
+                        INSERT INTO user_preferences
+                        (user_id, display_name, timezone, theme, editor, email, digest, digest_namespaces, follow, updated_at)
+                        SELECT id, $2, $3, $4, $5, $6, $7, $8, $9, NOW() FROM wiki_user WHERE username = $1
+                        ON CONFLICT (user_id) DO UPDATE SET
+                            display_name = EXCLUDED.display_name,
+                            timezone = EXCLUDED.timezone,
+                            theme = EXCLUDED.theme,
+                            editor = EXCLUDED.editor,
+                            email = EXCLUDED.email,
+                            digest = EXCLUDED.digest,
+                            digest_namespaces = EXCLUDED.digest_namespaces,
+                            follow = EXCLUDED.follow,
+                            updated_at = EXCLUDED.updated_at
+                    "#,
+                )
+                .await?,
+            set_expiry: db
+                .prepare("UPDATE document SET expires_on = $2 WHERE name = $1")
+                .await?,
+            stale_pages: db
+                .prepare(
+                    r#"
+                        SELECT document.name, document.expires_on, document_history.created_at, document_history.modified_by
+                        FROM document
+                        INNER JOIN document_history ON document_history.id = document.current_revision_id
+                        WHERE document.expires_on <= $1
+                        ORDER BY document.expires_on, document.name
+                    "#,
+                )
+                .await?,
+            unread_pages: db
+                .prepare(
+                    r#"
+                        SELECT
+                            document.name,
+                            document.current_revision_id,
+                            page_read.revision_id,
+                            document_history.created_at,
+                            document_history.modified_by
+                        FROM document
+                        INNER JOIN document_history ON document_history.id = document.current_revision_id
+                        LEFT JOIN page_read ON page_read.document_id = document.id
+                            AND page_read.user_id = (SELECT id FROM wiki_user WHERE username = $1)
+                        WHERE page_read.revision_id IS NULL OR page_read.revision_id < document.current_revision_id
+                        ORDER BY document_history.created_at DESC
+                    "#,
+                )
+                .await?,
+            mark_read: db
+                .prepare(
+                    r#"
+                        INSERT INTO page_read (user_id, document_id, revision_id, read_at)
+                        SELECT wiki_user.id, document.id, $3, NOW()
+                        FROM wiki_user, document
+                        WHERE wiki_user.username = $1 AND document.name = $2
+                        ON CONFLICT (user_id, document_id) DO UPDATE SET
+                            revision_id = GREATEST(page_read.revision_id, EXCLUDED.revision_id),
+                            read_at = EXCLUDED.read_at
+                    "#,
+                )
+                .await?,
+            mark_all_read: db
+                .prepare(
+                    r#"
+                        INSERT INTO page_read (user_id, document_id, revision_id, read_at)
+                        SELECT wiki_user.id, document.id, document.current_revision_id, NOW()
+                        FROM wiki_user, document
+                        WHERE wiki_user.username = $1 AND document.current_revision_id IS NOT NULL
+                        ON CONFLICT (user_id, document_id) DO UPDATE SET
+                            revision_id = GREATEST(page_read.revision_id, EXCLUDED.revision_id),
+                            read_at = EXCLUDED.read_at
+                    "#,
+                )
+                .await?,
+            appearance: db
+                .prepare("SELECT accent_color, link_color, custom_css, logo_hash FROM site_appearance")
+                .await?,
+            upsert_appearance: db
+                .prepare(
+                    r#"
+                        INSERT INTO site_appearance (accent_color, link_color, custom_css, updated_by, updated_at)
+                        VALUES ($1, $2, $3, $4, NOW())
+                        ON CONFLICT (id) DO UPDATE SET
+                            accent_color = EXCLUDED.accent_color,
+                            link_color = EXCLUDED.link_color,
+                            custom_css = EXCLUDED.custom_css,
+                            updated_by = EXCLUDED.updated_by,
+                            updated_at = EXCLUDED.updated_at
+                    "#,
+                )
+                .await?,
+            logo: db
+                .prepare(
+                    r#"
+                        SELECT logo_content_type, logo_hash, logo FROM site_appearance
+                        WHERE logo IS NOT NULL
+                    "#,
+                )
+                .await?,
+            render_generation: db
+                .prepare("SELECT generation FROM render_generation")
+                .await?,
+            bump_render_generation: db
+                .prepare(
+                    r#"
+                        INSERT INTO render_generation (generation) VALUES (1)
+                        ON CONFLICT (id) DO UPDATE SET generation = render_generation.generation + 1
+                    "#,
+                )
+                .await?,
+            current_revision_with_hash: db
+                .prepare(
+                    r#"
+                        SELECT h.id FROM document d
+                        JOIN document_history h ON h.id = d.current_revision_id
+                        WHERE d.name = $1 AND h.content_hash = $2
+                    "#,
+                )
+                .await?,
+            digest_revisions: db
+                .prepare(
+                    r#"
+                        SELECT name, id, previous_id, modified_by, size_delta
+                        FROM (
+                            SELECT
+                                document.name,
+                                document_history.id,
+                                LAG(document_history.id) OVER w AS previous_id,
+                                document_history.modified_by,
+                                document_history.created_at,
+                                octet_length(document_history.document_data) - COALESCE(
+                                    LAG(octet_length(document_history.document_data)) OVER w,
+                                    0
+                                ) AS size_delta
+                            FROM document_history
+                            INNER JOIN document ON document.id = document_history.document_id
+                            WHERE document_history.status = 'published'
+                                AND document.redirect_to IS NULL
+                                AND document_history.document_id IN (
+                                    SELECT document_id FROM document_history
+                                    WHERE created_at > $1 AND created_at <= $2
+                                )
+                            WINDOW w AS (PARTITION BY document_history.document_id ORDER BY document_history.id)
+                        ) revisions
+                        WHERE created_at > $1 AND created_at <= $2
+                        ORDER BY id
+                    "#,
+                )
+                .await?,
+            digest_recipients: db
+                .prepare(
+                    r#"
+                        SELECT
+                            wiki_user.username,
+                            user_preferences.email,
+                            user_preferences.digest_namespaces,
+                            COALESCE(user_preferences.follow, '')
+                        FROM user_preferences
+                        INNER JOIN wiki_user ON wiki_user.id = user_preferences.user_id
+                        WHERE user_preferences.digest AND user_preferences.email = wiki_user.verified_email
+                        ORDER BY wiki_user.username
+                    "#,
+                )
+                .await?,
+            digest_sent_until: db
+                .prepare("SELECT sent_until FROM digest_run")
+                .await?,
+            store_digest_sent_until: db
+                .prepare(
+                    r#"
+                        INSERT INTO digest_run (sent_until) VALUES ($1)
+                        ON CONFLICT (id) DO UPDATE SET sent_until = EXCLUDED.sent_until
+                    "#,
+                )
+                .await?,
+            protection_rules: db
+                .prepare(
+                    r#"
+                        SELECT id, pattern, level, reason, created_by, created_at
+                        FROM protection_rule ORDER BY pattern
+                    "#,
+                )
+                .await?,
+            upsert_protection_rule: db
+                .prepare(
+                    r#"
+                        INSERT INTO protection_rule (pattern, level, reason, created_by, created_at)
+                        VALUES ($1, $2, $3, $4, NOW())
+                        ON CONFLICT (pattern) DO UPDATE
+                        SET level = $2, reason = $3, created_by = $4, created_at = NOW()
+                    "#,
+                )
+                .await?,
+            delete_protection_rule: db
+                .prepare("DELETE FROM protection_rule WHERE id = $1")
+                .await?,
+            edit_filters: db
+                .prepare(
+                    r#"
+                        SELECT id, name, rule, action, enabled, updated_by, updated_at
+                        FROM edit_filter ORDER BY id
+                    "#,
+                )
+                .await?,
+            enabled_edit_filters: db
+                .prepare(
+                    r#"
+                        SELECT id, name, rule, action, enabled, updated_by, updated_at
+                        FROM edit_filter WHERE enabled ORDER BY id
+                    "#,
+                )
+                .await?,
+            insert_edit_filter: db
+                .prepare(
+                    r#"
+                        INSERT INTO edit_filter (name, rule, action, enabled, updated_by, updated_at)
+                        VALUES ($1, $2, $3, $4, $5, NOW())
+                    "#,
+                )
+                .await?,
+            update_edit_filter: db
+                .prepare(
+                    r#"
+                        UPDATE edit_filter
+                        SET name = $2, rule = $3, action = $4, enabled = $5, updated_by = $6, updated_at = NOW()
+                        WHERE id = $1
+                    "#,
+                )
+                .await?,
+            delete_edit_filter: db
+                .prepare("DELETE FROM edit_filter WHERE id = $1")
+                .await?,
+            insert_edit_filter_hit: db
+                .prepare(
+                    r#"
+                        INSERT INTO edit_filter_hit (filter_id, page_name, attribution, action, revision_id, hit_at)
+                        VALUES ($1, $2, $3, $4, $5, NOW())
+                    "#,
+                )
+                .await?,
+            recent_edit_filter_hits: db
+                .prepare(
+                    r#"
+                        SELECT f.name, h.page_name, h.attribution, h.action, h.revision_id, h.hit_at
+                        FROM edit_filter_hit h
+                        JOIN edit_filter f ON f.id = h.filter_id
+                        ORDER BY h.id DESC
+                        LIMIT $1
+                    "#,
+                )
+                .await?,
+            insert_secret_finding: db
+                .prepare(
+                    r#"
+                        INSERT INTO secret_finding (kind, line, page_name, attribution, revision_id, found_at)
+                        VALUES ($1, $2, $3, $4, $5, NOW())
+                    "#,
+                )
+                .await?,
+            recent_secret_findings: db
+                .prepare(
+                    r#"
+                        SELECT kind, line, page_name, attribution, revision_id, found_at
+                        FROM secret_finding
+                        ORDER BY id DESC
+                        LIMIT $1
+                    "#,
+                )
+                .await?,
+            edit_filter_tags: db
+                .prepare(
+                    r#"
+                        SELECT h.revision_id, f.name
+                        FROM edit_filter_hit h
+                        JOIN edit_filter f ON f.id = h.filter_id
+                        JOIN document_history dh ON dh.id = h.revision_id
+                        JOIN document d ON d.id = dh.document_id
+                        WHERE d.name = $1 AND h.action = 'tag'
+                        ORDER BY h.id
+                    "#,
+                )
+                .await?,
+            user_created_at: db
+                .prepare("SELECT created_at FROM wiki_user WHERE username = $1")
+                .await?,
+            pending_creation_count_by: db
+                .prepare(
+                    r#"
+                        SELECT COUNT(*)
+                        FROM document_history
+                        INNER JOIN document ON document.id = document_history.document_id
+                        WHERE document_history.modified_by = $1
+                            AND document_history.created_at > $2
+                            AND document_history.status = 'pending'
+                            AND document.current_revision_id IS NULL
+                    "#,
+                )
+                .await?,
+            max_change_id: db
+                .prepare(
+                    r#"
+                        SELECT GREATEST(
+                            (SELECT COALESCE(MAX(change_id), 0) FROM document_history),
+                            (SELECT COALESCE(MAX(change_id), 0) FROM move_log)
+                        )
+                    "#,
+                )
+                .await?,
+            revisions_after: db
+                .prepare(
+                    r#"
+                        SELECT h.id, d.name, h.created_at, h.modified_by, h.document_data,
+                            h.status, h.reviewed_by, h.reviewed_at,
+                            COALESCE(d.current_revision_id = h.id, FALSE)
+                        FROM document_history h
+                        JOIN document d ON d.id = h.document_id
+                        WHERE h.change_id > $1 AND h.changed_at > $2 AND h.change_id <= $3
+                        ORDER BY h.id
+                    "#,
+                )
+                .await?,
+            moves_after: db
+                .prepare(
+                    r#"
+                        SELECT old_name, new_name, moved_by, moved_at, reason, document.name
+                        FROM move_log
+                        JOIN document ON document.id = move_log.document_id
+                        WHERE change_id > $1 AND changed_at > $2 AND change_id <= $3
+                        ORDER BY change_id
+                    "#,
+                )
+                .await?,
+            insert_restored_revision: db
+                .prepare(
+                    r#"
+                        INSERT INTO document_history
+                            (id, created_at, document_id, modified_by, document_data, status, reviewed_by, reviewed_at, content_hash)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                        ON CONFLICT (id) DO UPDATE SET
+                            document_data = EXCLUDED.document_data,
+                            status = EXCLUDED.status,
+                            reviewed_by = EXCLUDED.reviewed_by,
+                            reviewed_at = EXCLUDED.reviewed_at,
+                            content_hash = EXCLUDED.content_hash
+                        RETURNING id
+                    "#,
+                )
+                .await?,
+            insert_restored_move: db
+                .prepare(
+                    r#"
+                        INSERT INTO move_log (document_id, old_name, new_name, moved_by, moved_at, reason)
+                        VALUES ($1, $2, $3, $4, $5, $6)
+                    "#,
+                )
+                .await?,
+            insert_restored_move_log: db
+                .prepare(
+                    r#"
+                        INSERT INTO move_log (document_id, old_name, new_name, moved_by, moved_at, reason)
+                        SELECT id, $2, $3, $4, $5, $6 FROM document WHERE name = $1
+                    "#,
+                )
+                .await?,
+            // restored revisions keep their ids, so new ones must follow them
+            reset_revision_ids: db
+                .prepare(
+                    r#"
+                        SELECT setval(
+                            pg_get_serial_sequence('document_history', 'id'),
+                            (SELECT COALESCE(MAX(id), 0) + 1 FROM document_history),
+                            false
+                        )
+                    "#,
+                )
+                .await?,
+            restore_checkpoint: db
+                .prepare("SELECT restored_until FROM restore_checkpoint")
+                .await?,
+            upsert_restore_checkpoint: db
+                .prepare(
+                    r#"
+                        INSERT INTO restore_checkpoint (restored_until) VALUES ($1)
+                        ON CONFLICT (id) DO UPDATE SET restored_until = EXCLUDED.restored_until
+                    "#,
+                )
+                .await?,
+            upsert_logo: db
+                .prepare(
+                    r#"
+                        INSERT INTO site_appearance (logo, logo_content_type, logo_hash, updated_by, updated_at)
+                        VALUES ($1, $2, $3, $4, NOW())
+                        ON CONFLICT (id) DO UPDATE SET
+                            logo = EXCLUDED.logo,
+                            logo_content_type = EXCLUDED.logo_content_type,
+                            logo_hash = EXCLUDED.logo_hash,
+                            updated_by = EXCLUDED.updated_by,
+                            updated_at = EXCLUDED.updated_at
+                    "#,
+                )
+                .await?,
+            timezone_exists: db
+                .prepare("SELECT EXISTS (SELECT 1 FROM pg_timezone_names WHERE name = $1)")
+                .await?,
+            insert_session: db
+                .prepare(
+                    r#"
+                        INSERT INTO user_session (token, user_id, created_at, last_seen_at, addr, user_agent, two_factor)
+                        VALUES ($1, $2, NOW(), NOW(), $3, $4, $5)
+                    "#,
+                )
+                .await?,
+            delete_session: db
+                .prepare("DELETE FROM user_session WHERE token = $1")
+                .await?,
+            touch_session: db
+                .prepare("UPDATE user_session SET last_seen_at = NOW(), addr = $2, user_agent = $3 WHERE token = $1")
+                .await?,
+            user_sessions: db
+                .prepare(
+                    r#"
+                        SELECT user_session.id, user_session.token, user_session.addr,
+                            user_session.user_agent, user_session.created_at, user_session.last_seen_at
+                        FROM user_session
+                        INNER JOIN wiki_user ON wiki_user.id = user_session.user_id
+                        WHERE wiki_user.username = $1
+                        ORDER BY user_session.last_seen_at DESC
+                    "#,
+                )
+                .await?,
+            revoke_session: db
+                .prepare(
+                    r#"
+                        DELETE FROM user_session
+                        WHERE id = $2 AND user_id = (SELECT id FROM wiki_user WHERE username = $1)
+                    "#,
+                )
+                .await?,
+            revoke_all_sessions: db
+                .prepare("DELETE FROM user_session WHERE user_id = (SELECT id FROM wiki_user WHERE username = $1)")
+                .await?,
+            revoke_other_sessions: db
+                .prepare("DELETE FROM user_session WHERE user_id = $1 AND token <> $2")
+                .await?,
+            set_session_two_factor: db
+                .prepare("UPDATE user_session SET two_factor = TRUE WHERE token = $1")
+                .await?,
+            user_credentials: db
+                .prepare(
+                    r#"
+                        SELECT id, password_hash, registered AND verified_email IS NULL,
+                            totp_secret IS NOT NULL
+                        FROM wiki_user WHERE username = $1
+                    "#,
+                )
+                .await?,
+            insert_user: db
+                .prepare(
+                    "INSERT INTO wiki_user (username, password_hash, created_at) VALUES ($1, $2, NOW())",
+                )
+                .await?,
+            account_by_id: db
+                .prepare(
+                    r#"
+                        SELECT wiki_user.id, wiki_user.username, wiki_user.password_hash,
+                            user_preferences.email, wiki_user.verified_email
+                        FROM wiki_user
+                        LEFT JOIN user_preferences ON user_preferences.user_id = wiki_user.id
+                        WHERE wiki_user.id = $1
+                    "#,
+                )
+                .await?,
+            account_by_username: db
+                .prepare(
+                    r#"
+                        SELECT wiki_user.id, wiki_user.username, wiki_user.password_hash,
+                            user_preferences.email, wiki_user.verified_email
+                        FROM wiki_user
+                        LEFT JOIN user_preferences ON user_preferences.user_id = wiki_user.id
+                        WHERE wiki_user.username = $1
+                    "#,
+                )
+                .await?,
+            account_by_verified_email: db
+                .prepare(
+                    r#"
+                        SELECT wiki_user.id, wiki_user.username, wiki_user.password_hash,
+                            user_preferences.email, wiki_user.verified_email
+                        FROM wiki_user
+                        LEFT JOIN user_preferences ON user_preferences.user_id = wiki_user.id
+                        WHERE lower(wiki_user.verified_email) = lower($1)
+                        ORDER BY wiki_user.id
+                        LIMIT 1
+                    "#,
+                )
+                .await?,
+            register_user: db
+                .prepare(
+                    r#"
+                        INSERT INTO wiki_user (username, password_hash, created_at, registered)
+                        VALUES ($1, $2, NOW(), TRUE)
+                        ON CONFLICT (username) DO NOTHING
+                        RETURNING id
+                    "#,
+                )
+                .await?,
+            set_verified_email: db
+                .prepare("UPDATE wiki_user SET verified_email = $2 WHERE id = $1")
+                .await?,
+            set_password_hash: db
+                .prepare("UPDATE wiki_user SET password_hash = $2 WHERE id = $1")
+                .await?,
+            delete_user_sessions: db
+                .prepare("DELETE FROM user_session WHERE user_id = $1")
+                .await?,
+            signing_key: db
+                .prepare("SELECT key FROM signing_key")
+                .await?,
+            insert_signing_key: db
+                .prepare("INSERT INTO signing_key (key) VALUES ($1) ON CONFLICT (id) DO NOTHING")
+                .await?,
+            two_factor: db
+                .prepare(
+                    r#"
+                        SELECT password_hash, totp_secret, totp_last_step,
+                            (SELECT COUNT(*) FROM recovery_code WHERE recovery_code.user_id = wiki_user.id)
+                        FROM wiki_user WHERE id = $1
+                    "#,
+                )
+                .await?,
+            set_totp: db
+                .prepare("UPDATE wiki_user SET totp_secret = $2, totp_last_step = $3 WHERE id = $1")
+                .await?,
+            advance_totp_step: db
+                .prepare(
+                    r#"
+                        UPDATE wiki_user SET totp_last_step = $2
+                        WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+                    "#,
+                )
+                .await?,
+            delete_recovery_codes: db
+                .prepare("DELETE FROM recovery_code WHERE user_id = $1")
+                .await?,
+            insert_recovery_codes: db
+                .prepare("INSERT INTO recovery_code (user_id, code_hash) SELECT $1::BIGINT, unnest($2::TEXT[])")
+                .await?,
+            use_recovery_code: db
+                .prepare("DELETE FROM recovery_code WHERE user_id = $1 AND code_hash = $2")
+                .await?,
+            site_totals: db
+                .prepare(
+                    r#"
+                        SELECT
+                            (SELECT count(*) FROM document WHERE current_revision_id IS NOT NULL),
+                            (SELECT count(*) FROM document_history),
+                            (SELECT count(*) FROM wiki_user),
+                            -- on disk, toasted and compressed; summing the lengths would read every revision
+                            pg_total_relation_size('document_history'),
+                            (SELECT COALESCE(sum(size_bytes), 0)::BIGINT FROM attachment_blob)
+                    "#,
+                )
+                .await?,
+            edits_per_day: db
+                .prepare(
+                    r#"
+                        SELECT (created_at AT TIME ZONE 'UTC')::date AS day, count(*) FROM document_history
+                        WHERE created_at >= $1
+                        GROUP BY day
+                        ORDER BY day
+                    "#,
+                )
+                .await?,
+            top_editors: db
+                .prepare(
+                    r#"
+                        SELECT modified_by, count(*) AS edits FROM document_history
+                        WHERE created_at >= $1
+                        GROUP BY modified_by
+                        ORDER BY edits DESC, modified_by
+                        LIMIT $2
+                    "#,
+                )
+                .await?,
+        })
+    }
+
+    pub async fn fetch_document_id<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Option<i64>> {
+        match timed!(self, db.query_opt(document_id, &[&name])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Which of `names` have a current revision.
+    pub async fn fetch_existing_names<C: GenericClient>(
+        &self,
+        db: &C,
+        names: &[String],
+    ) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(existing_names, &[&names])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// The current text of each of `names` that exists, as `(name, text)` pairs.
+    pub async fn fetch_current_documents<C: GenericClient>(
+        &self,
+        db: &C,
+        names: &[String],
+    ) -> DynResult<Vec<(String, String)>> {
+        let rows = timed!(self, db.query(current_documents, &[&names])).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+            .collect()
+    }
+
+    pub async fn fetch_current_revision<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Option<Revision>> {
+        match timed!(self, db.query_opt(current_revision, &[&name])).await? {
+            Some(row) => Ok(Some(Revision::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Locks the document row until the transaction ends and returns its
+    /// current text. `None` if the document doesn't exist yet.
+    pub async fn fetch_current_text_for_update<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Option<String>> {
+        match timed!(self, db.query_opt(current_revision_for_update, &[&name])).await? {
+            Some(row) => Ok(row.try_get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `fetch_current_text_for_update`, returning the current
+    /// revision's id instead of its text.
+    pub async fn fetch_current_revision_id_for_update<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Option<i64>> {
+        match timed!(self, db.query_opt(current_revision_id_for_update, &[&name])).await? {
+            Some(row) => Ok(row.try_get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn fetch_revision<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        revision_id: i64,
+        unpublished: bool,
+    ) -> DynResult<Option<Revision>> {
+        match timed!(self, db.query_opt(revision, &[&name, &revision_id, &unpublished])).await? {
+            Some(row) => Ok(Some(Revision::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The view of revision `revision_id` of `name`, or of its current
+    /// revision if `None`, in one round trip. Held and rejected revisions
+    /// are only found with `unpublished`, for reviewers.
+    pub async fn fetch_page_bundle<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        revision_id: Option<i64>,
+        unpublished: bool,
+    ) -> DynResult<Option<PageBundle>> {
+        match timed!(self, db.query_opt(page_bundle, &[&name, &revision_id, &unpublished])).await? {
+            Some(row) => Ok(Some(PageBundle::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every revision of `name` readers have seen, oldest first: pending
+    /// and rejected edits are left out.
+    pub async fn fetch_published_revisions<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Vec<Revision>> {
+        let rows = timed!(self, db.query(published_revisions, &[&name])).await?;
+        rows.iter().map(Revision::from_row).collect()
+    }
+
+    /// The latest 50 revisions of `name`, newest first. Held and rejected
+    /// revisions are only listed with `unpublished`, for reviewers.
+    pub async fn fetch_history<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        unpublished: bool,
+    ) -> DynResult<Vec<HistoryEntry>> {
+        let rows = timed!(self, db.query(history, &[&name, &unpublished])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(HistoryEntry {
+                    id: row.try_get(0)?,
+                    created_at: row.try_get(1)?,
+                    modified_by: row.try_get(2)?,
+                    size: row.try_get(3)?,
+                    size_delta: row.try_get(4)?,
+                    status: RevisionStatus::parse(row.try_get(5)?)?,
+                    reviewed_by: row.try_get(6)?,
+                    redaction: match row.try_get::<_, Option<String>>(7)? {
+                        Some(redacted_by) => Some(Redaction {
+                            redacted_by,
+                            redacted_at: row.try_get(8)?,
+                            reason: row.try_get::<_, Option<String>>(9)?.unwrap_or_default(),
+                        }),
+                        None => None,
+                    },
+                    summary: row.try_get(10)?,
+                })
+            })
+            .collect()
+    }
+
+    /// The most recent `limit` revisions of `name`, oldest first, for
+    /// `revision_graph::layout`. Held and rejected revisions are only
+    /// included with `unpublished`, for reviewers.
+    pub async fn fetch_revision_graph<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        limit: i64,
+        unpublished: bool,
+    ) -> DynResult<Vec<GraphRevision>> {
+        let rows = timed!(self, db.query(revision_graph, &[&name, &limit, &unpublished])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(GraphRevision {
+                    id: row.try_get(0)?,
+                    created_at: row.try_get(1)?,
+                    modified_by: row.try_get(2)?,
+                    status: RevisionStatus::parse(row.try_get(3)?)?,
+                    content_hash: row.try_get(4)?,
+                    redacted: row.try_get(5)?,
+                    merged_from: row.try_get(6)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Names of every document with a current revision, in order.
+    pub async fn fetch_current_names<C: GenericClient>(&self, db: &C) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(current_names, &[])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Names of every document, deleted ones too, in order.
+    pub async fn fetch_document_names<C: GenericClient>(&self, db: &C) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(document_names, &[])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Every page name linked to in the link graph, in order.
+    pub async fn fetch_link_target_names<C: GenericClient>(&self, db: &C) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(link_target_names, &[])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Points the link graph's links to `old_name` at `new_name` instead.
+    pub async fn rename_link_target<C: GenericClient>(
+        &self,
+        db: &C,
+        old_name: &str,
+        new_name: &str,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(rename_link_target, &[&old_name, &new_name])).await?;
+        Ok(())
+    }
+
+    /// The pages `name` links to, as recorded in the link graph.
+    pub async fn fetch_links<C: GenericClient>(&self, db: &C, name: &str) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(links, &[&name])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Up to `limit` current pages linking to `name`, in order.
+    pub async fn fetch_backlinks<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        limit: i64,
+    ) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(backlinks, &[&name, &limit])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Links from or to any of `names`, as `(source, target)` pairs, leaving
+    /// out links from pages without a current revision.
+    pub async fn fetch_link_edges<C: GenericClient>(
+        &self,
+        db: &C,
+        names: &[String],
+    ) -> DynResult<Vec<(String, String)>> {
+        let rows = timed!(self, db.query(link_edges, &[&names])).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+            .collect()
+    }
+
+    /// Up to `limit` current pages whose names look like `query` by
+    /// `pg_trgm` trigram similarity, closest first.
+    pub async fn fetch_similar_names<C: GenericClient>(
+        &self,
+        db: &C,
+        query: &str,
+        limit: i64,
+    ) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(similar_names, &[&query, &limit])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Up to `limit` current pages whose text invokes the template
+    /// `template`, named without its namespace, in order.
+    pub async fn fetch_transcluders<C: GenericClient>(
+        &self,
+        db: &C,
+        template: &str,
+        limit: i64,
+    ) -> DynResult<Vec<String>> {
+        let invocation = format!("{{{{{}", template);
+        let rows = timed!(self, db.query(transcluders, &[&invocation, &limit])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Current pages that no other current page links to, in order.
+    pub async fn fetch_orphans<C: GenericClient>(&self, db: &C) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(orphans, &[])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Link targets without a current page and how many current pages link
+    /// to each, most linked first.
+    pub async fn fetch_wanted<C: GenericClient>(&self, db: &C) -> DynResult<Vec<(String, i64)>> {
+        let rows = timed!(self, db.query(wanted, &[])).await?;
+        rows.iter().map(|row| Ok((row.try_get(0)?, row.try_get(1)?))).collect()
+    }
+
+    /// The name of a random current page, if there are any.
+    pub async fn fetch_random_name<C: GenericClient>(&self, db: &C) -> DynResult<Option<String>> {
+        match timed!(self, db.query_opt(random_name, &[])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the outgoing links of `name` in the link graph.
+    pub async fn replace_links<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        targets: &[String],
+    ) -> DynResult<()> {
+        timed!(self, db.execute(delete_links, &[&name])).await?;
+        timed!(self, db.execute(insert_links, &[&name, &targets])).await?;
+        Ok(())
+    }
+
+    /// Replaces the links and data of `name` with those of its new current
+    /// revision.
+    pub async fn replace_index<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        index: &DocumentIndex,
+    ) -> DynResult<()> {
+        self.replace_links(db, name, &index.links).await?;
+        let (keys, values): (Vec<&str>, Vec<&str>) = index
+            .data
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .unzip();
+        timed!(self, db.execute(delete_page_data, &[&name])).await?;
+        timed!(self, db.execute(insert_page_data, &[&name, &keys, &values]))
+            .await?;
+        timed!(self, db.execute(set_expiry, &[&name, &index.expires])).await?;
+        timed!(self, db.execute(bump_render_generation, &[])).await?;
+        Ok(())
+    }
+
+    /// Every data pair of the current pages whose data has all of the
+    /// `keys[i] = values[i]` pairs, as `(name, key, value)` rows.
+    pub async fn fetch_matching_page_data<C: GenericClient>(
+        &self,
+        db: &C,
+        keys: &[String],
+        values: &[String],
+    ) -> DynResult<Vec<(String, String, String)>> {
+        let rows = timed!(self, db.query(matching_page_data, &[&keys, &values])).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?)))
+            .collect()
+    }
+
+    /// Saves `document_data` as the new current revision of `name`, creating
+    /// the document if needed, and records the pages it links to. Returns the
+    /// new revision id.
+    pub async fn store_revision<C: GenericClient>(
+        &self,
+        tx: &C,
+        name: &str,
+        user_id: &str,
+        document_data: &str,
+        index: &DocumentIndex,
+    ) -> DynResult<i64> {
+        let now = Utc::now();
+        let row = timed!(self, tx.query_one(upsert_document, &[&name, &now])).await?;
+        let document_id: i64 = row.try_get(0)?;
+
+        let status = RevisionStatus::Published.as_str();
+        let row = timed!(self, tx.query_one(
+            insert_revision,
+            &[&document_id, &user_id, &document_data, &status, &content_hash(document_data.as_bytes())],
+        ))
+        .await?;
+        let document_history_id: i64 = row.try_get(0)?;
+
+        timed!(self, tx.execute(
+            set_current_revision,
+            &[&document_id, &document_history_id, &now],
+        ))
+        .await?;
+        self.replace_index(tx, name, index).await?;
+
+        Ok(document_history_id)
+    }
+
+    /// Explains an automatic revision in the page history.
+    pub async fn set_revision_summary<C: GenericClient>(
+        &self,
+        tx: &C,
+        revision_id: i64,
+        summary: &str,
+    ) -> DynResult<()> {
+        timed!(self, tx.execute(set_revision_summary, &[&revision_id, &summary])).await?;
+        Ok(())
+    }
+
+    /// Stores a revision for review without making it current. The document
+    /// row is created if needed so the revision has somewhere to live, but a
+    /// new page stays missing until a revision is approved.
+    pub async fn store_pending_revision<C: GenericClient>(
+        &self,
+        tx: &C,
+        name: &str,
+        user_id: &str,
+        document_data: &str,
+    ) -> DynResult<i64> {
+        let row = timed!(self, tx.query_one(ensure_document, &[&name])).await?;
+        let document_id: i64 = row.try_get(0)?;
+
+        let status = RevisionStatus::Pending.as_str();
+        let row = timed!(self, tx.query_one(
+            insert_revision,
+            &[&document_id, &user_id, &document_data, &status, &content_hash(document_data.as_bytes())],
+        ))
+        .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    /// Every revision waiting for review, oldest first.
+    pub async fn fetch_pending_revisions<C: GenericClient>(
+        &self,
+        db: &C,
+    ) -> DynResult<Vec<PendingRevision>> {
+        let rows = timed!(self, db.query(pending_revisions, &[])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(PendingRevision {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    modified_by: row.try_get(2)?,
+                    created_at: row.try_get(3)?,
+                    size: row.try_get(4)?,
+                    current_revision_id: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Marks a pending revision approved or rejected. Returns `None` if the
+    /// revision isn't pending, for example because another reviewer got there
+    /// first. An approved revision still has to be made current with
+    /// `publish_revision`.
+    pub async fn review_revision<C: GenericClient>(
+        &self,
+        tx: &C,
+        revision_id: i64,
+        status: RevisionStatus,
+        reviewer: &str,
+    ) -> DynResult<Option<ReviewedRevision>> {
+        let row = timed!(self, tx.query_opt(
+            review_revision,
+            &[&revision_id, &status.as_str(), &reviewer],
+        ))
+        .await?;
+        match row {
+            Some(row) => Ok(Some(ReviewedRevision {
+                id: revision_id,
+                document_id: row.try_get(0)?,
+                name: row.try_get(1)?,
+                modified_by: row.try_get(2)?,
+                document_data: row.try_get(3)?,
+                up_to_date: row.try_get(4)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Makes an approved revision the current one.
+    pub async fn publish_revision<C: GenericClient>(
+        &self,
+        tx: &C,
+        revision: &ReviewedRevision,
+        index: &DocumentIndex,
+    ) -> DynResult<()> {
+        timed!(self, tx.execute(
+            set_current_revision,
+            &[&revision.document_id, &revision.id, &Utc::now()],
+        ))
+        .await?;
+        self.replace_index(tx, &revision.name, index).await
+    }
+
+    /// Renames the document `old_name` and records the move. Returns false if
+    /// there is no such document.
+    pub async fn move_document<C: GenericClient>(
+        &self,
+        tx: &C,
+        old_name: &str,
+        new_name: &str,
+        moved_by: &str,
+        reason: &str,
+    ) -> DynResult<bool> {
+        let row = match timed!(self, tx.query_opt(rename_document, &[&old_name, &new_name])).await? {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+        let document_id: i64 = row.try_get(0)?;
+        timed!(self, tx.execute(
+            insert_move,
+            &[&document_id, &old_name, &new_name, &moved_by, &reason],
+        ))
+        .await?;
+        // links to both names change between missing and not
+        timed!(self, tx.execute(bump_render_generation, &[])).await?;
+        Ok(true)
+    }
+
+    /// What merging `source` into `target` would carry over or leave behind.
+    /// `None` unless both documents exist.
+    pub async fn fetch_merge_preview<C: GenericClient>(
+        &self,
+        db: &C,
+        source: &str,
+        target: &str,
+    ) -> DynResult<Option<MergePreview>> {
+        let (source_id, target_id) = match (
+            self.fetch_document_id(db, source).await?,
+            self.fetch_document_id(db, target).await?,
+        ) {
+            (Some(s), Some(t)) => (s, t),
+            _ => return Ok(None),
+        };
+        let row = timed!(self, db.query_one(merge_conflicts, &[&source_id, &target_id])).await?;
+        Ok(Some(MergePreview {
+            revisions: row.try_get(0)?,
+            conflicting_tags: row.try_get(1)?,
+            conflicting_attachments: row.try_get(2)?,
+        }))
+    }
+
+    /// Moves the history, tags, attachments and move log of `source` onto
+    /// `target` and leaves `source` as a redirect. Tags and attachments whose
+    /// names `target` already uses are dropped and left behind respectively.
+    /// The caller is expected to store the combined text as a new revision of
+    /// `target` in the same transaction. Returns false unless both documents
+    /// exist.
+    pub async fn merge_document<C: GenericClient>(
+        &self,
+        tx: &C,
+        source: &str,
+        target: &str,
+        merged_by: &str,
+        reason: &str,
+    ) -> DynResult<bool> {
+        let (source_id, target_id) = match (
+            self.fetch_document_id(tx, source).await?,
+            self.fetch_document_id(tx, target).await?,
+        ) {
+            (Some(s), Some(t)) => (s, t),
+            _ => return Ok(false),
+        };
+
+        // the source's current revision is about to move away
+        timed!(self, tx.execute(set_redirect, &[&source_id, &target])).await?;
+        timed!(self, tx.execute(merge_history, &[&source_id, &target_id])).await?;
+        timed!(self, tx.execute(merge_tags, &[&source_id, &target_id])).await?;
+        timed!(self, tx.execute(delete_tags, &[&source_id])).await?;
+        timed!(self, tx.execute(merge_attachments, &[&source_id, &target_id])).await?;
+        timed!(self, tx.execute(merge_moves, &[&source_id, &target_id])).await?;
+        timed!(self, tx.execute(
+            insert_move,
+            &[&target_id, &source, &target, &merged_by, &reason],
+        ))
+        .await?;
+        self.replace_index(tx, source, &DocumentIndex::default()).await?;
+        Ok(true)
+    }
+
+    /// Where `name` redirects to, if it was merged into another page and
+    /// hasn't been recreated since.
+    pub async fn fetch_redirect<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Option<String>> {
+        match timed!(self, db.query_opt(redirect, &[&name])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The name of the page revision `revision_id` now belongs to, which
+    /// differs from the one it was written under after a move.
+    pub async fn fetch_revision_page<C: GenericClient>(
+        &self,
+        db: &C,
+        revision_id: i64,
+    ) -> DynResult<Option<String>> {
+        match timed!(self, db.query_opt(revision_page, &[&revision_id])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every move of the document now called `name`, oldest first.
+    pub async fn fetch_moves<C: GenericClient>(&self, db: &C, name: &str) -> DynResult<Vec<MoveEntry>> {
+        let rows = timed!(self, db.query(moves, &[&name])).await?;
+        rows.iter().map(MoveEntry::from_row).collect()
+    }
+
+    /// Pages whose current revision is newer than `after`, oldest first.
+    pub async fn fetch_changes_after<C: GenericClient>(
+        &self,
+        db: &C,
+        after: i64,
+        limit: i64,
+    ) -> DynResult<Vec<ChangedPage>> {
+        let rows = timed!(self, db.query(changes_after, &[&after, &limit])).await?;
+        rows.iter().map(ChangedPage::from_row).collect()
+    }
+
+    /// The last local and remote revision ids synced with `remote`, or zeros
+    /// if it has never been synced.
+    pub async fn fetch_sync_cursor<C: GenericClient>(&self, db: &C, remote: &str) -> DynResult<(i64, i64)> {
+        match timed!(self, db.query_opt(sync_cursor, &[&remote])).await? {
+            Some(row) => Ok((row.try_get(0)?, row.try_get(1)?)),
+            None => Ok((0, 0)),
+        }
+    }
+
+    pub async fn store_sync_cursor<C: GenericClient>(
+        &self,
+        db: &C,
+        remote: &str,
+        local_after: i64,
+        remote_after: i64,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(upsert_sync_cursor, &[&remote, &local_after, &remote_after]))
+            .await?;
+        Ok(())
+    }
+
+    /// The local and remote revisions of `name` that were last known to
+    /// match, if it has been synced with `remote` before.
+    pub async fn fetch_sync_page<C: GenericClient>(
+        &self,
+        db: &C,
+        remote: &str,
+        name: &str,
+    ) -> DynResult<Option<(i64, i64)>> {
+        match timed!(self, db.query_opt(sync_page, &[&remote, &name])).await? {
+            Some(row) => Ok(Some((row.try_get(0)?, row.try_get(1)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Records that `local_revision_id` and `remote_revision_id` of `name`
+    /// hold the same text, and clears any conflict queued for it.
+    pub async fn store_sync_page<C: GenericClient>(
+        &self,
+        db: &C,
+        remote: &str,
+        name: &str,
+        local_revision_id: i64,
+        remote_revision_id: i64,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(
+            upsert_sync_page,
+            &[&remote, &name, &local_revision_id, &remote_revision_id],
+        ))
+        .await?;
+        timed!(self, db.execute(delete_sync_conflict, &[&remote, &name])).await?;
+        Ok(())
+    }
+
+    /// Pages edited on both sides since they were last synced with `remote`,
+    /// as `(name, local revision, remote revision, detected at)`.
+    pub async fn fetch_sync_conflicts<C: GenericClient>(
+        &self,
+        db: &C,
+        remote: &str,
+    ) -> DynResult<Vec<(String, i64, i64, DateTime<Utc>)>> {
+        let rows = timed!(self, db.query(sync_conflicts, &[&remote])).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?, row.try_get(3)?)))
+            .collect()
+    }
+
+    pub async fn queue_sync_conflict<C: GenericClient>(
+        &self,
+        db: &C,
+        remote: &str,
+        name: &str,
+        local_revision_id: i64,
+        remote_revision_id: i64,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(
+            insert_sync_conflict,
+            &[&remote, &name, &local_revision_id, &remote_revision_id],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// The latest edits and moves across the site, newest first.
+    pub async fn fetch_recent_changes<C: GenericClient>(
+        &self,
+        db: &C,
+        limit: i64,
+    ) -> DynResult<Vec<Change>> {
+        let rows = timed!(self, db.query(recent_changes, &[&limit])).await?;
+        rows.iter()
+            .map(|row| {
+                let revision_id: Option<i64> = row.try_get(5)?;
+                match revision_id {
+                    Some(revision_id) => Ok(Change::Edit {
+                        name: row.try_get(1)?,
+                        revision_id,
+                        modified_by: row.try_get(2)?,
+                        created_at: row.try_get(3)?,
+                    }),
+                    None => Ok(Change::Move(MoveEntry::from_row(row)?)),
+                }
+            })
+            .collect()
+    }
+
+    pub async fn fetch_annotations<C: GenericClient>(
+        &self,
+        db: &C,
+        revision_id: i64,
+    ) -> DynResult<Vec<Annotation>> {
+        let rows = timed!(self, db.query(annotations, &[&revision_id])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(Annotation {
+                    created_at: row.try_get(0)?,
+                    created_by: row.try_get(1)?,
+                    start_offset: row.try_get(2)?,
+                    end_offset: row.try_get(3)?,
+                    quote: row.try_get(4)?,
+                    body: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns false if `revision_id` isn't a revision of `name`.
+    pub async fn insert_annotation<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        revision_id: i64,
+        annotation: &NewAnnotation<'_>,
+    ) -> DynResult<bool> {
+        let inserted = timed!(self, db.execute(
+            insert_annotation,
+            &[
+                &name,
+                &revision_id,
+                &annotation.created_by,
+                &annotation.start_offset,
+                &annotation.end_offset,
+                &annotation.quote,
+                &annotation.body,
+            ],
+        ))
+        .await?;
+        Ok(inserted > 0)
+    }
+
+    /// Every tag on the document, oldest first.
+    pub async fn fetch_tags<C: GenericClient>(&self, db: &C, name: &str) -> DynResult<Vec<RevisionTag>> {
+        let rows = timed!(self, db.query(tags, &[&name])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(RevisionTag {
+                    label: row.try_get(0)?,
+                    revision_id: row.try_get(1)?,
+                    created_by: row.try_get(2)?,
+                    created_at: row.try_get(3)?,
+                })
+            })
+            .collect()
+    }
+
+    /// The revision `label` points at, if the document has such a tag.
+    pub async fn fetch_tagged_revision_id<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        label: &str,
+    ) -> DynResult<Option<i64>> {
+        let row = timed!(self, db.query_opt(tagged_revision, &[&name, &label])).await?;
+        Ok(match row {
+            Some(row) => Some(row.try_get(0)?),
+            None => None,
+        })
+    }
+
+    /// Replaces the text of revision `id` with `tombstone`, recording who
+    /// did it and why, and empties the quotes annotations on it copied from
+    /// the text. The history row itself stays.
+    pub async fn redact_revision<C: GenericClient>(
+        &self,
+        db: &C,
+        id: i64,
+        tombstone: &str,
+        redacted_by: &str,
+        reason: &str,
+    ) -> DynResult<()> {
+        let hash = content_hash(tombstone.as_bytes());
+        timed!(self, db.execute(redact_revision, &[&id, &tombstone, &hash, &redacted_by, &reason])).await?;
+        timed!(self, db.execute(clear_annotation_quotes, &[&id])).await?;
+        Ok(())
+    }
+
+    /// Tags a revision of `name`. Returns false if the revision doesn't
+    /// belong to the document or the label is already in use; tags never
+    /// move once set.
+    pub async fn insert_tag<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        revision_id: i64,
+        label: &str,
+        created_by: &str,
+    ) -> DynResult<bool> {
+        let inserted = timed!(self, db.execute(insert_tag, &[&name, &revision_id, &label, &created_by])).await?;
+        Ok(inserted > 0)
+    }
+
+    pub async fn fetch_attachments<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Vec<AttachmentInfo>> {
+        let rows = timed!(self, db.query(attachments, &[&name])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(AttachmentInfo {
+                    filename: row.try_get(0)?,
+                    content_type: row.try_get(1)?,
+                    size_bytes: row.try_get(2)?,
+                    uploaded_by: row.try_get(3)?,
+                    uploaded_at: row.try_get(4)?,
+                    content_hash: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    /// The attachment `filename` on the page `name`.
+    pub async fn fetch_attachment<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        filename: &str,
+    ) -> DynResult<Option<AttachmentContent>> {
+        match timed!(self, db.query_opt(attachment, &[&name, &filename])).await? {
+            Some(row) => Ok(Some(AttachmentContent::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Contents with the hash `content_hash`, as long as some page still has
+    /// them attached as `filename`.
+    pub async fn fetch_attachment_by_hash<C: GenericClient>(
+        &self,
+        db: &C,
+        content_hash: &str,
+        filename: &str,
+    ) -> DynResult<Option<AttachmentContent>> {
+        match timed!(self, db.query_opt(attachment_by_hash, &[&content_hash, &filename])).await? {
+            Some(row) => Ok(Some(AttachmentContent::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// A stored preview of the blob `content_hash`, see `previews.rs`.
+    pub async fn fetch_attachment_preview<C: GenericClient>(
+        &self,
+        db: &C,
+        content_hash: &str,
+        kind: &str,
+    ) -> DynResult<Option<Vec<u8>>> {
+        match timed!(self, db.query_opt(attachment_preview, &[&content_hash, &kind])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Keeps a preview of `content_hash`. Whichever of two concurrent first
+    /// views stores it first wins; both made the same thing.
+    pub async fn store_attachment_preview<C: GenericClient>(
+        &self,
+        db: &C,
+        content_hash: &str,
+        kind: &str,
+        data: &[u8],
+    ) -> DynResult<()> {
+        timed!(self, db.execute(insert_attachment_preview, &[&content_hash, &kind, &data])).await?;
+        Ok(())
+    }
+
+    /// Bytes stored by `uploaded_by` and by everyone, not counting the
+    /// attachment `filename` on `document_id`, which an upload would replace.
+    pub async fn fetch_attachment_usage<C: GenericClient>(
+        &self,
+        db: &C,
+        document_id: i64,
+        filename: &str,
+        uploaded_by: &str,
+    ) -> DynResult<(i64, i64)> {
+        let row = timed!(self, db.query_one(attachment_usage, &[&document_id, &filename, &uploaded_by])).await?;
+        Ok((row.try_get(0)?, row.try_get(1)?))
+    }
+
+    pub async fn upsert_attachment<C: GenericClient>(
+        &self,
+        db: &C,
+        document_id: i64,
+        attachment: &NewAttachment<'_>,
+    ) -> DynResult<()> {
+        let size = attachment.data.len() as i64;
+        timed!(self, db.execute(
+            insert_blob,
+            &[&attachment.content_hash, &attachment.data, &size],
+        ))
+        .await?;
+        let replaced: Option<String> = match timed!(
+            self,
+            db.query_opt(replaced_attachment_hash, &[&document_id, &attachment.filename])
+        )
+        .await?
+        {
+            Some(row) => Some(row.try_get(0)?),
+            None => None,
+        };
+        timed!(self, db.execute(
+            upsert_attachment,
+            &[
+                &document_id,
+                &attachment.filename,
+                &attachment.content_type,
+                &size,
+                &attachment.content_hash,
+                &attachment.uploaded_by,
+            ],
+        ))
+        .await?;
+
+        if replaced.as_deref() != Some(attachment.content_hash) {
+            timed!(self, db.execute(adjust_blob_refs, &[&attachment.content_hash, &1i64]))
+                .await?;
+            if let Some(replaced) = replaced {
+                timed!(self, db.execute(adjust_blob_refs, &[&replaced, &-1i64])).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Recounts references to every blob, in case they have drifted, then
+    /// deletes the blobs nothing uses. Returns how many were deleted and the
+    /// bytes freed.
+    pub async fn collect_unused_blobs<C: GenericClient>(&self, tx: &C) -> DynResult<(u64, i64)> {
+        timed!(self, tx.execute(recount_blob_refs, &[])).await?;
+        let rows = timed!(self, tx.query(delete_unused_blobs, &[])).await?;
+        let mut freed = 0;
+        for row in &rows {
+            let size: i64 = row.try_get(0)?;
+            freed += size;
+        }
+        Ok((rows.len() as u64, freed))
+    }
+
+    /// The user a session token belongs to.
+    pub async fn fetch_session_user<C: GenericClient>(
+        &self,
+        db: &C,
+        token: &str,
+    ) -> DynResult<Option<SessionUser>> {
+        let row = match timed!(self, db.query_opt(session_user, &[&token])).await? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let editor: &str = row.try_get(5)?;
+        let digest_namespaces: &str = row.try_get(8)?;
+        let follow: &str = row.try_get(13)?;
+        let preferences = Preferences {
+            display_name: row.try_get(1)?,
+            timezone: row.try_get(2)?,
+            utc_offset: row.try_get(3)?,
+            theme: row.try_get(4)?,
+            editor: editor.parse()?,
+            email: row.try_get(6)?,
+            digest: row.try_get(7)?,
+            digest_namespaces: digest_namespaces.split_whitespace().map(str::to_string).collect(),
+            follow: follow.split_whitespace().map(str::to_string).collect(),
+        };
+        Ok(Some(SessionUser {
+            username: row.try_get(0)?,
+            preferences,
+            edit_count: row.try_get(9)?,
+            age_days: row.try_get(10)?,
+            two_factor: row.try_get(11)?,
+            seen_stale: row.try_get(12)?,
+        }))
+    }
+
+    /// Saves `username`'s preferences. `utc_offset` is derived from the
+    /// timezone when the preferences are loaded, so it isn't stored.
+    pub async fn upsert_preferences<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+        preferences: &Preferences,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(
+            upsert_preferences,
+            &[
+                &username,
+                &preferences.display_name,
+                &preferences.timezone,
+                &preferences.theme,
+                &preferences.editor.as_str(),
+                &preferences.email,
+                &preferences.digest,
+                &preferences.digest_namespaces.join(" "),
+                &preferences.follow.join(" "),
+            ],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// Current pages whose `expires:` date is on or before `today`, longest
+    /// expired first.
+    pub async fn fetch_stale_pages<C: GenericClient>(&self, db: &C, today: NaiveDate) -> DynResult<Vec<StalePage>> {
+        let rows = timed!(self, db.query(stale_pages, &[&today])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(StalePage {
+                    name: row.try_get(0)?,
+                    expires_on: row.try_get(1)?,
+                    modified_at: row.try_get(2)?,
+                    modified_by: row.try_get(3)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Pages whose current revision `username` hasn't seen, most recently
+    /// changed first. Pages they have never opened count as unread.
+    pub async fn fetch_unread_pages<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+    ) -> DynResult<Vec<UnreadPage>> {
+        let rows = timed!(self, db.query(unread_pages, &[&username])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(UnreadPage {
+                    name: row.try_get(0)?,
+                    current_revision_id: row.try_get(1)?,
+                    read_revision_id: row.try_get(2)?,
+                    modified_at: row.try_get(3)?,
+                    modified_by: row.try_get(4)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Pages whose data has any of the pairs `keys[i]: values[i]`.
+    pub async fn fetch_tagged_names<C: GenericClient>(
+        &self,
+        db: &C,
+        keys: &[&str],
+        values: &[&str],
+    ) -> DynResult<Vec<String>> {
+        let rows = timed!(self, db.query(tagged_names, &[&keys, &values])).await?;
+        rows.iter().map(|row| Ok(row.try_get(0)?)).collect()
+    }
+
+    /// Records that `username` has seen revision `revision_id` of `name`.
+    /// Seeing an older revision never moves the marker back.
+    pub async fn mark_read<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+        name: &str,
+        revision_id: i64,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(mark_read, &[&username, &name, &revision_id])).await?;
+        Ok(())
+    }
+
+    /// Marks the current revision of every page as seen by `username`.
+    pub async fn mark_all_read<C: GenericClient>(&self, db: &C, username: &str) -> DynResult<u64> {
+        let marked = timed!(self, db.execute(mark_all_read, &[&username])).await?;
+        Ok(marked)
+    }
+
+    /// The site appearance, or the defaults if it was never saved.
+    pub async fn fetch_appearance<C: GenericClient>(&self, db: &C) -> DynResult<Appearance> {
+        let row = match timed!(self, db.query_opt(appearance, &[])).await? {
+            Some(row) => row,
+            None => return Ok(Appearance::default()),
+        };
+        Ok(Appearance {
+            accent_color: row.try_get(0)?,
+            link_color: row.try_get(1)?,
+            custom_css: row.try_get(2)?,
+            logo_hash: row.try_get(3)?,
+        })
+    }
+
+    /// Saves everything in `appearance` except the logo, see `upsert_logo`.
+    pub async fn upsert_appearance<C: GenericClient>(
+        &self,
+        db: &C,
+        appearance: &Appearance,
+        updated_by: &str,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(
+            upsert_appearance,
+            &[
+                &appearance.accent_color,
+                &appearance.link_color,
+                &appearance.custom_css,
+                &updated_by,
+            ],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn fetch_logo<C: GenericClient>(&self, db: &C) -> DynResult<Option<AttachmentContent>> {
+        match timed!(self, db.query_opt(logo, &[])).await? {
+            Some(row) => Ok(Some(AttachmentContent::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the logo, or removes it when `logo` is `None`.
+    pub async fn upsert_logo<C: GenericClient>(
+        &self,
+        db: &C,
+        logo: Option<&AttachmentContent>,
+        updated_by: &str,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(
+            upsert_logo,
+            &[
+                &logo.map(|l| &l.data[..]),
+                &logo.map(|l| &l.content_type[..]),
+                &logo.map(|l| &l.content_hash[..]),
+                &updated_by,
+            ],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// Bumped by every change that can alter how some page renders, see
+    /// `render_cache.rs`.
+    pub async fn fetch_render_generation<C: GenericClient>(&self, db: &C) -> DynResult<i64> {
+        match timed!(self, db.query_opt(render_generation, &[])).await? {
+            Some(row) => Ok(row.try_get(0)?),
+            None => Ok(0),
+        }
+    }
+
+    /// The current revision of `name`, if its text is `document_data`.
+    /// Compares hashes, so the stored text isn't fetched.
+    pub async fn fetch_current_revision_if_unchanged<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+        document_data: &str,
+    ) -> DynResult<Option<i64>> {
+        let hash = content_hash(document_data.as_bytes());
+        match timed!(self, db.query_opt(current_revision_with_hash, &[&name, &hash])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Published revisions made after `since` up to `until`, oldest first.
+    pub async fn fetch_digest_revisions<C: GenericClient>(
+        &self,
+        db: &C,
+        since: &DateTime<Utc>,
+        until: &DateTime<Utc>,
+    ) -> DynResult<Vec<DigestRevision>> {
+        let rows = timed!(self, db.query(digest_revisions, &[since, until])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(DigestRevision {
+                    name: row.try_get(0)?,
+                    id: row.try_get(1)?,
+                    previous_id: row.try_get(2)?,
+                    modified_by: row.try_get(3)?,
+                    size_delta: row.try_get(4)?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn fetch_digest_recipients<C: GenericClient>(&self, db: &C) -> DynResult<Vec<DigestRecipient>> {
+        let rows = timed!(self, db.query(digest_recipients, &[])).await?;
+        rows.iter()
+            .map(|row| {
+                let namespaces: &str = row.try_get(2)?;
+                let follow: &str = row.try_get(3)?;
+                Ok(DigestRecipient {
+                    username: row.try_get(0)?,
+                    email: row.try_get(1)?,
+                    namespaces: namespaces.split_whitespace().map(str::to_string).collect(),
+                    follow: follow.split_whitespace().map(str::to_string).collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// The end of the period the last digest covered, `None` before the
+    /// first one.
+    pub async fn fetch_digest_sent_until<C: GenericClient>(&self, db: &C) -> DynResult<Option<DateTime<Utc>>> {
+        match timed!(self, db.query_opt(digest_sent_until, &[])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn store_digest_sent_until<C: GenericClient>(&self, db: &C, until: &DateTime<Utc>) -> DynResult<()> {
+        timed!(self, db.execute(store_digest_sent_until, &[until])).await?;
+        Ok(())
+    }
+
+    /// Every protection rule, by pattern.
+    pub async fn fetch_protection_rules<C: GenericClient>(&self, db: &C) -> DynResult<Vec<ProtectionRule>> {
+        let rows = timed!(self, db.query(protection_rules, &[])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(ProtectionRule {
+                    id: row.try_get(0)?,
+                    pattern: row.try_get(1)?,
+                    level: row.try_get(2)?,
+                    reason: row.try_get(3)?,
+                    created_by: row.try_get(4)?,
+                    created_at: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Protects pages matching `pattern` at `level`, replacing any rule
+    /// with the same pattern.
+    pub async fn upsert_protection_rule<C: GenericClient>(
+        &self,
+        db: &C,
+        pattern: &str,
+        level: &str,
+        reason: &str,
+        created_by: &str,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(
+            upsert_protection_rule,
+            &[&pattern, &level, &reason, &created_by],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_protection_rule<C: GenericClient>(&self, db: &C, id: i64) -> DynResult<()> {
+        timed!(self, db.execute(delete_protection_rule, &[&id])).await?;
+        Ok(())
+    }
+
+    /// Every edit filter, in the order they were added.
+    pub async fn fetch_edit_filters<C: GenericClient>(&self, db: &C) -> DynResult<Vec<EditFilter>> {
+        let rows = timed!(self, db.query(edit_filters, &[])).await?;
+        rows.iter().map(EditFilter::from_row).collect()
+    }
+
+    pub async fn fetch_enabled_edit_filters<C: GenericClient>(&self, db: &C) -> DynResult<Vec<EditFilter>> {
+        let rows = timed!(self, db.query(enabled_edit_filters, &[])).await?;
+        rows.iter().map(EditFilter::from_row).collect()
+    }
+
+    /// Adds a filter, or replaces filter `id` if given.
+    pub async fn upsert_edit_filter<C: GenericClient>(
+        &self,
+        db: &C,
+        id: Option<i64>,
+        filter: &NewEditFilter<'_>,
+        updated_by: &str,
+    ) -> DynResult<()> {
+        let NewEditFilter {
+            name,
+            rule,
+            action,
+            enabled,
+        } = filter;
+        match id {
+            Some(id) => {
+                timed!(self, db.execute(
+                    update_edit_filter,
+                    &[&id, name, rule, action, enabled, &updated_by],
+                ))
+                .await?
+            }
+            None => {
+                timed!(self, db.execute(
+                    insert_edit_filter,
+                    &[name, rule, action, enabled, &updated_by],
+                ))
+                .await?
+            }
+        };
+        Ok(())
+    }
+
+    /// Deletes a filter along with its hits.
+    pub async fn delete_edit_filter<C: GenericClient>(&self, db: &C, id: i64) -> DynResult<()> {
+        timed!(self, db.execute(delete_edit_filter, &[&id])).await?;
+        Ok(())
+    }
+
+    pub async fn insert_edit_filter_hit<C: GenericClient>(
+        &self,
+        db: &C,
+        filter: &EditFilter,
+        page_name: &str,
+        attribution: &str,
+        revision_id: Option<i64>,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(
+            insert_edit_filter_hit,
+            &[&filter.id, &page_name, &attribution, &filter.action, &revision_id],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// The latest `limit` filter hits, newest first.
+    pub async fn fetch_recent_edit_filter_hits<C: GenericClient>(
+        &self,
+        db: &C,
+        limit: i64,
+    ) -> DynResult<Vec<EditFilterHit>> {
+        let rows = timed!(self, db.query(recent_edit_filter_hits, &[&limit])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(EditFilterHit {
+                    filter_name: row.try_get(0)?,
+                    page_name: row.try_get(1)?,
+                    attribution: row.try_get(2)?,
+                    action: row.try_get(3)?,
+                    revision_id: row.try_get(4)?,
+                    hit_at: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn insert_secret_finding<C: GenericClient>(
+        &self,
+        db: &C,
+        finding: &secrets::Finding,
+        page_name: &str,
+        attribution: &str,
+        revision_id: Option<i64>,
+    ) -> DynResult<()> {
+        let line = finding.line as i32;
+        timed!(self, db.execute(
+            insert_secret_finding,
+            &[&finding.kind, &line, &page_name, &attribution, &revision_id],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// The latest `limit` secret findings, newest first.
+    pub async fn fetch_recent_secret_findings<C: GenericClient>(
+        &self,
+        db: &C,
+        limit: i64,
+    ) -> DynResult<Vec<SecretFinding>> {
+        let rows = timed!(self, db.query(recent_secret_findings, &[&limit])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(SecretFinding {
+                    kind: row.try_get(0)?,
+                    line: row.try_get(1)?,
+                    page_name: row.try_get(2)?,
+                    attribution: row.try_get(3)?,
+                    revision_id: row.try_get(4)?,
+                    found_at: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    /// `(revision id, filter name)` for each tagging filter that matched a
+    /// revision of `name`.
+    pub async fn fetch_edit_filter_tags<C: GenericClient>(
+        &self,
+        db: &C,
+        name: &str,
+    ) -> DynResult<Vec<(i64, String)>> {
+        let rows = timed!(self, db.query(edit_filter_tags, &[&name])).await?;
+        rows.iter().map(|row| Ok((row.try_get(0)?, row.try_get(1)?))).collect()
+    }
+
+    /// When the account `username` was created.
+    pub async fn fetch_user_created_at<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+    ) -> DynResult<Option<DateTime<Utc>>> {
+        match timed!(self, db.query_opt(user_created_at, &[&username])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// How many pages `modified_by` submitted for review after `since` that
+    /// are still waiting: pending revisions of pages with no current revision.
+    pub async fn fetch_pending_creation_count_by<C: GenericClient>(
+        &self,
+        db: &C,
+        modified_by: &str,
+        since: &DateTime<Utc>,
+    ) -> DynResult<i64> {
+        let row = timed!(self, db.query_one(pending_creation_count_by, &[&modified_by, since])).await?;
+        Ok(row.try_get(0)?)
+    }
+
+    /// The newest change id, see `backup.rs`, or 0 if nothing has changed.
+    pub async fn fetch_max_change_id<C: GenericClient>(&self, db: &C) -> DynResult<i64> {
+        let row = timed!(self, db.query_one(max_change_id, &[])).await?;
+        Ok(row.try_get(0)?)
+    }
+
+    /// Revisions added or changed, including becoming current, in changes
+    /// `after_change + 1..=until_change` and after `after_time`, in id
+    /// order.
+    pub async fn fetch_revisions_after<C: GenericClient>(
+        &self,
+        db: &C,
+        after_change: i64,
+        after_time: &DateTime<Utc>,
+        until_change: i64,
+    ) -> DynResult<Vec<BackupRevision>> {
+        let rows = timed!(self, db.query(revisions_after, &[&after_change, after_time, &until_change])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(BackupRevision {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    created_at: row.try_get(2)?,
+                    modified_by: row.try_get(3)?,
+                    document_data: row.try_get(4)?,
+                    status: row.try_get(5)?,
+                    reviewed_by: row.try_get(6)?,
+                    reviewed_at: row.try_get(7)?,
+                    current: row.try_get(8)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Moves in the same changes as `fetch_revisions_after`, oldest first.
+    pub async fn fetch_moves_after<C: GenericClient>(
+        &self,
+        db: &C,
+        after_change: i64,
+        after_time: &DateTime<Utc>,
+        until_change: i64,
+    ) -> DynResult<Vec<BackupMove>> {
+        let rows = timed!(self, db.query(moves_after, &[&after_change, after_time, &until_change])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(BackupMove {
+                    entry: MoveEntry::from_row(row)?,
+                    name: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Stores a revision from a backup with its original id, author, time
+    /// and review state, creating the document if needed, or updates it if
+    /// an earlier archive restored it. If `index` is given the revision
+    /// becomes current.
+    pub async fn restore_revision<C: GenericClient>(
+        &self,
+        tx: &C,
+        revision: &BackupRevision,
+        index: Option<&DocumentIndex>,
+    ) -> DynResult<i64> {
+        let row = timed!(self, tx.query_one(ensure_document, &[&revision.name])).await?;
+        let document_id: i64 = row.try_get(0)?;
+
+        let row = timed!(self, tx.query_one(
+            insert_restored_revision,
+            &[
+                &revision.id,
+                &revision.created_at,
+                &document_id,
+                &revision.modified_by,
+                &revision.document_data,
+                &revision.status,
+                &revision.reviewed_by,
+                &revision.reviewed_at,
+                &content_hash(revision.document_data.as_bytes()),
+            ],
+        ))
+        .await?;
+        let document_history_id: i64 = row.try_get(0)?;
+
+        if let Some(index) = index {
+            timed!(self, tx.execute(
+                set_current_revision,
+                &[&document_id, &document_history_id, &revision.created_at],
+            ))
+            .await?;
+            self.replace_index(tx, &revision.name, index).await?;
+        }
+        Ok(document_history_id)
+    }
+
+    /// Replays a move from a backup, keeping its original time. Returns
+    /// false, changing nothing, if the old name doesn't exist.
+    pub async fn restore_move<C: GenericClient>(&self, tx: &C, entry: &MoveEntry) -> DynResult<bool> {
+        let row = match timed!(self, tx.query_opt(rename_document, &[&entry.old_name, &entry.new_name])).await? {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+        let document_id: i64 = row.try_get(0)?;
+        timed!(self, tx.execute(
+            insert_restored_move,
+            &[
+                &document_id,
+                &entry.old_name,
+                &entry.new_name,
+                &entry.moved_by,
+                &entry.moved_at,
+                &entry.reason,
+            ],
+        ))
+        .await?;
+        timed!(self, tx.execute(bump_render_generation, &[])).await?;
+        Ok(true)
+    }
+
+    /// Records a move from a backup on the page now called `name`, without
+    /// renaming anything, for moves made before the page's revisions were
+    /// restored under its later name.
+    pub async fn restore_move_log<C: GenericClient>(&self, tx: &C, name: &str, entry: &MoveEntry) -> DynResult<bool> {
+        let inserted = timed!(self, tx.execute(
+            insert_restored_move_log,
+            &[
+                &name,
+                &entry.old_name,
+                &entry.new_name,
+                &entry.moved_by,
+                &entry.moved_at,
+                &entry.reason,
+            ],
+        ))
+        .await?;
+        Ok(inserted > 0)
+    }
+
+    /// Makes new revisions follow the ids restored from a backup.
+    pub async fn reset_revision_ids<C: GenericClient>(&self, tx: &C) -> DynResult<()> {
+        timed!(self, tx.query_one(reset_revision_ids, &[])).await?;
+        Ok(())
+    }
+
+    /// The change id the last restored backup archive ended at, if any has
+    /// been restored.
+    pub async fn fetch_restore_checkpoint<C: GenericClient>(&self, db: &C) -> DynResult<Option<i64>> {
+        match timed!(self, db.query_opt(restore_checkpoint, &[])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_restore_checkpoint<C: GenericClient>(&self, tx: &C, restored_until: i64) -> DynResult<()> {
+        timed!(self, tx.execute(upsert_restore_checkpoint, &[&restored_until])).await?;
+        Ok(())
+    }
+
+    /// Whether Postgres knows `name` as a timezone.
+    pub async fn timezone_exists<C: GenericClient>(&self, db: &C, name: &str) -> DynResult<bool> {
+        let row = timed!(self, db.query_one(timezone_exists, &[&name])).await?;
+        Ok(row.try_get(0)?)
+    }
+
+    pub async fn insert_session<C: GenericClient>(
+        &self,
+        db: &C,
+        token: &str,
+        user_id: i64,
+        addr: Option<&str>,
+        user_agent: Option<&str>,
+        two_factor: bool,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(insert_session, &[&token, &user_id, &addr, &user_agent, &two_factor])).await?;
+        Ok(())
+    }
+
+    /// Marks a session as seen now, from `addr` with `user_agent`.
+    pub async fn touch_session<C: GenericClient>(
+        &self,
+        db: &C,
+        token: &str,
+        addr: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(touch_session, &[&token, &addr, &user_agent])).await?;
+        Ok(())
+    }
+
+    /// `username`'s sessions, most recently seen first.
+    pub async fn fetch_user_sessions<C: GenericClient>(&self, db: &C, username: &str) -> DynResult<Vec<SessionRecord>> {
+        let rows = timed!(self, db.query(user_sessions, &[&username])).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(SessionRecord {
+                    id: row.try_get(0)?,
+                    token: row.try_get(1)?,
+                    addr: row.try_get(2)?,
+                    user_agent: row.try_get(3)?,
+                    created_at: row.try_get(4)?,
+                    last_seen_at: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Logs out `username`'s session `id`. `false` if they have no such
+    /// session.
+    pub async fn revoke_session<C: GenericClient>(&self, db: &C, username: &str, id: i64) -> DynResult<bool> {
+        let deleted = timed!(self, db.execute(revoke_session, &[&username, &id])).await?;
+        Ok(deleted == 1)
+    }
+
+    /// Logs `user_id` out everywhere but the session `token`, which counts
+    /// as logged in with a second factor from now on.
+    pub async fn keep_only_session<C: GenericClient>(&self, db: &C, user_id: i64, token: &str) -> DynResult<()> {
+        timed!(self, db.execute(revoke_other_sessions, &[&user_id, &token])).await?;
+        timed!(self, db.execute(set_session_two_factor, &[&token])).await?;
+        Ok(())
+    }
+
+    /// Logs `username` out everywhere.
+    pub async fn revoke_all_sessions<C: GenericClient>(&self, db: &C, username: &str) -> DynResult<()> {
+        timed!(self, db.execute(revoke_all_sessions, &[&username])).await?;
+        Ok(())
+    }
+
+    pub async fn delete_session<C: GenericClient>(&self, db: &C, token: &str) -> DynResult<()> {
+        timed!(self, db.execute(delete_session, &[&token])).await?;
+        Ok(())
+    }
+
+    pub async fn fetch_user_credentials<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+    ) -> DynResult<Option<Credentials>> {
+        match timed!(self, db.query_opt(user_credentials, &[&username])).await? {
+            Some(row) => Ok(Some(Credentials {
+                user_id: row.try_get(0)?,
+                password_hash: row.try_get(1)?,
+                awaiting_verification: row.try_get(2)?,
+                two_factor: row.try_get(3)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn fetch_two_factor<C: GenericClient>(&self, db: &C, user_id: i64) -> DynResult<Option<TwoFactorState>> {
+        match timed!(self, db.query_opt(two_factor, &[&user_id])).await? {
+            Some(row) => Ok(Some(TwoFactorState {
+                password_hash: row.try_get(0)?,
+                secret: row.try_get(1)?,
+                last_step: row.try_get(2)?,
+                recovery_codes_left: row.try_get(3)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Turns two-factor authentication on with `secret`, whose code for
+    /// `step` was just given, and replaces the recovery codes; or turns it
+    /// off, dropping them, with `None`.
+    pub async fn set_two_factor<C: GenericClient>(
+        &self,
+        db: &C,
+        user_id: i64,
+        secret: Option<(&str, i64)>,
+        recovery_code_hashes: &[String],
+    ) -> DynResult<()> {
+        let (secret, step) = match secret {
+            Some((secret, step)) => (Some(secret), Some(step)),
+            None => (None, None),
+        };
+        timed!(self, db.execute(set_totp, &[&user_id, &secret, &step])).await?;
+        self.replace_recovery_codes(db, user_id, recovery_code_hashes).await
+    }
+
+    pub async fn replace_recovery_codes<C: GenericClient>(
+        &self,
+        db: &C,
+        user_id: i64,
+        hashes: &[String],
+    ) -> DynResult<()> {
+        timed!(self, db.execute(delete_recovery_codes, &[&user_id])).await?;
+        timed!(self, db.execute(insert_recovery_codes, &[&user_id, &hashes])).await?;
+        Ok(())
+    }
+
+    /// Records that the code for `step` was used. `false` if a code for it
+    /// or a later step already was, so a code can't be replayed.
+    pub async fn advance_totp_step<C: GenericClient>(&self, db: &C, user_id: i64, step: i64) -> DynResult<bool> {
+        let updated = timed!(self, db.execute(advance_totp_step, &[&user_id, &step])).await?;
+        Ok(updated == 1)
+    }
+
+    /// Spends the recovery code hashed as `hash`. `false` if there's no
+    /// such unused code.
+    pub async fn use_recovery_code<C: GenericClient>(&self, db: &C, user_id: i64, hash: &str) -> DynResult<bool> {
+        let deleted = timed!(self, db.execute(use_recovery_code, &[&user_id, &hash])).await?;
+        Ok(deleted == 1)
+    }
+
+    pub async fn insert_user<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+        password_hash: &str,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(insert_user, &[&username, &password_hash]))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn fetch_account_by_id<C: GenericClient>(&self, db: &C, user_id: i64) -> DynResult<Option<Account>> {
+        match timed!(self, db.query_opt(account_by_id, &[&user_id])).await? {
+            Some(row) => Ok(Some(Account::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn fetch_account_by_username<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+    ) -> DynResult<Option<Account>> {
+        match timed!(self, db.query_opt(account_by_username, &[&username])).await? {
+            Some(row) => Ok(Some(Account::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The account that verified `email`, ignoring case. The oldest, if
+    /// several have.
+    pub async fn fetch_account_by_verified_email<C: GenericClient>(
+        &self,
+        db: &C,
+        email: &str,
+    ) -> DynResult<Option<Account>> {
+        match timed!(self, db.query_opt(account_by_verified_email, &[&email])).await? {
+            Some(row) => Ok(Some(Account::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates a user signing up at `/register`. `None` if the username is
+    /// taken.
+    pub async fn register_user<C: GenericClient>(
+        &self,
+        db: &C,
+        username: &str,
+        password_hash: &str,
+    ) -> DynResult<Option<i64>> {
+        match timed!(self, db.query_opt(register_user, &[&username, &password_hash])).await? {
+            Some(row) => Ok(Some(row.try_get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_verified_email<C: GenericClient>(&self, db: &C, user_id: i64, email: &str) -> DynResult<()> {
+        timed!(self, db.execute(set_verified_email, &[&user_id, &email])).await?;
+        Ok(())
+    }
+
+    /// Replaces a user's password and logs them out everywhere.
+    pub async fn reset_password<C: GenericClient>(
+        &self,
+        db: &C,
+        user_id: i64,
+        password_hash: &str,
+    ) -> DynResult<()> {
+        timed!(self, db.execute(set_password_hash, &[&user_id, &password_hash])).await?;
+        timed!(self, db.execute(delete_user_sessions, &[&user_id])).await?;
+        Ok(())
+    }
+
+    /// The key `accounts.rs` signs links with, storing `candidate` if there
+    /// isn't one yet.
+    pub async fn fetch_or_create_signing_key<C: GenericClient>(
+        &self,
+        db: &C,
+        candidate: &[u8],
+    ) -> DynResult<Vec<u8>> {
+        timed!(self, db.execute(insert_signing_key, &[&candidate])).await?;
+        let row = timed!(self, db.query_one(signing_key, &[])).await?;
+        Ok(row.try_get(0)?)
+    }
+
+    /// Page, revision and user counts and storage used, for `/admin/stats`.
+    pub async fn fetch_site_totals<C: GenericClient>(&self, db: &C) -> DynResult<SiteTotals> {
+        let row = timed!(self, db.query_one(site_totals, &[])).await?;
+        Ok(SiteTotals {
+            pages: row.try_get(0)?,
+            revisions: row.try_get(1)?,
+            users: row.try_get(2)?,
+            revision_bytes: row.try_get(3)?,
+            attachment_bytes: row.try_get(4)?,
+        })
+    }
+
+    /// Revisions saved on each UTC day since `since`, oldest first. Days
+    /// without any are left out.
+    pub async fn fetch_edits_per_day<C: GenericClient>(
+        &self,
+        db: &C,
+        since: &DateTime<Utc>,
+    ) -> DynResult<Vec<(NaiveDate, i64)>> {
+        let rows = timed!(self, db.query(edits_per_day, &[since])).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+            .collect()
+    }
+
+    /// The `limit` users with the most revisions since `since`, and how
+    /// many, most first.
+    pub async fn fetch_top_editors<C: GenericClient>(
+        &self,
+        db: &C,
+        since: &DateTime<Utc>,
+        limit: i64,
+    ) -> DynResult<Vec<(String, i64)>> {
+        let rows = timed!(self, db.query(top_editors, &[since, &limit])).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+            .collect()
+    }
+}
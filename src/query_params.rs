@@ -0,0 +1,102 @@
+//! Typed query strings. A handler declares the parameters it takes as a
+//! struct deriving `Deserialize` and extracts it with
+//! `QueryParams::from_request`. Unknown parameters are ignored, but a
+//! malformed one, such as `?limit=lots`, fails the request with a 400
+//! instead of quietly falling back to a default, see `Handler::respond`.
+
+use hyper::{Body, Request};
+use serde::de::{DeserializeOwned, Deserializer, Error as _};
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub struct QueryError(String);
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "malformed query string: {}", self.0)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+pub struct QueryParams<T>(pub T);
+
+impl<T: DeserializeOwned> QueryParams<T> {
+    pub fn from_request(req: &Request<Body>) -> Result<QueryParams<T>, QueryError> {
+        QueryParams::parse(req.uri().query().unwrap_or(""))
+    }
+
+    pub fn parse(query: &str) -> Result<QueryParams<T>, QueryError> {
+        serde_urlencoded::from_str(query)
+            .map(QueryParams)
+            .map_err(|err| QueryError(err.to_string()))
+    }
+}
+
+/// For `#[serde(deserialize_with = "query_params::flag")]`: `true` or `1`,
+/// or `false`, `0` or empty.
+pub fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    match &value[..] {
+        "true" | "1" => Ok(true),
+        "false" | "0" | "" => Ok(false),
+        other => Err(D::Error::custom(format!("expected true or false, got {:?}", other))),
+    }
+}
+
+/// For `#[serde(deserialize_with = "query_params::non_empty")]`: a string
+/// parameter that counts as missing when empty, as form fields left blank
+/// are sent.
+pub fn non_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.filter(|value| !value.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Params {
+        #[serde(default, deserialize_with = "flag")]
+        dry_run: bool,
+        #[serde(default, deserialize_with = "non_empty")]
+        root: Option<String>,
+        limit: Option<usize>,
+    }
+
+    fn parse(query: &str) -> Result<Params, QueryError> {
+        QueryParams::<Params>::parse(query).map(|QueryParams(params)| params)
+    }
+
+    #[test]
+    fn flag_values() {
+        for (query, expected) in [
+            ("dry_run=true", true),
+            ("dry_run=1", true),
+            ("dry_run=false", false),
+            ("dry_run=0", false),
+            ("dry_run=", false),
+            ("", false),
+        ] {
+            assert_eq!(parse(query).unwrap().dry_run, expected, "{:?}", query);
+        }
+        let err = parse("dry_run=yes").err().unwrap();
+        assert!(err.to_string().contains("expected true or false"), "{}", err);
+    }
+
+    #[test]
+    fn non_empty_values() {
+        assert_eq!(parse("root=Home").unwrap().root.as_deref(), Some("Home"));
+        assert_eq!(parse("root=").unwrap().root, None);
+        assert_eq!(parse("").unwrap().root, None);
+    }
+
+    #[test]
+    fn malformed_parameters_fail() {
+        assert!(parse("limit=lots").is_err());
+        assert!(parse("limit=-1").is_err());
+        // unknown parameters are ignored
+        assert_eq!(parse("limit=5&other=x").unwrap().limit, Some(5));
+    }
+}
@@ -0,0 +1,86 @@
+//! Updating links when a page moves. The pages that link to the old name,
+//! from the backlink index, get a revision with every `/wiki/OldName` link
+//! pointing at the new name instead, attributed to `AUTHOR` on behalf of
+//! whoever moved the page and explained by a revision summary.
+//!
+//! Links are found in the text rather than the parsed document, so
+//! reference definitions and raw HTML are covered too, but so is a link
+//! quoted in a code block.
+
+use crate::replace::Change;
+use crate::routes::{Route, WIKI_PREFIX};
+use crate::{DynResult, HandlerInner};
+
+pub const AUTHOR: &str = "link-updater";
+
+/// Most pages updated by one move. Links on any others are left alone and
+/// reported.
+pub const MAX_PAGES: i64 = 500;
+
+/// The attribution of the updates made for `moved_by`.
+pub fn author(moved_by: &str) -> String {
+    format!("{} (for {})", AUTHOR, moved_by)
+}
+
+pub fn summary(old_name: &str, new_name: &str) -> String {
+    format!("Updated links after {:?} was moved to {:?}", old_name, new_name)
+}
+
+/// `text` with links to `old_name`, and to its history, edit page and so
+/// on, pointing at `new_name`, keeping any query and fragment. `None` if
+/// there are none.
+pub fn rewrite(text: &str, old_name: &str, new_name: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, _) in text.match_indices(WIKI_PREFIX) {
+        // only site-relative links, not https://elsewhere/wiki/...
+        let before = text[..start].chars().next_back();
+        if start < copied || before.is_some_and(|c| c.is_alphanumeric() || c == '/' || c == '.') {
+            continue;
+        }
+        let rest = &text[start..];
+        let end = if before == Some('<') {
+            rest.find('>')
+        } else {
+            rest.find(|c: char| c.is_whitespace() || matches!(c, ')' | '>' | '"' | '\'' | '[' | ']'))
+        };
+        let url = &rest[..end.unwrap_or(rest.len())];
+        let path = &url[..url.find(&['?', '#'][..]).unwrap_or(url.len())];
+
+        let moved = match Route::router(path) {
+            Ok(route) if matches!(route, Route::Wiki(ref rw) if rw.name == old_name) => {
+                route.with_page_name(|_| new_name.to_string())
+            }
+            _ => None,
+        };
+        if let Some(moved) = moved {
+            out.push_str(&text[copied..start]);
+            out.push_str(&moved.to_string());
+            copied = start + path.len();
+        }
+    }
+    if copied == 0 {
+        return None;
+    }
+    out.push_str(&text[copied..]);
+    Some(out)
+}
+
+/// The pages linking to `old_name` and how their current revisions would
+/// change, without storing anything, and the pages past `MAX_PAGES` that
+/// won't be updated.
+pub async fn plan(inner: &HandlerInner, old_name: &str, new_name: &str) -> DynResult<(Vec<Change>, Vec<String>)> {
+    let mut names = inner
+        .queries
+        .fetch_backlinks(&inner.db, old_name, i64::MAX)
+        .await?;
+    let over_limit = names.split_off(names.len().min(MAX_PAGES as usize));
+    let mut changes = Vec::new();
+    for (name, old) in inner.queries.fetch_current_documents(&inner.db, &names).await? {
+        if let Some(new) = rewrite(&old, old_name, new_name) {
+            changes.push(Change { name, old, new });
+        }
+    }
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((changes, over_limit))
+}
@@ -0,0 +1,212 @@
+//! Rendered current revisions, so a page is rendered once rather than on
+//! every view. How a page renders also depends on other pages (templates,
+//! data queries, which links are missing), so each entry records the
+//! database's render generation, which every save and move bumps, and
+//! entries from an older generation are rendered again.
+//!
+//! Saves warm the cache in the background: the saved page and the pages
+//! linking to or transcluding it are rendered before anyone asks for them.
+//!
+//! With `--render-stale-secs`, a stale entry is served as it is for up to
+//! that long after a reader first finds it stale, while it's rendered again
+//! in the background, so busy pages don't wait on a render after every save.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use tokio::sync::RwLock as AsyncRwLock;
+
+use crate::plugins::Plugins;
+use crate::transclusion::TEMPLATE_NAMESPACE;
+use crate::{render_document, DynResult, HandlerInner};
+
+/// Pages kept at most. Past that, entries from older generations go first,
+/// then arbitrary ones.
+const MAX_ENTRIES: usize = 2000;
+
+/// Pages warmed after a save, on top of the saved one.
+const MAX_WARMED_DEPENDENTS: i64 = 50;
+
+pub struct RenderCache {
+    entries: RwLock<HashMap<String, Entry>>,
+    /// How long a stale entry may still be served, `None` to never serve
+    /// one.
+    stale_window: Option<Duration>,
+    /// Lookups answered from the cache, stale entries included.
+    hits: AtomicU64,
+    /// Lookups that had to render.
+    misses: AtomicU64,
+}
+
+/// How well the cache is doing since startup, for `/admin/stats`.
+pub struct CacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Hits as a percentage of lookups, `None` before the first lookup.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 * 100.0 / lookups as f64)
+    }
+}
+
+struct Entry {
+    revision_id: i64,
+    generation: i64,
+    html: String,
+    /// When a reader first found the entry stale.
+    stale_since: Option<Instant>,
+    /// Whether a background render has been asked for.
+    refreshing: bool,
+}
+
+impl RenderCache {
+    pub fn new(stale_window: Option<Duration>) -> RenderCache {
+        RenderCache {
+            entries: RwLock::default(),
+            stale_window,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.read().unwrap().len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The rendering of `text`, the current revision of `name`.
+    pub async fn render(
+        &self,
+        inner: &HandlerInner,
+        plugins: &Plugins,
+        name: &str,
+        revision_id: i64,
+        text: &str,
+    ) -> DynResult<String> {
+        // read before rendering, so a change made meanwhile by another
+        // process leaves the entry stale rather than wrongly fresh
+        let generation = inner.queries.fetch_render_generation(&inner.db).await?;
+        if let Some(entry) = self.entries.read().unwrap().get(name) {
+            if entry.revision_id == revision_id && entry.generation == generation {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(entry.html.clone());
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let html = render_document(inner, plugins, text).await?;
+        self.store(name, revision_id, generation, html.clone());
+        Ok(html)
+    }
+
+    /// Like `render`, but a stale entry still inside the stale window is
+    /// returned as it is. The flag is set when the caller should have it
+    /// rendered again with `refresh`; it's set for one caller only.
+    pub async fn render_or_stale(
+        &self,
+        inner: &HandlerInner,
+        plugins: &Plugins,
+        name: &str,
+        revision_id: i64,
+        text: &str,
+    ) -> DynResult<(String, bool)> {
+        let window = match self.stale_window {
+            Some(window) => window,
+            None => return Ok((self.render(inner, plugins, name, revision_id, text).await?, false)),
+        };
+        let generation = inner.queries.fetch_render_generation(&inner.db).await?;
+        if let Some(entry) = self.entries.write().unwrap().get_mut(name) {
+            if entry.revision_id == revision_id && entry.generation == generation {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok((entry.html.clone(), false));
+            }
+            let stale_since = *entry.stale_since.get_or_insert_with(Instant::now);
+            if stale_since.elapsed() <= window {
+                let refresh = !entry.refreshing;
+                entry.refreshing = true;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok((entry.html.clone(), refresh));
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let html = render_document(inner, plugins, text).await?;
+        self.store(name, revision_id, generation, html.clone());
+        Ok((html, false))
+    }
+
+    /// Renders the current revision of `name` into the cache, after
+    /// `render_or_stale` served a stale entry.
+    pub async fn refresh(&self, inner: &AsyncRwLock<HandlerInner>, plugins: &Plugins, name: &str) -> DynResult<()> {
+        let locked = inner.read().await;
+        let refreshed = match locked.queries.fetch_current_revision(&locked.db, name).await {
+            Ok(Some(revision)) => self
+                .render(&locked, plugins, name, revision.id, &revision.document_data)
+                .await
+                .map(drop),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if refreshed.is_err() {
+            // let a later reader try again
+            if let Some(entry) = self.entries.write().unwrap().get_mut(name) {
+                entry.refreshing = false;
+            }
+        }
+        refreshed
+    }
+
+    fn store(&self, name: &str, revision_id: i64, generation: i64, html: String) {
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(name) {
+            entries.retain(|_, entry| entry.generation >= generation);
+            if entries.len() >= MAX_ENTRIES {
+                if let Some(victim) = entries.keys().next().cloned() {
+                    entries.remove(&victim);
+                }
+            }
+        }
+        let entry = Entry {
+            revision_id,
+            generation,
+            html,
+            stale_since: None,
+            refreshing: false,
+        };
+        entries.insert(name.to_string(), entry);
+    }
+
+    /// Renders `name` and the pages showing it into the cache. The lock is
+    /// taken per page so a long warm-up doesn't hold up saves.
+    pub async fn warm(&self, inner: &AsyncRwLock<HandlerInner>, plugins: &Plugins, name: &str) -> DynResult<()> {
+        let mut names = vec![name.to_string()];
+        {
+            let locked = inner.read().await;
+            let queries = &locked.queries;
+            match name.strip_prefix(TEMPLATE_NAMESPACE) {
+                Some(template) => {
+                    names.extend(queries.fetch_transcluders(&locked.db, template, MAX_WARMED_DEPENDENTS).await?)
+                }
+                None => names.extend(queries.fetch_backlinks(&locked.db, name, MAX_WARMED_DEPENDENTS).await?),
+            }
+        }
+
+        for name in names {
+            let locked = inner.read().await;
+            if let Some(revision) = locked.queries.fetch_current_revision(&locked.db, &name).await? {
+                self.render(&locked, plugins, &name, revision.id, &revision.document_data)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
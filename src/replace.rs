@@ -0,0 +1,207 @@
+use std::borrow::Cow;
+
+use regex::Regex;
+use similar::TextDiff;
+use tokio_postgres::GenericClient;
+
+use crate::auth::User;
+use crate::edit_filter;
+use crate::plugins::{Plugins, SaveContext};
+use crate::queries::Queries;
+use crate::secrets;
+use crate::{index_document, DynResult, HandlerInner};
+
+/// What `wiki replace` and `/admin/replace` look for.
+pub enum Pattern {
+    Literal(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn new(find: &str, regex: bool) -> DynResult<Pattern> {
+        if find.is_empty() {
+            return Err("refusing to replace an empty pattern".into());
+        }
+        if regex {
+            Ok(Pattern::Regex(Regex::new(find)?))
+        } else {
+            Ok(Pattern::Literal(find.to_string()))
+        }
+    }
+
+    /// Replaces every match. For regexes, `replacement` may refer to capture
+    /// groups as `$1` or `${name}`.
+    pub fn replace_all<'t>(&self, text: &'t str, replacement: &str) -> Cow<'t, str> {
+        match self {
+            Pattern::Literal(find) if text.contains(find.as_str()) => {
+                Cow::Owned(text.replace(find.as_str(), replacement))
+            }
+            Pattern::Literal(..) => Cow::Borrowed(text),
+            Pattern::Regex(re) => re.replace_all(text, replacement),
+        }
+    }
+}
+
+/// A page whose current revision would change.
+pub struct Change {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl Change {
+    pub fn unified_diff(&self) -> String {
+        TextDiff::from_lines(&self.old, &self.new)
+            .unified_diff()
+            .context_radius(2)
+            .header(&self.name, &self.name)
+            .to_string()
+    }
+}
+
+/// Runs the replacement over the current revision of every page without
+/// storing anything.
+pub async fn plan(
+    inner: &HandlerInner,
+    pattern: &Pattern,
+    replacement: &str,
+) -> DynResult<Vec<Change>> {
+    let names = inner.queries.fetch_current_names(&inner.db).await?;
+    let mut documents = inner
+        .queries
+        .fetch_current_documents(&inner.db, &names)
+        .await?;
+    documents.sort();
+
+    let mut changes = Vec::new();
+    for (name, old) in documents {
+        if let Cow::Owned(new) = pattern.replace_all(&old, replacement) {
+            if new != old {
+                changes.push(Change { name, old, new });
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// Who changes are applied for, and what they may do.
+pub struct Editor<'a> {
+    /// Who the revisions are attributed to.
+    pub author: &'a str,
+    /// Whose permissions apply; `None` from the command line.
+    pub user: Option<&'a User>,
+    /// Whether the page may be changed, see `Handler::may_write`.
+    pub may_write: &'a (dyn Fn(&str) -> bool + Sync),
+    /// Store the changes as pending revisions, for review.
+    pub held: bool,
+    pub secrets: secrets::Policy,
+}
+
+/// What became of one change.
+#[derive(Debug)]
+pub enum Outcome {
+    Saved(i64),
+    /// Stored as a pending revision.
+    Held(i64),
+    /// Edited since `plan` ran.
+    Edited,
+    /// The editor may not change the page.
+    Forbidden,
+    /// A plugin or edit filter refused the new text.
+    Refused(String),
+}
+
+impl Outcome {
+    /// Why the change didn't go live, if it didn't.
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            Outcome::Saved(..) => None,
+            Outcome::Held(id) => Some(format!("held for review as revision {}", id)),
+            Outcome::Edited => Some("edited meanwhile".to_string()),
+            Outcome::Forbidden => Some("not editable by you".to_string()),
+            Outcome::Refused(message) => Some(format!("refused: {}", message)),
+        }
+    }
+}
+
+/// Stores each change as a new revision, with `summary` if given, in one
+/// transaction. Each goes through the checks an ordinary save does: the
+/// editor's permissions on the page, plugins' `pre_save` and the edit
+/// filters, and it's held for review when `editor.held` is set. Warning
+/// filters can't be confirmed, so they refuse the change too.
+pub async fn apply(
+    inner: &mut HandlerInner,
+    plugins: &Plugins,
+    changes: &[Change],
+    editor: &Editor<'_>,
+    summary: Option<&str>,
+) -> DynResult<Vec<(String, Outcome)>> {
+    let HandlerInner { db, queries } = inner;
+    let tx = db.transaction().await?;
+
+    let mut outcomes = Vec::new();
+    for change in changes {
+        let save = SaveContext {
+            name: &change.name,
+            user: editor.user,
+            attribution: editor.author,
+        };
+        let outcome = if !(editor.may_write)(&change.name) {
+            Outcome::Forbidden
+        } else {
+            let mut text = change.new.clone();
+            match plugins.pre_save(&save, &mut text) {
+                Err(err) => Outcome::Refused(err.to_string()),
+                Ok(()) => store(queries, &tx, &save, change, &text, editor, summary).await?,
+            }
+        };
+        outcomes.push((change.name.clone(), outcome));
+    }
+    tx.commit().await?;
+
+    for (name, outcome) in &outcomes {
+        if let Outcome::Saved(revision_id) = *outcome {
+            let save = SaveContext {
+                name,
+                user: editor.user,
+                attribution: editor.author,
+            };
+            plugins.post_save(&save, revision_id);
+        }
+    }
+    Ok(outcomes)
+}
+
+async fn store<C: GenericClient>(
+    queries: &Queries,
+    tx: &C,
+    save: &SaveContext<'_>,
+    change: &Change,
+    text: &str,
+    editor: &Editor<'_>,
+    summary: Option<&str>,
+) -> DynResult<Outcome> {
+    let current = queries.fetch_current_text_for_update(tx, &change.name).await?;
+    if current.as_deref() != Some(&change.old[..]) {
+        return Ok(Outcome::Edited);
+    }
+    let matched = match edit_filter::check(queries, tx, save, text, false, editor.secrets).await? {
+        Ok(matched) => matched,
+        Err(edit_filter::Refusal::Block(message)) | Err(edit_filter::Refusal::Warn(message)) => {
+            return Ok(Outcome::Refused(message))
+        }
+    };
+    let (revision_id, outcome) = if editor.held {
+        let id = queries.store_pending_revision(tx, &change.name, editor.author, text).await?;
+        (id, Outcome::Held(id))
+    } else {
+        let index = index_document(text);
+        let id = queries.store_revision(tx, &change.name, editor.author, text, &index).await?;
+        (id, Outcome::Saved(id))
+    };
+    if let Some(summary) = summary {
+        queries.set_revision_summary(tx, revision_id, summary).await?;
+    }
+    edit_filter::record(queries, tx, save, &matched, revision_id).await?;
+    Ok(outcome)
+}
@@ -0,0 +1,224 @@
+//! Resized copies of image attachments. An attachment URL with `?w=` or
+//! `?h=`, or both, serves the image scaled down to fit, keeping its aspect
+//! ratio; it's never scaled up. Pages ask for a size in the image's alt
+//! text, after a `|`:
+//!
+//! ```text
+//! ![Floor plan|300](/wiki/Office/attachments/plan.png)
+//! ![Floor plan|300x200](/wiki/Office/attachments/plan.png)
+//! ```
+//!
+//! Sizes are rounded up to a multiple of `STEP` pixels, so there are only
+//! so many copies of each image, and each is made once and kept by content
+//! hash like a preview, see `previews.rs`.
+
+use std::io::Cursor;
+
+use comrak::nodes::{AstNode, NodeValue};
+use image::{ImageFormat, ImageReader};
+use serde::Deserialize;
+use tracing::{event, Level};
+
+use crate::queries::AttachmentContent;
+use crate::{DynResult, HandlerInner};
+
+/// The largest width or height served.
+const MAX_PIXELS: u32 = 2048;
+
+const STEP: u32 = 32;
+
+/// `?w=` and `?h=` on an attachment URL.
+#[derive(Debug, Default, Deserialize)]
+pub struct SizeParams {
+    w: Option<u32>,
+    h: Option<u32>,
+}
+
+/// The box a resized image fits in. A side left out doesn't constrain it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Size {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl Size {
+    pub fn of(params: &SizeParams) -> Size {
+        let snap = |pixels: u32| (pixels.max(1).div_ceil(STEP) * STEP).min(MAX_PIXELS);
+        Size {
+            width: params.w.map(snap),
+            height: params.h.map(snap),
+        }
+    }
+
+    fn is_any(&self) -> bool {
+        self.width.is_some() || self.height.is_some()
+    }
+
+    /// The key the copy is kept under in `attachment_preview`.
+    fn kind(&self) -> String {
+        let side = |pixels: Option<u32>| pixels.map_or(String::new(), |pixels| pixels.to_string());
+        format!("resized-{}x{}", side(self.width), side(self.height))
+    }
+
+    /// `alt` without a trailing `|300` or `|300x200`, and the size asked for.
+    pub fn split_alt(alt: &str) -> Option<(&str, SizeParams)> {
+        let (text, size) = alt.rsplit_once('|')?;
+        let (w, h) = size.trim().split_once('x').unwrap_or((size.trim(), ""));
+        let side = |pixels: &str| -> Option<Option<u32>> {
+            match pixels {
+                "" => Some(None),
+                pixels => pixels.parse().ok().filter(|&pixels| pixels > 0).map(Some),
+            }
+        };
+        let params = SizeParams { w: side(w)?, h: side(h)? };
+        if params.w.is_none() && params.h.is_none() {
+            return None;
+        }
+        Some((text.trim_end(), params))
+    }
+}
+
+/// Moves a size in an image's alt text onto its URL as `?w=` and `?h=`, for
+/// images on this wiki. Other sites wouldn't know what to do with it, so
+/// their images keep the alt text as written.
+pub fn apply_sizes<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        let mut data = node.data.borrow_mut();
+        let image = match data.value {
+            NodeValue::Image(ref mut image) => image,
+            _ => continue,
+        };
+        let url = String::from_utf8_lossy(&image.url).into_owned();
+        if url.contains("://") || url.starts_with("//") {
+            continue;
+        }
+        let last = match node.last_child() {
+            Some(last) => last,
+            None => continue,
+        };
+        let mut last = last.data.borrow_mut();
+        let text = match last.value {
+            NodeValue::Text(ref mut text) => text,
+            _ => continue,
+        };
+        let alt = String::from_utf8_lossy(text).into_owned();
+        let (alt, params) = match Size::split_alt(&alt) {
+            Some(split) => split,
+            None => continue,
+        };
+
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        if let Some(w) = params.w {
+            query.append_pair("w", &w.to_string());
+        }
+        if let Some(h) = params.h {
+            query.append_pair("h", &h.to_string());
+        }
+        let (path, fragment) = url.split_at(url.find('#').unwrap_or(url.len()));
+        let separator = if path.contains('?') { '&' } else { '?' };
+        image.url = format!("{}{}{}{}", path, separator, query.finish(), fragment).into_bytes();
+        *text = alt.as_bytes().to_vec();
+    }
+}
+
+/// `content` scaled down to fit `size`, or as it is if it isn't an image
+/// that can be resized or already fits.
+pub async fn resized(inner: &HandlerInner, content: AttachmentContent, size: Size) -> DynResult<AttachmentContent> {
+    // a GIF would lose its animation
+    let format = match &content.content_type[..] {
+        "image/jpeg" => ImageFormat::Jpeg,
+        "image/png" | "image/webp" => ImageFormat::Png,
+        _ => return Ok(content),
+    };
+    if !size.is_any() || fits(&content.data, size) {
+        return Ok(content);
+    }
+
+    let kind = size.kind();
+    let queries = &inner.queries;
+    let data = match queries.fetch_attachment_preview(&inner.db, &content.content_hash, &kind).await? {
+        Some(data) => data,
+        None => {
+            let original = content.data.clone();
+            match tokio::task::spawn_blocking(move || resize(&original, size, format)).await? {
+                Ok(data) => {
+                    queries
+                        .store_attachment_preview(&inner.db, &content.content_hash, &kind, &data)
+                        .await?;
+                    data
+                }
+                Err(err) => {
+                    event!(Level::WARN, "can't resize {}: {}", content.content_hash, err);
+                    return Ok(content);
+                }
+            }
+        }
+    };
+    Ok(AttachmentContent {
+        content_type: format.to_mime_type().to_string(),
+        content_hash: format!("{}-{}", content.content_hash, kind),
+        data,
+    })
+}
+
+/// Whether the image in `data` is already within `size`, reading only its
+/// header. An image whose size can't be read doesn't fit, so `resize`
+/// reports why.
+fn fits(data: &[u8], size: Size) -> bool {
+    let dimensions = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    match dimensions {
+        Some((width, height)) => {
+            size.width.is_none_or(|max| width <= max) && size.height.is_none_or(|max| height <= max)
+        }
+        None => false,
+    }
+}
+
+fn resize(data: &[u8], size: Size, format: ImageFormat) -> DynResult<Vec<u8>> {
+    let image = image::load_from_memory(data)?;
+    let resized = image.resize(
+        size.width.unwrap_or(u32::MAX),
+        size.height.unwrap_or(u32::MAX),
+        image::imageops::FilterType::Lanczos3,
+    );
+    let mut encoded = Vec::new();
+    match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => resized.into_rgb8().write_to(&mut Cursor::new(&mut encoded), format)?,
+        _ => resized.write_to(&mut Cursor::new(&mut encoded), format)?,
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(alt: &str) -> Option<(&str, Option<u32>, Option<u32>)> {
+        Size::split_alt(alt).map(|(alt, params)| (alt, params.w, params.h))
+    }
+
+    #[test]
+    fn sizes_in_alt_text() {
+        assert_eq!(split("Floor plan|300"), Some(("Floor plan", Some(300), None)));
+        assert_eq!(split("Floor plan | 300x200"), Some(("Floor plan", Some(300), Some(200))));
+        assert_eq!(split("Floor plan|x200"), Some(("Floor plan", None, Some(200))));
+        assert_eq!(split("a|b|300"), Some(("a|b", Some(300), None)));
+        assert_eq!(split("Floor plan"), None);
+        assert_eq!(split("Floor plan|big"), None);
+        assert_eq!(split("Floor plan|0"), None);
+        assert_eq!(split("Floor plan|x"), None);
+    }
+
+    #[test]
+    fn sizes_are_snapped() {
+        let size = Size::of(&SizeParams { w: Some(300), h: Some(1) });
+        assert_eq!(size, Size { width: Some(320), height: Some(32) });
+        let size = Size::of(&SizeParams { w: Some(100_000), h: None });
+        assert_eq!(size, Size { width: Some(MAX_PIXELS), height: None });
+        assert_eq!(size.kind(), "resized-2048x");
+    }
+}
@@ -0,0 +1,158 @@
+//! Lays out a page's history for `/wiki/:name/graph`: one row per revision,
+//! oldest at the top, in one lane for the page's own revisions and another
+//! for each page merged into it. A revision that brings back the exact text
+//! of an earlier one, going by `content_hash`, is drawn as reverting to it.
+//! The coordinates are turned into SVG by `templates/wiki/graph.html`.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::queries::{GraphRevision, RevisionStatus};
+
+/// Revisions drawn, the most recent ones.
+pub const LIMIT: i64 = 500;
+
+const ROW_HEIGHT: i64 = 28;
+const LANE_WIDTH: i64 = 24;
+const MARGIN: i64 = 16;
+/// Room to the right of the lanes for revert arcs to bulge into.
+const REVERT_GUTTER: i64 = 48;
+
+pub struct Node {
+    pub id: i64,
+    pub x: i64,
+    pub y: i64,
+    pub created_at: DateTime<Utc>,
+    pub modified_by: String,
+    pub status: RevisionStatus,
+    pub redacted: bool,
+    /// The earlier revision whose text this one restores.
+    pub reverts: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Parent,
+    Revert,
+}
+
+impl EdgeKind {
+    pub fn class(self) -> &'static str {
+        match self {
+            EdgeKind::Parent => "graph-parent",
+            EdgeKind::Revert => "graph-revert",
+        }
+    }
+}
+
+pub struct Edge {
+    pub kind: EdgeKind,
+    /// SVG path data.
+    pub path: String,
+}
+
+pub struct Lane {
+    pub x: i64,
+    /// The page the lane's revisions were written on, `None` for the page's own.
+    pub merged_from: Option<String>,
+}
+
+pub struct Layout {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    pub lanes: Vec<Lane>,
+    /// Where revision labels start.
+    pub label_x: i64,
+    pub height: i64,
+}
+
+/// Lays out `revisions`, which are in id order.
+pub fn layout(revisions: &[GraphRevision]) -> Layout {
+    let mut lanes = vec![Lane {
+        x: MARGIN,
+        merged_from: None,
+    }];
+    let mut nodes: Vec<Node> = Vec::with_capacity(revisions.len());
+    let mut edges = Vec::new();
+    // the last node drawn in each lane
+    let mut lane_tips: Vec<Option<usize>> = vec![None];
+    // the latest node with each text, redacted revisions aside
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+
+    for (row, revision) in revisions.iter().enumerate() {
+        let lane = match revision.merged_from {
+            None => 0,
+            Some(ref page) => match lanes.iter().position(|lane| lane.merged_from.as_ref() == Some(page)) {
+                Some(lane) => lane,
+                None => {
+                    lanes.push(Lane {
+                        x: MARGIN + lanes.len() as i64 * LANE_WIDTH,
+                        merged_from: Some(page.clone()),
+                    });
+                    lane_tips.push(None);
+                    lanes.len() - 1
+                }
+            },
+        };
+        let (x, y) = (lanes[lane].x, MARGIN + row as i64 * ROW_HEIGHT);
+        let parent = lane_tips[lane];
+        if let Some(parent) = parent {
+            let from = &nodes[parent];
+            edges.push(Edge {
+                kind: EdgeKind::Parent,
+                path: format!("M {} {} L {} {}", from.x, from.y, x, y),
+            });
+        }
+
+        // saving the parent's text again isn't a revert
+        let restored = match revision.redacted {
+            true => None,
+            false => by_hash
+                .get(&revision.content_hash[..])
+                .copied()
+                .filter(|&earlier| Some(earlier) != parent),
+        };
+        if let Some(earlier) = restored {
+            edges.push(Edge {
+                kind: EdgeKind::Revert,
+                path: revert_arc(&nodes[earlier], x, y, lanes.len()),
+            });
+        }
+
+        nodes.push(Node {
+            id: revision.id,
+            x,
+            y,
+            created_at: revision.created_at,
+            modified_by: revision.modified_by.clone(),
+            status: revision.status,
+            redacted: revision.redacted,
+            reverts: restored.map(|earlier| nodes[earlier].id),
+        });
+        lane_tips[lane] = Some(row);
+        if !revision.redacted {
+            by_hash.insert(&revision.content_hash, row);
+        }
+    }
+
+    Layout {
+        nodes,
+        edges,
+        label_x: MARGIN + lanes.len() as i64 * LANE_WIDTH + REVERT_GUTTER,
+        height: 2 * MARGIN + (revisions.len().max(1) as i64 - 1) * ROW_HEIGHT,
+        lanes,
+    }
+}
+
+/// A curve from `earlier` out into the gutter and back to (`x`, `y`),
+/// bulging further the more rows it spans so nested reverts stay apart.
+fn revert_arc(earlier: &Node, x: i64, y: i64, lanes: usize) -> String {
+    let gutter = MARGIN + lanes as i64 * LANE_WIDTH;
+    let rows = (y - earlier.y) / ROW_HEIGHT;
+    let bulge = gutter + (8 + 4 * rows).min(REVERT_GUTTER - 8);
+    format!(
+        "M {} {} C {} {} {} {} {} {}",
+        earlier.x, earlier.y, bulge, earlier.y, bulge, y, x, y
+    )
+}
@@ -1,6 +1,39 @@
 use std::borrow::Cow;
 
-const WIKI_PREFIX: &'static str = "/wiki/";
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, PercentEncode, NON_ALPHANUMERIC};
+
+pub const WIKI_PREFIX: &str = "/wiki/";
+const API_WIKI_PREFIX: &str = "/api/v1/wiki/";
+const API_OPENAPI_PATH: &str = "/api/v1/openapi.json";
+const API_DATA_PATH: &str = "/api/v1/data";
+const API_CHANGES_PATH: &str = "/api/v1/changes";
+const API_LINT_PATH: &str = "/api/v1/lint";
+const API_GRAPH_PATH: &str = "/api/v1/graph";
+const API_TITLES_PATH: &str = "/api/v1/titles";
+const GRAPHQL_PATH: &str = "/graphql";
+const METRICS_PATH: &str = "/metrics";
+const READYZ_PATH: &str = "/readyz";
+const MAINTENANCE_PREFIX: &str = "/maintenance/";
+const BLOB_PREFIX: &str = "/attachments/";
+/// Page names under `/wiki/` reserved for generated pages, see `special.rs`.
+pub const SPECIAL_PREFIX: &str = "Special:";
+const STATIC_PREFIX: &str = "/static/";
+
+/// Whether `label` can be used to tag a revision. Labels appear in
+/// `/wiki/:name/tag/:label` paths, so they follow the page name rules.
+pub fn is_valid_tag_label(label: &str) -> bool {
+    is_valid_page_name(label)
+}
+
+/// Whether `name` can be used for a page. Names must survive a round trip
+/// through the router, so path and query separators are not allowed.
+pub fn is_valid_page_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(SPECIAL_PREFIX)
+        && name.trim() == name
+        && !name.contains(&['/', '?', '#'][..])
+        && !name.chars().any(char::is_control)
+}
 
 #[derive(Debug)]
 pub enum RouteError {
@@ -15,31 +48,285 @@ impl std::fmt::Display for RouteError {
 
 impl std::error::Error for RouteError {}
 
+#[derive(Debug, PartialEq)]
 pub enum Route<'a> {
     Root,
     Login,
+    Logout,
+    /// Signing up under `--open-registration`, see `accounts.rs`.
+    Register,
+    /// Following the link mailed to confirm an address.
+    VerifyEmail,
+    /// Asking for a password reset link.
+    ForgotPassword,
+    /// Following a password reset link.
+    ResetPassword,
+    Search,
+    Changes,
+    Review,
+    /// Pages changed since the user last read them.
+    Unread,
+    Settings,
+    /// Turning two-factor authentication on and off, see `two_factor.rs`.
+    TwoFactor,
+    /// The user's logged-in devices.
+    Sessions,
+    /// Site-wide settings for admins, see `appearance.rs`.
+    Admin,
+    /// Rules run over every save, see `edit_filter.rs`.
+    EditFilters,
+    /// Protecting pages by name pattern, see `protection.rs`.
+    Protection,
+    /// Counts and usage for admins, see `stats.rs`.
+    Stats,
+    /// Find and replace across every page, see `replace.rs`.
+    Replace,
+    /// The site logo uploaded at `/admin`.
+    Logo,
+    Maintenance(MaintenanceReport),
+    /// `/about`, `/terms` and `/privacy`, see `footer.rs`.
+    Legal(LegalPage),
+    /// A map of the pages around `?root=`, see `link_graph.rs`.
+    LinkGraph,
+    /// `/wiki/Special:<name>`, see `special.rs`.
+    Special(Cow<'a, str>),
     Wiki(RouteWiki<'a>),
+    Attachment(RouteAttachment<'a>),
+    /// Attachment contents addressed by hash, see `attachments::content_hash`.
+    Blob(RouteBlob<'a>),
+    /// `/attachments/:hash/:filename/preview`, a picture of the first page of
+    /// a PDF, see `previews.rs`.
+    BlobPreview(RouteBlob<'a>),
+    Tag(RouteTag<'a>),
+    ApiWiki(RouteApiWiki<'a>),
+    ApiOpenApi,
+    /// Structured page data, see `data.rs`.
+    ApiData,
+    /// Current revisions newer than a given one, for `wiki sync`.
+    ApiChanges,
+    /// Warnings for posted Markdown, see `lint.rs`.
+    ApiLint,
+    /// The pages around `?root=` and the links between them, see
+    /// `link_graph.rs`.
+    ApiGraph,
+    /// Page names like `?q=`, for the quick switcher, see `titles.rs`.
+    ApiTitles,
+    /// Read-only GraphQL queries, when `--graphql` is on, see `graphql.rs`.
+    GraphQl,
+    /// A compiled-in file from `static/`, see `assets.rs`.
+    Static(Cow<'a, str>),
+    /// Query latencies for Prometheus, see `metrics.rs`.
+    Metrics,
+    /// Whether the database is reachable, for load balancers, see
+    /// `database.rs`.
+    Readyz,
+    /// A path served by a compiled-in plugin.
+    Plugin(Cow<'a, str>),
 }
 
 impl<'a> std::fmt::Display for Route<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.into_uri_path())
+        write!(f, "{}", self.to_uri_path())
     }
 }
 
-#[derive(Debug, Clone)]
+/// Site-wide reports under `/maintenance/`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaintenanceReport {
+    Duplicates,
+    /// The same list as `Special:WantedPages`.
+    Wanted,
+    /// Pages past their `expires:` date, see `front_matter.rs`.
+    Stale,
+}
+
+impl MaintenanceReport {
+    fn slug(self) -> &'static str {
+        match self {
+            MaintenanceReport::Duplicates => "duplicates",
+            MaintenanceReport::Wanted => "wanted",
+            MaintenanceReport::Stale => "stale",
+        }
+    }
+
+    fn from_slug(slug: &str) -> Option<MaintenanceReport> {
+        match slug {
+            "duplicates" => Some(MaintenanceReport::Duplicates),
+            "wanted" => Some(MaintenanceReport::Wanted),
+            "stale" => Some(MaintenanceReport::Stale),
+            _ => None,
+        }
+    }
+}
+
+/// Site pages linked from the footer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LegalPage {
+    About,
+    Terms,
+    Privacy,
+}
+
+impl LegalPage {
+    pub const ALL: &'static [LegalPage] = &[LegalPage::About, LegalPage::Terms, LegalPage::Privacy];
+
+    fn slug(self) -> &'static str {
+        match self {
+            LegalPage::About => "about",
+            LegalPage::Terms => "terms",
+            LegalPage::Privacy => "privacy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct RouteWiki<'a> {
     pub name: Cow<'a, str>,
     pub subview: RouteWikiSubview,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RouteWikiSubview {
     View,
     Edit,
     History,
+    /// The history drawn as a graph, see `revision_graph.rs`.
+    Graph,
     Revision(i64),
+    Annotations(i64),
+    /// Adds a tag to a revision.
+    TagRevision(i64),
+    /// Replaces a revision's text with a tombstone, see
+    /// `Handler::serve_wiki_page_redact_post`.
+    RedactRevision(i64),
     Diff(i64, i64),
+    Move,
+    /// Merges this page into another, see `Handler::serve_wiki_page_merge_post`.
+    Merge,
+    ExportBundle,
+    /// Every published revision as a git bundle, see `git_bundle.rs`.
+    ExportGit,
+    /// Diffs a posted draft against the current revision without saving it.
+    PreviewDiff,
+}
+
+/// A page's attachment list, or one attachment when `filename` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteAttachment<'a> {
+    pub name: Cow<'a, str>,
+    pub filename: Option<Cow<'a, str>>,
+}
+
+impl<'a> RouteAttachment<'a> {
+    pub fn to_list(name: &'a str) -> Route<'a> {
+        Route::Attachment(RouteAttachment {
+            name: name.into(),
+            filename: None,
+        })
+    }
+
+    pub fn to_file(name: &'a str, filename: &'a str) -> Route<'a> {
+        Route::Attachment(RouteAttachment {
+            name: name.into(),
+            filename: Some(filename.into()),
+        })
+    }
+
+    pub fn to_owned(&self) -> RouteAttachment<'static> {
+        RouteAttachment {
+            name: Cow::Owned(self.name[..].to_string()),
+            filename: self.filename.as_ref().map(|f| Cow::Owned(f[..].to_string())),
+        }
+    }
+}
+
+/// `/attachments/:hash/:filename`. The filename picks the content type and
+/// keeps saved files sensibly named; the hash makes the URL immutable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteBlob<'a> {
+    pub content_hash: Cow<'a, str>,
+    pub filename: Cow<'a, str>,
+}
+
+impl<'a> RouteBlob<'a> {
+    pub fn to(content_hash: &'a str, filename: &'a str) -> Route<'a> {
+        Route::Blob(RouteBlob {
+            content_hash: content_hash.into(),
+            filename: filename.into(),
+        })
+    }
+
+    pub fn to_preview(content_hash: &'a str, filename: &'a str) -> Route<'a> {
+        Route::BlobPreview(RouteBlob {
+            content_hash: content_hash.into(),
+            filename: filename.into(),
+        })
+    }
+
+    pub fn to_owned(&self) -> RouteBlob<'static> {
+        RouteBlob {
+            content_hash: Cow::Owned(self.content_hash[..].to_string()),
+            filename: Cow::Owned(self.filename[..].to_string()),
+        }
+    }
+}
+
+/// A tagged revision of a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteTag<'a> {
+    pub name: Cow<'a, str>,
+    pub label: Cow<'a, str>,
+}
+
+impl<'a> RouteTag<'a> {
+    pub fn to(name: &'a str, label: &'a str) -> Route<'a> {
+        Route::Tag(RouteTag {
+            name: name.into(),
+            label: label.into(),
+        })
+    }
+
+    pub fn to_owned(&self) -> RouteTag<'static> {
+        RouteTag {
+            name: Cow::Owned(self.name[..].to_string()),
+            label: Cow::Owned(self.label[..].to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteApiWiki<'a> {
+    pub name: Cow<'a, str>,
+    pub action: RouteApiWikiAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteApiWikiAction {
+    /// The current revision: `GET` like `Revision`, `PUT` replaces it.
+    Page,
+    Append,
+    /// One revision's content, in whichever form the `Accept` header asks for.
+    Revision(i64),
+    /// The current revision's first paragraph and image, for link previews.
+    Summary,
+    /// A link to the current revision that keeps pointing at it.
+    Permalink,
+}
+
+impl<'a> RouteApiWiki<'a> {
+    pub fn to_append(name: &'a str) -> Route<'a> {
+        Route::ApiWiki(RouteApiWiki {
+            name: name.into(),
+            action: RouteApiWikiAction::Append,
+        })
+    }
+
+    pub fn to_owned(&self) -> RouteApiWiki<'static> {
+        RouteApiWiki {
+            name: Cow::Owned(self.name[..].to_string()),
+            action: self.action,
+        }
+    }
 }
 
 impl<'a> RouteWiki<'a> {
@@ -64,6 +351,69 @@ impl<'a> RouteWiki<'a> {
         })
     }
 
+    pub fn to_annotations(name: &'a str, revision: i64) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::Annotations(revision),
+        })
+    }
+
+    pub fn to_diff(name: &'a str, first: i64, second: i64) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::Diff(first, second),
+        })
+    }
+
+    pub fn to_tag_revision(name: &'a str, revision: i64) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::TagRevision(revision),
+        })
+    }
+
+    pub fn to_redact_revision(name: &'a str, revision: i64) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::RedactRevision(revision),
+        })
+    }
+
+    pub fn to_move(name: &'a str) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::Move,
+        })
+    }
+
+    pub fn to_merge(name: &'a str) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::Merge,
+        })
+    }
+
+    pub fn to_export_bundle(name: &'a str) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::ExportBundle,
+        })
+    }
+
+    pub fn to_export_git(name: &'a str) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::ExportGit,
+        })
+    }
+
+    pub fn to_preview_diff(name: &'a str) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::PreviewDiff,
+        })
+    }
+
     pub fn to_history(name: &'a str) -> Route<'a> {
         Route::Wiki(RouteWiki {
             name: name.into(),
@@ -71,6 +421,13 @@ impl<'a> RouteWiki<'a> {
         })
     }
 
+    pub fn to_graph(name: &'a str) -> Route<'a> {
+        Route::Wiki(RouteWiki {
+            name: name.into(),
+            subview: RouteWikiSubview::Graph,
+        })
+    }
+
     pub fn to_owned(&self) -> RouteWiki<'static> {
         RouteWiki {
             name: Cow::Owned(self.name[..].to_string()),
@@ -84,85 +441,595 @@ impl<'a> Route<'a> {
         match self {
             Route::Root => Route::Root,
             Route::Login => Route::Login,
+            Route::Logout => Route::Logout,
+            Route::Register => Route::Register,
+            Route::VerifyEmail => Route::VerifyEmail,
+            Route::ForgotPassword => Route::ForgotPassword,
+            Route::ResetPassword => Route::ResetPassword,
+            Route::Search => Route::Search,
+            Route::Changes => Route::Changes,
+            Route::Review => Route::Review,
+            Route::Unread => Route::Unread,
+            Route::Settings => Route::Settings,
+            Route::TwoFactor => Route::TwoFactor,
+            Route::Sessions => Route::Sessions,
+            Route::Admin => Route::Admin,
+            Route::EditFilters => Route::EditFilters,
+            Route::Protection => Route::Protection,
+            Route::Stats => Route::Stats,
+            Route::Replace => Route::Replace,
+            Route::Logo => Route::Logo,
+            Route::Maintenance(report) => Route::Maintenance(*report),
+            Route::Legal(page) => Route::Legal(*page),
+            Route::LinkGraph => Route::LinkGraph,
+            Route::Special(ref s) => Route::Special(Cow::Owned(s[..].to_string())),
             Route::Wiki(ref s) => Route::Wiki(s.to_owned()),
+            Route::Attachment(ref s) => Route::Attachment(s.to_owned()),
+            Route::Blob(ref s) => Route::Blob(s.to_owned()),
+            Route::BlobPreview(ref s) => Route::BlobPreview(s.to_owned()),
+            Route::Tag(ref s) => Route::Tag(s.to_owned()),
+            Route::ApiWiki(ref s) => Route::ApiWiki(s.to_owned()),
+            Route::ApiOpenApi => Route::ApiOpenApi,
+            Route::ApiData => Route::ApiData,
+            Route::ApiChanges => Route::ApiChanges,
+            Route::ApiLint => Route::ApiLint,
+            Route::ApiGraph => Route::ApiGraph,
+            Route::ApiTitles => Route::ApiTitles,
+            Route::GraphQl => Route::GraphQl,
+            Route::Static(ref f) => Route::Static(Cow::Owned(f[..].to_string())),
+            Route::Metrics => Route::Metrics,
+            Route::Readyz => Route::Readyz,
+            Route::Plugin(ref p) => Route::Plugin(Cow::Owned(p[..].to_string())),
         }
     }
 
-    pub fn into_uri_path(&self) -> String {
+    /// The same route with its page name passed through `f`, or `None` if the
+    /// route has no page name or `f` leaves it unchanged.
+    pub fn with_page_name(&self, f: impl Fn(&str) -> String) -> Option<Route<'static>> {
+        let mut route = self.to_owned();
+        let name = match route {
+            Route::Wiki(ref mut rw) => &mut rw.name,
+            Route::Attachment(ref mut ra) => &mut ra.name,
+            Route::Tag(ref mut rt) => &mut rt.name,
+            Route::ApiWiki(ref mut ra) => &mut ra.name,
+            _ => return None,
+        };
+        let renamed = f(name);
+        if renamed == *name {
+            return None;
+        }
+        *name = renamed.into();
+        Some(route)
+    }
+
+    /// Routes under `/api/v1`, and `/graphql`, which may be called
+    /// cross-origin.
+    pub fn is_api(&self) -> bool {
+        matches!(
+            self,
+            Route::ApiWiki(..)
+                | Route::ApiOpenApi
+                | Route::ApiData
+                | Route::ApiChanges
+                | Route::ApiLint
+                | Route::ApiGraph
+                | Route::ApiTitles
+                | Route::GraphQl
+        )
+    }
+
+    /// Routes answered with a page built on `base.html`, at least for GET.
+    pub fn is_html_page(&self) -> bool {
+        !self.is_api()
+            && !matches!(
+                self,
+                Route::Root
+                    | Route::Blob(..)
+                    | Route::BlobPreview(..)
+                    | Route::Logo
+                    | Route::Static(..)
+                    | Route::Plugin(..)
+                    | Route::Metrics
+                    | Route::Readyz
+            )
+    }
+
+    /// The path this route is served at. Names and other values are
+    /// percent-encoded, `/` included, so `router` gives back the same route.
+    pub fn to_uri_path(&self) -> String {
         match self {
             Route::Root => "/".to_string(),
             Route::Login => "/login".to_string(),
-            Route::Wiki(ref s) => match s.subview {
-                RouteWikiSubview::View => format!("{}{}", WIKI_PREFIX, s.name),
-                RouteWikiSubview::Edit => format!("{}{}/edit", WIKI_PREFIX, s.name),
-                RouteWikiSubview::History => format!("{}{}/history", WIKI_PREFIX, s.name),
-                RouteWikiSubview::Revision(r) => format!("{}{}/rev/{}", WIKI_PREFIX, s.name, r),
-                RouteWikiSubview::Diff(a, b) => format!("{}{}/diff/{}-{}", WIKI_PREFIX, s.name, a, b),
+            Route::Logout => "/logout".to_string(),
+            Route::Register => "/register".to_string(),
+            Route::VerifyEmail => "/verify".to_string(),
+            Route::ForgotPassword => "/forgot".to_string(),
+            Route::ResetPassword => "/reset".to_string(),
+            Route::Search => "/search".to_string(),
+            Route::Changes => "/changes".to_string(),
+            Route::Review => "/review".to_string(),
+            Route::Unread => "/unread".to_string(),
+            Route::Settings => "/settings".to_string(),
+            Route::TwoFactor => "/settings/two-factor".to_string(),
+            Route::Sessions => "/settings/sessions".to_string(),
+            Route::Admin => "/admin".to_string(),
+            Route::EditFilters => "/admin/filters".to_string(),
+            Route::Protection => "/admin/protection".to_string(),
+            Route::Stats => "/admin/stats".to_string(),
+            Route::Replace => "/admin/replace".to_string(),
+            Route::Logo => "/logo".to_string(),
+            Route::Maintenance(report) => format!("{}{}", MAINTENANCE_PREFIX, report.slug()),
+            Route::Legal(page) => format!("/{}", page.slug()),
+            Route::LinkGraph => "/graph".to_string(),
+            Route::Special(ref s) => format!("{}{}{}", WIKI_PREFIX, SPECIAL_PREFIX, seg(s)),
+            Route::Wiki(ref s) => {
+                let name = seg(&s.name);
+                match s.subview {
+                    RouteWikiSubview::View => format!("{}{}", WIKI_PREFIX, name),
+                    RouteWikiSubview::Edit => format!("{}{}/edit", WIKI_PREFIX, name),
+                    RouteWikiSubview::History => format!("{}{}/history", WIKI_PREFIX, name),
+                    RouteWikiSubview::Graph => format!("{}{}/graph", WIKI_PREFIX, name),
+                    RouteWikiSubview::Revision(r) => format!("{}{}/rev/{}", WIKI_PREFIX, name, r),
+                    RouteWikiSubview::Annotations(r) => {
+                        format!("{}{}/rev/{}/annotations", WIKI_PREFIX, name, r)
+                    }
+                    RouteWikiSubview::TagRevision(r) => format!("{}{}/rev/{}/tag", WIKI_PREFIX, name, r),
+                    RouteWikiSubview::RedactRevision(r) => format!("{}{}/rev/{}/redact", WIKI_PREFIX, name, r),
+                    RouteWikiSubview::Diff(a, b) => format!("{}{}/diff/{}..{}", WIKI_PREFIX, name, a, b),
+                    RouteWikiSubview::Move => format!("{}{}/move", WIKI_PREFIX, name),
+                    RouteWikiSubview::Merge => format!("{}{}/merge", WIKI_PREFIX, name),
+                    RouteWikiSubview::ExportBundle => format!("{}{}/export-bundle", WIKI_PREFIX, name),
+                    RouteWikiSubview::ExportGit => format!("{}{}/export.git", WIKI_PREFIX, name),
+                    RouteWikiSubview::PreviewDiff => format!("{}{}/preview-diff", WIKI_PREFIX, name),
+                }
+            }
+            Route::Attachment(ref s) => match s.filename {
+                Some(ref f) => format!("{}{}/attachments/{}", WIKI_PREFIX, seg(&s.name), seg(f)),
+                None => format!("{}{}/attachments", WIKI_PREFIX, seg(&s.name)),
             },
+            Route::Blob(ref s) => format!("{}{}/{}", BLOB_PREFIX, seg(&s.content_hash), seg(&s.filename)),
+            Route::BlobPreview(ref s) => {
+                format!("{}{}/{}/preview", BLOB_PREFIX, seg(&s.content_hash), seg(&s.filename))
+            }
+            Route::Tag(ref s) => format!("{}{}/tag/{}", WIKI_PREFIX, seg(&s.name), seg(&s.label)),
+            Route::ApiWiki(ref s) => {
+                let name = seg(&s.name);
+                match s.action {
+                    RouteApiWikiAction::Page => format!("{}{}", API_WIKI_PREFIX, name),
+                    RouteApiWikiAction::Append => format!("{}{}/append", API_WIKI_PREFIX, name),
+                    RouteApiWikiAction::Revision(r) => format!("{}{}/rev/{}", API_WIKI_PREFIX, name, r),
+                    RouteApiWikiAction::Summary => format!("{}{}/summary", API_WIKI_PREFIX, name),
+                    RouteApiWikiAction::Permalink => format!("{}{}/permalink", API_WIKI_PREFIX, name),
+                }
+            }
+            Route::ApiOpenApi => API_OPENAPI_PATH.to_string(),
+            Route::ApiData => API_DATA_PATH.to_string(),
+            Route::ApiChanges => API_CHANGES_PATH.to_string(),
+            Route::ApiLint => API_LINT_PATH.to_string(),
+            Route::ApiGraph => API_GRAPH_PATH.to_string(),
+            Route::ApiTitles => API_TITLES_PATH.to_string(),
+            Route::GraphQl => GRAPHQL_PATH.to_string(),
+            Route::Static(ref f) => format!("{}{}", STATIC_PREFIX, seg(f)),
+            Route::Metrics => METRICS_PATH.to_string(),
+            Route::Readyz => READYZ_PATH.to_string(),
+            Route::Plugin(ref p) => p.to_string(),
         }
     }
 
+    /// Matches a request path, still percent-encoded, against the routes.
+    /// Each segment is decoded on its own, so `%2F` stays inside a name
+    /// rather than splitting it. A trailing slash is ignored; empty segments
+    /// anywhere else, including empty names, match nothing.
     pub fn router(path: &'a str) -> std::result::Result<Self, RouteError> {
-        if path == "/" {
-            return Ok(Route::Root);
+        let segments = split_path(path).ok_or(RouteError::NotFound)?;
+        let keys: Vec<&str> = segments.iter().map(|s| &s[..]).collect();
+        let at = |i: usize| segments[i].clone();
+
+        let route = match keys[..] {
+            [] => Route::Root,
+            ["login"] => Route::Login,
+            ["logout"] => Route::Logout,
+            ["register"] => Route::Register,
+            ["verify"] => Route::VerifyEmail,
+            ["forgot"] => Route::ForgotPassword,
+            ["reset"] => Route::ResetPassword,
+            ["search"] => Route::Search,
+            ["changes"] => Route::Changes,
+            ["review"] => Route::Review,
+            ["unread"] => Route::Unread,
+            ["settings"] => Route::Settings,
+            ["settings", "two-factor"] => Route::TwoFactor,
+            ["settings", "sessions"] => Route::Sessions,
+            ["admin"] => Route::Admin,
+            ["admin", "filters"] => Route::EditFilters,
+            ["admin", "protection"] => Route::Protection,
+            ["admin", "stats"] => Route::Stats,
+            ["admin", "replace"] => Route::Replace,
+            ["logo"] => Route::Logo,
+            ["about"] => Route::Legal(LegalPage::About),
+            ["terms"] => Route::Legal(LegalPage::Terms),
+            ["privacy"] => Route::Legal(LegalPage::Privacy),
+            ["graph"] => Route::LinkGraph,
+            ["metrics"] => Route::Metrics,
+            ["readyz"] => Route::Readyz,
+            ["api", "v1", "openapi.json"] => Route::ApiOpenApi,
+            ["api", "v1", "data"] => Route::ApiData,
+            ["api", "v1", "changes"] => Route::ApiChanges,
+            ["api", "v1", "lint"] => Route::ApiLint,
+            ["api", "v1", "graph"] => Route::ApiGraph,
+            ["api", "v1", "titles"] => Route::ApiTitles,
+            ["graphql"] => Route::GraphQl,
+            ["api", "v1", "wiki", _, ref rest @ ..] => {
+                let action = match rest[..] {
+                    [] => RouteApiWikiAction::Page,
+                    ["append"] => RouteApiWikiAction::Append,
+                    ["rev", rev] => RouteApiWikiAction::Revision(number(rev)?),
+                    ["summary"] => RouteApiWikiAction::Summary,
+                    ["permalink"] => RouteApiWikiAction::Permalink,
+                    _ => return Err(RouteError::NotFound),
+                };
+                Route::ApiWiki(RouteApiWiki { name: at(3), action })
+            }
+            ["attachments", _, _] => Route::Blob(RouteBlob {
+                content_hash: at(1),
+                filename: at(2),
+            }),
+            ["attachments", _, _, "preview"] => Route::BlobPreview(RouteBlob {
+                content_hash: at(1),
+                filename: at(2),
+            }),
+            ["maintenance", slug] => {
+                Route::Maintenance(MaintenanceReport::from_slug(slug).ok_or(RouteError::NotFound)?)
+            }
+            ["static", _] => Route::Static(at(1)),
+            ["wiki", name, ref rest @ ..] if name.starts_with(SPECIAL_PREFIX) => {
+                if !rest.is_empty() {
+                    return Err(RouteError::NotFound);
+                }
+                Route::Special(strip_cow(at(1), SPECIAL_PREFIX.len()))
+            }
+            ["wiki", _, ref rest @ ..] => {
+                let name = at(1);
+                let subview = match rest[..] {
+                    [] => RouteWikiSubview::View,
+                    ["edit"] => RouteWikiSubview::Edit,
+                    ["history"] => RouteWikiSubview::History,
+                    ["graph"] => RouteWikiSubview::Graph,
+                    ["move"] => RouteWikiSubview::Move,
+                    ["merge"] => RouteWikiSubview::Merge,
+                    ["export-bundle"] => RouteWikiSubview::ExportBundle,
+                    ["export.git"] => RouteWikiSubview::ExportGit,
+                    ["preview-diff"] => RouteWikiSubview::PreviewDiff,
+                    ["rev", rev] => RouteWikiSubview::Revision(number(rev)?),
+                    ["rev", rev, "annotations"] => RouteWikiSubview::Annotations(number(rev)?),
+                    ["rev", rev, "tag"] => RouteWikiSubview::TagRevision(number(rev)?),
+                    ["rev", rev, "redact"] => RouteWikiSubview::RedactRevision(number(rev)?),
+                    ["diff", revs] => {
+                        let (first, second) = revs.split_once("..").ok_or(RouteError::NotFound)?;
+                        RouteWikiSubview::Diff(revision_id(first)?, revision_id(second)?)
+                    }
+                    ["attachments"] => return Ok(Route::Attachment(RouteAttachment { name, filename: None })),
+                    ["attachments", _] => {
+                        return Ok(Route::Attachment(RouteAttachment {
+                            name,
+                            filename: Some(at(3)),
+                        }))
+                    }
+                    ["tag", _] => return Ok(Route::Tag(RouteTag { name, label: at(3) })),
+                    _ => return Err(RouteError::NotFound),
+                };
+                Route::Wiki(RouteWiki { name, subview })
+            }
+            _ => return Err(RouteError::NotFound),
+        };
+        Ok(route)
+    }
+}
+
+/// Where a path from before a route changed form lives now, for a
+/// permanent redirect: diffs were `/wiki/:name/diff/:a-:b` before they
+/// were `/wiki/:name/diff/:a..:b`.
+pub fn moved(path: &str) -> Option<Route<'static>> {
+    let (page, revs) = path.trim_end_matches('/').rsplit_once("/diff/")?;
+    let (first, second) = revs.split_once('-')?;
+    let (first, second) = (revision_id(first).ok()?, revision_id(second).ok()?);
+    match Route::router(page).ok()? {
+        Route::Wiki(rw) if rw.subview == RouteWikiSubview::View => {
+            Some(RouteWiki::to_diff(&rw.name, first, second).to_owned())
         }
+        _ => None,
+    }
+}
+
+/// Characters escaped in a path segment: everything but unreserved
+/// characters and a few sub-delimiters that read better left alone.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'!')
+    .remove(b'*')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b':')
+    .remove(b'@')
+    .remove(b',')
+    .remove(b';')
+    .remove(b'=')
+    .remove(b'+')
+    .remove(b'$');
+
+fn seg(value: &str) -> PercentEncode<'_> {
+    utf8_percent_encode(value, SEGMENT)
+}
+
+/// Splits `/a/b%2Fc/` into `["a", "b/c"]`. `None` for paths that aren't
+/// absolute, have empty segments or don't decode to UTF-8.
+fn split_path(path: &str) -> Option<Vec<Cow<'_, str>>> {
+    let path = path.strip_prefix('/')?;
+    if path.is_empty() {
+        return Some(Vec::new());
+    }
+    let path = path.strip_suffix('/').unwrap_or(path);
+    path.split('/')
+        .map(|segment| match segment {
+            "" => None,
+            _ => percent_decode_str(segment).decode_utf8().ok(),
+        })
+        .collect()
+}
+
+fn number(segment: &str) -> Result<i64, RouteError> {
+    segment.parse().map_err(|_| RouteError::NotFound)
+}
+
+/// Revision ids start at 1.
+fn revision_id(segment: &str) -> Result<i64, RouteError> {
+    number(segment).ok().filter(|&id| id > 0).ok_or(RouteError::NotFound)
+}
 
-        if path == "/login" {
-            return Ok(Route::Login);
+/// `value` without its first `len` bytes, borrowing where `value` does.
+fn strip_cow(value: Cow<'_, str>, len: usize) -> Cow<'_, str> {
+    match value {
+        Cow::Borrowed(b) => Cow::Borrowed(&b[len..]),
+        Cow::Owned(o) => Cow::Owned(o[len..].to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small xorshift generator, so the property tests are reproducible
+    /// without pulling in a property testing crate.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
         }
 
-        if path.starts_with(WIKI_PREFIX) {
-            let mut doc_paths = path[WIKI_PREFIX.len()..].split('/');
-            let name = doc_paths.next().unwrap();
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
 
-            match (doc_paths.next(), doc_paths.next()) {
-                (Some("edit"), None) => {
-                    return Ok(Route::Wiki(RouteWiki {
-                        name: name.into(),
-                        subview: RouteWikiSubview::Edit,
-                    }));
-                }
-                (Some("history"), None) => {
-                    return Ok(Route::Wiki(RouteWiki {
-                        name: name.into(),
-                        subview: RouteWikiSubview::History,
-                    }));
-                }
-                (Some("rev"), Some(rev)) => {
-                    if doc_paths.next().is_some() {
-                        return Err(RouteError::NotFound);
-                    }
-                    let r: i64 = match rev.parse() {
-                        Ok(r) => r,
-                        Err(..) => return Err(RouteError::NotFound),
-                    };
-                    return Ok(Route::Wiki(RouteWiki {
-                        name: name.into(),
-                        subview: RouteWikiSubview::Revision(r),
-                    }));
-                }
-                (Some("diff"), Some(diffrevs)) => {
-                    let mut parts = diffrevs.splitn(2, '-');
-                    let first = parts.next().ok_or_else(|| RouteError::NotFound)?.parse().map_err(|_| RouteError::NotFound)?;
-                    let second = parts.next().ok_or_else(|| RouteError::NotFound)?.parse().map_err(|_| RouteError::NotFound)?;
-                    if doc_paths.next().is_some() {
-                        return Err(RouteError::NotFound);
-                    }
-                    return Ok(Route::Wiki(RouteWiki {
-                        name: name.into(),
-                        subview: RouteWikiSubview::Diff(first, second),
-                    }));
-                }
-                (Some(_), _) => return Err(RouteError::NotFound),
-                (None, _) => {
-                    return Ok(Route::Wiki(RouteWiki {
-                        name: name.into(),
-                        subview: RouteWikiSubview::View,
-                    }));
+        fn value(&mut self) -> String {
+            const CHARS: &[&str] = &[
+                "a", "Z", "0", " ", "/", "%", "%2F", "?", "#", "-", ".", ":", "+", "&", "\"", "é", "e\u{301}", "日",
+                "😀", "\\",
+            ];
+            let len = 1 + self.below(8);
+            (0..len).map(|_| CHARS[self.below(CHARS.len())]).collect()
+        }
+
+        /// A page name the router can produce: not empty, not special.
+        fn name(&mut self) -> String {
+            loop {
+                let name = self.value();
+                if !name.starts_with(SPECIAL_PREFIX) {
+                    return name;
                 }
+            }
+        }
+
+        fn number(&mut self) -> i64 {
+            match self.below(4) {
+                0 => 0,
+                1 => i64::MIN,
+                2 => i64::MAX,
+                _ => self.next() as i64,
+            }
+        }
+
+        fn revision_id(&mut self) -> i64 {
+            match self.below(3) {
+                0 => 1,
+                1 => i64::MAX,
+                _ => (self.next() >> 1).max(1) as i64,
+            }
+        }
+
+        fn route(&mut self) -> Route<'static> {
+            let subview = match self.below(14) {
+                0 => RouteWikiSubview::View,
+                1 => RouteWikiSubview::Edit,
+                2 => RouteWikiSubview::History,
+                3 => RouteWikiSubview::Revision(self.number()),
+                4 => RouteWikiSubview::Annotations(self.number()),
+                5 => RouteWikiSubview::TagRevision(self.number()),
+                6 => RouteWikiSubview::Diff(self.revision_id(), self.revision_id()),
+                7 => RouteWikiSubview::Move,
+                8 => RouteWikiSubview::Merge,
+                9 => RouteWikiSubview::ExportBundle,
+                10 => RouteWikiSubview::ExportGit,
+                11 => RouteWikiSubview::RedactRevision(self.number()),
+                12 => RouteWikiSubview::Graph,
+                _ => RouteWikiSubview::PreviewDiff,
+            };
+            let action = match self.below(5) {
+                0 => RouteApiWikiAction::Page,
+                1 => RouteApiWikiAction::Append,
+                2 => RouteApiWikiAction::Summary,
+                3 => RouteApiWikiAction::Permalink,
+                _ => RouteApiWikiAction::Revision(self.number()),
             };
+            match self.below(41) {
+                0 => Route::Root,
+                1 => Route::Login,
+                2 => Route::Logout,
+                3 => Route::Search,
+                4 => Route::Changes,
+                5 => Route::Review,
+                6 => Route::Settings,
+                7 => Route::Maintenance(MaintenanceReport::Duplicates),
+                8 if self.below(2) == 0 => Route::Maintenance(MaintenanceReport::Wanted),
+                8 => Route::Maintenance(MaintenanceReport::Stale),
+                9 => Route::Special(self.value().into()),
+                10 => Route::Wiki(RouteWiki {
+                    name: self.name().into(),
+                    subview,
+                }),
+                11 => Route::Attachment(RouteAttachment {
+                    name: self.name().into(),
+                    filename: if self.below(2) == 0 { None } else { Some(self.value().into()) },
+                }),
+                12 => Route::Blob(RouteBlob {
+                    content_hash: self.value().into(),
+                    filename: self.value().into(),
+                }),
+                13 => Route::Tag(RouteTag {
+                    name: self.name().into(),
+                    label: self.value().into(),
+                }),
+                14 => Route::ApiWiki(RouteApiWiki {
+                    name: self.value().into(),
+                    action,
+                }),
+                15 => Route::ApiOpenApi,
+                16 => Route::ApiData,
+                17 => Route::ApiChanges,
+                18 => Route::Static(self.value().into()),
+                19 => Route::BlobPreview(RouteBlob {
+                    content_hash: self.value().into(),
+                    filename: self.value().into(),
+                }),
+                20 => Route::Admin,
+                21 => Route::Logo,
+                22 => Route::Unread,
+                23 => Route::ApiLint,
+                24 => Route::EditFilters,
+                25 => Route::Protection,
+                26 => Route::Legal(LegalPage::ALL[self.below(LegalPage::ALL.len())]),
+                27 => Route::Readyz,
+                28 => Route::LinkGraph,
+                29 => Route::ApiGraph,
+                30 => Route::Stats,
+                31 => Route::ApiTitles,
+                32 => Route::Register,
+                33 => Route::VerifyEmail,
+                34 => Route::ForgotPassword,
+                35 => Route::ResetPassword,
+                36 => Route::TwoFactor,
+                37 => Route::Sessions,
+                38 => Route::GraphQl,
+                39 => Route::Replace,
+                _ => Route::Metrics,
+            }
+        }
+    }
+
+    #[test]
+    fn every_route_round_trips() {
+        let mut rng = Rng(0x5eed);
+        for _ in 0..20_000 {
+            let route = rng.route();
+            let path = route.to_uri_path();
+            assert_eq!(Route::router(&path).ok(), Some(route), "path {:?}", path);
         }
+    }
+
+    #[test]
+    fn trailing_slash_round_trips() {
+        let mut rng = Rng(0xf00d);
+        for _ in 0..5_000 {
+            let route = rng.route();
+            let path = format!("{}/", route.to_uri_path());
+            if path == "//" {
+                continue;
+            }
+            assert_eq!(Route::router(&path).ok(), Some(route), "path {:?}", path);
+        }
+    }
+
+    #[test]
+    fn arbitrary_paths_never_panic() {
+        let mut rng = Rng(0xbad);
+        let parts = ["/", "wiki", "api", "v1", "rev", "diff", "-", "..", "1", "%", "%2F", "%FF", "Special:", "é", ""];
+        for _ in 0..50_000 {
+            let len = rng.below(8);
+            let path: String = (0..len).map(|_| parts[rng.below(parts.len())]).collect();
+            let _ = Route::router(&path);
+        }
+    }
+
+    #[test]
+    fn edge_cases() {
+        assert_eq!(Route::router("/").ok(), Some(Route::Root));
+        assert_eq!(Route::router("/search/").ok(), Some(Route::Search));
+        assert_eq!(Route::router("/wiki/Home/").ok(), Some(RouteWiki::to("Home")));
+        assert_eq!(
+            Route::router("/wiki/a%2Fb/edit").ok(),
+            Some(RouteWiki::to_edit("a/b"))
+        );
+        assert_eq!(
+            Route::router("/wiki/Home/diff/2..1").ok(),
+            Some(RouteWiki::to_diff("Home", 2, 1))
+        );
+
+        for path in &[
+            "",
+            "//",
+            "/wiki",
+            "/wiki/",
+            "/wiki//edit",
+            "/wiki/Home//",
+            "/wiki/Home/edit/extra",
+            "/wiki/Home/rev/1/annotations/extra",
+            "/wiki/Home/diff/1",
+            "/wiki/Home/diff/1-2",
+            "/wiki/Home/diff/1..",
+            "/wiki/Home/diff/0..2",
+            "/wiki/Home/diff/-1..2",
+            "/wiki/Home/diff/1..2..3",
+            "/wiki/Home/diff/1..99999999999999999999",
+            "/wiki/Home/rev/x",
+            "/wiki/Special:Random/edit",
+            "/wiki/%FF",
+            "/api/v1/wiki/",
+            "/api/v1/wiki/Home/rev",
+            "/attachments/hash",
+            "/static/",
+            "/static/a/b",
+            "/maintenance/unknown",
+            "/search/extra",
+            "/graph/Home",
+            "/api/v1/graph/Home",
+        ] {
+            assert!(Route::router(path).is_err(), "path {:?}", path);
+        }
+    }
 
-        return Err(RouteError::NotFound);
+    #[test]
+    fn old_diff_paths_move() {
+        assert_eq!(moved("/wiki/Home/diff/1-2"), Some(RouteWiki::to_diff("Home", 1, 2)));
+        assert_eq!(moved("/wiki/a%2Fdiff%2F/diff/3-2/"), Some(RouteWiki::to_diff("a/diff/", 3, 2)));
+        for path in &["/wiki/Home/diff/1..2", "/wiki/Home/diff/-1-2", "/wiki/Home/diff/1-", "/wiki/Home/edit/diff/1-2", "/api/v1/wiki/Home/diff/1-2"] {
+            assert_eq!(moved(path), None, "path {:?}", path);
+        }
     }
 }
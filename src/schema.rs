@@ -0,0 +1,252 @@
+//! Checks the database against `provision_database.sql` at startup, so a
+//! database provisioned from an older version fails with a list of what's
+//! missing rather than with whichever statement `Queries::prepare` happens
+//! to reach first.
+//!
+//! Only tables and columns the code expects are compared; extra ones are
+//! fine.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::DynResult;
+
+pub const PROVISION_SQL: &str = include_str!("../provision_database.sql");
+
+/// Words that end a column's type in a column definition.
+const CONSTRAINT_WORDS: &[&str] = &["NOT", "NULL", "PRIMARY", "UNIQUE", "DEFAULT", "REFERENCES", "CHECK"];
+
+/// Lines in a `CREATE TABLE` that aren't column definitions.
+const TABLE_CONSTRAINTS: &[&str] = &["PRIMARY", "UNIQUE", "CHECK", "CONSTRAINT", "FOREIGN"];
+
+pub struct Mismatch {
+    problems: Vec<String>,
+}
+
+// `main` reports errors with `Debug`, which would print the list on one line
+impl fmt::Debug for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "the database doesn't match provision_database.sql:")?;
+        for problem in &self.problems {
+            writeln!(f, "  {}", problem)?;
+        }
+        write!(f, "provision a new database or add these by hand")
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Fails with a `Mismatch` listing every expected table or column that is
+/// missing or has another type.
+pub async fn check(db: &tokio_postgres::Client) -> DynResult<()> {
+    let rows = db
+        .query(
+            r#"
+                SELECT table_name::text, column_name::text, data_type::text
+                FROM information_schema.columns
+                WHERE table_schema = current_schema()
+            "#,
+            &[],
+        )
+        .await?;
+    let mut actual: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for row in &rows {
+        actual
+            .entry(row.try_get(0)?)
+            .or_default()
+            .insert(row.try_get(1)?, row.try_get(2)?);
+    }
+
+    let mut problems = Vec::new();
+    for (table, columns) in expected_tables() {
+        let actual_columns = match actual.get(table) {
+            Some(actual_columns) => actual_columns,
+            None => {
+                problems.push(format!("missing table {}", table));
+                continue;
+            }
+        };
+        for (column, expected_type) in columns {
+            match actual_columns.get(column) {
+                None => problems.push(format!("missing column {}.{} ({})", table, column, expected_type)),
+                Some(actual_type) if *actual_type != expected_type => problems.push(format!(
+                    "column {}.{} is {}, expected {}",
+                    table, column, actual_type, expected_type
+                )),
+                Some(_) => (),
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Mismatch { problems }.into())
+    }
+}
+
+/// Every table created by `provision_database.sql`, with its columns and
+/// their types as `information_schema` names them.
+fn expected_tables() -> Vec<(&'static str, Vec<(&'static str, String)>)> {
+    tables_in(PROVISION_SQL)
+}
+
+/// The tables `sql` creates. A column definition may go on over several
+/// lines; it ends at a comma outside parentheses.
+fn tables_in(sql: &str) -> Vec<(&str, Vec<(&str, String)>)> {
+    let mut tables = Vec::new();
+    let mut lines = sql.lines();
+    while let Some(line) = lines.next() {
+        let table = match line
+            .strip_prefix("CREATE TABLE ")
+            .and_then(|rest| rest.strip_suffix(" ("))
+        {
+            Some(table) => table.trim(),
+            None => continue,
+        };
+        let mut columns = Vec::new();
+        let mut definition: Vec<&str> = Vec::new();
+        let mut depth = 0;
+        for line in lines.by_ref() {
+            let line = line.split("--").next().unwrap_or("").trim();
+            if depth == 0 && line.starts_with(')') {
+                break;
+            }
+            depth += line.matches('(').count() as isize - line.matches(')').count() as isize;
+            definition.extend(line.split_whitespace());
+            if depth == 0 && line.ends_with(',') {
+                columns.extend(column(&definition));
+                definition.clear();
+            }
+        }
+        columns.extend(column(&definition));
+        tables.push((table, columns));
+    }
+    tables
+}
+
+/// The name and type of the column `definition` declares, given as its
+/// words, or `None` if it's a table constraint.
+fn column<'a>(definition: &[&'a str]) -> Option<(&'a str, String)> {
+    let (&name, rest) = definition.split_first()?;
+    if TABLE_CONSTRAINTS.contains(&name) {
+        return None;
+    }
+    let type_words: Vec<&str> = rest
+        .iter()
+        .map(|word| word.trim_end_matches(','))
+        .take_while(|word| !CONSTRAINT_WORDS.contains(word))
+        .collect();
+    Some((name, information_schema_type(&type_words.join(" "))))
+}
+
+fn information_schema_type(sql_type: &str) -> String {
+    let sql_type = sql_type.to_ascii_lowercase();
+    match &sql_type[..] {
+        "bigserial" => "bigint".to_string(),
+        "serial" => "integer".to_string(),
+        _ => sql_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn created_tables() -> Vec<&'static str> {
+        PROVISION_SQL
+            .lines()
+            .filter(|line| line.starts_with("CREATE TABLE"))
+            .map(|line| line.split_whitespace().nth(2).unwrap())
+            .collect()
+    }
+
+    fn columns_of(table: &str) -> Vec<(&'static str, String)> {
+        let tables = expected_tables();
+        let (_, columns) = tables.into_iter().find(|(name, _)| *name == table).unwrap();
+        columns
+    }
+
+    fn expect(columns: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+        columns.iter().map(|&(name, sql_type)| (name, sql_type.to_string())).collect()
+    }
+
+    #[test]
+    fn every_provisioned_table_is_read() {
+        let tables = expected_tables();
+        let names: Vec<&str> = tables.iter().map(|&(name, _)| name).collect();
+        assert_eq!(names, created_tables());
+        for (table, columns) in &tables {
+            assert!(!columns.is_empty(), "{}", table);
+            for (column, sql_type) in columns {
+                assert!(!sql_type.is_empty(), "{}.{}", table, column);
+                assert!(!sql_type.contains(&['(', ')', ','][..]), "{}.{} is {}", table, column, sql_type);
+            }
+        }
+    }
+
+    #[test]
+    fn every_provisioned_table_is_dropped_first() {
+        for table in created_tables() {
+            let drop = format!("DROP TABLE {} CASCADE;", table);
+            assert!(PROVISION_SQL.contains(&drop), "{} is never dropped", table);
+        }
+    }
+
+    #[test]
+    fn provisioned_column_types() {
+        assert_eq!(
+            columns_of("document"),
+            expect(&[
+                ("id", "bigint"),
+                ("name", "character varying"),
+                ("last_modified", "timestamp with time zone"),
+                ("current_revision_id", "bigint"),
+                ("redirect_to", "character varying"),
+                ("expires_on", "date"),
+            ])
+        );
+        // the primary key line isn't a column
+        assert_eq!(
+            columns_of("recovery_code"),
+            expect(&[("user_id", "bigint"), ("code_hash", "character varying")])
+        );
+        assert_eq!(
+            columns_of("attachment_preview"),
+            expect(&[("content_hash", "character varying"), ("kind", "character varying"), ("data", "bytea")])
+        );
+        assert_eq!(columns_of("site_appearance")[0], ("id", "boolean".to_string()));
+    }
+
+    #[test]
+    fn definitions_over_several_lines() {
+        let sql = "\
+CREATE TABLE ledger (
+    id BIGSERIAL PRIMARY KEY,
+    -- a comment, with (an unclosed parenthesis
+    amount INTEGER NOT NULL
+        CHECK (amount > 0),
+    label character
+        varying NULL,
+    CONSTRAINT ledger_label UNIQUE (
+        label,
+        amount
+    ),
+    note TEXT
+);
+";
+        assert_eq!(
+            tables_in(sql),
+            vec![(
+                "ledger",
+                expect(&[("id", "bigint"), ("amount", "integer"), ("label", "character varying"), ("note", "text")])
+            )]
+        );
+    }
+}
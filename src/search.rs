@@ -0,0 +1,334 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio_postgres::types::ToSql;
+
+use crate::{DynResult, HandlerInner};
+
+/// The document as searched: its name weighted as a title (`A`) above its
+/// text (`D`), so `SearchWeights` can rank the two apart.
+const DOCUMENT_VECTOR: &str = "setweight(to_tsvector('english', document.name), 'A') \
+    || setweight(to_tsvector('english', document_history.document_data), 'D')";
+
+/// Days over which the recency boost halves.
+const RECENCY_DAYS: f64 = 30.0;
+
+#[derive(Debug)]
+pub enum SearchQueryError {
+    BadDate(String),
+    BadTag(String),
+}
+
+impl std::fmt::Display for SearchQueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SearchQueryError::BadDate(d) => write!(f, "invalid date {:?}, expected YYYY-MM-DD", d),
+            SearchQueryError::BadTag(t) => write!(f, "invalid tag {:?}, expected a value or key=value", t),
+        }
+    }
+}
+
+impl std::error::Error for SearchQueryError {}
+
+/// How search results are ranked, set with `--search-weights`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchWeights {
+    /// Weight of a match in the page name.
+    pub title: f32,
+    /// Weight of a match in the text.
+    pub body: f32,
+    /// How much more a page edited just now ranks than an old one with the
+    /// same matches; the boost halves every `RECENCY_DAYS` days. 0 ranks
+    /// by matches alone.
+    pub recency: f32,
+}
+
+impl Default for SearchWeights {
+    fn default() -> SearchWeights {
+        // Postgres' own weights for A and D
+        SearchWeights {
+            title: 1.0,
+            body: 0.1,
+            recency: 0.5,
+        }
+    }
+}
+
+impl FromStr for SearchWeights {
+    type Err = String;
+
+    /// Parses `title=1,body=0.1,recency=0.5`; keys left out keep their
+    /// default.
+    fn from_str(s: &str) -> Result<SearchWeights, String> {
+        let mut weights = SearchWeights::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=weight, got {:?}", pair))?;
+            let value: f32 = value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|v: &f32| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| format!("expected a weight of 0 or more for {}, got {:?}", key, value))?;
+            match key.trim() {
+                "title" => weights.title = value,
+                "body" => weights.body = value,
+                "recency" => weights.recency = value,
+                other => return Err(format!("unknown weight {:?}, expected title, body or recency", other)),
+            }
+        }
+        Ok(weights)
+    }
+}
+
+/// The pieces of the search statement built by `SearchQuery::to_sql`.
+pub struct SearchSql {
+    pub predicates: String,
+    pub order_by: String,
+    pub params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+/// A current page matching a search.
+pub struct Hit {
+    pub name: String,
+    pub last_modified_at: DateTime<Utc>,
+    pub last_modified_by: String,
+}
+
+/// A `tag:` filter, matched against the pairs in a page's data block, see
+/// `data.rs`: `tag:type=project` wants that pair, `tag:design` the value
+/// under any key.
+#[derive(Debug, PartialEq)]
+pub struct TagFilter {
+    pub key: Option<String>,
+    pub value: String,
+}
+
+/// A parsed search string such as `rust "error handling" ns:projects tag:type=project author:alice after:2021-01-01`.
+///
+/// Recognised `key:value` tokens become filters, text in double quotes an
+/// exact phrase; everything else is free text.
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    pub text: Vec<String>,
+    /// Matched with `phraseto_tsquery`, so the words must appear in order.
+    pub phrases: Vec<String>,
+    pub namespace: Option<String>,
+    pub tag: Option<TagFilter>,
+    pub author: Option<String>,
+    pub before: Option<NaiveDate>,
+    pub after: Option<NaiveDate>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<SearchQuery, SearchQueryError> {
+        let mut out = SearchQuery::default();
+        let mut rest = query;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            // an unclosed quote runs to the end
+            if let Some(quoted) = rest.strip_prefix('"') {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let phrase = quoted[..end].trim();
+                if !phrase.is_empty() {
+                    out.phrases.push(phrase.to_string());
+                }
+                rest = quoted.get(end + 1..).unwrap_or("");
+                continue;
+            }
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (token, after) = rest.split_at(end);
+            rest = after;
+
+            let handled = match token.split_once(':') {
+                Some((key, value)) if !value.is_empty() => out.apply_filter(key, value)?,
+                _ => false,
+            };
+            if !handled {
+                out.text.push(token.to_string());
+            }
+        }
+        Ok(out)
+    }
+
+    /// Sets the filter named `key`, returning false if no such filter exists.
+    pub fn apply_filter(&mut self, key: &str, value: &str) -> Result<bool, SearchQueryError> {
+        match key {
+            "ns" => self.namespace = Some(value.to_string()),
+            "tag" => self.tag = Some(parse_tag(value)?),
+            "author" => self.author = Some(value.to_string()),
+            "before" => self.before = Some(parse_date(value)?),
+            "after" => self.after = Some(parse_date(value)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+            && self.phrases.is_empty()
+            && self.namespace.is_none()
+            && self.tag.is_none()
+            && self.author.is_none()
+            && self.before.is_none()
+            && self.after.is_none()
+    }
+
+    /// The free text and phrases as one string, for matching page names.
+    pub fn all_text(&self) -> String {
+        let words: Vec<&str> = self.text.iter().chain(&self.phrases).map(|s| &s[..]).collect();
+        words.join(" ")
+    }
+
+    /// Builds the `WHERE` clause predicates, the `ORDER BY` expression and
+    /// their parameters.
+    ///
+    /// Both refer to `document` and to the current revision aliased as
+    /// `document_history`, and number their parameters from `$1`. Text
+    /// searches rank by `ts_rank` under `weights`; searches by filters
+    /// alone list the most recently changed pages first.
+    pub fn to_sql(&self, weights: &SearchWeights) -> SearchSql {
+        let mut predicates: Vec<String> = Vec::new();
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+
+        let mut tsqueries = Vec::new();
+        if !self.text.is_empty() {
+            params.push(Box::new(self.text.join(" ")));
+            tsqueries.push(format!("plainto_tsquery('english', ${})", params.len()));
+        }
+        for phrase in &self.phrases {
+            params.push(Box::new(phrase.clone()));
+            tsqueries.push(format!("phraseto_tsquery('english', ${})", params.len()));
+        }
+        let order_by = if tsqueries.is_empty() {
+            "document.last_modified DESC".to_string()
+        } else {
+            let tsquery = tsqueries.join(" && ");
+            predicates.push(format!("({}) @@ ({})", DOCUMENT_VECTOR, tsquery));
+            // the weights are configuration, not input, so they're written
+            // into the statement; the array runs D, C, B, A
+            format!(
+                "ts_rank(ARRAY[{body}, 0, 0, {title}]::float4[], {vector}, {tsquery}) \
+                    * (1 + {recency} / (1 + extract(epoch FROM now() - document.last_modified) / 86400 / {days})) DESC, \
+                    document.last_modified DESC",
+                body = weights.body,
+                title = weights.title,
+                vector = DOCUMENT_VECTOR,
+                tsquery = tsquery,
+                recency = weights.recency,
+                days = RECENCY_DAYS,
+            )
+        };
+        if let Some(ref ns) = self.namespace {
+            params.push(Box::new(format!("{}:%", escape_like(ns))));
+            predicates.push(format!("document.name LIKE ${}", params.len()));
+        }
+        if let Some(ref tag) = self.tag {
+            let key = match tag.key {
+                Some(ref key) => {
+                    params.push(Box::new(key.clone()));
+                    format!(" AND page_data.key = ${}", params.len())
+                }
+                None => String::new(),
+            };
+            params.push(Box::new(tag.value.clone()));
+            predicates.push(format!(
+                "EXISTS (SELECT 1 FROM page_data WHERE page_data.document_id = document.id{} AND page_data.value = ${})",
+                key,
+                params.len()
+            ));
+        }
+        if let Some(ref author) = self.author {
+            params.push(Box::new(author.clone()));
+            predicates.push(format!(
+                "EXISTS (SELECT 1 FROM document_history h WHERE h.document_id = document.id AND h.modified_by = ${})",
+                params.len()
+            ));
+        }
+        if let Some(before) = self.before {
+            params.push(Box::new(start_of_day(before)));
+            predicates.push(format!("document.last_modified < ${}", params.len()));
+        }
+        if let Some(after) = self.after {
+            params.push(Box::new(start_of_day(after)));
+            predicates.push(format!("document.last_modified >= ${}", params.len()));
+        }
+
+        if predicates.is_empty() {
+            predicates.push("TRUE".to_string());
+        }
+
+        SearchSql {
+            predicates: predicates.join(" AND "),
+            order_by,
+            params,
+        }
+    }
+}
+
+/// The first `limit` current pages matching `query`, best first.
+pub async fn run(inner: &HandlerInner, query: &SearchQuery, weights: &SearchWeights, limit: i64) -> DynResult<Vec<Hit>> {
+    let search = query.to_sql(weights);
+    let params: Vec<&(dyn ToSql + Sync)> = search.params.iter().map(|p| &**p as _).collect();
+
+    let sql = format!(
+        r#"
+            SELECT document.name, document_history.created_at, document_history.modified_by
+            FROM document
+            INNER JOIN document_history ON document_history.id = document.current_revision_id
+            WHERE {}
+            ORDER BY {}
+            LIMIT {}
+        "#,
+        search.predicates, search.order_by, limit
+    );
+
+    let rows = inner.queries.metrics.time("search", inner.db.query(&sql[..], &params)).await?;
+    rows.iter()
+        .map(|row| {
+            Ok(Hit {
+                name: row.try_get(0)?,
+                last_modified_at: row.try_get(1)?,
+                last_modified_by: row.try_get(2)?,
+            })
+        })
+        .collect()
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, SearchQueryError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| SearchQueryError::BadDate(value.to_string()))
+}
+
+fn parse_tag(value: &str) -> Result<TagFilter, SearchQueryError> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => Ok(TagFilter {
+            key: Some(key.to_string()),
+            value: value.to_string(),
+        }),
+        Some(_) => Err(SearchQueryError::BadTag(value.to_string())),
+        None => Ok(TagFilter {
+            key: None,
+            value: value.to_string(),
+        }),
+    }
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).expect("midnight always exists").and_utc()
+}
+
+fn escape_like(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
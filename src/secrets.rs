@@ -0,0 +1,151 @@
+//! Looks for credentials pasted into a page before it's saved, since once
+//! saved they stay in the history. What happens to a save with findings is
+//! set with `--secrets-policy`; the save goes through `edit_filter::check`
+//! so a warning can be confirmed like an edit filter's. Findings are kept
+//! in the `secret_finding` table, without the secret, and listed at
+//! `/admin/filters`.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Off,
+    /// The save is refused until resent with `edit_filter::CONFIRM_HEADER`.
+    Warn,
+    /// The save is refused.
+    Block,
+}
+
+impl Policy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Policy::Off => "off",
+            Policy::Warn => "warn",
+            Policy::Block => "block",
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Policy, String> {
+        match s {
+            "off" => Ok(Policy::Off),
+            "warn" => Ok(Policy::Warn),
+            "block" => Ok(Policy::Block),
+            _ => Err(format!("expected off, warn or block, got {:?}", s)),
+        }
+    }
+}
+
+/// Tokens at least this long are checked for entropy.
+const MIN_TOKEN_LENGTH: usize = 32;
+
+/// Bits per character above which a token looks random. Hex digests top
+/// out at 4, so commit ids and checksums aren't flagged.
+const MIN_ENTROPY: f64 = 4.3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: &'static str,
+    /// 1-based.
+    pub line: usize,
+}
+
+struct Patterns {
+    known: Vec<(&'static str, Regex)>,
+    token: Regex,
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        let known = [
+            ("AWS access key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+            ("private key", r"-----BEGIN (?:[A-Z]+ )*PRIVATE KEY( BLOCK)?-----"),
+            ("GitHub token", r"\bgh[pousr]_[A-Za-z0-9]{36,}\b"),
+            ("Slack token", r"\bxox[abprs]-[A-Za-z0-9-]{10,}"),
+        ];
+        Patterns {
+            known: known
+                .iter()
+                .map(|&(kind, pattern)| (kind, Regex::new(pattern).expect("secret pattern is valid")))
+                .collect(),
+            token: Regex::new(r"[A-Za-z0-9+=_-]+").expect("token pattern is valid"),
+        }
+    })
+}
+
+/// Shannon entropy of `s` in bits per character.
+fn entropy(s: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in s.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let len = s.chars().count() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn looks_random(token: &str) -> bool {
+    token.len() >= MIN_TOKEN_LENGTH
+        && token.chars().any(|c| c.is_ascii_digit())
+        && token.chars().any(|c| c.is_ascii_uppercase())
+        && token.chars().any(|c| c.is_ascii_lowercase())
+        && entropy(token) > MIN_ENTROPY
+}
+
+/// Likely secrets in `new_text` that aren't already in `old_text`, so a
+/// page isn't flagged again on every save for something that was allowed
+/// before.
+pub fn scan(new_text: &str, old_text: &str) -> Vec<Finding> {
+    let patterns = patterns();
+    let mut findings = Vec::new();
+    for (i, line) in new_text.lines().enumerate() {
+        let known = patterns.known.iter().flat_map(|(kind, re)| re.find_iter(line).map(move |m| (*kind, m.as_str())));
+        let random = patterns
+            .token
+            .find_iter(line)
+            .map(|m| m.as_str())
+            .filter(|token| looks_random(token))
+            .map(|token| ("random-looking token", token));
+        for (kind, found) in known.chain(random) {
+            let finding = Finding { kind, line: i + 1 };
+            if !old_text.contains(found) && !findings.contains(&finding) {
+                findings.push(finding);
+            }
+        }
+    }
+    findings
+}
+
+/// Describes `findings` for the editor, e.g. "an AWS access key on line 3".
+pub fn describe(findings: &[Finding]) -> String {
+    const LISTED: usize = 3;
+    let mut described: Vec<String> = findings
+        .iter()
+        .take(LISTED)
+        .map(|finding| {
+            let article = if finding.kind.starts_with(['A', 'E', 'I', 'O', 'U', 'a', 'e', 'i', 'o', 'u']) {
+                "an"
+            } else {
+                "a"
+            };
+            format!("{} {} on line {}", article, finding.kind, finding.line)
+        })
+        .collect();
+    if findings.len() > LISTED {
+        described.push(format!("{} more", findings.len() - LISTED));
+    }
+    described.join(", ")
+}
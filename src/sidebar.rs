@@ -0,0 +1,52 @@
+//! Site navigation shown on every page, edited as the ordinary wiki page
+//! `PAGE`. Only admins may change it, see `permissions::Action::for_request`.
+
+use std::sync::RwLock;
+
+use crate::plugins::Plugins;
+use crate::{render_document, DynResult, HandlerInner};
+
+pub const PAGE: &str = "_Sidebar";
+
+/// A rendered site page such as the sidebar or `footer::PAGE`, re-rendered
+/// only when its current revision changes.
+pub struct SitePage {
+    page: &'static str,
+    cached: RwLock<Option<Cached>>,
+}
+
+struct Cached {
+    revision_id: i64,
+    html: String,
+}
+
+impl SitePage {
+    pub fn new(page: &'static str) -> SitePage {
+        SitePage {
+            page,
+            cached: RwLock::new(None),
+        }
+    }
+
+    /// Brings the cached rendering up to date. The page may also be edited
+    /// by another process, e.g. `wiki sync`, so this asks the database
+    /// rather than waiting to hear about saves.
+    pub async fn refresh(&self, inner: &HandlerInner, plugins: &Plugins) -> DynResult<()> {
+        let revision = inner.queries.fetch_current_revision(&inner.db, self.page).await?;
+        let cached_id = self.cached.read().unwrap().as_ref().map(|c| c.revision_id);
+        let cached = match revision {
+            Some(ref revision) if cached_id == Some(revision.id) => return Ok(()),
+            Some(revision) => Some(Cached {
+                revision_id: revision.id,
+                html: render_document(inner, plugins, &revision.document_data).await?,
+            }),
+            None => None,
+        };
+        *self.cached.write().unwrap() = cached;
+        Ok(())
+    }
+
+    pub fn html(&self) -> Option<String> {
+        self.cached.read().unwrap().as_ref().map(|c| c.html.clone())
+    }
+}
@@ -0,0 +1,91 @@
+//! Private mode, see `--site-token`: every request has to carry one shared
+//! secret, as `Authorization: Bearer <token>` or in the `site_token` cookie.
+//! Browsers get the cookie by visiting any page with `?site_token=<token>`,
+//! which the unlock form does for them. The cookie is `Secure`, so browsers
+//! only keep it over HTTPS, or from `localhost`.
+
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use ring::constant_time::verify_slices_are_equal;
+
+use crate::auth;
+use crate::DynResult;
+
+pub const COOKIE: &str = "site_token";
+const QUERY_PARAM: &str = "site_token";
+
+pub enum Check {
+    Allowed,
+    /// The token came in the query string: set the cookie and send the
+    /// browser back to the same URL without it.
+    Unlock { location: String },
+    Denied,
+}
+
+pub fn check(req: &Request<Body>, token: &str) -> Check {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if bearer.into_iter().chain(auth::cookie(req, COOKIE)).any(|t| matches(t, token)) {
+        return Check::Allowed;
+    }
+
+    if req.method() != Method::GET {
+        return Check::Denied;
+    }
+    let query = req.uri().query().unwrap_or("");
+    let mut presented = false;
+    let mut rest = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        if key == QUERY_PARAM {
+            presented |= matches(&value, token);
+        } else {
+            rest.append_pair(&key, &value);
+        }
+    }
+    if !presented {
+        return Check::Denied;
+    }
+    let rest = rest.finish();
+    let location = if rest.is_empty() {
+        req.uri().path().to_string()
+    } else {
+        format!("{}?{}", req.uri().path(), rest)
+    };
+    Check::Unlock { location }
+}
+
+/// Whether `token` can be used, which mostly means it survives being a
+/// cookie value.
+pub fn is_valid(token: &str) -> bool {
+    !token.is_empty() && token.bytes().all(|b| b.is_ascii_graphic() && !b";,\"\\".contains(&b))
+}
+
+fn matches(presented: &str, token: &str) -> bool {
+    verify_slices_are_equal(presented.as_bytes(), token.as_bytes()).is_ok()
+}
+
+pub fn unlock_response(token: &str, location: &str) -> DynResult<Response<Body>> {
+    let response = Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, location)
+        .header(
+            header::SET_COOKIE,
+            format!("{}={}; Path=/; HttpOnly; Secure; SameSite=Lax", COOKIE, token),
+        )
+        .body(Body::empty())?;
+    Ok(response)
+}
+
+pub fn denied_response() -> DynResult<Response<Body>> {
+    let response = Response::builder()
+        .header("Content-Type", "text/html; charset=utf8")
+        .header(header::WWW_AUTHENTICATE, "Bearer")
+        .status(StatusCode::UNAUTHORIZED)
+        .body(Body::from(format!(
+            r#"<form method="get"><p>This wiki is private.</p><input type="password" name="{}" placeholder="Site token" autofocus> <button>Unlock</button></form>"#,
+            QUERY_PARAM
+        )))?;
+    Ok(response)
+}
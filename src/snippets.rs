@@ -0,0 +1,48 @@
+//! Header and footer snippets per namespace: the page `Template:Header:Drafts`
+//! is shown above, and `Template:Footer:Drafts` below, every page named
+//! `Drafts:...`. Only admins may edit them, like the sidebar.
+
+use crate::plugins::Plugins;
+use crate::{render_document, DynResult, HandlerInner};
+
+const HEADER_PREFIX: &str = "Template:Header:";
+const FOOTER_PREFIX: &str = "Template:Footer:";
+
+/// The namespace of `name`: whatever comes before its first `:`.
+pub fn namespace(name: &str) -> Option<&str> {
+    name.split_once(':').map(|(ns, _)| ns).filter(|ns| !ns.is_empty())
+}
+
+/// Whether `name` is a header or footer snippet.
+pub fn is_snippet(name: &str) -> bool {
+    name.starts_with(HEADER_PREFIX) || name.starts_with(FOOTER_PREFIX)
+}
+
+/// Rendered snippets for one page, `None` where the snippet page doesn't
+/// exist.
+#[derive(Default)]
+pub struct Snippets {
+    pub header: Option<String>,
+    pub footer: Option<String>,
+}
+
+pub async fn for_page(inner: &HandlerInner, plugins: &Plugins, name: &str) -> DynResult<Snippets> {
+    let ns = match namespace(name) {
+        Some(ns) => ns,
+        None => return Ok(Snippets::default()),
+    };
+    let header_name = format!("{}{}", HEADER_PREFIX, ns);
+    let footer_name = format!("{}{}", FOOTER_PREFIX, ns);
+    let names = [header_name.clone(), footer_name.clone()];
+
+    let mut snippets = Snippets::default();
+    for (name, body) in inner.queries.fetch_current_documents(&inner.db, &names).await? {
+        let rendered = Some(render_document(inner, plugins, &body).await?);
+        if name == header_name {
+            snippets.header = rendered;
+        } else if name == footer_name {
+            snippets.footer = rendered;
+        }
+    }
+    Ok(snippets)
+}
@@ -0,0 +1,56 @@
+//! Generated pages under `/wiki/Special:`. Each entry in `REGISTRY` maps a
+//! name to a handler, so a new report only needs a handler, a template and
+//! a line here rather than a route of its own.
+
+use futures::future::{self, BoxFuture};
+use hyper::{Body, Request, Response};
+
+use crate::{DynResult, Handler};
+
+type SpecialHandler =
+    for<'a> fn(&'a Handler, Request<Body>) -> BoxFuture<'a, DynResult<Response<Body>>>;
+
+pub struct SpecialPage {
+    pub name: &'static str,
+    /// Shown on `Special:SpecialPages`.
+    pub description: &'static str,
+    pub handler: SpecialHandler,
+}
+
+pub const REGISTRY: &[SpecialPage] = &[
+    SpecialPage {
+        name: "SpecialPages",
+        description: "This list",
+        handler: |h, req| Box::pin(future::ready(h.special_pages_page(req))),
+    },
+    SpecialPage {
+        name: "RecentChanges",
+        description: "The latest edits and moves across the site",
+        handler: |h, req| Box::pin(h.changes_page(req)),
+    },
+    SpecialPage {
+        name: "AllPages",
+        description: "Every page, alphabetically",
+        handler: |h, req| Box::pin(h.all_pages_page(req)),
+    },
+    SpecialPage {
+        name: "Orphans",
+        description: "Pages no other page links to",
+        handler: |h, req| Box::pin(h.orphans_page(req)),
+    },
+    SpecialPage {
+        name: "WantedPages",
+        description: "Links to pages that don't exist yet, most linked first",
+        handler: |h, req| Box::pin(h.wanted_pages_page(req)),
+    },
+    SpecialPage {
+        name: "Random",
+        description: "Go to a random page",
+        handler: |h, req| Box::pin(h.random_page(req)),
+    },
+];
+
+/// Names are matched ignoring ASCII case, so `Special:allpages` works too.
+pub fn find(name: &str) -> Option<&'static SpecialPage> {
+    REGISTRY.iter().find(|page| page.name.eq_ignore_ascii_case(name))
+}
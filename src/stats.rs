@@ -0,0 +1,58 @@
+//! Rollups for `/admin/stats`: edits per day over the last `DAYS` days,
+//! drawn as a sparkline, and who made most of them.
+
+use chrono::{Duration, NaiveDate};
+
+/// Days covered by the edit chart and the top editors.
+pub const DAYS: i64 = 30;
+
+/// Editors listed.
+pub const TOP_EDITORS: i64 = 10;
+
+const SPARKLINE_WIDTH: i64 = 300;
+const SPARKLINE_HEIGHT: i64 = 40;
+
+/// One count per day for the `DAYS` days up to and including `today`,
+/// filling in the days `counts` leaves out with zero.
+pub fn daily_counts(counts: &[(NaiveDate, i64)], today: NaiveDate) -> Vec<(NaiveDate, i64)> {
+    (0..DAYS)
+        .rev()
+        .map(|ago| {
+            let day = today - Duration::days(ago);
+            let count = counts
+                .iter()
+                .find(|(d, _)| *d == day)
+                .map_or(0, |&(_, count)| count);
+            (day, count)
+        })
+        .collect()
+}
+
+pub struct Sparkline {
+    pub width: i64,
+    pub height: i64,
+    /// SVG `points` of a polyline, one point per day.
+    pub points: String,
+    pub max: i64,
+}
+
+/// Scales `counts` into a `SPARKLINE_WIDTH` by `SPARKLINE_HEIGHT` box, the
+/// busiest day touching the top.
+pub fn sparkline(counts: &[(NaiveDate, i64)]) -> Sparkline {
+    let max = counts.iter().map(|&(_, count)| count).max().unwrap_or(0);
+    let step = SPARKLINE_WIDTH / (counts.len() as i64 - 1).max(1);
+    let points: Vec<String> = counts
+        .iter()
+        .enumerate()
+        .map(|(i, &(_, count))| {
+            let y = SPARKLINE_HEIGHT - count * SPARKLINE_HEIGHT / max.max(1);
+            format!("{},{}", i as i64 * step, y)
+        })
+        .collect();
+    Sparkline {
+        width: SPARKLINE_WIDTH,
+        height: SPARKLINE_HEIGHT,
+        points: points.join(" "),
+        max,
+    }
+}
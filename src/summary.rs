@@ -0,0 +1,79 @@
+//! Short plain-text summaries of rendered pages, for `<meta>` descriptions
+//! and link previews.
+
+/// Longest description produced, in characters. Chat apps and search engines
+/// cut off somewhere around here anyway.
+const MAX_DESCRIPTION: usize = 200;
+
+/// The text of the first non-empty paragraph in `html`, without markup and
+/// shortened to `MAX_DESCRIPTION` characters at a word boundary.
+pub fn description(html: &str) -> String {
+    let mut rest = html;
+    while let Some(start) = rest.find("<p>") {
+        let after = &rest[start + "<p>".len()..];
+        let end = after.find("</p>").unwrap_or(after.len());
+        let text = plain_text(&after[..end]);
+        if !text.is_empty() {
+            return truncate(&text, MAX_DESCRIPTION);
+        }
+        rest = &after[end..];
+    }
+    String::new()
+}
+
+/// The `src` of the first image in `html`, as written.
+pub fn first_image(html: &str) -> Option<String> {
+    const IMG: &str = "<img src=\"";
+
+    let start = html.find(IMG)? + IMG.len();
+    let src = &html[start..];
+    let src = &src[..src.find('"')?];
+    Some(decode_entities(src)).filter(|s| !s.is_empty())
+}
+
+/// `src` as an absolute URL, for readers outside the wiki such as link
+/// previews. `None` for relative paths when `base_url` isn't known.
+pub fn absolute_url(src: String, base_url: &str) -> Option<String> {
+    if src.starts_with("https://") || src.starts_with("http://") {
+        Some(src)
+    } else if src.starts_with('/') && !base_url.is_empty() {
+        Some(format!("{}{}", base_url, src))
+    } else {
+        None
+    }
+}
+
+/// Drops tags, decodes the entities comrak produces and collapses whitespace.
+fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    decode_entities(&text).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) => &cut[..space],
+        None => &cut[..],
+    };
+    format!("{}…", cut.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+}
@@ -0,0 +1,408 @@
+//! `wiki sync`: two-way sync of current revisions with another instance
+//! over its `/api/v1` routes.
+//!
+//! For every page, `sync_page` remembers the local and remote revisions that
+//! last held the same text. A page changed on one side since then is copied
+//! to the other; a page changed on both is settled by the `ConflictPolicy`.
+//! Cursors in `sync_cursor` keep each run to the pages that changed since the
+//! previous one.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use hyper::body::Bytes;
+use hyper::{header, Body, Method, Request, StatusCode, Uri};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::auth;
+use crate::plugins::{Plugins, SaveContext};
+use crate::queries::ChangedPage;
+use crate::{index_document, DynResult, HandlerInner};
+
+/// Pages fetched per request when listing changes.
+const PAGE_SIZE: i64 = 500;
+
+/// What to do with a page edited on both sides since it was last synced.
+#[derive(Debug, Clone, Copy)]
+pub enum ConflictPolicy {
+    /// Keep whichever side was edited last.
+    Newest,
+    /// Leave both sides alone and record the page in `sync_conflict` until
+    /// it is resolved with `--keep-local` or `--keep-remote`.
+    Queue,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ConflictPolicy, String> {
+        match s {
+            "newest" => Ok(ConflictPolicy::Newest),
+            "queue" => Ok(ConflictPolicy::Queue),
+            other => Err(format!("unknown conflict policy {:?}, expected newest or queue", other)),
+        }
+    }
+}
+
+/// What happened to one page.
+#[derive(Debug)]
+pub enum Outcome {
+    Pulled,
+    Pushed,
+    /// Pushed, but the remote holds it for review.
+    Held,
+    /// Both sides already had the same text.
+    Matched,
+    Conflict,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Outcome::Pulled => "pulled",
+            Outcome::Pushed => "pushed",
+            Outcome::Held => "pushed, held for review on the remote",
+            Outcome::Matched => "already in sync",
+            Outcome::Conflict => "conflict, edited on both sides",
+        })
+    }
+}
+
+/// Another instance, reached over HTTP or HTTPS.
+pub struct Remote {
+    /// The URL as given, which also keys the sync state.
+    url: String,
+    https: bool,
+    host: String,
+    port: u16,
+    /// Path prefix the remote is served under, without a trailing slash.
+    base_path: String,
+    session: Option<String>,
+    /// The remote's `--site-token`, see `site_token.rs`.
+    site_token: Option<String>,
+    tls: TlsConnector,
+}
+
+impl Remote {
+    /// `session` is a session cookie value for the remote, needed when its
+    /// site policy doesn't let anonymous users edit. `site_token` is needed
+    /// when the remote is private.
+    pub fn new(url: &str, session: Option<String>, site_token: Option<String>) -> DynResult<Remote> {
+        let url = url.trim_end_matches('/').to_string();
+        let uri: Uri = url.parse()?;
+        let https = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => return Err(format!("{}: expected an http:// or https:// URL", url).into()),
+        };
+        let host = uri.host().ok_or_else(|| format!("{}: missing host", url))?.to_string();
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let base_path = uri.path().trim_end_matches('/').to_string();
+
+        let mut config = rustls::ClientConfig::new();
+        config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+        Ok(Remote {
+            url,
+            https,
+            host,
+            port,
+            base_path,
+            session,
+            site_token,
+            tls: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    async fn send(&self, method: Method, path: &str, accept: &str, body: Body) -> DynResult<(StatusCode, Bytes)> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base_path, path))
+            .header(header::HOST, format!("{}:{}", self.host, self.port))
+            .header(header::ACCEPT, accept);
+        if let Some(ref session) = self.session {
+            req = req.header(header::COOKIE, format!("{}={}", auth::SESSION_COOKIE, session));
+        }
+        if let Some(ref token) = self.site_token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = req.body(body)?;
+
+        let stream = TcpStream::connect((&self.host[..], self.port)).await?;
+        if self.https {
+            let domain = webpki::DNSNameRef::try_from_ascii_str(&self.host)?;
+            exchange(self.tls.connect(domain, stream).await?, req).await
+        } else {
+            exchange(stream, req).await
+        }
+    }
+
+    async fn json(&self, path: &str) -> DynResult<serde_json::Value> {
+        let (status, body) = self.send(Method::GET, path, "application/json", Body::empty()).await?;
+        if !status.is_success() {
+            return Err(format!("{}{}: {}", self.url, path, status).into());
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Pages whose current revision on the remote is newer than `after`.
+    async fn changes_after(&self, after: i64) -> DynResult<Vec<ChangedPage>> {
+        let body = self
+            .json(&format!("/api/v1/changes?after={}&limit={}", after, PAGE_SIZE))
+            .await?;
+        body["pages"]
+            .as_array()
+            .ok_or("malformed change list")?
+            .iter()
+            .map(changed_page)
+            .collect()
+    }
+
+    /// The remote's current revision of `name`.
+    async fn current(&self, name: &str) -> DynResult<ChangedPage> {
+        let body = self.json(&format!("/api/v1/wiki/{}", encode(name))).await?;
+        changed_page(&body)
+    }
+
+    async fn text(&self, name: &str, revision_id: i64) -> DynResult<String> {
+        let path = format!("/api/v1/wiki/{}/rev/{}", encode(name), revision_id);
+        let (status, body) = self.send(Method::GET, &path, "text/markdown", Body::empty()).await?;
+        if !status.is_success() {
+            return Err(format!("{}{}: {}", self.url, path, status).into());
+        }
+        Ok(String::from_utf8(body.to_vec())?)
+    }
+
+    /// Stores `text` as the new revision of `name` on the remote. Returns
+    /// the revision id and whether it is held for review.
+    async fn store(&self, name: &str, text: &str) -> DynResult<(i64, bool)> {
+        let path = format!("/api/v1/wiki/{}", encode(name));
+        let (status, body) = self
+            .send(Method::PUT, &path, "application/json", Body::from(text.to_string()))
+            .await?;
+        if !status.is_success() {
+            let reason = String::from_utf8_lossy(&body);
+            return Err(format!("{}{}: {} {}", self.url, path, status, reason.trim()).into());
+        }
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        let revision = body["revision"].as_i64().ok_or("malformed store response")?;
+        Ok((revision, body["pending"].as_bool().unwrap_or(false)))
+    }
+}
+
+async fn exchange<S>(io: S, req: Request<Body>) -> DynResult<(StatusCode, Bytes)>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(io).await?;
+    tokio::spawn(connection);
+    let response = sender.send_request(req).await?;
+    let status = response.status();
+    Ok((status, hyper::body::to_bytes(response.into_body()).await?))
+}
+
+fn encode(name: &str) -> String {
+    utf8_percent_encode(name, NON_ALPHANUMERIC).to_string()
+}
+
+/// Reads an entry of `/api/v1/changes`, or the JSON form of a revision.
+fn changed_page(value: &serde_json::Value) -> DynResult<ChangedPage> {
+    let field = |key: &str| value[key].as_str().ok_or_else(|| format!("missing {:?}", key));
+    Ok(ChangedPage {
+        name: field("name")?.to_string(),
+        revision_id: value["revision"].as_i64().ok_or("missing \"revision\"")?,
+        created_at: DateTime::parse_from_rfc3339(field("created_at")?)?.with_timezone(&Utc),
+        created_by: field("created_by")?.to_string(),
+    })
+}
+
+pub struct Sync<'a> {
+    pub inner: &'a mut HandlerInner,
+    pub plugins: &'a Plugins,
+    pub remote: &'a Remote,
+    pub policy: ConflictPolicy,
+}
+
+impl<'a> Sync<'a> {
+    /// Settles queued conflicts in favour of the named side, then syncs
+    /// every page changed on either side since the last run.
+    pub async fn run(
+        &mut self,
+        keep_local: &[String],
+        keep_remote: &[String],
+    ) -> DynResult<Vec<(String, Outcome)>> {
+        let mut outcomes = Vec::new();
+        for name in keep_local {
+            outcomes.push((name.clone(), self.push(name).await?));
+        }
+        for name in keep_remote {
+            let remote = self.remote.current(name).await?;
+            outcomes.push((name.clone(), self.pull(&remote).await?));
+        }
+
+        let key = &self.remote.url;
+        let (mut local_after, mut remote_after) =
+            self.inner.queries.fetch_sync_cursor(&self.inner.db, key).await?;
+
+        let mut pages: BTreeMap<String, (Option<ChangedPage>, Option<ChangedPage>)> = BTreeMap::new();
+        loop {
+            let changes = self
+                .inner
+                .queries
+                .fetch_changes_after(&self.inner.db, local_after, PAGE_SIZE)
+                .await?;
+            let done = (changes.len() as i64) < PAGE_SIZE;
+            for page in changes {
+                local_after = local_after.max(page.revision_id);
+                let name = page.name.clone();
+                pages.entry(name).or_default().0 = Some(page);
+            }
+            if done {
+                break;
+            }
+        }
+        loop {
+            let changes = self.remote.changes_after(remote_after).await?;
+            let done = (changes.len() as i64) < PAGE_SIZE;
+            for page in changes {
+                remote_after = remote_after.max(page.revision_id);
+                let name = page.name.clone();
+                pages.entry(name).or_default().1 = Some(page);
+            }
+            if done {
+                break;
+            }
+        }
+
+        let queued: HashSet<String> = self
+            .inner
+            .queries
+            .fetch_sync_conflicts(&self.inner.db, key)
+            .await?
+            .into_iter()
+            .map(|(name, ..)| name)
+            .collect();
+        let resolved: HashSet<&String> = keep_local.iter().chain(keep_remote).collect();
+        // still-queued conflicts are reported on every run until resolved
+        for name in &queued {
+            if !resolved.contains(name) {
+                outcomes.push((name.clone(), Outcome::Conflict));
+            }
+        }
+
+        for (name, (local, remote)) in pages {
+            if resolved.contains(&name) || queued.contains(&name) {
+                continue;
+            }
+
+            let synced = self.inner.queries.fetch_sync_page(&self.inner.db, key, &name).await?;
+            let local_changed = matches!(local, Some(ref l) if synced.map(|s| s.0) != Some(l.revision_id));
+            let remote_changed = matches!(remote, Some(ref r) if synced.map(|s| s.1) != Some(r.revision_id));
+            let outcome = match (local, remote) {
+                (Some(_), Some(ref remote)) if local_changed && remote_changed => {
+                    self.settle(&name, remote).await?
+                }
+                (Some(_), _) if local_changed => self.push(&name).await?,
+                (_, Some(ref remote)) if remote_changed => self.pull(remote).await?,
+                _ => continue,
+            };
+            outcomes.push((name, outcome));
+        }
+
+        self.inner
+            .queries
+            .store_sync_cursor(&self.inner.db, key, local_after, remote_after)
+            .await?;
+        Ok(outcomes)
+    }
+
+    /// Handles a page edited on both sides.
+    async fn settle(&mut self, name: &str, remote: &ChangedPage) -> DynResult<Outcome> {
+        let local = self
+            .inner
+            .queries
+            .fetch_current_revision(&self.inner.db, name)
+            .await?
+            .ok_or("page vanished during sync")?;
+        let remote_text = self.remote.text(name, remote.revision_id).await?;
+        if local.document_data == remote_text {
+            self.inner
+                .queries
+                .store_sync_page(&self.inner.db, &self.remote.url, name, local.id, remote.revision_id)
+                .await?;
+            return Ok(Outcome::Matched);
+        }
+
+        match self.policy {
+            ConflictPolicy::Newest if local.created_at >= remote.created_at => self.push(name).await,
+            ConflictPolicy::Newest => self.pull(remote).await,
+            ConflictPolicy::Queue => {
+                self.inner
+                    .queries
+                    .queue_sync_conflict(&self.inner.db, &self.remote.url, name, local.id, remote.revision_id)
+                    .await?;
+                Ok(Outcome::Conflict)
+            }
+        }
+    }
+
+    /// Copies the local current revision of `name` to the remote.
+    async fn push(&mut self, name: &str) -> DynResult<Outcome> {
+        let local = self
+            .inner
+            .queries
+            .fetch_current_revision(&self.inner.db, name)
+            .await?
+            .ok_or_else(|| format!("{}: no such page", name))?;
+        let (remote_id, pending) = self.remote.store(name, &local.document_data).await?;
+        // a held revision is recorded too, or every sync would push it
+        // again until it's reviewed
+        self.inner
+            .queries
+            .store_sync_page(&self.inner.db, &self.remote.url, name, local.id, remote_id)
+            .await?;
+        Ok(if pending { Outcome::Held } else { Outcome::Pushed })
+    }
+
+    /// Stores the remote revision as the local current revision, attributed
+    /// to its author on the remote.
+    async fn pull(&mut self, remote: &ChangedPage) -> DynResult<Outcome> {
+        let name = &remote.name;
+        let text = self.remote.text(name, remote.revision_id).await?;
+        let author = format!("{} via {}", remote.created_by, self.remote.host);
+
+        let HandlerInner { db, queries } = &mut *self.inner;
+        let tx = db.transaction().await?;
+        let current = queries.fetch_current_revision(&tx, name).await?;
+        let (local_id, outcome) = match current {
+            Some(ref local) if local.document_data == text => (local.id, Outcome::Matched),
+            _ => {
+                let index = index_document(&text);
+                let id = queries.store_revision(&tx, name, &author, &text, &index).await?;
+                (id, Outcome::Pulled)
+            }
+        };
+        queries
+            .store_sync_page(&tx, &self.remote.url, name, local_id, remote.revision_id)
+            .await?;
+        tx.commit().await?;
+
+        if let Outcome::Pulled = outcome {
+            let save = SaveContext {
+                name,
+                user: None,
+                attribution: &author,
+            };
+            self.plugins.post_save(&save, local_id);
+        }
+        Ok(outcome)
+    }
+}
@@ -0,0 +1,102 @@
+//! Socket activation and readiness notification for running under systemd,
+//! enabled with the `systemd` feature. See sd_listen_fds(3) and sd_notify(3);
+//! both protocols are simple enough that libsystemd isn't needed.
+
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::time::Duration;
+
+use tracing::{event, Level};
+
+use crate::listen::Listener;
+use crate::DynResult;
+
+/// The first inherited descriptor, after stdin, stdout and stderr.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the sockets passed with `LISTEN_FDS`, if they were meant for this
+/// process. Inherited sockets serve plain HTTP; use `--listen` for TLS.
+pub fn inherited_listeners() -> DynResult<Vec<Listener>> {
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count: RawFd = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    // child processes mustn't think the sockets are theirs
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if !for_us {
+        return Ok(Vec::new());
+    }
+
+    let mut listeners = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // SAFETY: systemd hands these descriptors to this process, and they
+        // are only taken once since the variables were cleared above
+        let unix = unsafe { UnixListener::from_raw_fd(fd) };
+        let listener = if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            Listener::inherited_unix(unix)?
+        } else {
+            // SAFETY: as above; the descriptor moves out of the UnixListener
+            let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+            tcp.set_nonblocking(true)?;
+            Listener::inherited_tcp(tcp)?
+        };
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Tells systemd the server is accepting connections, and starts the
+/// watchdog pings if the unit has `WatchdogSec=` set. Does nothing outside
+/// systemd.
+pub fn notify_ready() {
+    if let Err(err) = notify("READY=1") {
+        event!(Level::WARN, "sd_notify: {}", err);
+    }
+
+    let interval = env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|_| {
+            env::var("WATCHDOG_PID")
+                .map(|pid| pid == std::process::id().to_string())
+                .unwrap_or(true)
+        });
+    if let Some(usec) = interval {
+        // ping at twice the required rate so one late tick isn't fatal
+        let period = Duration::from_micros(usec / 2);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            loop {
+                ticks.tick().await;
+                if let Err(err) = notify("WATCHDOG=1") {
+                    event!(Level::WARN, "sd_notify watchdog: {}", err);
+                }
+            }
+        });
+    }
+}
+
+fn notify(state: &str) -> io::Result<()> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let path = path.to_string_lossy();
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(&*path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
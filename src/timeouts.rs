@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use hyper::Method;
+
+use crate::routes::{Route, RouteWikiSubview};
+
+/// How long a handler may run before the client gets a 503. A handler that
+/// times out is dropped, which rolls back any open transaction.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Page views, search and other reads.
+    pub render: Duration,
+    /// Saves, uploads and other requests that change something.
+    pub write: Duration,
+    /// Export bundles, which walk many pages.
+    pub export: Duration,
+}
+
+impl Timeouts {
+    pub fn limit(&self, class: RequestClass) -> Duration {
+        match class {
+            RequestClass::Render => self.render,
+            RequestClass::Write => self.write,
+            RequestClass::Export => self.export,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestClass {
+    Render,
+    Write,
+    Export,
+}
+
+impl RequestClass {
+    pub fn for_request(route: &Route<'_>, method: &Method) -> RequestClass {
+        match route {
+            Route::Wiki(ref rw)
+                if matches!(
+                    rw.subview,
+                    RouteWikiSubview::ExportBundle | RouteWikiSubview::ExportGit
+                ) =>
+            {
+                RequestClass::Export
+            }
+            // posts a draft but writes nothing
+            Route::Wiki(ref rw) if matches!(rw.subview, RouteWikiSubview::PreviewDiff) => {
+                RequestClass::Render
+            }
+            Route::ApiLint => RequestClass::Render,
+            _ if method == Method::GET || method == Method::HEAD => RequestClass::Render,
+            _ => RequestClass::Write,
+        }
+    }
+}
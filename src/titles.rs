@@ -0,0 +1,36 @@
+//! Typo-tolerant page name matching with `pg_trgm`, for the suggestions on
+//! missing pages, the quick switcher behind `/api/v1/titles` and the "did
+//! you mean" row on `/search`.
+
+use crate::{DynResult, HandlerInner};
+
+/// Names suggested on a missing page or above search results.
+pub const SUGGESTIONS: i64 = 5;
+
+/// Names offered by the quick switcher.
+pub const SWITCHER_RESULTS: i64 = 10;
+
+/// Queries are cut to this many characters; trigrams of longer ones match
+/// next to nothing and cost more to compare.
+const MAX_QUERY_CHARS: usize = 100;
+
+/// Up to `limit` existing pages with names like `query`, closest first.
+/// Nothing for a blank query.
+pub async fn similar(inner: &HandlerInner, query: &str, limit: i64) -> DynResult<Vec<String>> {
+    let query: String = query.trim().chars().take(MAX_QUERY_CHARS).collect();
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+    inner.queries.fetch_similar_names(&inner.db, &query, limit).await
+}
+
+/// Pages to suggest for `query` on `/search`: similar names other than the
+/// query itself, which the results already cover.
+pub async fn did_you_mean(inner: &HandlerInner, query: &str) -> DynResult<Vec<String>> {
+    let names = similar(inner, query, SUGGESTIONS + 1).await?;
+    Ok(names
+        .into_iter()
+        .filter(|name| !name.eq_ignore_ascii_case(query.trim()))
+        .take(SUGGESTIONS as usize)
+        .collect())
+}
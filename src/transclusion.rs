@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::routes::RouteWiki;
+use crate::{front_matter, DynResult, HandlerInner};
+
+pub const TEMPLATE_NAMESPACE: &str = "Template:";
+
+/// How many levels of templates-within-templates are expanded.
+const MAX_DEPTH: usize = 5;
+
+/// How many invocations are expanded in one render, over all levels, so a
+/// template that calls itself many times over can't grow a page without
+/// bound before `MAX_DEPTH` is reached.
+const MAX_EXPANSIONS: usize = 500;
+
+/// A `{{Name|key=value|positional}}` call found in a document.
+#[derive(Debug)]
+struct Invocation {
+    start: usize,
+    end: usize,
+    name: String,
+    params: HashMap<String, String>,
+}
+
+/// Finds template invocations outside of fenced code blocks. Triple-brace
+/// parameter placeholders are skipped.
+fn find_invocations(markdown: &str) -> Vec<Invocation> {
+    let mut out = Vec::new();
+    let mut in_fence = false;
+    let mut line_start = 0;
+
+    for line in markdown.split_inclusive('\n') {
+        let offset = line_start;
+        line_start += line.len();
+
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        let mut pos = 0;
+        while let Some(idx) = line[pos..].find("{{") {
+            let start = pos + idx;
+            if line[start..].starts_with("{{{") {
+                pos = match line[start..].find("}}}") {
+                    Some(end) => start + end + 3,
+                    None => line.len(),
+                };
+                continue;
+            }
+            let end = match line[start..].find("}}") {
+                Some(end) => start + end + 2,
+                None => break,
+            };
+            if let Some(inv) = parse_invocation(&line[start + 2..end - 2]) {
+                out.push(Invocation {
+                    start: offset + start,
+                    end: offset + end,
+                    ..inv
+                });
+            }
+            pos = end;
+        }
+    }
+    out
+}
+
+fn parse_invocation(inner: &str) -> Option<Invocation> {
+    let mut parts = inner.split('|');
+    let name = parts.next()?.trim();
+    if name.is_empty() || name.contains('\n') {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    let mut positional = 0;
+    for part in parts {
+        match part.split_once('=') {
+            Some((key, value)) => {
+                params.insert(key.trim().to_string(), value.trim().to_string());
+            }
+            None => {
+                positional += 1;
+                params.insert(positional.to_string(), part.trim().to_string());
+            }
+        }
+    }
+
+    Some(Invocation {
+        start: 0,
+        end: 0,
+        name: name.to_string(),
+        params,
+    })
+}
+
+/// Backslash-escapes Markdown punctuation so parameter values are inserted
+/// as literal text.
+fn escape_markdown(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\`*_[]<>#|{}!&".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Replaces `{{{key}}}` and `{{{key|default}}}` placeholders in a template body.
+fn substitute(body: &str, params: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(idx) = rest.find("{{{") {
+        out.push_str(&rest[..idx]);
+        let after = &rest[idx + 3..];
+        let end = match after.find("}}}") {
+            Some(end) => end,
+            None => {
+                out.push_str(&rest[idx..]);
+                return out;
+            }
+        };
+
+        let placeholder = &after[..end];
+        let (key, default) = match placeholder.split_once('|') {
+            Some((key, default)) => (key.trim(), Some(default)),
+            None => (placeholder.trim(), None),
+        };
+        match (params.get(key), default) {
+            (Some(value), _) => out.push_str(&escape_markdown(value)),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => {}
+        }
+        rest = &after[end + 3..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn missing_template_link(name: &str) -> String {
+    let page = format!("{}{}", TEMPLATE_NAMESPACE, name);
+    format!("[{}]({})", page, RouteWiki::to(&page))
+}
+
+/// Expands template invocations in `markdown`, fetching each round of
+/// templates with one query. Invocations past `MAX_EXPANSIONS` are left
+/// as a notice.
+pub async fn expand(inner: &HandlerInner, markdown: &str) -> DynResult<String> {
+    let mut document = markdown.to_string();
+    let mut expansions = 0;
+
+    for depth in 0..=MAX_DEPTH {
+        let invocations = find_invocations(&document);
+        if invocations.is_empty() {
+            break;
+        }
+
+        if depth == MAX_DEPTH {
+            let mut out = document.clone();
+            for inv in invocations.iter().rev() {
+                out.replace_range(inv.start..inv.end, "**Template recursion limit reached**");
+            }
+            document = out;
+            break;
+        }
+
+        let names: Vec<String> = invocations
+            .iter()
+            .map(|inv| format!("{}{}", TEMPLATE_NAMESPACE, inv.name))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let mut templates = HashMap::new();
+        for (name, body) in inner.queries.fetch_current_documents(&inner.db, &names).await? {
+            let body = front_matter::strip(&body).to_string();
+            templates.insert(name[TEMPLATE_NAMESPACE.len()..].to_string(), body);
+        }
+
+        // the earliest invocations get what's left of the budget
+        let allowed = MAX_EXPANSIONS - expansions;
+        let mut out = document.clone();
+        for (i, inv) in invocations.iter().enumerate().rev() {
+            let replacement = match templates.get(&inv.name) {
+                _ if i >= allowed => "**Template expansion limit reached**".to_string(),
+                Some(body) => substitute(body.trim_end_matches('\n'), &inv.params),
+                None => missing_template_link(&inv.name),
+            };
+            out.replace_range(inv.start..inv.end, &replacement);
+        }
+        expansions += invocations.len().min(allowed);
+        document = out;
+    }
+
+    Ok(document)
+}
@@ -0,0 +1,75 @@
+//! Trust levels, worked out from how many of a user's edits were published
+//! and how old their account is. Autoconfirmed users skip the anonymous
+//! challenge and `--moderation`, trusted users may review held edits.
+//! Admins are always trusted.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrustLevel {
+    New,
+    Autoconfirmed,
+    Trusted,
+}
+
+impl TrustLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TrustLevel::New => "new",
+            TrustLevel::Autoconfirmed => "autoconfirmed",
+            TrustLevel::Trusted => "trusted",
+        }
+    }
+}
+
+/// What it takes to reach a level: both counts must be met.
+#[derive(Debug, Clone, Copy)]
+pub struct Threshold {
+    /// Published edits.
+    pub edits: i64,
+    /// Whole days since the account was created.
+    pub days: i64,
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    /// Parses `EDITS,DAYS`.
+    fn from_str(s: &str) -> Result<Threshold, String> {
+        let (edits, days) = s
+            .split_once(',')
+            .and_then(|(edits, days)| Some((edits.trim().parse().ok()?, days.trim().parse().ok()?)))
+            .filter(|&(edits, days): &(i64, i64)| edits >= 0 && days >= 0)
+            .ok_or_else(|| format!("expected EDITS,DAYS, got {:?}", s))?;
+        Ok(Threshold { edits, days })
+    }
+}
+
+/// Set with `--autoconfirmed-after` and `--trusted-after`.
+#[derive(Debug, Clone, Copy)]
+pub struct TrustThresholds {
+    pub autoconfirmed: Threshold,
+    pub trusted: Threshold,
+}
+
+impl Default for TrustThresholds {
+    fn default() -> TrustThresholds {
+        TrustThresholds {
+            autoconfirmed: Threshold { edits: 10, days: 4 },
+            trusted: Threshold { edits: 100, days: 30 },
+        }
+    }
+}
+
+impl TrustThresholds {
+    pub fn level(&self, edits: i64, days: i64, is_admin: bool) -> TrustLevel {
+        let meets = |threshold: Threshold| edits >= threshold.edits && days >= threshold.days;
+        if is_admin || meets(self.trusted) {
+            TrustLevel::Trusted
+        } else if meets(self.autoconfirmed) {
+            TrustLevel::Autoconfirmed
+        } else {
+            TrustLevel::New
+        }
+    }
+}
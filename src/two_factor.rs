@@ -0,0 +1,191 @@
+//! Two-factor authentication with time-based one-time passwords (RFC 6238),
+//! the six-digit codes authenticator apps show. Turning it on at
+//! `/settings/two-factor` shows a QR code for a new secret and takes a code
+//! from it, to be sure the app has it, and hands out recovery codes for a
+//! lost device, each good for one login. Logging in then asks for a code,
+//! or a recovery code, after the password.
+//!
+//! With `--require-admin-2fa`, users listed with `--admin` only get admin
+//! rights once they've turned it on.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use ring::{digest, hmac};
+
+use crate::auth::{self, RandomError};
+use crate::{DynResult, HandlerInner};
+
+/// Seconds each code is shown for.
+const STEP: i64 = 30;
+
+const DIGITS: u32 = 6;
+
+/// Steps either side of now that are accepted, for clocks that drift.
+const SKEW: i64 = 1;
+
+const SECRET_BYTES: usize = 20;
+
+pub const RECOVERY_CODES: usize = 10;
+
+/// How long after the password the code may be given, in seconds.
+const PENDING_LIFETIME: i64 = 5 * 60;
+
+/// Wrong codes allowed for one account within `PENDING_LIFETIME`.
+const MAX_FAILURES: usize = 5;
+
+const ALPHABET: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A new secret, base32-encoded as authenticator apps expect.
+pub fn new_secret() -> Result<String, RandomError> {
+    let mut secret = [0u8; SECRET_BYTES];
+    auth::random_bytes(&mut secret)?;
+    Ok(base32::encode(ALPHABET, &secret))
+}
+
+/// The `otpauth://` URI apps scan, labelled with the site and username.
+pub fn provisioning_uri(issuer: &str, username: &str, secret: &str) -> String {
+    let label = format!("{}:{}", issuer, username);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&digits={}&period={}",
+        utf8_percent_encode(&label, NON_ALPHANUMERIC),
+        secret,
+        utf8_percent_encode(issuer, NON_ALPHANUMERIC),
+        DIGITS,
+        STEP
+    )
+}
+
+/// `uri` as an SVG QR code, to be inlined in the page.
+pub fn qr_svg(uri: &str) -> DynResult<String> {
+    let code = QrCode::new(uri.as_bytes())?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .quiet_zone(true)
+        .build())
+}
+
+/// The code for the time step `step`, as in RFC 4226.
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let tag = tag.as_ref();
+    let offset = (tag[tag.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([tag[offset], tag[offset + 1], tag[offset + 2], tag[offset + 3]]);
+    (truncated & 0x7fff_ffff) % 10u32.pow(DIGITS)
+}
+
+/// The time step `code` belongs to under `secret`, if it's current. Steps
+/// up to `last_step` have been used already, so their codes don't count.
+pub fn check_code(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = base32::decode(ALPHABET, secret)?;
+    let now = Utc::now().timestamp() / STEP;
+    (now - SKEW..=now + SKEW)
+        .filter(|&step| last_step.is_none_or(|last| step > last))
+        .find(|&step| code_at(&key, step) == code)
+}
+
+/// New recovery codes such as `k3x9-m2pq-7hwa`, shown to the user once;
+/// only their hashes are stored.
+pub fn new_recovery_codes() -> Result<Vec<String>, RandomError> {
+    let mut codes = Vec::with_capacity(RECOVERY_CODES);
+    for _ in 0..RECOVERY_CODES {
+        let mut bytes = [0u8; 8];
+        auth::random_bytes(&mut bytes)?;
+        let code = base32::encode(ALPHABET, &bytes).to_ascii_lowercase();
+        codes.push(format!("{}-{}-{}", &code[0..4], &code[4..8], &code[8..12]));
+    }
+    Ok(codes)
+}
+
+/// What's stored for a recovery code. They're random enough that a plain
+/// hash will do, and it ignores case, spaces and dashes.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normal: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    auth::to_hex(digest::digest(&digest::SHA256, normal.as_bytes()).as_ref())
+}
+
+/// Logins waiting for a code after the right password. The login form
+/// carries a signed token rather than a session, so nothing is stored until
+/// the code is right, and wrong codes are counted per account.
+pub struct PendingLogins {
+    key: hmac::Key,
+    failures: Mutex<HashMap<i64, Vec<i64>>>,
+}
+
+impl PendingLogins {
+    /// Shares the key `accounts.rs` signs its links with.
+    pub async fn load(inner: &HandlerInner) -> DynResult<PendingLogins> {
+        let mut candidate = [0u8; 32];
+        auth::random_bytes(&mut candidate)?;
+        let key = inner
+            .queries
+            .fetch_or_create_signing_key(&inner.db, &candidate)
+            .await?;
+        Ok(PendingLogins {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            failures: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn payload(user_id: i64, expires: i64, password_hash: &str) -> String {
+        format!("login.{}.{}.{}", user_id, expires, password_hash)
+    }
+
+    /// A token standing for `user_id` having given the password, bound to
+    /// its hash so a password change voids it.
+    pub fn issue(&self, user_id: i64, password_hash: &str) -> String {
+        let expires = Utc::now().timestamp() + PENDING_LIFETIME;
+        let tag = hmac::sign(&self.key, PendingLogins::payload(user_id, expires, password_hash).as_bytes());
+        format!("{}.{}.{}", user_id, expires, auth::to_hex(tag.as_ref()))
+    }
+
+    pub fn user_of(token: &str) -> Option<i64> {
+        token.split('.').next()?.parse().ok()
+    }
+
+    /// Whether `token` is a current one for `user_id`.
+    pub fn verify(&self, token: &str, user_id: i64, password_hash: &str) -> bool {
+        let mut parts = token.splitn(3, '.');
+        let (expires, tag) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(_), Some(expires), Some(tag)) => (expires, tag),
+            _ => return false,
+        };
+        let (expires, tag) = match (expires.parse::<i64>(), auth::from_hex(tag)) {
+            (Ok(expires), Some(tag)) => (expires, tag),
+            _ => return false,
+        };
+        let payload = PendingLogins::payload(user_id, expires, password_hash);
+        hmac::verify(&self.key, payload.as_bytes(), &tag).is_ok() && expires >= Utc::now().timestamp()
+    }
+
+    /// Whether `user_id` has given too many wrong codes lately to try again.
+    pub fn is_locked_out(&self, user_id: i64) -> bool {
+        let now = Utc::now().timestamp();
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, times| {
+            times.retain(|&at| at > now - PENDING_LIFETIME);
+            !times.is_empty()
+        });
+        failures.get(&user_id).map_or(0, Vec::len) >= MAX_FAILURES
+    }
+
+    pub fn record_failure(&self, user_id: i64) {
+        let now = Utc::now().timestamp();
+        self.failures.lock().unwrap().entry(user_id).or_default().push(now);
+    }
+}
@@ -0,0 +1,52 @@
+use askama::Template;
+
+use crate::views::PageContext;
+
+/// Signing up under `--open-registration`, see `accounts.rs`. Holds on to
+/// what was typed when the form comes back with an error.
+#[derive(Template)]
+#[template(path = "account/register.html")]
+pub struct Register<'a> {
+    pub ctx: PageContext,
+    pub username: &'a str,
+    pub email: &'a str,
+    pub error: Option<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "account/forgot.html")]
+pub struct Forgot {
+    pub ctx: PageContext,
+}
+
+/// Choosing a new password, reached from a mailed link.
+#[derive(Template)]
+#[template(path = "account/reset.html")]
+pub struct Reset<'a> {
+    pub ctx: PageContext,
+    pub token: &'a str,
+    pub error: Option<&'a str>,
+}
+
+/// A mailed link that doesn't work any more.
+#[derive(Template)]
+#[template(path = "account/bad_link.html")]
+pub struct BadLink {
+    pub ctx: PageContext,
+    pub message: String,
+}
+
+/// The mail with a verification or password reset link. Like the digest,
+/// links are absolute since it's read outside the wiki.
+#[derive(Template)]
+#[template(path = "account/mail.html")]
+pub struct AccountMail<'a> {
+    pub site_name: &'a str,
+    pub subject: &'a str,
+    pub username: &'a str,
+    /// A password reset rather than a verification.
+    pub reset: bool,
+    pub link: String,
+    /// How long the link works.
+    pub hours: i64,
+}
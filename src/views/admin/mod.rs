@@ -0,0 +1,264 @@
+use askama::Template;
+use chrono::Utc;
+
+use crate::appearance::Appearance;
+use crate::attachments::UploadLimits;
+use crate::metrics::QuerySummary;
+use crate::render_cache::CacheStats;
+use crate::{edit_filter, protection, stats};
+use crate::queries::{EditFilter, EditFilterHit, ProtectionRule, SecretFinding, SiteTotals};
+use crate::routes::{Route, RouteWiki};
+use crate::views::{filters, PageContext};
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+pub struct Admin<'a> {
+    pub ctx: PageContext,
+    pub appearance: &'a Appearance,
+    pub errors: Vec<String>,
+    pub upload_limits: UploadLimits,
+}
+
+impl<'a> Admin<'a> {
+    pub fn admin_link(&self) -> Route<'static> {
+        Route::Admin
+    }
+
+    pub fn logo_link(&self) -> Route<'static> {
+        Route::Logo
+    }
+
+    pub fn filters_link(&self) -> Route<'static> {
+        Route::EditFilters
+    }
+
+    pub fn protection_link(&self) -> Route<'static> {
+        Route::Protection
+    }
+
+    pub fn stats_link(&self) -> Route<'static> {
+        Route::Stats
+    }
+
+    pub fn replace_link(&self) -> Route<'static> {
+        Route::Replace
+    }
+
+    pub fn limit(&self, limit: &Option<u64>) -> String {
+        match limit {
+            Some(bytes) => size(*bytes as f64),
+            None => "unlimited".to_string(),
+        }
+    }
+}
+
+/// `bytes` in KiB, MiB or GiB, whichever reads best.
+fn size(bytes: f64) -> String {
+    const UNITS: &[&str] = &["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/stats.html")]
+pub struct Stats {
+    pub ctx: PageContext,
+    pub totals: SiteTotals,
+    /// Edits over the last `stats::DAYS` days.
+    pub sparkline: stats::Sparkline,
+    pub recent_edits: i64,
+    /// Over the same days, most edits first.
+    pub top_editors: Vec<EditorRecord>,
+    pub cache: CacheStats,
+    pub queries: QuerySummary,
+}
+
+pub struct EditorRecord {
+    pub name: String,
+    pub edits: i64,
+}
+
+pub struct SlowQuery {
+    pub name: &'static str,
+    pub mean_ms: String,
+    pub runs: u64,
+}
+
+impl Stats {
+    pub fn days(&self) -> i64 {
+        stats::DAYS
+    }
+
+    pub fn size(&self, bytes: &i64) -> String {
+        size(*bytes as f64)
+    }
+
+    /// The render cache's hit rate, empty before the first lookup.
+    pub fn hit_rate(&self) -> String {
+        match self.cache.hit_rate() {
+            Some(rate) => format!(" ({:.1}%)", rate),
+            None => String::new(),
+        }
+    }
+
+    pub fn slowest_queries(&self) -> Vec<SlowQuery> {
+        self.queries
+            .slowest
+            .iter()
+            .map(|&(name, mean, runs)| SlowQuery {
+                name,
+                mean_ms: format!("{:.1}", mean.as_secs_f64() * 1000.0),
+                runs,
+            })
+            .collect()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/filters.html")]
+pub struct EditFilters {
+    pub ctx: PageContext,
+    pub filters: Vec<EditFilter>,
+    pub hits: Vec<EditFilterHit>,
+    pub secret_findings: Vec<SecretFinding>,
+    /// `--secrets-policy`.
+    pub secrets_policy: &'static str,
+    pub errors: Vec<String>,
+    pub test: FilterTest,
+    /// A filter that wasn't saved, echoed back with the errors. Its id is 0
+    /// if it was being added.
+    pub rejected: Option<EditFilter>,
+}
+
+impl EditFilters {
+    pub fn filters_link(&self) -> Route<'static> {
+        Route::EditFilters
+    }
+
+    pub fn page_link<'b>(&self, name: &'b str) -> Route<'b> {
+        RouteWiki::to(name)
+    }
+
+    pub fn actions(&self) -> Vec<&'static str> {
+        edit_filter::Action::ALL.iter().map(|action| action.as_str()).collect()
+    }
+
+    pub fn action_selected(&self, filter: &EditFilter, action: &str) -> bool {
+        filter.action == action
+    }
+
+    /// Whether `filter` is the one that failed to save, so it's shown open.
+    pub fn is_rejected(&self, filter: &EditFilter) -> bool {
+        matches!(self.rejected, Some(ref rejected) if rejected.id == filter.id)
+    }
+
+    /// The add form's inputs: blank, or a new filter that failed to save.
+    pub fn new_filter(&self) -> EditFilter {
+        match self.rejected {
+            Some(ref rejected) if rejected.id == 0 => rejected.clone(),
+            _ => EditFilter {
+                id: 0,
+                name: String::new(),
+                rule: String::new(),
+                action: String::new(),
+                enabled: true,
+                updated_by: String::new(),
+                updated_at: Utc::now(),
+            },
+        }
+    }
+
+    pub fn variables(&self) -> &'static [&'static str] {
+        edit_filter::VARIABLES
+    }
+}
+
+/// The test console's inputs, echoed back, and what it found.
+#[derive(Default)]
+pub struct FilterTest {
+    pub rule: String,
+    pub page: String,
+    pub user: String,
+    pub old_text: String,
+    pub new_text: String,
+    pub variables: Vec<(&'static str, String)>,
+    /// Empty until a test is run.
+    pub results: Vec<FilterTestResult>,
+}
+
+pub struct FilterTestResult {
+    pub name: String,
+    pub action: String,
+    pub outcome: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/protection.html")]
+pub struct Protection {
+    pub ctx: PageContext,
+    pub rules: Vec<ProtectedPattern>,
+    pub errors: Vec<String>,
+    /// The add form's inputs, echoed back when they're rejected.
+    pub patterns: String,
+    pub reason: String,
+}
+
+impl Protection {
+    pub fn protection_link(&self) -> Route<'static> {
+        Route::Protection
+    }
+
+    pub fn levels(&self) -> Vec<&'static str> {
+        protection::Level::ALL.iter().map(|level| level.as_str()).collect()
+    }
+}
+
+pub struct ProtectedPattern {
+    pub rule: ProtectionRule,
+    /// How many existing pages the pattern covers.
+    pub pages: usize,
+}
+
+#[derive(Template)]
+#[template(path = "admin/replace.html")]
+pub struct Replace {
+    pub ctx: PageContext,
+    pub errors: Vec<String>,
+    /// The form's inputs, echoed back with the preview.
+    pub find: String,
+    pub replacement: String,
+    pub regex: bool,
+    pub summary: String,
+    /// The pages the replacement would change, once previewed.
+    pub preview: Option<Vec<ReplacePreview>>,
+    /// What became of each page, once applied.
+    pub outcomes: Vec<ReplaceOutcome>,
+}
+
+impl Replace {
+    pub fn replace_link(&self) -> Route<'static> {
+        Route::Replace
+    }
+}
+
+pub struct ReplacePreview {
+    pub name: String,
+    pub link: Route<'static>,
+    pub diff: String,
+}
+
+pub struct ReplaceOutcome {
+    pub name: String,
+    pub link: Route<'static>,
+    /// Why the page wasn't changed, if it wasn't.
+    pub problem: Option<String>,
+}
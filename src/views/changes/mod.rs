@@ -0,0 +1,30 @@
+use askama::Template;
+use chrono::offset::Utc;
+use chrono::DateTime;
+
+use crate::routes::Route;
+use crate::views::wiki::MoveRecord;
+use crate::views::{filters, PageContext};
+
+#[derive(Template)]
+#[template(path = "changes.html")]
+pub struct Changes {
+    pub ctx: PageContext,
+    pub changes: Vec<ChangeRecord>,
+}
+
+pub enum ChangeRecord {
+    Edit(EditRecord),
+    Move(MoveRecord),
+}
+
+pub struct EditRecord {
+    pub name: String,
+    pub link: Route<'static>,
+    pub revision_id: i64,
+    pub revision_link: Route<'static>,
+    pub modified_by: String,
+    pub created_at: DateTime<Utc>,
+    /// A revision newer than the viewer last read.
+    pub unread: bool,
+}
@@ -0,0 +1,23 @@
+use askama::Template;
+
+/// The weekly digest mail, see `digest.rs`. Links are absolute since it's
+/// read outside the wiki.
+#[derive(Template)]
+#[template(path = "digest.html")]
+pub struct Digest<'a> {
+    pub site_name: &'a str,
+    pub subject: &'a str,
+    pub username: &'a str,
+    /// Empty if the reader wants every namespace.
+    pub namespaces: &'a [String],
+    pub new_pages: Vec<DigestEntry>,
+    pub most_edited: Vec<DigestEntry>,
+    pub notable: Vec<DigestEntry>,
+    pub settings_link: String,
+}
+
+pub struct DigestEntry {
+    pub name: String,
+    pub link: String,
+    pub detail: String,
+}
@@ -0,0 +1,29 @@
+use askama::Template;
+
+use crate::routes::{Route, RouteWiki};
+use crate::views::PageContext;
+
+/// Shown when a request fails, see `Handler::respond`.
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct Error {
+    pub ctx: PageContext,
+    pub kind: ErrorKind,
+    /// Existing pages named like a missing one, see `titles.rs`.
+    pub suggestions: Vec<String>,
+}
+
+impl Error {
+    pub fn page_link<'b>(&self, name: &'b str) -> Route<'b> {
+        RouteWiki::to(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    NotFound,
+    /// The request failed or the handler panicked.
+    Failed,
+    /// The database is down, see `database.rs`.
+    Unavailable,
+}
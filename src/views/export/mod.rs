@@ -0,0 +1,33 @@
+//! Standalone pages written by `wiki export-static`. They don't extend
+//! `base.html`: a snapshot has no login, search or editor to link to.
+
+use askama::Template;
+use chrono::{DateTime, Utc};
+
+#[derive(Template)]
+#[template(path = "export/page.html")]
+pub struct Page<'a> {
+    pub site_name: &'a str,
+    pub page_title: &'a str,
+    pub description: String,
+    pub last_modified_at: DateTime<Utc>,
+    pub last_modified_by: &'a str,
+    pub index_link: &'a str,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    pub rendered: String,
+}
+
+#[derive(Template)]
+#[template(path = "export/index.html")]
+pub struct Index<'a> {
+    pub site_name: &'a str,
+    pub exported_at: DateTime<Utc>,
+    pub pages: Vec<IndexEntry<'a>>,
+}
+
+pub struct IndexEntry<'a> {
+    pub name: &'a str,
+    /// Relative to the index.
+    pub link: String,
+}
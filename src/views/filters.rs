@@ -0,0 +1,48 @@
+//! Custom askama filters. Template modules bring this into scope with
+//! `use crate::views::filters;`.
+
+use chrono::{DateTime, FixedOffset, Utc};
+
+use crate::views::PageContext;
+
+/// Formats a timestamp in the viewer's timezone. The offset is the zone's
+/// current one, so timestamps from the other side of a DST change are off by
+/// the DST shift.
+pub fn localtime(at: &DateTime<Utc>, ctx: &PageContext) -> askama::Result<String> {
+    match FixedOffset::east_opt(ctx.utc_offset) {
+        Some(offset) if ctx.utc_offset != 0 => {
+            Ok(at.with_timezone(&offset).format("%Y-%m-%d %H:%M:%S %:z").to_string())
+        }
+        _ => Ok(at.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+    }
+}
+
+/// Renders a `<time>` element showing how long ago `at` was, with the full
+/// local timestamp as a tooltip. The output is HTML, so follow it with `|safe`.
+pub fn timestamp(at: &DateTime<Utc>, ctx: &PageContext) -> askama::Result<String> {
+    Ok(format!(
+        r#"<time datetime="{}" title="{}">{}</time>"#,
+        at.to_rfc3339(),
+        localtime(at, ctx)?,
+        relative(*at, Utc::now())
+    ))
+}
+
+/// "3 hours ago" and so on. Times in the future, which only happen with clock
+/// skew, count as now.
+fn relative(at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (now - at).num_seconds();
+    let (count, unit) = match seconds {
+        s if s < 60 => return "just now".to_string(),
+        s if s < 60 * 60 => (s / 60, "minute"),
+        s if s < 24 * 60 * 60 => (s / (60 * 60), "hour"),
+        s if s < 30 * 24 * 60 * 60 => (s / (24 * 60 * 60), "day"),
+        s if s < 365 * 24 * 60 * 60 => (s / (30 * 24 * 60 * 60), "month"),
+        s => (s / (365 * 24 * 60 * 60), "year"),
+    };
+    if count == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", count, unit)
+    }
+}
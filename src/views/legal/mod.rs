@@ -0,0 +1,15 @@
+use askama::Template;
+
+use crate::routes::Route;
+use crate::views::PageContext;
+
+/// `/about`, `/terms` or `/privacy`, see `footer.rs`.
+#[derive(Template)]
+#[template(path = "legal.html")]
+pub struct Legal {
+    pub ctx: PageContext,
+    pub title: &'static str,
+    /// `None` until the page has been written, only shown to admins.
+    pub html: Option<String>,
+    pub edit_link: Route<'static>,
+}
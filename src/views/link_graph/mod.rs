@@ -0,0 +1,31 @@
+use askama::Template;
+
+use crate::assets;
+use crate::link_graph;
+use crate::routes::Route;
+use crate::views::PageContext;
+
+#[derive(Template)]
+#[template(path = "link_graph.html")]
+pub struct LinkGraph {
+    pub ctx: PageContext,
+    /// `None` until a page has been picked.
+    pub root: Option<String>,
+    pub depth: usize,
+    /// Where `graph.js` fetches the graph from.
+    pub api_link: Option<String>,
+}
+
+impl LinkGraph {
+    pub fn graph_link(&self) -> Route<'static> {
+        Route::LinkGraph
+    }
+
+    pub fn static_link(&self, file: &'static str) -> Route<'static> {
+        Route::Static(assets::hashed_name(file).into())
+    }
+
+    pub fn max_depth(&self) -> usize {
+        link_graph::MAX_DEPTH
+    }
+}
@@ -0,0 +1,17 @@
+use askama::Template;
+
+use crate::views::PageContext;
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct Login<'a> {
+    pub ctx: PageContext,
+    pub error: Option<&'a str>,
+    /// `--open-registration` is on, see `accounts.rs`.
+    pub can_register: bool,
+    /// A mailer is set up, so passwords can be reset.
+    pub can_reset: bool,
+    /// The password was right and a code is needed next, see
+    /// `two_factor.rs`.
+    pub pending: Option<String>,
+}
@@ -0,0 +1,41 @@
+use askama::Template;
+use chrono::offset::Utc;
+use chrono::{DateTime, NaiveDate};
+
+use crate::routes::Route;
+use crate::views::{filters, PageContext};
+
+#[derive(Template)]
+#[template(path = "maintenance/duplicates.html")]
+pub struct Duplicates {
+    pub ctx: PageContext,
+    pub pairs: Vec<DuplicateRecord>,
+    /// Lowest similarity shown, as a percentage.
+    pub threshold: u32,
+}
+
+pub struct DuplicateRecord {
+    pub first: String,
+    pub first_link: String,
+    pub second: String,
+    pub second_link: String,
+    /// Opens the editor for `first` with `second` appended.
+    pub merge_link: String,
+    pub similarity: u32,
+}
+
+#[derive(Template)]
+#[template(path = "maintenance/stale.html")]
+pub struct Stale {
+    pub ctx: PageContext,
+    pub pages: Vec<StaleRecord>,
+}
+
+pub struct StaleRecord {
+    pub name: String,
+    pub link: Route<'static>,
+    pub edit_link: Route<'static>,
+    pub expires_on: NaiveDate,
+    pub modified_at: DateTime<Utc>,
+    pub modified_by: String,
+}
@@ -1 +1,144 @@
+use std::sync::Arc;
+
+use crate::appearance::Appearance;
+use crate::assets;
+use crate::footer;
+use crate::routes::{LegalPage, Route};
+
+pub mod account;
+pub mod admin;
+pub mod changes;
+pub mod digest;
+pub mod error;
+pub mod export;
+pub mod filters;
+pub mod legal;
+pub mod link_graph;
+pub mod login;
+pub mod maintenance;
+pub mod review;
+pub mod search;
+pub mod settings;
+pub mod special;
+pub mod timeout;
+pub mod unread;
 pub mod wiki;
+
+/// Site-wide values every page template needs, rendered by `base.html`.
+pub struct PageContext {
+    pub site_name: String,
+    /// Scheme and host the client used, e.g. `https://wiki.example.com`.
+    pub base_url: String,
+    pub current_user: Option<String>,
+    pub theme: String,
+    /// The viewer's offset from UTC in seconds, see `filters::localtime`.
+    pub utc_offset: i32,
+    pub flash: Option<&'static str>,
+    /// Whether edits are being held for review, see `--moderation`.
+    pub moderation: bool,
+    /// The rendered `sidebar::PAGE`, if it exists.
+    pub sidebar: Option<String>,
+    /// The rendered `footer::PAGE`, if it exists.
+    pub footer: Option<String>,
+    /// The pages linked after the footer, see `footer.rs`.
+    pub legal_pages: Vec<LegalPage>,
+    pub appearance: Arc<Appearance>,
+    pub is_admin: bool,
+    /// Whether the viewer is trusted to review held edits.
+    pub can_review: bool,
+}
+
+impl PageContext {
+    pub fn home_link(&self) -> Route<'static> {
+        Route::Root
+    }
+
+    pub fn search_link(&self) -> Route<'static> {
+        Route::Search
+    }
+
+    pub fn changes_link(&self) -> Route<'static> {
+        Route::Changes
+    }
+
+    pub fn special_link(&self) -> Route<'static> {
+        Route::Special("SpecialPages".into())
+    }
+
+    pub fn legal_link(&self, page: &LegalPage) -> Route<'static> {
+        Route::Legal(*page)
+    }
+
+    pub fn legal_title(&self, page: &LegalPage) -> &'static str {
+        footer::title(*page)
+    }
+
+    pub fn review_link(&self) -> Route<'static> {
+        Route::Review
+    }
+
+    pub fn settings_link(&self) -> Route<'static> {
+        Route::Settings
+    }
+
+    pub fn unread_link(&self) -> Route<'static> {
+        Route::Unread
+    }
+
+    pub fn admin_link(&self) -> Route<'static> {
+        Route::Admin
+    }
+
+    pub fn logo_link(&self) -> Route<'static> {
+        Route::Logo
+    }
+
+    pub fn login_link(&self) -> Route<'static> {
+        Route::Login
+    }
+
+    pub fn logout_link(&self) -> Route<'static> {
+        Route::Logout
+    }
+
+    pub fn static_link(&self, file: &'static str) -> Route<'static> {
+        Route::Static(assets::hashed_name(file).into())
+    }
+}
+
+/// Maps the `flash` query parameter to a message. Only known keys are shown so
+/// links can't inject arbitrary text into the page.
+pub fn flash_message(key: &str) -> Option<&'static str> {
+    match key {
+        "saved" => Some("Your changes have been saved."),
+        "unchanged" => Some("Nothing was saved: the text is the same as the current revision."),
+        "logged-in" => Some("You are now logged in."),
+        "logged-out" => Some("You have been logged out."),
+        "verify-sent" => Some("Your account has been created. Follow the link mailed to you to confirm your address, then log in."),
+        "email-verified" => Some("Your email address has been confirmed."),
+        "verify-sent-settings" => Some("Your settings have been saved. Follow the link mailed to you to confirm your new address."),
+        "reset-sent" => Some("If that account has a confirmed email address, a link to choose a new password is on its way."),
+        "password-reset" => Some("Your password has been changed and you have been logged out everywhere. Log in with the new one."),
+        "moved" => Some("The page has been moved."),
+        "merged" => Some("The pages have been merged."),
+        "redirected" => Some("The page you followed has been merged into this one."),
+        "untranslated" => Some("This page hasn't been translated into that language yet, so it is shown in the default language."),
+        "tagged" => Some("The revision has been tagged."),
+        "redacted" => Some("The revision has been redacted."),
+        "pending" => Some("Your changes have been saved and will appear once a reviewer approves them."),
+        "approved" => Some("The revision has been approved."),
+        "rejected" => Some("The revision has been rejected."),
+        "already-reviewed" => Some("That revision has already been reviewed."),
+        "review-outdated" => Some("The page was edited after that revision was written, so approving it would undo those edits. Reject it and make the change by hand."),
+        "settings-saved" => Some("Your settings have been saved."),
+        "appearance-saved" => Some("The site appearance has been saved."),
+        "filter-saved" => Some("The edit filter has been saved."),
+        "filter-deleted" => Some("The edit filter has been deleted."),
+        "protection-saved" => Some("The protection has been saved."),
+        "protection-removed" => Some("The protection has been removed."),
+        "session-revoked" => Some("That device has been logged out."),
+        "logged-out-everywhere" => Some("You have been logged out on every device."),
+        "marked-read" => Some("Every page has been marked as read."),
+        _ => None,
+    }
+}
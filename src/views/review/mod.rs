@@ -0,0 +1,31 @@
+use askama::Template;
+use chrono::offset::Utc;
+use chrono::DateTime;
+
+use crate::routes::Route;
+use crate::views::{filters, PageContext};
+
+#[derive(Template)]
+#[template(path = "review.html")]
+pub struct Review {
+    pub ctx: PageContext,
+    pub pending: Vec<PendingRecord>,
+}
+
+impl Review {
+    pub fn review_link(&self) -> Route<'static> {
+        Route::Review
+    }
+}
+
+pub struct PendingRecord {
+    pub name: String,
+    pub link: Route<'static>,
+    pub revision_id: i64,
+    pub revision_link: Route<'static>,
+    /// Diff against the current revision, unless the page is new.
+    pub diff_link: Option<Route<'static>>,
+    pub modified_by: String,
+    pub created_at: DateTime<Utc>,
+    pub size: i32,
+}
@@ -0,0 +1,34 @@
+use askama::Template;
+use chrono::offset::Utc;
+use chrono::DateTime;
+
+use crate::routes::{Route, RouteWiki};
+use crate::views::{filters, PageContext};
+
+#[derive(Template)]
+#[template(path = "search/results.html")]
+pub struct Results<'a> {
+    pub ctx: PageContext,
+    pub query: &'a str,
+    pub namespace: &'a str,
+    pub tag: &'a str,
+    pub author: &'a str,
+    pub before: &'a str,
+    pub after: &'a str,
+    pub results: Vec<SearchResult>,
+    /// Pages with names like the search text, see `titles.rs`.
+    pub did_you_mean: Vec<String>,
+}
+
+impl<'a> Results<'a> {
+    pub fn page_link<'b>(&self, name: &'b str) -> Route<'b> {
+        RouteWiki::to(name)
+    }
+}
+
+pub struct SearchResult {
+    pub name: String,
+    pub link: Route<'static>,
+    pub last_modified_at: DateTime<Utc>,
+    pub last_modified_by: String,
+}
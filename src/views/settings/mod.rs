@@ -0,0 +1,109 @@
+use askama::Template;
+
+use crate::digest;
+use crate::preferences::{Editor, Preferences, THEMES};
+use crate::queries::SessionRecord;
+use crate::routes::Route;
+use crate::trust::TrustLevel;
+use crate::views::{filters, PageContext};
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+pub struct Settings<'a> {
+    pub ctx: PageContext,
+    pub username: &'a str,
+    pub preferences: &'a Preferences,
+    pub edit_count: i64,
+    pub trust: TrustLevel,
+    pub errors: Vec<String>,
+}
+
+impl<'a> Settings<'a> {
+    pub fn settings_link(&self) -> Route<'static> {
+        Route::Settings
+    }
+
+    pub fn two_factor_link(&self) -> Route<'static> {
+        Route::TwoFactor
+    }
+
+    pub fn sessions_link(&self) -> Route<'static> {
+        Route::Sessions
+    }
+
+    pub fn unread_link(&self) -> Route<'static> {
+        Route::Unread
+    }
+
+    pub fn main_namespace(&self) -> &'static str {
+        digest::MAIN_NAMESPACE
+    }
+
+    pub fn themes(&self) -> &'static [&'static str] {
+        THEMES
+    }
+
+    pub fn toolbar_selected(&self) -> bool {
+        self.preferences.editor == Editor::Toolbar
+    }
+
+    pub fn theme_selected(&self, theme: &str) -> bool {
+        self.preferences.theme.as_deref() == Some(theme)
+    }
+}
+
+/// Turning two-factor authentication on and off, see `two_factor.rs`.
+#[derive(Template)]
+#[template(path = "two_factor.html")]
+pub struct TwoFactor<'a> {
+    pub ctx: PageContext,
+    pub enabled: bool,
+    pub recovery_codes_left: i64,
+    /// Shown once, right after they're made.
+    pub new_recovery_codes: Vec<String>,
+    /// `None` once it's on.
+    pub enrollment: Option<Enrollment>,
+    /// The user is an admin under `--require-admin-2fa`.
+    pub admin_required: bool,
+    pub error: Option<&'a str>,
+}
+
+impl<'a> TwoFactor<'a> {
+    pub fn two_factor_link(&self) -> Route<'static> {
+        Route::TwoFactor
+    }
+
+    pub fn settings_link(&self) -> Route<'static> {
+        Route::Settings
+    }
+}
+
+/// A new secret for the authenticator app, as text and as a QR code.
+pub struct Enrollment {
+    pub secret: String,
+    pub qr_svg: String,
+}
+
+/// The user's logged-in devices, each of which can be logged out.
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub struct Sessions {
+    pub ctx: PageContext,
+    pub sessions: Vec<SessionRecord>,
+    /// The session making this request.
+    pub current: String,
+}
+
+impl Sessions {
+    pub fn sessions_link(&self) -> Route<'static> {
+        Route::Sessions
+    }
+
+    pub fn settings_link(&self) -> Route<'static> {
+        Route::Settings
+    }
+
+    pub fn is_current(&self, session: &SessionRecord) -> bool {
+        session.token == self.current
+    }
+}
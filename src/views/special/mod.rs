@@ -0,0 +1,52 @@
+use askama::Template;
+
+use crate::routes::{Route, RouteWiki};
+use crate::views::PageContext;
+
+#[derive(Template)]
+#[template(path = "special/index.html")]
+pub struct SpecialPages {
+    pub ctx: PageContext,
+    pub pages: Vec<SpecialRecord>,
+}
+
+pub struct SpecialRecord {
+    pub name: &'static str,
+    pub link: Route<'static>,
+    pub description: &'static str,
+}
+
+/// A titled list of pages, shared by the simpler reports.
+#[derive(Template)]
+#[template(path = "special/page_list.html")]
+pub struct PageList {
+    pub ctx: PageContext,
+    pub title: &'static str,
+    pub intro: &'static str,
+    pub empty: &'static str,
+    /// Heading for the `count` column, if the report has one.
+    pub count_heading: Option<&'static str>,
+    pub pages: Vec<PageRecord>,
+}
+
+pub struct PageRecord {
+    pub name: String,
+    pub link: Route<'static>,
+    /// Whether `link` leads to the editor because the page doesn't exist.
+    pub missing: bool,
+    pub count: i64,
+    /// Changed since the viewer last read it, see `Queries::fetch_unread_pages`.
+    pub unread: bool,
+}
+
+impl PageRecord {
+    pub fn existing(name: String) -> PageRecord {
+        PageRecord {
+            link: RouteWiki::to(&name).to_owned(),
+            missing: false,
+            count: 0,
+            unread: false,
+            name,
+        }
+    }
+}
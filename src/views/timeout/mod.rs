@@ -0,0 +1,11 @@
+use askama::Template;
+
+use crate::views::PageContext;
+
+#[derive(Template)]
+#[template(path = "timeout.html")]
+pub struct Timeout {
+    pub ctx: PageContext,
+    /// Whether the request could have changed something.
+    pub write: bool,
+}
@@ -0,0 +1,34 @@
+use askama::Template;
+use chrono::offset::Utc;
+use chrono::DateTime;
+
+use crate::routes::Route;
+use crate::views::{filters, PageContext};
+
+#[derive(Template)]
+#[template(path = "unread.html")]
+pub struct Unread {
+    pub ctx: PageContext,
+    pub pages: Vec<UnreadRecord>,
+    /// What the list is limited to, see `follow.rs`.
+    pub follow: Vec<String>,
+}
+
+impl Unread {
+    pub fn unread_link(&self) -> Route<'static> {
+        Route::Unread
+    }
+
+    pub fn settings_link(&self) -> Route<'static> {
+        Route::Settings
+    }
+}
+
+pub struct UnreadRecord {
+    pub name: String,
+    pub link: Route<'static>,
+    /// Changes since the last revision read, or `None` for a page never read.
+    pub diff_link: Option<Route<'static>>,
+    pub modified_by: String,
+    pub modified_at: DateTime<Utc>,
+}
@@ -1,53 +1,345 @@
 use askama::Template;
 use chrono::offset::Utc;
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDate};
 
+use crate::assets;
+use crate::challenge::IssuedChallenge;
+use crate::previews::Preview;
+use crate::queries::{Redaction, RevisionStatus};
+use crate::revision_graph;
 use crate::routes::{Route, RouteWiki};
+use crate::views::{filters, PageContext};
 
 #[derive(Template)]
 #[template(path = "wiki/history.html")]
 pub struct History<'a> {
+    pub ctx: PageContext,
     pub page_title: &'a str,
     pub history_records: Vec<HistoryRecord>,
+    pub moves: Vec<MoveRecord>,
+    pub can_edit: bool,
+    /// Whether to offer redacting revisions.
+    pub can_admin: bool,
 }
 
 impl<'a> History<'a> {
     pub fn route_view(&self) -> Route<'a> {
         RouteWiki::to(self.page_title)
     }
+
+    pub fn route_export_git(&self) -> Route<'a> {
+        RouteWiki::to_export_git(self.page_title)
+    }
+
+    pub fn route_history(&self) -> Route<'a> {
+        RouteWiki::to_history(self.page_title)
+    }
+
+    pub fn route_graph(&self) -> Route<'a> {
+        RouteWiki::to_graph(self.page_title)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "wiki/graph.html")]
+pub struct Graph<'a> {
+    pub ctx: PageContext,
+    pub page_title: &'a str,
+    pub layout: revision_graph::Layout,
+    /// Whether older revisions were left out, see `revision_graph::LIMIT`.
+    pub truncated: bool,
+}
+
+impl<'a> Graph<'a> {
+    pub fn route_history(&self) -> Route<'a> {
+        RouteWiki::to_history(self.page_title)
+    }
+
+    pub fn revision_link(&self, revision: &i64) -> Route<'a> {
+        RouteWiki::to_revision(self.page_title, *revision)
+    }
 }
 
 pub struct HistoryRecord {
     pub created_at: DateTime<Utc>,
     pub document_history_id: i64,
     pub created_by: String,
+    pub size: i32,
+    pub size_delta: i32,
     pub link: Route<'static>,
+    pub tags: Vec<TagLink>,
+    /// Edit filters that tagged the revision.
+    pub filter_tags: Vec<String>,
+    pub tag_link: Route<'static>,
+    pub status: RevisionStatus,
+    pub reviewed_by: Option<String>,
+    pub redaction: Option<Redaction>,
+    /// Why an automatic edit was made.
+    pub summary: Option<String>,
+    pub redact_link: Route<'static>,
+}
+
+pub struct TagLink {
+    pub label: String,
+    pub link: Route<'static>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl HistoryRecord {
+    /// Review state for revisions that went through moderation, empty for
+    /// ones that were published directly.
+    pub fn review_display(&self) -> String {
+        let reviewer = self.reviewed_by.as_deref().unwrap_or("?");
+        match self.status {
+            RevisionStatus::Published => String::new(),
+            RevisionStatus::Pending => "Pending review".to_string(),
+            RevisionStatus::Approved => format!("Approved by {}", reviewer),
+            RevisionStatus::Rejected => format!("Rejected by {}", reviewer),
+        }
+    }
+
+    pub fn size_delta_display(&self) -> String {
+        match self.size_delta {
+            d if d > 0 => format!("+{}", d),
+            d if d < 0 => format!("\u{2212}{}", -d),
+            _ => "0".to_string(),
+        }
+    }
 }
 
 
 #[derive(Template)]
 #[template(path = "wiki/view.html")]
 pub struct View<'a> {
+    pub ctx: PageContext,
     pub page_title: &'a str,
+    /// Plain text for the meta description and link previews.
+    pub description: String,
+    /// Absolute URL of the first image on the page, for link previews.
+    pub image: Option<String>,
     pub last_modified_at: DateTime<Utc>,
     pub last_modified_by: String,
     pub history_link: Route<'static>,
     pub edit_link: Route<'static>,
+    pub move_link: Route<'static>,
+    pub merge_link: Route<'static>,
+    pub export_link: Route<'static>,
+    /// The map of pages around this one, see `link_graph.rs`.
+    pub link_graph_link: String,
+    pub attachments_link: Route<'static>,
+    pub canonical_link: Route<'static>,
+    /// This revision, which keeps showing the same text.
+    pub permalink: Route<'static>,
+    /// Set when showing a revision other than the current one.
+    pub old_revision: Option<i64>,
+    /// The page's `expires:` date once it has passed, see `front_matter.rs`.
+    pub expired_on: Option<NaiveDate>,
+    pub annotate_link: Route<'static>,
+    /// Solved in the browser before an annotation is posted, as in the
+    /// editor.
+    pub annotate_challenge: Option<IssuedChallenge>,
+    pub annotations: Vec<Annotation>,
+    /// Labels on the revision shown.
+    pub tags: Vec<String>,
+    /// Pages linking here, shown next to the link graph.
+    pub backlink_count: i64,
+    pub can_edit: bool,
+    pub can_annotate: bool,
+    pub can_admin: bool,
+    /// Namespace snippets, see `snippets.rs`.
+    pub header: Option<String>,
+    pub footer: Option<String>,
     pub rendered: String,
+    pub attachments: Vec<AttachmentRecord>,
+    /// The page's language, when `--languages` is set.
+    pub language: Option<String>,
+    /// The existing translations of the page, this one included, see
+    /// `languages.rs`.
+    pub variants: Vec<LanguageVariant>,
+}
+
+pub struct LanguageVariant {
+    pub code: String,
+    pub link: Route<'static>,
+    pub current: bool,
+    /// Whether it's in the default language, for `hreflang="x-default"`.
+    pub is_default: bool,
+}
+
+impl<'a> View<'a> {
+    pub fn login_link(&self) -> Route<'static> {
+        Route::Login
+    }
+
+    pub fn static_link(&self, file: &'static str) -> Route<'static> {
+        Route::Static(assets::hashed_name(file).into())
+    }
+}
+
+pub struct Annotation {
+    pub created_at: DateTime<Utc>,
+    pub created_by: String,
+    pub start_offset: i32,
+    pub end_offset: i32,
+    pub quote: String,
+    pub body: String,
 }
 
 #[derive(Template)]
 #[template(path = "wiki/diff.html")]
 pub struct Diff<'a> {
+    pub ctx: PageContext,
     pub page_title: &'a str,
     pub first: RevisionSpec,
     pub second: RevisionSpec,
+    /// Large diffs are split into pages, see `diff.rs`.
+    pub page: usize,
+    pub pages: usize,
+    pub previous_link: Option<String>,
+    pub next_link: Option<String>,
     pub rendered: String,
 }
 
+impl<'a> Diff<'a> {
+    pub fn static_link(&self, file: &'static str) -> Route<'static> {
+        Route::Static(assets::hashed_name(file).into())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "wiki/edit.html")]
+pub struct Edit<'a> {
+    pub ctx: PageContext,
+    pub page_title: &'a str,
+    pub document_data: String,
+    /// Rendered edit notices for the page and its namespace, see
+    /// `edit_notices.rs`.
+    pub notices: Vec<String>,
+    pub view_link: Route<'static>,
+    pub preview_diff_link: Route<'static>,
+    /// Set when the editor is anonymous and a challenge is required to save.
+    pub challenge: Option<IssuedChallenge>,
+    /// Whether the Markdown toolbar is switched on, from the user's settings
+    /// or, for anonymous editors, a cookie.
+    pub toolbar: bool,
+    pub logged_in: bool,
+}
+
+/// Cookie holding the editor mode of anonymous editors, `toolbar` or `plain`.
+pub const EDITOR_COOKIE: &str = "editor";
+
+impl<'a> Edit<'a> {
+    pub fn static_link(&self, file: &'static str) -> Route<'static> {
+        Route::Static(assets::hashed_name(file).into())
+    }
+
+    pub fn settings_link(&self) -> Route<'static> {
+        Route::Settings
+    }
+}
+
 pub struct RevisionSpec {
     pub document_history_id: i64,
     pub created_at: DateTime<Utc>,
     pub created_by: String,
     pub history_link: Route<'static>,
 }
+
+#[derive(Template)]
+#[template(path = "wiki/attachments.html")]
+pub struct Attachments<'a> {
+    pub ctx: PageContext,
+    pub page_title: &'a str,
+    pub view_link: Route<'static>,
+    pub attachments: Vec<AttachmentRecord>,
+}
+
+pub struct AttachmentRecord {
+    pub filename: String,
+    pub content_hash: String,
+    pub content_type: String,
+    pub size: i64,
+    pub uploaded_by: String,
+    pub uploaded_at: DateTime<Utc>,
+    pub link: Route<'static>,
+    /// Addressed by content hash, so it always shows these bytes.
+    pub permalink: Route<'static>,
+    pub preview: Preview,
+}
+
+#[derive(Template)]
+#[template(path = "wiki/move.html")]
+pub struct Move<'a> {
+    pub ctx: PageContext,
+    pub page_title: &'a str,
+    pub view_link: Route<'static>,
+    pub move_link: Route<'static>,
+    pub new_name: &'a str,
+    pub reason: &'a str,
+    /// Pages linking here, whose links can be updated, see
+    /// `rename_links.rs`.
+    pub backlinks: Vec<String>,
+    pub update_links: bool,
+    /// Set by `--update-links-on-move`, which updates them regardless.
+    pub always_update_links: bool,
+    pub error: Option<String>,
+}
+
+impl<'a> Move<'a> {
+    pub fn page_link<'b>(&self, name: &'b str) -> Route<'b> {
+        RouteWiki::to(name)
+    }
+}
+
+/// Shown after a move when links on some pages weren't updated.
+#[derive(Template)]
+#[template(path = "wiki/moved.html")]
+pub struct Moved<'a> {
+    pub ctx: PageContext,
+    pub old_name: &'a str,
+    pub new_name: &'a str,
+    pub new_link: Route<'static>,
+    pub not_updated: Vec<LinkUpdate>,
+}
+
+pub struct LinkUpdate {
+    pub name: String,
+    pub link: Route<'static>,
+    /// Why its links still point at the old name, see `replace::Outcome`.
+    pub problem: String,
+}
+
+#[derive(Template)]
+#[template(path = "wiki/merge.html")]
+pub struct Merge<'a> {
+    pub ctx: PageContext,
+    pub page_title: &'a str,
+    pub view_link: Route<'static>,
+    pub merge_link: Route<'static>,
+    pub into: &'a str,
+    pub reason: &'a str,
+    /// Set once a page to merge into has been chosen.
+    pub preview: Option<MergePreview>,
+    pub error: Option<String>,
+}
+
+pub struct MergePreview {
+    pub into_link: Route<'static>,
+    /// The combined text, editable before confirming.
+    pub document_data: String,
+    pub rendered: String,
+    pub revisions: i64,
+    pub conflicting_tags: Vec<String>,
+    pub conflicting_attachments: Vec<String>,
+}
+
+pub struct MoveRecord {
+    pub old_name: String,
+    pub old_link: Route<'static>,
+    pub new_name: String,
+    pub new_link: Route<'static>,
+    pub moved_by: String,
+    pub moved_at: DateTime<Utc>,
+    pub reason: String,
+}
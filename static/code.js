@@ -0,0 +1,42 @@
+// A "Copy" button on each code block in a page. The label is drawn by CSS
+// from data-label, so the buttons add no text to #content and don't shift
+// the offsets annotations are stored at. Hidden where the clipboard API
+// isn't available, which includes pages not served over HTTPS.
+(function () {
+    var content = document.getElementById("content");
+    if (!content || !navigator.clipboard) {
+        return;
+    }
+
+    function codeText(code) {
+        var copy = code.cloneNode(true);
+        copy.querySelectorAll(".code-language").forEach(function (label) {
+            label.remove();
+        });
+        return copy.textContent;
+    }
+
+    content.querySelectorAll("pre > code").forEach(function (code) {
+        var pre = code.parentNode;
+        var button = document.createElement("button");
+        button.type = "button";
+        button.className = "copy-code";
+        button.dataset.label = "Copy";
+        button.setAttribute("aria-label", "Copy code");
+        button.addEventListener("click", function () {
+            navigator.clipboard.writeText(codeText(code)).then(
+                function () {
+                    button.dataset.label = "Copied";
+                },
+                function () {
+                    button.dataset.label = "Copy failed";
+                }
+            );
+            setTimeout(function () {
+                button.dataset.label = "Copy";
+            }, 2000);
+        });
+        pre.classList.add("has-copy-code");
+        pre.insertBefore(button, code);
+    });
+})();
@@ -0,0 +1,26 @@
+// Collapsed stretches of unchanged lines on a diff page that were too long
+// to send along carry the address of their lines in data-src; fetch them
+// the first time they're opened.
+(function () {
+    document.querySelectorAll("details.diff-context[data-src]").forEach(function (details) {
+        details.addEventListener("toggle", function () {
+            if (!details.open || details.dataset.loaded) {
+                return;
+            }
+            details.dataset.loaded = "true";
+            fetch(details.dataset.src, { credentials: "same-origin" })
+                .then(function (response) {
+                    if (!response.ok) {
+                        throw new Error(response.statusText);
+                    }
+                    return response.text();
+                })
+                .then(function (html) {
+                    details.insertAdjacentHTML("beforeend", html);
+                })
+                .catch(function () {
+                    details.insertAdjacentHTML("beforeend", "<p class=\"error\">Couldn't load these lines.</p>");
+                });
+        });
+    });
+})();
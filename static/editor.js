@@ -0,0 +1,60 @@
+// Markdown toolbar for the page editor. Every button edits the textarea
+// text directly, so saving still sends plain Markdown.
+(function () {
+    var textarea = document.querySelector("#editor textarea[name=document]");
+    if (!textarea) {
+        return;
+    }
+
+    function wrap(before, after, placeholder) {
+        var start = textarea.selectionStart;
+        var end = textarea.selectionEnd;
+        var selected = textarea.value.slice(start, end) || placeholder;
+        textarea.setRangeText(before + selected + after, start, end, "end");
+        textarea.setSelectionRange(start + before.length, start + before.length + selected.length);
+        textarea.focus();
+    }
+
+    function prefixLines(prefix) {
+        var value = textarea.value;
+        var start = value.lastIndexOf("\n", textarea.selectionStart - 1) + 1;
+        var end = textarea.selectionEnd;
+        var lines = value.slice(start, end).split("\n").map(function (line) {
+            return prefix + line;
+        });
+        textarea.setRangeText(lines.join("\n"), start, end, "select");
+        textarea.focus();
+    }
+
+    var buttons = [
+        ["B", "Bold", function () { wrap("**", "**", "bold text"); }],
+        ["I", "Italic", function () { wrap("_", "_", "italic text"); }],
+        ["S", "Strikethrough", function () { wrap("~~", "~~", "struck text"); }],
+        ["H", "Heading", function () { prefixLines("## "); }],
+        ["Link", "Link to a page", function () {
+            var target = prompt("Page name or URL");
+            if (target === null) {
+                return;
+            }
+            if (!/^[a-z]+:/i.test(target) && target.charAt(0) !== "/") {
+                target = "/wiki/" + encodeURIComponent(target);
+            }
+            wrap("[", "](" + target + ")", "link text");
+        }],
+        ["Code", "Inline code", function () { wrap("`", "`", "code"); }],
+        ["List", "Bulleted list", function () { prefixLines("- "); }],
+        ["Quote", "Quote", function () { prefixLines("> "); }],
+    ];
+
+    var toolbar = document.createElement("div");
+    toolbar.className = "editor-toolbar";
+    buttons.forEach(function (b) {
+        var button = document.createElement("button");
+        button.type = "button";
+        button.textContent = b[0];
+        button.title = b[1];
+        button.addEventListener("click", b[2]);
+        toolbar.appendChild(button);
+    });
+    textarea.parentNode.insertBefore(toolbar, textarea);
+})();
@@ -0,0 +1,206 @@
+// The map on /graph: fetches the pages around a root from /api/v1/graph
+// and lays them out with a small force simulation. Linked pages pull
+// together, all pages push apart. Pages can be dragged; a click opens one
+// and a double click makes it the root.
+(function () {
+    var svg = document.querySelector("svg.link-graph[data-src]");
+    if (!svg) {
+        return;
+    }
+    var status = document.querySelector(".link-graph-status");
+    var SVG_NS = "http://www.w3.org/2000/svg";
+    var RADIUS = 6;
+    var SPRING = 0.02;
+    var SPRING_LENGTH = 70;
+    var REPULSION = 1800;
+    var GRAVITY = 0.01;
+    var DAMPING = 0.85;
+    var STEPS = 300;
+
+    function element(name, attributes) {
+        var el = document.createElementNS(SVG_NS, name);
+        Object.keys(attributes).forEach(function (key) {
+            el.setAttribute(key, attributes[key]);
+        });
+        return el;
+    }
+
+    function draw(graph) {
+        var width = svg.clientWidth;
+        var height = svg.clientHeight;
+        var byName = {};
+        var nodes = graph.nodes.map(function (node, i) {
+            // start on a spiral so no two pages sit on the same spot
+            var angle = i * 2.4;
+            var radius = 20 + node.distance * SPRING_LENGTH + i;
+            var n = {
+                data: node,
+                x: width / 2 + Math.cos(angle) * radius,
+                y: height / 2 + Math.sin(angle) * radius,
+                vx: 0,
+                vy: 0,
+                pinned: i === 0,
+            };
+            byName[node.name] = n;
+            return n;
+        });
+        var edges = graph.edges.map(function (edge) {
+            return { source: byName[edge.source], target: byName[edge.target] };
+        });
+        nodes[0].x = width / 2;
+        nodes[0].y = height / 2;
+
+        var lines = edges.map(function (edge) {
+            var line = element("line", {});
+            svg.appendChild(line);
+            return line;
+        });
+        var groups = nodes.map(function (n, i) {
+            var group = element("g", {});
+            var circle = element("circle", {
+                r: i === 0 ? RADIUS * 1.5 : RADIUS,
+                class: i === 0 ? "root" : n.data.exists ? "" : "missing",
+            });
+            var title = element("title", {});
+            title.textContent = n.data.name;
+            circle.appendChild(title);
+            var label = element("text", { x: RADIUS + 4, y: 4 });
+            label.textContent = n.data.name;
+            group.appendChild(circle);
+            group.appendChild(label);
+            svg.appendChild(group);
+            dragBehaviour(circle, n);
+            return group;
+        });
+
+        function render() {
+            edges.forEach(function (edge, i) {
+                lines[i].setAttribute("x1", edge.source.x);
+                lines[i].setAttribute("y1", edge.source.y);
+                lines[i].setAttribute("x2", edge.target.x);
+                lines[i].setAttribute("y2", edge.target.y);
+            });
+            nodes.forEach(function (n, i) {
+                groups[i].setAttribute("transform", "translate(" + n.x + "," + n.y + ")");
+            });
+        }
+
+        function step() {
+            nodes.forEach(function (a, i) {
+                for (var j = i + 1; j < nodes.length; j++) {
+                    var b = nodes[j];
+                    var dx = b.x - a.x;
+                    var dy = b.y - a.y;
+                    var distanceSquared = Math.max(dx * dx + dy * dy, 1);
+                    var distance = Math.sqrt(distanceSquared);
+                    var force = REPULSION / distanceSquared;
+                    a.vx -= (force * dx) / distance;
+                    a.vy -= (force * dy) / distance;
+                    b.vx += (force * dx) / distance;
+                    b.vy += (force * dy) / distance;
+                }
+                a.vx += (width / 2 - a.x) * GRAVITY;
+                a.vy += (height / 2 - a.y) * GRAVITY;
+            });
+            edges.forEach(function (edge) {
+                var dx = edge.target.x - edge.source.x;
+                var dy = edge.target.y - edge.source.y;
+                var distance = Math.max(Math.sqrt(dx * dx + dy * dy), 1);
+                var force = (distance - SPRING_LENGTH) * SPRING;
+                edge.source.vx += (force * dx) / distance;
+                edge.source.vy += (force * dy) / distance;
+                edge.target.vx -= (force * dx) / distance;
+                edge.target.vy -= (force * dy) / distance;
+            });
+            nodes.forEach(function (n) {
+                if (n.pinned || n.dragging) {
+                    n.vx = 0;
+                    n.vy = 0;
+                    return;
+                }
+                n.vx *= DAMPING;
+                n.vy *= DAMPING;
+                n.x = Math.min(Math.max(n.x + n.vx, RADIUS), width - RADIUS);
+                n.y = Math.min(Math.max(n.y + n.vy, RADIUS), height - RADIUS);
+            });
+        }
+
+        var remaining = 0;
+        function animate() {
+            step();
+            render();
+            remaining--;
+            if (remaining > 0) {
+                window.requestAnimationFrame(animate);
+            }
+        }
+        function wake() {
+            if (remaining <= 0) {
+                remaining = STEPS;
+                window.requestAnimationFrame(animate);
+            }
+            remaining = STEPS;
+        }
+
+        function dragBehaviour(circle, n) {
+            var moved = false;
+            circle.addEventListener("pointerdown", function (event) {
+                circle.setPointerCapture(event.pointerId);
+                n.dragging = true;
+                moved = false;
+            });
+            circle.addEventListener("pointermove", function (event) {
+                if (!n.dragging) {
+                    return;
+                }
+                var box = svg.getBoundingClientRect();
+                n.x = event.clientX - box.left;
+                n.y = event.clientY - box.top;
+                moved = true;
+                wake();
+            });
+            circle.addEventListener("pointerup", function () {
+                n.dragging = false;
+                // a dragged page stays where it was left
+                n.pinned = n.pinned || moved;
+            });
+            // wait to see whether a click is the first of a double click
+            var opening = null;
+            circle.addEventListener("click", function () {
+                if (!moved && opening === null) {
+                    opening = setTimeout(function () {
+                        window.location = n.data.link;
+                    }, 250);
+                }
+            });
+            circle.addEventListener("dblclick", function () {
+                clearTimeout(opening);
+                var url = new URL(window.location.href);
+                url.searchParams.set("root", n.data.name);
+                window.location = url.toString();
+            });
+        }
+
+        if (graph.truncated) {
+            status.textContent = "Only the closest " + nodes.length + " pages are shown.";
+        }
+        wake();
+    }
+
+    fetch(svg.dataset.src, { credentials: "same-origin" })
+        .then(function (response) {
+            if (response.status === 404) {
+                throw new Error("That page doesn't exist.");
+            }
+            if (!response.ok) {
+                throw new Error("Couldn't load the graph.");
+            }
+            return response.json();
+        })
+        .then(draw)
+        .catch(function (err) {
+            svg.hidden = true;
+            status.textContent = err.message;
+            status.className += " error";
+        });
+})();
@@ -0,0 +1,98 @@
+// Page previews: hovering a link to another wiki page shows its first
+// paragraph and image, fetched from /api/v1/wiki/<name>/summary.
+(function () {
+    var content = document.getElementById("content");
+    if (!content) {
+        return;
+    }
+    // see WIKI_PREFIX and API_WIKI_PREFIX in routes.rs
+    var WIKI_PREFIX = "/wiki/";
+    var API_WIKI_PREFIX = "/api/v1/wiki/";
+    var DELAY = 400;
+
+    var card = document.createElement("div");
+    card.className = "hovercard";
+    card.hidden = true;
+    document.body.appendChild(card);
+
+    var summaries = {};
+    var timer = null;
+    var current = null;
+
+    function summaryUrl(link) {
+        if (link.origin !== window.location.origin || link.classList.contains("missing")) {
+            return null;
+        }
+        var path = link.pathname;
+        if (path.indexOf(WIKI_PREFIX) !== 0) {
+            return null;
+        }
+        var name = path.slice(WIKI_PREFIX.length);
+        // subpages such as /history or /edit aren't previewed
+        if (!name || name.indexOf("/") !== -1) {
+            return null;
+        }
+        return API_WIKI_PREFIX + name + "/summary";
+    }
+
+    function fetchSummary(url) {
+        if (!summaries[url]) {
+            summaries[url] = fetch(url, { headers: { "Accept": "application/json" } }).then(function (resp) {
+                return resp.ok ? resp.json() : null;
+            });
+        }
+        return summaries[url];
+    }
+
+    function show(link, summary) {
+        card.textContent = "";
+        if (summary.thumbnail) {
+            var img = document.createElement("img");
+            img.src = summary.thumbnail;
+            img.alt = "";
+            card.appendChild(img);
+        }
+        var title = document.createElement("b");
+        title.textContent = summary.name;
+        card.appendChild(title);
+        var extract = document.createElement("p");
+        extract.textContent = summary.extract || "This page has no text yet.";
+        card.appendChild(extract);
+
+        var rect = link.getBoundingClientRect();
+        card.style.left = (window.scrollX + rect.left) + "px";
+        card.style.top = (window.scrollY + rect.bottom + 4) + "px";
+        card.hidden = false;
+    }
+
+    function hide() {
+        clearTimeout(timer);
+        current = null;
+        card.hidden = true;
+    }
+
+    content.addEventListener("mouseover", function (ev) {
+        var link = ev.target.closest("a[href]");
+        if (!link || link === current) {
+            return;
+        }
+        var url = summaryUrl(link);
+        if (!url) {
+            return;
+        }
+        hide();
+        current = link;
+        timer = setTimeout(function () {
+            fetchSummary(url).then(function (summary) {
+                if (summary && current === link) {
+                    show(link, summary);
+                }
+            });
+        }, DELAY);
+    });
+    content.addEventListener("mouseout", function (ev) {
+        if (current && !current.contains(ev.relatedTarget)) {
+            hide();
+        }
+    });
+})();
@@ -0,0 +1,23 @@
+// The "Copy" button next to "Permanent link" on a page: copies the absolute
+// address of the revision being shown. Hidden where the clipboard API isn't
+// available, which includes pages not served over HTTPS.
+(function () {
+    var button = document.querySelector(".copy-permalink");
+    if (!button || !navigator.clipboard) {
+        return;
+    }
+    button.hidden = false;
+    button.addEventListener("click", function () {
+        navigator.clipboard.writeText(button.dataset.url).then(
+            function () {
+                button.textContent = "Copied";
+            },
+            function () {
+                button.textContent = "Copy failed";
+            }
+        );
+        setTimeout(function () {
+            button.textContent = "Copy";
+        }, 2000);
+    });
+})();
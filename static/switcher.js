@@ -0,0 +1,122 @@
+// The quick switcher: Ctrl+K (Cmd+K on macOS) opens a box that jumps to a
+// page by name, forgiving typos, using /api/v1/titles. Arrow keys pick a
+// page, Enter opens it and Escape closes the box.
+(function () {
+    // see API_TITLES_PATH in routes.rs
+    var API_TITLES_PATH = "/api/v1/titles";
+    var DELAY = 150;
+
+    var box = document.createElement("div");
+    box.className = "switcher";
+    box.hidden = true;
+    var input = document.createElement("input");
+    input.type = "search";
+    input.placeholder = "Go to page";
+    input.setAttribute("aria-label", "Go to page");
+    var list = document.createElement("ul");
+    box.appendChild(input);
+    box.appendChild(list);
+    document.body.appendChild(box);
+
+    var timer = null;
+    var selected = 0;
+    // answers can arrive out of order; only the latest request counts
+    var latest = 0;
+
+    function links() {
+        return list.querySelectorAll("a");
+    }
+
+    function select(index) {
+        var all = links();
+        if (all.length === 0) {
+            return;
+        }
+        selected = (index + all.length) % all.length;
+        all.forEach(function (link, i) {
+            link.classList.toggle("selected", i === selected);
+        });
+    }
+
+    function show(titles) {
+        list.textContent = "";
+        titles.forEach(function (title) {
+            var item = document.createElement("li");
+            var link = document.createElement("a");
+            link.href = title.link;
+            link.textContent = title.name;
+            item.appendChild(link);
+            list.appendChild(item);
+        });
+        select(0);
+    }
+
+    function lookup() {
+        var query = input.value.trim();
+        var request = ++latest;
+        if (!query) {
+            show([]);
+            return;
+        }
+        fetch(API_TITLES_PATH + "?q=" + encodeURIComponent(query), { credentials: "same-origin" })
+            .then(function (response) {
+                return response.ok ? response.json() : { titles: [] };
+            })
+            .then(function (body) {
+                if (request === latest) {
+                    show(body.titles);
+                }
+            })
+            .catch(function () {});
+    }
+
+    function open() {
+        box.hidden = false;
+        input.value = "";
+        show([]);
+        input.focus();
+    }
+
+    function close() {
+        box.hidden = true;
+        clearTimeout(timer);
+    }
+
+    document.addEventListener("keydown", function (event) {
+        if (event.key === "k" && (event.ctrlKey || event.metaKey)) {
+            event.preventDefault();
+            if (box.hidden) {
+                open();
+            } else {
+                close();
+            }
+        }
+    });
+
+    input.addEventListener("input", function () {
+        clearTimeout(timer);
+        timer = setTimeout(lookup, DELAY);
+    });
+
+    input.addEventListener("keydown", function (event) {
+        if (event.key === "Escape") {
+            close();
+        } else if (event.key === "ArrowDown") {
+            event.preventDefault();
+            select(selected + 1);
+        } else if (event.key === "ArrowUp") {
+            event.preventDefault();
+            select(selected - 1);
+        } else if (event.key === "Enter") {
+            var link = links()[selected];
+            if (link) {
+                window.location = link.href;
+            }
+        }
+    });
+
+    input.addEventListener("blur", function () {
+        // let a click on a result land before the box goes
+        setTimeout(close, 200);
+    });
+})();